@@ -0,0 +1,54 @@
+//! Time large `>(...)` transformations, which spend much of their time in
+//! the interpreter's control flow rather than in the commands themselves.
+//!
+//! Run with `cargo bench --bench execute`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use ludwig::{Editor, compile};
+
+const ROUNDS: u32 = 5;
+const LINES: usize = 100_000;
+
+/// Procedures to time, as a name, any setup commands, and the commands timed.
+const PROCEDURES: &[(&str, &str, &str)] = &[
+    ("advance", "", ">(A)"),
+    ("insert each line", "", ">(J I/-/ A)"),
+    ("nested compounds", "", ">(3(J) (EQS/x/[:J]) 2(ZR ZL) A)"),
+    ("exits", "", ">(A 3(J XS) (XS J) (2(2XS)))"),
+    ("exit handlers", "", ">(J[ZR[ZL:XF]:XF] ZL[:J] A[:XF])"),
+    (
+        "deep exits",
+        "",
+        ">(A (((((((((8XS J) J) J) J) J) J) J) J)))",
+    ),
+    ("EN procedure", "SA/step/2J ZL A/ SR/step/", ">(EN/step/)"),
+];
+
+fn main() {
+    let mut text = String::new();
+    for i in 0..LINES {
+        text.push_str(&format!("line {i}: the quick brown fox\n"));
+    }
+
+    println!("{LINES} lines, best of {ROUNDS}");
+    for &(name, setup, commands) in PROCEDURES {
+        let setup = compile(setup).unwrap();
+        let code = compile(commands).unwrap();
+        let mut best = Duration::MAX;
+        for _ in 0..ROUNDS {
+            let mut editor = Editor::from_str(&text);
+            // Leave room for the insertions.
+            let _ = editor.current_frame_mut().set_space_limit(2 * text.len());
+            editor.execute(&setup);
+            let start = Instant::now();
+            black_box(editor.execute(&code));
+            best = best.min(start.elapsed());
+        }
+        println!(
+            "{name}: {best:?} ({:.0} ns per line)",
+            best.as_nanos() as f64 / LINES as f64
+        );
+    }
+}
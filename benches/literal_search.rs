@@ -0,0 +1,66 @@
+//! Compare literal search with `LiteralSearcher` against a character-by-character
+//! scan that reads each character with `Rope::char`.
+//!
+//! Run with `cargo bench --bench literal_search`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use ludwig::literal::LiteralSearcher;
+use ropey::Rope;
+
+const ROUNDS: u32 = 5;
+
+/// The search the frame used before `LiteralSearcher`.
+fn naive_find(rope: &Rope, pattern: &[char], case_sensitive: bool) -> Option<usize> {
+    let len = rope.len_chars();
+    (0..=len.saturating_sub(pattern.len())).find(|&start| {
+        pattern.iter().enumerate().all(|(i, &pc)| {
+            let rc = rope.char(start + i);
+            if case_sensitive {
+                rc == pc
+            } else {
+                rc.to_lowercase().eq(pc.to_lowercase())
+            }
+        })
+    })
+}
+
+fn best_of(mut f: impl FnMut() -> Option<usize>) -> (Duration, Option<usize>) {
+    let mut best = Duration::MAX;
+    let mut found = None;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        found = black_box(f());
+        best = best.min(start.elapsed());
+    }
+    (best, found)
+}
+
+fn main() {
+    let mut text = String::new();
+    for i in 0..100_000 {
+        text.push_str(&format!(
+            "line {i}: the quick brown fox jumps over the lazy dog\n"
+        ));
+    }
+    text.push_str("Needle in the Haystack\n");
+    let rope = Rope::from_str(&text);
+
+    println!("{} chars, best of {ROUNDS}", rope.len_chars());
+    for (pattern, case_sensitive) in [
+        ("Needle in the Haystack", true),
+        ("needle in the haystack", false),
+        ("zq", true),
+    ] {
+        let chars: Vec<char> = pattern.chars().collect();
+        let (naive, naive_found) = best_of(|| naive_find(&rope, &chars, case_sensitive));
+        let searcher = LiteralSearcher::new(pattern, case_sensitive);
+        let (bmh, bmh_found) = best_of(|| searcher.find_forward(rope.slice(..), 0));
+        assert_eq!(naive_found, bmh_found);
+        println!(
+            "{pattern:?} case_sensitive={case_sensitive}: naive {naive:?}, literal {bmh:?} ({:.1}x)",
+            naive.as_secs_f64() / bmh.as_secs_f64()
+        );
+    }
+}
@@ -4,12 +4,14 @@
 //! into a main event loop.
 
 use anyhow::Result;
+use crossterm::event::KeyCode;
 
 use crate::TrailParam;
 use crate::code::{CmdOp, CompiledCode, Instruction};
 use crate::compiler;
+use crate::debugger::{DebugAction, Debugger, Step, Stepper, describe};
 use crate::edit_mode::EditMode;
-use crate::editor::Editor;
+use crate::editor::{Editor, Prompter};
 use crate::frame::EditCommands;
 use crate::keybind::{self, KeyAction};
 use crate::lead_param::LeadParam;
@@ -23,6 +25,8 @@ pub struct App {
     pub mode: EditMode,
     pub file_path: Option<String>,
     pub running: bool,
+    /// Breakpoints kept from one debugging run to the next.
+    stepper: Stepper,
 }
 
 impl App {
@@ -33,6 +37,7 @@ impl App {
             mode: EditMode::Insert,
             file_path,
             running: true,
+            stepper: Stepper::new(),
         }
     }
 
@@ -82,6 +87,9 @@ impl App {
             KeyAction::Save => {
                 self.handle_save(terminal);
             }
+            KeyAction::Debug => {
+                self.debug_input(terminal);
+            }
             KeyAction::ToggleMode => {
                 self.mode = match self.mode {
                     EditMode::Insert => EditMode::Overtype,
@@ -126,7 +134,7 @@ impl App {
                 self.execute_code(&code, terminal);
             }
             Err(e) => {
-                self.screen.show_message(terminal, &format!("Error: {}", e));
+                self.screen.show_message(terminal, &e.to_string());
                 terminal.beep();
             }
         }
@@ -134,6 +142,7 @@ impl App {
 
     /// Execute compiled code, intercepting window commands.
     fn execute_code(&mut self, code: &CompiledCode, terminal: &mut dyn Terminal) {
+        self.editor.start_command_line();
         for instr in code.instructions() {
             if let Instruction::SimpleCmd { op, lead, .. } = instr
                 && self.try_handle_window_cmd(*op, *lead, terminal)
@@ -141,12 +150,64 @@ impl App {
                 continue;
             }
             // Not a window command — pass single instruction to interpreter
-            let single = CompiledCode::new(vec![instr.clone()]);
-            let outcome = self.editor.execute(&single);
+            let mut prompter = TerminalPrompter {
+                screen: &mut self.screen,
+                terminal: &mut *terminal,
+            };
+            let outcome = self
+                .editor
+                .execute_instructions_with_prompter(std::slice::from_ref(instr), &mut prompter);
             if !outcome.is_success() {
+                self.report_failure(terminal);
+                return;
+            }
+        }
+    }
+
+    /// Explain why the last execution failed, and beep.
+    fn report_failure(&mut self, terminal: &mut dyn Terminal) {
+        let failure = self
+            .editor
+            .take_failure()
+            .map(|f| format!("Command failed {f}"));
+        let message = self.editor.take_message();
+        let text = [failure, message].into_iter().flatten().collect::<Vec<_>>();
+        let text = text.join("\n");
+        if !text.is_empty() {
+            self.screen.show_message(terminal, &text);
+        }
+        terminal.beep();
+    }
+
+    /// Read a command and run it in the step debugger, pausing before its
+    /// first instruction. Window commands are not intercepted while
+    /// debugging.
+    fn debug_input(&mut self, terminal: &mut dyn Terminal) {
+        let input = read_line(&mut self.screen, terminal, "Debug: ");
+        self.screen
+            .clear_message(self.editor.current_frame(), terminal);
+        let Some(input) = input.filter(|input| !input.is_empty()) else {
+            return;
+        };
+        let code = match compiler::compile(&input) {
+            Ok(code) => code,
+            Err(e) => {
+                self.screen.show_message(terminal, &e.to_string());
                 terminal.beep();
                 return;
             }
+        };
+        self.stepper.step_into();
+        let mut debugger = TerminalDebugger {
+            screen: &mut self.screen,
+            terminal: &mut *terminal,
+            stepper: &mut self.stepper,
+        };
+        let outcome = self.editor.execute_debugged(&code, &mut debugger);
+        self.screen
+            .clear_message(self.editor.current_frame(), terminal);
+        if !outcome.is_success() {
+            self.report_failure(terminal);
         }
     }
 
@@ -234,55 +295,15 @@ impl App {
 
     /// Handle command input mode (after pressing Escape).
     fn command_input(&mut self, terminal: &mut dyn Terminal) {
-        const PROMPT: &str = "Command: ";
-        let prompt_len = PROMPT.len();
-
-        // Show prompt via buffered screen
-        self.screen.msg_rows = 1;
-        self.screen.update_message_row(terminal, PROMPT, prompt_len);
-
-        // Read command line
-        let mut input = String::new();
-
-        loop {
-            let key = match terminal.read_key() {
-                Ok(key) => key,
-                Err(_) => continue,
-            };
-
-            match key.code {
-                crossterm::event::KeyCode::Enter => {
-                    break;
-                }
-                crossterm::event::KeyCode::Esc => {
-                    // Cancel command input
-                    self.screen
-                        .clear_message(self.editor.current_frame(), terminal);
-                    return;
-                }
-                crossterm::event::KeyCode::Backspace => {
-                    if !input.is_empty() {
-                        input.pop();
-                        let line = format!("{}{}", PROMPT, input);
-                        self.screen
-                            .update_message_row(terminal, &line, prompt_len + input.len());
-                    }
-                }
-                crossterm::event::KeyCode::Char(ch) => {
-                    input.push(ch);
-                    let line = format!("{}{}", PROMPT, input);
-                    self.screen
-                        .update_message_row(terminal, &line, prompt_len + input.len());
-                }
-                _ => {}
-            }
-        }
+        let input = read_line(&mut self.screen, terminal, "Command: ");
 
         // Clear prompt
         self.screen
             .clear_message(self.editor.current_frame(), terminal);
 
-        if !input.is_empty() {
+        if let Some(input) = input
+            && !input.is_empty()
+        {
             self.execute_command_string(&input, terminal);
         }
     }
@@ -320,12 +341,30 @@ impl App {
 
     /// Handle save.
     fn handle_save(&mut self, terminal: &mut dyn Terminal) {
-        if let Some(path) = &self.file_path {
-            let mut contents = self.editor.to_string();
-            if !contents.is_empty() && !contents.ends_with('\n') {
-                contents.push('\n');
+        if self.editor.is_paged() {
+            match self.editor.save_paged() {
+                Ok(()) => {
+                    let path = self.file_path.as_deref().unwrap_or_default();
+                    self.screen
+                        .show_message(terminal, &format!("{} saved.", path));
+                }
+                Err(e) => {
+                    self.screen
+                        .show_message(terminal, &format!("Save failed: {}", e));
+                    terminal.beep();
+                }
             }
-            let line_count = contents.lines().count();
+        } else if let Some(path) = &self.file_path {
+            let contents = match self.editor.file_contents() {
+                Ok(contents) => contents,
+                Err(e) => {
+                    self.screen
+                        .show_message(terminal, &format!("Save failed: {}", e));
+                    terminal.beep();
+                    return;
+                }
+            };
+            let line_count = self.editor.to_string().lines().count();
 
             // Create backup
             let backup = format!("{}~1", path);
@@ -335,6 +374,9 @@ impl App {
 
             match std::fs::write(path, &contents) {
                 Ok(()) => {
+                    if let Some(journal) = self.editor.current_frame_mut().journal_mut() {
+                        let _ = journal.rebase(&contents);
+                    }
                     self.screen.show_message(
                         terminal,
                         &format!(
@@ -358,3 +400,136 @@ impl App {
         }
     }
 }
+
+/// Answers `&prompt&` dereferences by reading a line on the message row.
+struct TerminalPrompter<'a> {
+    screen: &'a mut Screen,
+    terminal: &'a mut dyn Terminal,
+}
+
+impl Prompter for TerminalPrompter<'_> {
+    fn prompt(&mut self, prompt: &str) -> Option<String> {
+        let input = read_line(self.screen, self.terminal, prompt);
+        self.screen.msg_rows = 0;
+        input
+    }
+    fn interrupted(&mut self) -> bool {
+        self.terminal.poll_interrupt()
+    }
+}
+
+/// Pauses where its stepper says, showing the instruction and the frame
+/// until the user decides how to go on.
+struct TerminalDebugger<'a> {
+    screen: &'a mut Screen,
+    terminal: &'a mut dyn Terminal,
+    stepper: &'a mut Stepper,
+}
+
+impl TerminalDebugger<'_> {
+    /// Describe `step` in the message area, with the keys that continue.
+    fn show(&mut self, step: &Step<'_>) {
+        let breakpoint = if self.stepper.is_breakpoint(step) {
+            "  (breakpoint)"
+        } else {
+            ""
+        };
+        let dot = step.frame.dot();
+        let mut state = format!(
+            "Frame {}, dot {}:{}, depth {}",
+            step.frame.name(),
+            dot.line + 1,
+            dot.column + 1,
+            step.depth
+        );
+        if !step.repeats.is_empty() {
+            let repeats: Vec<_> = step.repeats.iter().map(ToString::to_string).collect();
+            state += &format!(", repeat {}", repeats.join(" "));
+        }
+        let text = format!(
+            "{} {}{}\n{}\nS)tep  O)ver  C)ontinue  B)reakpoint  A)bort",
+            step.location().unwrap_or_else(|| "Next:".to_string()),
+            describe(step.instruction),
+            breakpoint,
+            state
+        );
+        self.screen.show_message(self.terminal, &text);
+        self.screen.fixup(step.frame, self.terminal);
+    }
+}
+
+impl Prompter for TerminalDebugger<'_> {
+    fn prompt(&mut self, prompt: &str) -> Option<String> {
+        let input = read_line(self.screen, self.terminal, prompt);
+        self.screen.msg_rows = 0;
+        input
+    }
+    fn interrupted(&mut self) -> bool {
+        self.terminal.poll_interrupt()
+    }
+}
+
+impl Debugger for TerminalDebugger<'_> {
+    fn before(&mut self, step: &Step<'_>) -> DebugAction {
+        if !self.stepper.should_pause(step) {
+            return DebugAction::Continue;
+        }
+        self.show(step);
+        let action = loop {
+            let key = match self.terminal.read_key() {
+                Ok(key) => key,
+                Err(_) => continue,
+            };
+            match key.code {
+                KeyCode::Char('s' | 'S') | KeyCode::Enter => self.stepper.step_into(),
+                KeyCode::Char('o' | 'O') => self.stepper.step_over(step),
+                KeyCode::Char('c' | 'C') => self.stepper.resume(),
+                KeyCode::Char('b' | 'B') => {
+                    self.stepper.toggle_breakpoint(step);
+                    self.show(step);
+                    continue;
+                }
+                KeyCode::Char('a' | 'A') | KeyCode::Esc => break DebugAction::Abort,
+                _ => continue,
+            }
+            break DebugAction::Continue;
+        };
+        self.screen.msg_rows = 0;
+        action
+    }
+}
+
+/// Read a line of input on the message row after `prompt`.
+///
+/// Returns `None` if the user cancels with Escape.
+fn read_line(screen: &mut Screen, terminal: &mut dyn Terminal, prompt: &str) -> Option<String> {
+    let prompt_len = prompt.len();
+
+    // Show prompt via buffered screen
+    screen.msg_rows = 1;
+    screen.update_message_row(terminal, prompt, prompt_len);
+
+    let mut input = String::new();
+    loop {
+        let key = match terminal.read_key() {
+            Ok(key) => key,
+            Err(_) => continue,
+        };
+
+        match key.code {
+            crossterm::event::KeyCode::Enter => return Some(input),
+            crossterm::event::KeyCode::Esc => return None,
+            crossterm::event::KeyCode::Backspace if !input.is_empty() => {
+                input.pop();
+                let line = format!("{}{}", prompt, input);
+                screen.update_message_row(terminal, &line, prompt_len + input.len());
+            }
+            crossterm::event::KeyCode::Char(ch) => {
+                input.push(ch);
+                let line = format!("{}{}", prompt, input);
+                screen.update_message_row(terminal, &line, prompt_len + input.len());
+            }
+            _ => {}
+        }
+    }
+}
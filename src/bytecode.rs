@@ -0,0 +1,549 @@
+//! Flat bytecode for compiled Ludwig commands.
+//!
+//! [`lower`] turns the instruction tree of [`CompiledCode`] into a list of
+//! [`Op`]s in which compound commands and exit handlers become jumps, and
+//! [`run`] executes it with a single loop. An exit such as `2XS` jumps
+//! straight to the end of the compound command it leaves, rather than
+//! returning through each level in between.
+//!
+//! Code lowered for a debugger instead has exits return through each level,
+//! so that the debugger hears the outcome of every compound command they
+//! leave, just as it would for a failure.
+//!
+//! [`CompiledCode`]: crate::code::CompiledCode
+
+use crate::code::{ExecOutcome, ExitHandler, ExitLevels, Instruction, RepeatCount, SourceSpan};
+use crate::debugger::{DebugAction, Repeat, Step};
+use crate::exec_context::ExecutionContext;
+use crate::interpreter::{execute_command, unwrap_exit_level};
+use crate::{CmdFailure, FailedCommand};
+
+/// A lowered sequence of instructions, borrowing the instructions it was
+/// lowered from.
+pub(crate) struct Program<'a> {
+    ops: Vec<Op<'a>>,
+}
+
+/// One bytecode operation. Jump targets are indices into [`Program::ops`].
+///
+/// Each instruction in a sequence becomes a `Step`, its own operations, and a
+/// `Next` that goes on to the following instruction if it succeeded or to the
+/// end of the sequence if not. What follows the end of a sequence decides
+/// what to do with its outcome: `Repeat` for the body of a compound command,
+/// `EndHandler` for exit handler code, and the end of the program at the top.
+#[derive(Debug)]
+enum Op<'a> {
+    /// Check the limits and offer the instruction to any debugger, going to
+    /// `exit` with [`ExecOutcome::Abort`] if execution is to stop.
+    Step {
+        instruction: &'a Instruction,
+        exit: usize,
+    },
+    /// Execute a simple command.
+    Command(&'a Instruction),
+    /// `Step`, `Command` and `Next` in one, for a simple command without an
+    /// exit handler when there is no debugger.
+    Run {
+        instruction: &'a Instruction,
+        exit: usize,
+    },
+    /// Exit with an outcome, as `XS`, `XF` or `XA` does, leaving the
+    /// following operations to return it through each level.
+    Exit(ExecOutcome),
+    /// Exit by jumping straight to where the exit leads.
+    ExitTo(ExitTarget),
+    /// Tell the debugger how the instruction went.
+    After(&'a Instruction),
+    /// Start repeating a compound command's body as `repeat` says. If it
+    /// repeats as many times as a counter that is negative, fail instead and
+    /// go to `skip`, past the body.
+    Enter {
+        repeat: RepeatCount,
+        span: SourceSpan,
+        skip: usize,
+    },
+    /// Start the next iteration, or go to `done` once there have been
+    /// enough. An `empty` body runs no instructions to count against the
+    /// limits, so its iterations are counted instead, going to `done` with
+    /// [`ExecOutcome::Abort`] if execution is to stop.
+    Iterate { done: usize, empty: bool },
+    /// At the end of an iteration, go back to `start` if it succeeded.
+    Repeat { start: usize },
+    /// Finish repeating.
+    Leave,
+    /// Run an exit handler's success or failure code, if it has any.
+    Handle {
+        on_success: Option<usize>,
+        on_failure: Option<usize>,
+        done: usize,
+    },
+    /// The end of exit handler code.
+    EndHandler { done: usize },
+    /// Go on to the next instruction if the last one succeeded, otherwise to
+    /// `exit`.
+    Next { exit: usize },
+}
+
+/// Where an exit jumps to, with `depth` and `repeats` counted from those when
+/// the program started.
+#[derive(Debug)]
+enum ExitTarget {
+    /// The `Repeat` at `pc` of the compound command being left, with the
+    /// outcome of its body.
+    Repeat {
+        pc: usize,
+        outcome: ExecOutcome,
+        depth: usize,
+        repeats: usize,
+    },
+    /// Out of the program altogether, with `outcome`.
+    Out(ExecOutcome),
+}
+
+/// Lower `instructions`, the top level of a command or procedure, to be run
+/// with a debugger attached if `debugging`.
+pub(crate) fn lower(instructions: &[Instruction], debugging: bool) -> Program<'_> {
+    let mut lowering = Lowering {
+        // Most instructions lower to one operation, or three with a debugger.
+        ops: Vec::with_capacity(3 * instructions.len()),
+        debugging,
+        loops: Vec::new(),
+        depth: 0,
+    };
+    lowering.sequence(instructions);
+    Program { ops: lowering.ops }
+}
+
+struct Lowering<'a> {
+    ops: Vec<Op<'a>>,
+    debugging: bool,
+    /// The compound commands around the code being lowered, innermost last.
+    loops: Vec<Loop>,
+    /// How many compound commands and exit handlers are around the code.
+    depth: usize,
+}
+
+/// A compound command around the code being lowered.
+struct Loop {
+    /// Exits that leave this compound command, to be pointed at its `Repeat`.
+    exits: Vec<usize>,
+    depth: usize,
+}
+
+impl<'a> Lowering<'a> {
+    fn emit(&mut self, op: Op<'a>) -> usize {
+        self.ops.push(op);
+        self.ops.len() - 1
+    }
+
+    fn sequence(&mut self, instructions: &'a [Instruction]) {
+        let start = self.ops.len();
+        for instruction in instructions {
+            if !self.debugging
+                && let Instruction::SimpleCmd {
+                    exit_handler: None, ..
+                } = instruction
+            {
+                self.emit(Op::Run {
+                    instruction,
+                    exit: 0,
+                });
+                continue;
+            }
+            self.emit(Op::Step {
+                instruction,
+                exit: 0,
+            });
+            self.instruction(instruction);
+            self.emit(Op::Next { exit: 0 });
+        }
+        // Nested sequences have been pointed at their own ends, which are
+        // never 0, so whatever still goes to 0 belongs to this one.
+        let end = self.ops.len();
+        for op in &mut self.ops[start..end] {
+            if let Op::Step { exit, .. } | Op::Run { exit, .. } | Op::Next { exit } = op
+                && *exit == 0
+            {
+                *exit = end;
+            }
+        }
+    }
+
+    fn instruction(&mut self, instruction: &'a Instruction) {
+        match instruction {
+            Instruction::SimpleCmd { exit_handler, .. } => {
+                self.emit(Op::Command(instruction));
+                self.after(instruction);
+                self.handler(exit_handler.as_ref());
+            }
+            Instruction::CompoundCmd {
+                repeat,
+                body,
+                exit_handler,
+                span,
+            } => {
+                let enter = self.emit(Op::Enter {
+                    repeat: *repeat,
+                    span: *span,
+                    skip: 0,
+                });
+                self.depth += 1;
+                let empty = body.instructions().is_empty();
+                let start = self.emit(Op::Iterate { done: 0, empty });
+                self.loops.push(Loop {
+                    exits: Vec::new(),
+                    depth: self.depth,
+                });
+                self.sequence(body.instructions());
+                let this = self.loops.pop().expect("pushed above");
+                let repeat = self.emit(Op::Repeat { start });
+                self.ops[start] = Op::Iterate {
+                    done: repeat + 1,
+                    empty,
+                };
+                for exit in this.exits {
+                    if let Op::ExitTo(ExitTarget::Repeat { pc, .. }) = &mut self.ops[exit] {
+                        *pc = repeat;
+                    }
+                }
+                self.depth -= 1;
+                let leave = self.emit(Op::Leave);
+                if let Op::Enter { skip, .. } = &mut self.ops[enter] {
+                    *skip = leave + 1;
+                }
+                self.after(instruction);
+                self.handler(exit_handler.as_ref());
+            }
+            Instruction::ExitSuccess(levels, _) => {
+                self.exit(
+                    *levels,
+                    |remaining| ExecOutcome::ExitSuccess { remaining },
+                    ExecOutcome::ExitSuccessAll,
+                );
+                self.after(instruction);
+            }
+            Instruction::ExitFailure(levels, _) => {
+                self.exit(
+                    *levels,
+                    |remaining| ExecOutcome::ExitFailure { remaining },
+                    ExecOutcome::ExitFailureAll,
+                );
+                self.after(instruction);
+            }
+            Instruction::ExitAbort(_) => {
+                self.emit(match self.debugging {
+                    true => Op::Exit(ExecOutcome::Abort),
+                    false => Op::ExitTo(ExitTarget::Out(ExecOutcome::Abort)),
+                });
+                self.after(instruction);
+            }
+        }
+    }
+
+    fn after(&mut self, instruction: &'a Instruction) {
+        if self.debugging {
+            self.emit(Op::After(instruction));
+        }
+    }
+
+    /// Lower `XS` or `XF`, whose outcome leaving `n` levels is `count(n)`,
+    /// or `all` leaving every level.
+    fn exit(&mut self, levels: ExitLevels, count: fn(usize) -> ExecOutcome, all: ExecOutcome) {
+        let enclosing = self.loops.len();
+        let op = match levels {
+            ExitLevels::Count(n) if self.debugging => Op::Exit(count(n)),
+            ExitLevels::All if self.debugging => Op::Exit(all),
+            ExitLevels::Count(n) if n <= enclosing => Op::ExitTo(ExitTarget::Repeat {
+                pc: 0,
+                outcome: unwrap_exit_level(count(1)),
+                depth: self.loops[enclosing - n].depth,
+                repeats: enclosing - n + 1,
+            }),
+            ExitLevels::Count(n) => Op::ExitTo(ExitTarget::Out(count(n - enclosing))),
+            ExitLevels::All => Op::ExitTo(ExitTarget::Out(all)),
+        };
+        let pc = self.emit(op);
+        if let Op::ExitTo(ExitTarget::Repeat { repeats, .. }) = self.ops[pc] {
+            self.loops[repeats - 1].exits.push(pc);
+        }
+    }
+
+    fn handler(&mut self, handler: Option<&'a ExitHandler>) {
+        let Some(handler) = handler else {
+            return;
+        };
+        let handle = self.emit(Op::Handle {
+            on_success: None,
+            on_failure: None,
+            done: 0,
+        });
+        self.depth += 1;
+        let mut branches = [None, None];
+        let mut ends = Vec::new();
+        for (branch, code) in branches
+            .iter_mut()
+            .zip([&handler.on_success, &handler.on_failure])
+        {
+            if let Some(code) = code {
+                *branch = Some(self.ops.len());
+                self.sequence(code.instructions());
+                ends.push(self.emit(Op::EndHandler { done: 0 }));
+            }
+        }
+        let [on_success, on_failure] = branches;
+        self.depth -= 1;
+        let done = self.ops.len();
+        self.ops[handle] = Op::Handle {
+            on_success,
+            on_failure,
+            done,
+        };
+        for pc in ends {
+            self.ops[pc] = Op::EndHandler { done };
+        }
+    }
+}
+
+/// Run `program`, returning the outcome of its last instruction to run.
+pub(crate) fn run(ctx: &mut ExecutionContext, program: &Program<'_>) -> ExecOutcome {
+    let base_depth = ctx.depth;
+    let base_repeats = ctx.repeats.len();
+    let mut outcome = ExecOutcome::Success;
+    // Why the last command failed, for the debugger.
+    let mut reason = None;
+    let mut pc = 0;
+    while let Some(op) = program.ops.get(pc) {
+        pc += 1;
+        match op {
+            Op::Step { instruction, exit } => {
+                if let Some(message) = ctx.check_limits() {
+                    stop(ctx, instruction, message);
+                    outcome = ExecOutcome::Abort;
+                    pc = *exit;
+                } else if let Some(debugger) = ctx.debugger.as_deref_mut() {
+                    let step = Step {
+                        instruction,
+                        source: ctx.source.as_deref(),
+                        depth: ctx.depth,
+                        repeats: &ctx.repeats,
+                        frame: ctx.frame_set.current_frame(),
+                    };
+                    if debugger.before(&step) == DebugAction::Abort {
+                        outcome = ExecOutcome::Abort;
+                        pc = *exit;
+                    }
+                }
+            }
+            Op::Command(instruction) => (outcome, reason) = execute_command(ctx, instruction),
+            Op::Run { instruction, exit } => {
+                if let Some(message) = ctx.check_limits() {
+                    stop(ctx, instruction, message);
+                    outcome = ExecOutcome::Abort;
+                    pc = *exit;
+                    continue;
+                }
+                outcome = execute_command(ctx, instruction).0;
+                if outcome == ExecOutcome::Success {
+                    ctx.failure = None;
+                } else {
+                    pc = *exit;
+                }
+            }
+            Op::Exit(exit_outcome) => outcome = *exit_outcome,
+            Op::ExitTo(ExitTarget::Repeat {
+                pc: repeat,
+                outcome: body_outcome,
+                depth,
+                repeats,
+            }) => {
+                ctx.depth = base_depth + depth;
+                ctx.repeats.truncate(base_repeats + repeats);
+                outcome = *body_outcome;
+                pc = *repeat;
+            }
+            Op::ExitTo(ExitTarget::Out(exit_outcome)) => {
+                ctx.depth = base_depth;
+                ctx.repeats.truncate(base_repeats);
+                return *exit_outcome;
+            }
+            Op::After(instruction) => {
+                if let Some(debugger) = ctx.debugger.as_deref_mut() {
+                    let step = Step {
+                        instruction,
+                        source: ctx.source.as_deref(),
+                        depth: ctx.depth,
+                        repeats: &ctx.repeats,
+                        frame: ctx.frame_set.current_frame(),
+                    };
+                    let reason = match instruction {
+                        Instruction::SimpleCmd { .. } => reason.as_ref(),
+                        _ => None,
+                    };
+                    debugger.after(&step, &outcome, reason);
+                }
+            }
+            Op::Enter { repeat, span, skip } => {
+                let count = match *repeat {
+                    RepeatCount::Once => Some(1),
+                    RepeatCount::Times(n) => Some(n),
+                    RepeatCount::Indefinite => None,
+                    RepeatCount::Counter(name) => {
+                        match usize::try_from(ctx.current_frame().counter(name)) {
+                            Ok(n) => Some(n),
+                            Err(_) => {
+                                ctx.failure = Some(FailedCommand {
+                                    source: ctx.source.clone(),
+                                    span: *span,
+                                    reason: Some(CmdFailure::OutOfRange),
+                                });
+                                outcome = ExecOutcome::Failure;
+                                pc = *skip;
+                                continue;
+                            }
+                        }
+                    }
+                };
+                ctx.depth += 1;
+                ctx.repeats.push(Repeat {
+                    iteration: 0,
+                    count,
+                });
+            }
+            Op::Iterate { done, empty } => {
+                // An empty body, as in `>()`, would otherwise never stop.
+                if *empty && let Some(message) = ctx.check_limits() {
+                    ctx.message = Some(message);
+                    outcome = ExecOutcome::Abort;
+                    pc = *done;
+                    continue;
+                }
+                let repeat = ctx.repeats.last_mut().expect("entered a compound command");
+                if repeat.count.is_some_and(|n| repeat.iteration >= n) {
+                    outcome = ExecOutcome::Success;
+                    pc = *done;
+                } else {
+                    repeat.iteration += 1;
+                }
+            }
+            Op::Repeat { start } => {
+                outcome = unwrap_exit_level(outcome);
+                if outcome == ExecOutcome::Success {
+                    pc = *start;
+                }
+            }
+            Op::Leave => {
+                ctx.repeats.pop();
+                ctx.depth -= 1;
+            }
+            Op::Handle {
+                on_success,
+                on_failure,
+                done,
+            } => {
+                let code = match outcome {
+                    ExecOutcome::Success => on_success,
+                    ExecOutcome::Failure => on_failure,
+                    // XS/XF/XA/Abort propagate through handlers without triggering them
+                    _ => {
+                        pc = *done;
+                        continue;
+                    }
+                };
+                match code {
+                    Some(code) => {
+                        ctx.depth += 1;
+                        pc = *code;
+                    }
+                    None => {
+                        outcome = ExecOutcome::Success;
+                        pc = *done;
+                    }
+                }
+            }
+            Op::EndHandler { done } => {
+                ctx.depth -= 1;
+                pc = *done;
+            }
+            Op::Next { exit } => {
+                // A failure that was handled, e.g. by `[:]`, is not to blame
+                // for anything that fails later.
+                if outcome == ExecOutcome::Success {
+                    ctx.failure = None;
+                } else {
+                    pc = *exit;
+                }
+            }
+        }
+    }
+    outcome
+}
+
+/// Record that execution stopped at `instruction` because it reached a limit.
+fn stop(ctx: &mut ExecutionContext, instruction: &Instruction, message: String) {
+    ctx.message = Some(message);
+    if let Instruction::SimpleCmd { span, .. } = instruction {
+        ctx.failure = Some(FailedCommand {
+            source: ctx.source.clone(),
+            span: *span,
+            reason: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+
+    #[test]
+    fn test_simple_commands_fused() {
+        let code = compile("A J").unwrap();
+        let program = lower(code.instructions(), false);
+        assert!(matches!(
+            program.ops[..],
+            [Op::Run { exit: 2, .. }, Op::Run { exit: 2, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_exit_jumps_to_compound_left() {
+        let code = compile("(A (2XS))").unwrap();
+        let program = lower(code.instructions(), false);
+        let Some(Op::ExitTo(ExitTarget::Repeat {
+            pc, depth, repeats, ..
+        })) = program.ops.iter().find(|op| matches!(op, Op::ExitTo(_)))
+        else {
+            panic!("no exit in {:?}", program.ops);
+        };
+        assert!(matches!(program.ops[*pc], Op::Repeat { start: 2 }));
+        assert_eq!((*depth, *repeats), (1, 1));
+    }
+
+    #[test]
+    fn test_exit_beyond_program() {
+        let code = compile("(3XF)").unwrap();
+        let program = lower(code.instructions(), false);
+        assert!(program.ops.iter().any(|op| matches!(
+            op,
+            Op::ExitTo(ExitTarget::Out(ExecOutcome::ExitFailure { remaining: 2 }))
+        )));
+    }
+
+    #[test]
+    fn test_debugging_keeps_steps() {
+        let code = compile("A (XS)").unwrap();
+        let program = lower(code.instructions(), true);
+        assert!(
+            !program
+                .ops
+                .iter()
+                .any(|op| matches!(op, Op::Run { .. } | Op::ExitTo(_)))
+        );
+        let steps = program
+            .ops
+            .iter()
+            .filter(|op| matches!(op, Op::Step { .. }));
+        let afters = program.ops.iter().filter(|op| matches!(op, Op::After(_)));
+        assert_eq!((steps.count(), afters.count()), (3, 3));
+    }
+}
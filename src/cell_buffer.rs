@@ -148,13 +148,11 @@ impl CellBuffer {
         if row >= self.height {
             return;
         }
-        let mut c = col;
-        for ch in s.chars() {
+        for (c, ch) in (col..).zip(s.chars()) {
             if c >= self.width {
                 break;
             }
             self.cells[row * self.width + c] = Cell { ch };
-            c += 1;
         }
     }
 
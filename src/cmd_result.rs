@@ -1,3 +1,7 @@
+use std::fmt;
+
+use crate::code::SourceSpan;
+
 /// The result of executing a Ludwig command.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CmdResult {
@@ -18,6 +22,52 @@ pub enum CmdFailure {
     SyntaxError,
     /// A frame with the given name already exists.
     FrameExists,
+    /// Insertion would exceed the frame's space limit.
+    NoRoom,
+    /// A file could not be read or written.
+    FileError,
+}
+
+impl fmt::Display for CmdFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CmdFailure::NotImplemented => "not implemented",
+            CmdFailure::OutOfRange => "out of range",
+            CmdFailure::MarkNotDefined => "mark not defined",
+            CmdFailure::SyntaxError => "invalid parameter",
+            CmdFailure::FrameExists => "frame already exists",
+            CmdFailure::NoRoom => "no room",
+            CmdFailure::FileError => "file error",
+        })
+    }
+}
+
+/// The command that made an execution fail, and where it came from.
+///
+/// Displays as `at MYMACRO:3:12 (mark not defined)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedCommand {
+    /// The span or frame the command was compiled from, or `None` for
+    /// top-level code.
+    pub source: Option<String>,
+    pub span: SourceSpan,
+    /// Why the command failed, if it was a command of its own that failed
+    /// rather than an `EX` that could not run its span.
+    pub reason: Option<CmdFailure>,
+}
+
+impl fmt::Display for FailedCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at ")?;
+        if let Some(source) = &self.source {
+            write!(f, "{source}:")?;
+        }
+        write!(f, "{}", self.span.start)?;
+        if let Some(reason) = &self.reason {
+            write!(f, " ({reason})")?;
+        }
+        Ok(())
+    }
 }
 
 impl CmdResult {
@@ -1,5 +1,7 @@
 //! Tree-based intermediate representation for compiled Ludwig commands.
 
+use std::fmt;
+
 use crate::lead_param::LeadParam;
 use crate::trail_param::TrailParam;
 
@@ -28,19 +30,56 @@ pub enum Instruction {
         lead: LeadParam,
         tpars: Vec<TrailParam>,
         exit_handler: Option<ExitHandler>,
+        /// Where the command sits in its source, exit handler excluded.
+        span: SourceSpan,
     },
     /// A parenthesized group: (cmds), N(cmds), >(cmds)
     CompoundCmd {
         repeat: RepeatCount,
         body: CompiledCode,
         exit_handler: Option<ExitHandler>,
+        /// Where the command sits in its source, from its leading parameter
+        /// to its closing parenthesis.
+        span: SourceSpan,
     },
     /// XS / nXS — exit `n` nesting levels with success
-    ExitSuccess(ExitLevels),
+    ExitSuccess(ExitLevels, SourceSpan),
     /// XF / nXF — exit `n` nesting levels with failure
-    ExitFailure(ExitLevels),
+    ExitFailure(ExitLevels, SourceSpan),
     /// XA — abort all execution
-    ExitAbort,
+    ExitAbort(SourceSpan),
+}
+
+impl Instruction {
+    /// Where the instruction sits in its source, exit handler excluded.
+    pub fn span(&self) -> SourceSpan {
+        match self {
+            Instruction::SimpleCmd { span, .. } | Instruction::CompoundCmd { span, .. } => *span,
+            Instruction::ExitSuccess(_, span)
+            | Instruction::ExitFailure(_, span)
+            | Instruction::ExitAbort(span) => *span,
+        }
+    }
+}
+
+/// A place in Ludwig source: a line and column, both counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SourcePos {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for SourcePos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// The source from `start` up to, but not including, `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceSpan {
+    pub start: SourcePos,
+    pub end: SourcePos,
 }
 
 /// Exit handler: code to run on success and/or failure of a command.
@@ -59,6 +98,8 @@ pub enum RepeatCount {
     Times(usize),
     /// >(cmds) — repeat until body fails
     Indefinite,
+    /// #X(cmds) — execute as many times as counter X holds when it starts
+    Counter(char),
 }
 
 /// How many nesting levels to exit.
@@ -161,6 +202,7 @@ pub enum CmdOp {
     SpanJump,
     SpanIndex,
     SpanAssign,
+    SpanPrint,
 
     // Block commands
     BlockDefine,
@@ -214,7 +256,10 @@ pub enum CmdOp {
     ExecuteString,
     DoLastCommand,
 
-    Extended,
+    CounterSet,
+    CounterAdd,
+    CounterEqual,
+    CounterInsert,
 
     ExitAbort,
     ExitFailure,
@@ -253,7 +298,7 @@ pub enum CmdOp {
 }
 
 /// Outcome of executing compiled code.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExecOutcome {
     Success,
     Failure,
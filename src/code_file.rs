@@ -0,0 +1,566 @@
+//! Span libraries: spans saved to disk with their compiled code.
+//!
+//! A library lets a set of procedures be loaded into a later session without
+//! compiling them again, and keeps the code `SR` compiled for each span even
+//! if its text has changed since. A library is a header giving the format
+//! version, then one record per span; text is written as a byte length and
+//! the bytes that follow on the next line, so that it may hold anything:
+//!
+//! ```text
+//! ludwig-code 1
+//! span <name length> <text length>
+//! <name>
+//! <text>
+//! code
+//! <instructions>
+//! end
+//! ```
+//!
+//! The `code` block is present only for spans that have been compiled. Each
+//! instruction is one of the following, where `<span>` is its place in the
+//! source as `line:column-line:column` and `<handler>` says which branches of
+//! any exit handler follow it (`-`, `h`, `hs`, `hf` or `hsf`):
+//!
+//! ```text
+//! cmd <command name> <lead> <span> <trailing parameter count> <handler>
+//! tpar <delimiter> <length>
+//! <content>
+//! compound <repeat> <span> <handler>
+//! <instructions>
+//! end
+//! success
+//! <instructions>
+//! end
+//! failure
+//! <instructions>
+//! end
+//! xs <levels> <span>
+//! xf <levels> <span>
+//! xa <span>
+//! ```
+//!
+//! Commands are written by name, so a library stays valid however the
+//! opcodes are numbered. A library with a different version is refused, as is
+//! a command with more or fewer trailing parameters than it takes.
+
+use std::io;
+
+use crate::code::{
+    CompiledCode, ExitHandler, ExitLevels, Instruction, RepeatCount, SourcePos, SourceSpan,
+};
+use crate::compiler::{command_name, command_op};
+use crate::lead_param::LeadParam;
+use crate::marks::MarkId;
+use crate::pretty::{lead_text, levels_text};
+use crate::trail_param::TrailParam;
+
+const MAGIC: &str = "ludwig-code";
+const VERSION: u32 = 1;
+
+/// A span as saved in a library.
+#[derive(Debug, Clone)]
+pub struct SavedSpan {
+    /// The span's name, in upper case.
+    pub name: String,
+    pub text: String,
+    /// The code compiled for the span, if it has been compiled.
+    pub code: Option<CompiledCode>,
+}
+
+/// Write `spans` as a library.
+pub fn write_library(spans: &[SavedSpan]) -> String {
+    let mut out = format!("{MAGIC} {VERSION}\n");
+    for span in spans {
+        out.push_str(&format!("span {} {}\n", span.name.len(), span.text.len()));
+        out.push_str(&span.name);
+        out.push('\n');
+        out.push_str(&span.text);
+        out.push('\n');
+        if let Some(code) = &span.code {
+            out.push_str("code\n");
+            write_sequence(&mut out, code);
+        }
+    }
+    out
+}
+
+/// Read the spans in a library written by [`write_library`].
+///
+/// Fails with [`io::ErrorKind::InvalidData`] if `library` is not a library
+/// of this version, or is damaged.
+pub fn read_library(library: &str) -> io::Result<Vec<SavedSpan>> {
+    let mut reader = Reader {
+        rest: library,
+        line: 0,
+    };
+    let header = reader.line()?;
+    match header.strip_prefix(MAGIC).and_then(|v| v.strip_prefix(' ')) {
+        Some(version) if version == VERSION.to_string() => {}
+        Some(version) => {
+            return Err(reader.error(&format!("unsupported version {version}")));
+        }
+        None => return Err(reader.error("not a span library")),
+    }
+    let mut spans = Vec::new();
+    while !reader.rest.is_empty() {
+        let fields = reader.fields("span", 2)?;
+        let (name_len, text_len) = (reader.number(fields[0])?, reader.number(fields[1])?);
+        let name = reader.text(name_len)?.to_string();
+        let text = reader.text(text_len)?.to_string();
+        let code = match reader.rest.starts_with("code\n") {
+            true => {
+                reader.line()?;
+                Some(reader.sequence()?)
+            }
+            false => None,
+        };
+        spans.push(SavedSpan { name, text, code });
+    }
+    Ok(spans)
+}
+
+/// Write the instructions of `code`, then `end`.
+fn write_sequence(out: &mut String, code: &CompiledCode) {
+    for instr in code.instructions() {
+        write_instruction(out, instr);
+    }
+    out.push_str("end\n");
+}
+
+fn write_instruction(out: &mut String, instr: &Instruction) {
+    match instr {
+        Instruction::SimpleCmd {
+            op,
+            lead,
+            tpars,
+            exit_handler,
+            span,
+        } => {
+            // The compiler only produces opcodes that have a name.
+            let name = command_name(*op).expect("compiled command has a name");
+            out.push_str(&format!(
+                "cmd {} {} {} {} {}\n",
+                name,
+                lead_field(*lead),
+                span_field(*span),
+                tpars.len(),
+                handler_field(exit_handler.as_ref())
+            ));
+            for tpar in tpars {
+                out.push_str(&format!("tpar {} {}\n", tpar.delim, tpar.content.len()));
+                out.push_str(&tpar.content);
+                out.push('\n');
+            }
+            write_handler(out, exit_handler.as_ref());
+        }
+        Instruction::CompoundCmd {
+            repeat,
+            body,
+            exit_handler,
+            span,
+        } => {
+            let repeat = match repeat {
+                RepeatCount::Once => "once".to_string(),
+                RepeatCount::Times(n) => n.to_string(),
+                RepeatCount::Indefinite => ">".to_string(),
+                RepeatCount::Counter(name) => format!("#{name}"),
+            };
+            out.push_str(&format!(
+                "compound {} {} {}\n",
+                repeat,
+                span_field(*span),
+                handler_field(exit_handler.as_ref())
+            ));
+            write_sequence(out, body);
+            write_handler(out, exit_handler.as_ref());
+        }
+        Instruction::ExitSuccess(levels, span) => {
+            out.push_str(&format!(
+                "xs {} {}\n",
+                levels_field(*levels),
+                span_field(*span)
+            ));
+        }
+        Instruction::ExitFailure(levels, span) => {
+            out.push_str(&format!(
+                "xf {} {}\n",
+                levels_field(*levels),
+                span_field(*span)
+            ));
+        }
+        Instruction::ExitAbort(span) => out.push_str(&format!("xa {}\n", span_field(*span))),
+    }
+}
+
+fn write_handler(out: &mut String, handler: Option<&ExitHandler>) {
+    let Some(handler) = handler else {
+        return;
+    };
+    if let Some(code) = &handler.on_success {
+        out.push_str("success\n");
+        write_sequence(out, code);
+    }
+    if let Some(code) = &handler.on_failure {
+        out.push_str("failure\n");
+        write_sequence(out, code);
+    }
+}
+
+fn lead_field(lead: LeadParam) -> String {
+    match lead {
+        LeadParam::None => "none".to_string(),
+        lead => lead_text(lead),
+    }
+}
+
+fn levels_field(levels: ExitLevels) -> String {
+    match levels {
+        ExitLevels::Count(n) => n.to_string(),
+        levels => levels_text(levels),
+    }
+}
+
+fn span_field(span: SourceSpan) -> String {
+    format!("{}-{}", span.start, span.end)
+}
+
+fn handler_field(handler: Option<&ExitHandler>) -> String {
+    let Some(handler) = handler else {
+        return "-".to_string();
+    };
+    let mut field = "h".to_string();
+    if handler.on_success.is_some() {
+        field.push('s');
+    }
+    if handler.on_failure.is_some() {
+        field.push('f');
+    }
+    field
+}
+
+/// Reads a library, keeping track of the line for error messages.
+struct Reader<'a> {
+    rest: &'a str,
+    line: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {}: {}", self.line, message),
+        )
+    }
+
+    /// The next line, without its newline.
+    fn line(&mut self) -> io::Result<&'a str> {
+        self.line += 1;
+        let Some((line, rest)) = self.rest.split_once('\n') else {
+            return Err(self.error("unexpected end of library"));
+        };
+        self.rest = rest;
+        Ok(line)
+    }
+
+    /// The `count` fields of the next line, which must start with `keyword`.
+    fn fields(&mut self, keyword: &str, count: usize) -> io::Result<Vec<&'a str>> {
+        let line = self.line()?;
+        let mut fields = line.split(' ');
+        if fields.next() != Some(keyword) {
+            return Err(self.error(&format!("expected {keyword}")));
+        }
+        let fields: Vec<_> = fields.collect();
+        if fields.len() != count {
+            return Err(self.error(&format!("{keyword} needs {count} fields")));
+        }
+        Ok(fields)
+    }
+
+    /// `len` bytes of text, followed by a newline.
+    fn text(&mut self, len: usize) -> io::Result<&'a str> {
+        self.line += 1;
+        let text = self.rest.get(..len);
+        match (text, self.rest.get(len..)) {
+            (Some(text), Some(rest)) if rest.starts_with('\n') => {
+                self.line += text.matches('\n').count();
+                self.rest = &rest[1..];
+                Ok(text)
+            }
+            _ => Err(self.error("text does not match its length")),
+        }
+    }
+
+    fn number(&self, field: &str) -> io::Result<usize> {
+        field
+            .parse()
+            .map_err(|_| self.error(&format!("expected a number, not '{field}'")))
+    }
+
+    /// Instructions up to the next `end`.
+    fn sequence(&mut self) -> io::Result<CompiledCode> {
+        let mut instructions = Vec::new();
+        loop {
+            let line = self.line()?;
+            let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let fields: Vec<_> = rest.split(' ').collect();
+            let instruction = match (keyword, fields.as_slice()) {
+                ("end", [""]) => return Ok(CompiledCode::new(instructions)),
+                ("cmd", [name, lead, span, count, handler]) => {
+                    let (op, expected) = command_op(name)
+                        .ok_or_else(|| self.error(&format!("unknown command '{name}'")))?;
+                    let lead = self.lead(lead)?;
+                    let span = self.span(span)?;
+                    let count = self.number(count)?;
+                    if count != expected {
+                        return Err(self.error(&format!(
+                            "{name} takes {expected} trailing parameters, not {count}"
+                        )));
+                    }
+                    let handler = self.handler_field(handler)?;
+                    let mut tpars = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        let fields = self.fields("tpar", 2)?;
+                        let mut delim = fields[0].chars();
+                        let (Some(delim), None) = (delim.next(), delim.next()) else {
+                            return Err(self.error("expected a delimiter"));
+                        };
+                        let len = self.number(fields[1])?;
+                        tpars.push(TrailParam::new(delim, self.text(len)?.to_string()));
+                    }
+                    Instruction::SimpleCmd {
+                        op,
+                        lead,
+                        tpars,
+                        exit_handler: self.handler(handler)?,
+                        span,
+                    }
+                }
+                ("compound", [repeat, span, handler]) => {
+                    let repeat = match *repeat {
+                        "once" => RepeatCount::Once,
+                        ">" => RepeatCount::Indefinite,
+                        _ if repeat.starts_with('#') => RepeatCount::Counter(self.counter(repeat)?),
+                        n => RepeatCount::Times(self.number(n)?),
+                    };
+                    let span = self.span(span)?;
+                    let handler = self.handler_field(handler)?;
+                    Instruction::CompoundCmd {
+                        repeat,
+                        body: self.sequence()?,
+                        exit_handler: self.handler(handler)?,
+                        span,
+                    }
+                }
+                ("xs", [levels, span]) => {
+                    Instruction::ExitSuccess(self.levels(levels)?, self.span(span)?)
+                }
+                ("xf", [levels, span]) => {
+                    Instruction::ExitFailure(self.levels(levels)?, self.span(span)?)
+                }
+                ("xa", [span]) => Instruction::ExitAbort(self.span(span)?),
+                _ => return Err(self.error(&format!("unexpected '{line}'"))),
+            };
+            instructions.push(instruction);
+        }
+    }
+
+    /// Which branches of an exit handler follow, or `None` if there is no
+    /// handler.
+    fn handler_field(&self, field: &str) -> io::Result<Option<(bool, bool)>> {
+        match field {
+            "-" => Ok(None),
+            "h" => Ok(Some((false, false))),
+            "hs" => Ok(Some((true, false))),
+            "hf" => Ok(Some((false, true))),
+            "hsf" => Ok(Some((true, true))),
+            _ => Err(self.error(&format!("bad exit handler '{field}'"))),
+        }
+    }
+
+    fn handler(&mut self, branches: Option<(bool, bool)>) -> io::Result<Option<ExitHandler>> {
+        let Some((success, failure)) = branches else {
+            return Ok(None);
+        };
+        let mut branch = |present: bool, keyword: &str| -> io::Result<Option<CompiledCode>> {
+            if !present {
+                return Ok(None);
+            }
+            self.fields(keyword, 0)?;
+            self.sequence().map(Some)
+        };
+        Ok(Some(ExitHandler {
+            on_success: branch(success, "success")?,
+            on_failure: branch(failure, "failure")?,
+        }))
+    }
+
+    fn lead(&self, field: &str) -> io::Result<LeadParam> {
+        Ok(match field {
+            "none" => LeadParam::None,
+            "+" => LeadParam::Plus,
+            "-" => LeadParam::Minus,
+            ">" => LeadParam::Pindef,
+            "<" => LeadParam::Nindef,
+            "=" => LeadParam::Marker(MarkId::Equals),
+            "%" => LeadParam::Marker(MarkId::Modified),
+            _ if field.starts_with('#') => LeadParam::Counter(self.counter(field)?),
+            _ => match (field.strip_prefix('@'), field.strip_prefix('-')) {
+                (Some(n), _) => match n.parse() {
+                    Ok(n) => LeadParam::Marker(MarkId::Numbered(n)),
+                    Err(_) => return Err(self.error(&format!("bad mark '{field}'"))),
+                },
+                (_, Some(n)) => LeadParam::Nint(self.number(n)?),
+                _ => LeadParam::Pint(self.number(field)?),
+            },
+        })
+    }
+
+    /// The name of the counter in `field`, written as `#` and a letter.
+    fn counter(&self, field: &str) -> io::Result<char> {
+        let mut chars = field.chars().skip(1);
+        match (chars.next(), chars.next()) {
+            (Some(name), None) if name.is_ascii_uppercase() => Ok(name),
+            _ => Err(self.error(&format!("bad counter '{field}'"))),
+        }
+    }
+
+    fn levels(&self, field: &str) -> io::Result<ExitLevels> {
+        match field {
+            ">" => Ok(ExitLevels::All),
+            n => Ok(ExitLevels::Count(self.number(n)?)),
+        }
+    }
+
+    fn span(&self, field: &str) -> io::Result<SourceSpan> {
+        let pos = |text: &str| -> Option<SourcePos> {
+            let (line, column) = text.split_once(':')?;
+            Some(SourcePos {
+                line: line.parse().ok()?,
+                column: column.parse().ok()?,
+            })
+        };
+        field
+            .split_once('-')
+            .and_then(|(start, end)| {
+                Some(SourceSpan {
+                    start: pos(start)?,
+                    end: pos(end)?,
+                })
+            })
+            .ok_or_else(|| self.error(&format!("bad source span '{field}'")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+
+    fn saved(name: &str, text: &str, compiled: bool) -> SavedSpan {
+        SavedSpan {
+            name: name.to_string(),
+            text: text.to_string(),
+            code: compiled.then(|| compile(text).unwrap()),
+        }
+    }
+
+    fn round_trip(spans: &[SavedSpan]) -> Vec<SavedSpan> {
+        read_library(&write_library(spans)).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_code() {
+        let sources = [
+            "",
+            "a 2j -3d <a >j +a -a @2a =j %j",
+            "i/text/ r'old'new' eqs$x$",
+            "3(a j) >(g/x/[:xf]) (2xs xf) >xs >xf xa",
+            "a[j:zl] a[] a[:] a[j] a[:j] 2(a)[xs:xf]",
+            "i/two\nlines/ ! comment\n  (\n  a\n)",
+            "#a(~s/a/1/) #bA ~a/b/ -~e/b/#a/",
+        ];
+        for source in sources {
+            let spans = [saved("M", source, true)];
+            let loaded = round_trip(&spans);
+            assert_eq!(loaded.len(), 1);
+            assert_eq!(
+                format!("{:?}", loaded[0].code),
+                format!("{:?}", spans[0].code),
+                "{source:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_round_trip_spans() {
+        let spans = [
+            saved("FIRST", "A J", true),
+            saved("TEXT ONLY", "not\ncompiled\n", false),
+            saved("EMPTY", "", false),
+            saved("CAFÉ", "I/é/", true),
+        ];
+        let loaded = round_trip(&spans);
+        assert_eq!(loaded.len(), spans.len());
+        for (loaded, span) in loaded.iter().zip(&spans) {
+            assert_eq!(loaded.name, span.name);
+            assert_eq!(loaded.text, span.text);
+            assert_eq!(format!("{:?}", loaded.code), format!("{:?}", span.code));
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let spans = [saved("M", "2a[:j] i/x/", true)];
+        assert_eq!(
+            write_library(&spans),
+            "ludwig-code 1\n\
+             span 1 11\nM\n2a[:j] i/x/\n\
+             code\n\
+             cmd A 2 1:1-1:3 0 hf\n\
+             failure\n\
+             cmd J none 1:5-1:6 0 -\n\
+             end\n\
+             cmd I none 1:8-1:12 1 -\n\
+             tpar / 1\nx\n\
+             end\n"
+        );
+    }
+
+    #[test]
+    fn test_rejects_other_versions() {
+        let err = read_library("ludwig-code 2\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "line 1: unsupported version 2");
+        let err = read_library("ludwig-journal 1\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: not a span library");
+    }
+
+    #[test]
+    fn test_rejects_damage() {
+        let library = write_library(&[saved("M", "a (j)", true)]);
+        // Cut off part way through.
+        let cut = &library[..library.len() - 5];
+        assert!(read_library(cut).is_err());
+        let err = read_library(&library.replace("cmd A", "cmd QQ")).unwrap_err();
+        assert_eq!(err.to_string(), "line 6: unknown command 'QQ'");
+        let err = read_library(&library.replace("span 1 5", "span 1 9")).unwrap_err();
+        assert_eq!(err.to_string(), "line 4: text does not match its length");
+    }
+
+    #[test]
+    fn test_rejects_wrong_tpar_counts() {
+        let library = write_library(&[saved("M", "a i/x/", true)]);
+        let err = read_library(&library.replace("1:2 0 -", "1:2 1 -")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 6: A takes 0 trailing parameters, not 1"
+        );
+        // A huge count is refused before anything is allocated for it.
+        let damaged = library.replace("1:7 1 -", "1:7 99999999999999999 -");
+        let err = read_library(&damaged).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 7: I takes 1 trailing parameters, not 99999999999999999"
+        );
+    }
+}
@@ -1,39 +1,245 @@
 //! Recursive descent compiler for Ludwig command strings.
 //!
 //! Transforms a textual command string into a tree-structured [`CompiledCode`].
+//! A syntax error is reported as a [`CompileError`] giving its place in the
+//! source.
 
-use itertools::Itertools;
+use itertools::{Itertools, PeekingNext};
 use phf::{Map, phf_map};
+use std::fmt;
 use std::iter::Peekable;
 use std::str::Chars;
 
-use anyhow::{Result, bail};
-
 use crate::code::*;
 use crate::lead_param::LeadParam;
 use crate::marks::{MarkId, NUMBERED_MARK_RANGE};
 use crate::trail_param::TrailParam;
 
 /// Compile a Ludwig command string into a [`CompiledCode`] tree.
-pub fn compile(input: &str) -> Result<CompiledCode> {
+pub fn compile(input: &str) -> Result<CompiledCode, CompileError> {
+    compile_noting_comments(input).map(|(code, _)| code)
+}
+
+/// Compile `input` as [`compile`] does, also giving its `!` comments in
+/// order, as the compiled code does not keep them.
+pub fn compile_noting_comments(input: &str) -> Result<(CompiledCode, Vec<Comment>), CompileError> {
     let mut compiler = Compiler {
-        chars: input.chars().peekable(),
+        input,
+        chars: Source::new(input),
+        comments: Vec::new(),
+        depth: 0,
     };
     let code = compiler.compile_sequence()?;
     compiler.skip_whitespace_and_comments();
     if compiler.chars.peek().is_some() {
-        bail!("Syntax error: unexpected characters after commands.");
+        let start = compiler.chars.pos;
+        compiler.chars.next();
+        return Err(compiler.error(
+            CompileErrorKind::UnexpectedCharacters,
+            start,
+            "unexpected characters after commands",
+        ));
     }
-    Ok(code)
+    Ok((code, compiler.comments))
 }
 
-struct Compiler<'a> {
+/// A `!` comment, as noted by [`compile_noting_comments`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    /// Where the `!` is.
+    pub pos: SourcePos,
+    /// The text after the `!`, up to the end of the line.
+    pub text: String,
+    /// How many compound command bodies and exit handlers it is inside.
+    pub depth: usize,
+    /// Whether it follows code on the same line.
+    pub trailing: bool,
+}
+
+/// The kinds of syntax error in Ludwig commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompileErrorKind {
+    /// Text left over after the last command, such as a stray `)`.
+    UnexpectedCharacters,
+    /// A leading parameter the command does not accept.
+    InvalidLeadParam,
+    /// A `(` with no matching `)`.
+    UnclosedParen,
+    /// A `[` with no matching `]`.
+    UnclosedExitHandler,
+    /// A marker leading parameter other than `@1` to `@9`.
+    InvalidMarker,
+    /// A leading parameter with no command after it.
+    ExpectedCommand,
+    /// A command name that is not known.
+    UnknownCommand,
+    /// A command that needs a trailing parameter not followed by a delimiter.
+    ExpectedDelimiter,
+    /// A trailing parameter with no closing delimiter.
+    UnclosedTrailParam,
+}
+
+impl CompileErrorKind {
+    /// A short code identifying the kind of error, for looking it up.
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::UnexpectedCharacters => "E01",
+            Self::InvalidLeadParam => "E02",
+            Self::UnclosedParen => "E03",
+            Self::UnclosedExitHandler => "E04",
+            Self::InvalidMarker => "E05",
+            Self::ExpectedCommand => "E06",
+            Self::UnknownCommand => "E07",
+            Self::ExpectedDelimiter => "E08",
+            Self::UnclosedTrailParam => "E09",
+        }
+    }
+}
+
+/// A syntax error in Ludwig commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub kind: CompileErrorKind,
+    /// The text at fault. An unclosed construct is blamed on its opening.
+    pub span: SourceSpan,
+    pub message: String,
+    /// The source line on which `span` starts.
+    pub line_text: String,
+}
+
+impl fmt::Display for CompileError {
+    /// The error and its position on one line, then the source line with
+    /// carets under the text at fault.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Syntax error at line {}, column {}: {}. [{}]",
+            self.span.start.line,
+            self.span.start.column,
+            self.message,
+            self.kind.code()
+        )?;
+        write_excerpt(f, self.span, &self.line_text)
+    }
+}
+
+/// Write `line_text`, the source line on which `span` starts, with carets
+/// under the text `span` covers on the line below.
+pub(crate) fn write_excerpt(
+    f: &mut fmt::Formatter<'_>,
+    span: SourceSpan,
+    line_text: &str,
+) -> fmt::Result {
+    let SourceSpan { start, end } = span;
+    let line_len = line_text.chars().count();
+    let width = if end.line == start.line {
+        end.column.saturating_sub(start.column)
+    } else {
+        (line_len + 1).saturating_sub(start.column)
+    };
+    // Controls would upset the alignment of the carets.
+    let excerpt: String = line_text
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    writeln!(f, "{excerpt}")?;
+    write!(
+        f,
+        "{}{}",
+        " ".repeat(start.column - 1),
+        "^".repeat(width.max(1))
+    )
+}
+
+impl std::error::Error for CompileError {}
+
+/// The characters of the source, tracking the position of the next one.
+struct Source<'a> {
     chars: Peekable<Chars<'a>>,
+    pos: SourcePos,
+}
+
+impl<'a> Source<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            chars: input.chars().peekable(),
+            pos: SourcePos { line: 1, column: 1 },
+        }
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+}
+
+impl Iterator for Source<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.chars.next()?;
+        if ch == '\n' {
+            self.pos.line += 1;
+            self.pos.column = 1;
+        } else {
+            self.pos.column += 1;
+        }
+        Some(ch)
+    }
+}
+
+impl PeekingNext for Source<'_> {
+    fn peeking_next<F>(&mut self, accept: F) -> Option<char>
+    where
+        F: FnOnce(&char) -> bool,
+    {
+        if accept(self.chars.peek()?) {
+            self.next()
+        } else {
+            None
+        }
+    }
+}
+
+struct Compiler<'a> {
+    input: &'a str,
+    chars: Source<'a>,
+    /// The comments skipped so far.
+    comments: Vec<Comment>,
+    /// How many compound command bodies and exit handlers are open.
+    depth: usize,
 }
 
 impl Compiler<'_> {
+    /// An error of `kind` for the source from `start` up to the current position.
+    fn error(&self, kind: CompileErrorKind, start: SourcePos, message: &str) -> CompileError {
+        CompileError {
+            kind,
+            span: SourceSpan {
+                start,
+                end: self.chars.pos,
+            },
+            message: message.to_string(),
+            line_text: self
+                .input
+                .lines()
+                .nth(start.line - 1)
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+
+    /// An error of `kind` for the single character at `at`.
+    fn error_at(&self, kind: CompileErrorKind, at: SourcePos, message: &str) -> CompileError {
+        let mut error = self.error(kind, at, message);
+        error.span.end = SourcePos {
+            line: at.line,
+            column: at.column + 1,
+        };
+        error
+    }
+
     /// Parse a sequence of instructions until a terminator (EOF, `)`, `]`, `:`).
-    fn compile_sequence(&mut self) -> Result<CompiledCode> {
+    fn compile_sequence(&mut self) -> Result<CompiledCode, CompileError> {
         let mut instructions = Vec::new();
         loop {
             self.skip_whitespace_and_comments();
@@ -49,49 +255,86 @@ impl Compiler<'_> {
     }
 
     /// Parse one command: leading param, then `(` for compound or command name for simple.
-    fn compile_command(&mut self) -> Result<Instruction> {
+    fn compile_command(&mut self) -> Result<Instruction, CompileError> {
         self.skip_whitespace_and_comments();
+        let lead_start = self.chars.pos;
         let lead = self.parse_leading_param()?;
 
         self.skip_whitespace_and_comments();
         match self.chars.peek() {
-            Some(&'(') => self.compile_compound(lead),
-            _ => self.compile_simple(lead),
+            Some(&'(') => self.compile_compound(lead, lead_start),
+            _ => self.compile_simple(lead, lead_start),
         }
     }
 
     /// Parse a compound command: `(body)` with optional exit handler.
-    fn compile_compound(&mut self, lead: LeadParam) -> Result<Instruction> {
-        // Consume '('
-        self.chars.next();
-
+    fn compile_compound(
+        &mut self,
+        lead: LeadParam,
+        lead_start: SourcePos,
+    ) -> Result<Instruction, CompileError> {
+        let open = self.chars.pos;
         let repeat = match lead {
             LeadParam::None | LeadParam::Plus => RepeatCount::Once,
             LeadParam::Pint(n) => RepeatCount::Times(n),
             LeadParam::Pindef => RepeatCount::Indefinite,
-            _ => bail!("Syntax error: invalid leading parameter for compound command."),
+            LeadParam::Counter(name) => RepeatCount::Counter(name),
+            _ => {
+                return Err(self.error(
+                    CompileErrorKind::InvalidLeadParam,
+                    lead_start,
+                    "invalid leading parameter for compound command",
+                ));
+            }
         };
 
+        // Consume '('
+        self.chars.next();
+
+        self.depth += 1;
         let body = self.compile_sequence()?;
+        self.depth -= 1;
 
         // Expect ')'
         match self.chars.next() {
             Some(')') => {}
-            _ => bail!("Syntax error: unclosed parenthesis."),
+            _ => {
+                return Err(self.error_at(
+                    CompileErrorKind::UnclosedParen,
+                    open,
+                    "unclosed parenthesis",
+                ));
+            }
         }
 
+        let span = SourceSpan {
+            start: lead_start,
+            end: self.chars.pos,
+        };
         let exit_handler = self.parse_exit_handler()?;
 
         Ok(Instruction::CompoundCmd {
             repeat,
             body,
             exit_handler,
+            span,
         })
     }
 
     /// Parse a simple command (or exit command) with optional trailing param and exit handler.
-    fn compile_simple(&mut self, lead: LeadParam) -> Result<Instruction> {
-        let cmd = self.parse_command()?;
+    fn compile_simple(
+        &mut self,
+        lead: LeadParam,
+        lead_start: SourcePos,
+    ) -> Result<Instruction, CompileError> {
+        let (cmd, name) = self.parse_command()?;
+        let name_span = SourceSpan {
+            start: lead_start,
+            end: self.chars.pos,
+        };
+        let invalid_lead = |this: &Self, message: &str| {
+            this.error(CompileErrorKind::InvalidLeadParam, lead_start, message)
+        };
 
         // Handle exit commands
         match cmd.op {
@@ -100,27 +343,27 @@ impl Compiler<'_> {
                     LeadParam::None | LeadParam::Plus => ExitLevels::Count(1),
                     LeadParam::Pint(n) => ExitLevels::Count(n),
                     LeadParam::Pindef => ExitLevels::All,
-                    _ => bail!("Syntax error: invalid leading parameter for XS."),
+                    _ => return Err(invalid_lead(self, "invalid leading parameter for XS")),
                 };
                 let _ = self.parse_exit_handler()?;
-                return Ok(Instruction::ExitSuccess(levels));
+                return Ok(Instruction::ExitSuccess(levels, name_span));
             }
             CmdOp::ExitFailure => {
                 let levels = match lead {
                     LeadParam::None | LeadParam::Plus => ExitLevels::Count(1),
                     LeadParam::Pint(n) => ExitLevels::Count(n),
                     LeadParam::Pindef => ExitLevels::All,
-                    _ => bail!("Syntax error: invalid leading parameter for XF."),
+                    _ => return Err(invalid_lead(self, "invalid leading parameter for XF")),
                 };
                 let _ = self.parse_exit_handler()?;
-                return Ok(Instruction::ExitFailure(levels));
+                return Ok(Instruction::ExitFailure(levels, name_span));
             }
             CmdOp::ExitAbort => {
                 if lead != LeadParam::None && lead != LeadParam::Plus {
-                    bail!("Syntax error: XA does not accept a leading parameter.");
+                    return Err(invalid_lead(self, "XA does not accept a leading parameter"));
                 }
                 let _ = self.parse_exit_handler()?;
-                return Ok(Instruction::ExitAbort);
+                return Ok(Instruction::ExitAbort(name_span));
             }
             _ => {}
         }
@@ -128,7 +371,10 @@ impl Compiler<'_> {
         // Validate leading parameter
         let kind = lead_param_kind(&lead);
         if !cmd.allows_lead(&kind) {
-            bail!("Syntax error.");
+            return Err(invalid_lead(
+                self,
+                &format!("{name} does not accept this leading parameter"),
+            ));
         }
 
         // Parse trailing parameters if needed.
@@ -136,14 +382,19 @@ impl Compiler<'_> {
         // same delimiter: delim text1 delim text2 delim ...
         let mut tpars = Vec::new();
         if cmd.tpar_count > 0 {
+            let open = self.chars.pos;
             let first = self.parse_trailing_param()?;
             let delim = first.delim;
             tpars.push(first);
             for _ in 1..cmd.tpar_count {
-                tpars.push(self.parse_trailing_param_with_delim(delim)?);
+                tpars.push(self.parse_trailing_param_with_delim(delim, open)?);
             }
         }
 
+        let span = SourceSpan {
+            start: lead_start,
+            end: self.chars.pos,
+        };
         let exit_handler = self.parse_exit_handler()?;
 
         Ok(Instruction::SimpleCmd {
@@ -151,16 +402,19 @@ impl Compiler<'_> {
             lead,
             tpars,
             exit_handler,
+            span,
         })
     }
 
     /// Parse an optional exit handler: `[success_code : fail_code]`.
-    fn parse_exit_handler(&mut self) -> Result<Option<ExitHandler>> {
+    fn parse_exit_handler(&mut self) -> Result<Option<ExitHandler>, CompileError> {
         self.skip_whitespace_and_comments();
         if self.chars.peek() != Some(&'[') {
             return Ok(None);
         }
+        let open = self.chars.pos;
         self.chars.next(); // consume '['
+        self.depth += 1;
 
         let on_success = {
             let code = self.compile_sequence()?;
@@ -187,8 +441,15 @@ impl Compiler<'_> {
 
         match self.chars.next() {
             Some(']') => {}
-            _ => bail!("Syntax error: unclosed exit handler bracket."),
+            _ => {
+                return Err(self.error_at(
+                    CompileErrorKind::UnclosedExitHandler,
+                    open,
+                    "unclosed exit handler bracket",
+                ));
+            }
         }
+        self.depth -= 1;
 
         Ok(Some(ExitHandler {
             on_success,
@@ -196,8 +457,24 @@ impl Compiler<'_> {
         }))
     }
 
-    /// Parse leading parameter (digits, +, -, >, <, @, =, %).
-    fn parse_leading_param(&mut self) -> Result<LeadParam> {
+    /// Parse leading parameter (digits, +, -, >, <, @, =, %, or `#` and a
+    /// counter name).
+    fn parse_leading_param(&mut self) -> Result<LeadParam, CompileError> {
+        let start = self.chars.pos;
+        if self.chars.peek() == Some(&'#') {
+            self.chars.next();
+            return match self.chars.peek() {
+                Some(&ch) if ch.is_ascii_alphabetic() => {
+                    self.chars.next();
+                    Ok(LeadParam::Counter(ch.to_ascii_uppercase()))
+                }
+                _ => Err(self.error(
+                    CompileErrorKind::InvalidLeadParam,
+                    start,
+                    "counter name must be a letter",
+                )),
+            };
+        }
         let buf: String = self
             .chars
             .peeking_take_while(|&ch| {
@@ -208,39 +485,45 @@ impl Compiler<'_> {
         if buf.is_empty() {
             return Ok(LeadParam::None);
         }
+        let invalid = || {
+            self.error(
+                CompileErrorKind::InvalidLeadParam,
+                start,
+                &format!("invalid leading parameter {buf}"),
+            )
+        };
         match buf.as_str() {
             "+" => Ok(LeadParam::Plus),
             "-" => Ok(LeadParam::Minus),
             ">" | "." => Ok(LeadParam::Pindef),
             "<" | "," => Ok(LeadParam::Nindef),
             "@" => Ok(LeadParam::Marker(MarkId::Numbered(1))),
-            s if s.starts_with('@') => {
-                let id = s[1..].parse::<u8>()?;
-                if !NUMBERED_MARK_RANGE.contains(&id) {
-                    bail!("Syntax error: marker ID must be between 1 and 9.");
+            s if s.starts_with('@') => match s[1..].parse::<u8>() {
+                Ok(id) if NUMBERED_MARK_RANGE.contains(&id) => {
+                    Ok(LeadParam::Marker(MarkId::Numbered(id)))
                 }
-                Ok(LeadParam::Marker(MarkId::Numbered(id)))
-            }
+                _ => Err(self.error(
+                    CompileErrorKind::InvalidMarker,
+                    start,
+                    "marker ID must be between 1 and 9",
+                )),
+            },
             s if s.starts_with('=') => Ok(LeadParam::Marker(MarkId::Equals)),
             s if s.starts_with('%') => Ok(LeadParam::Marker(MarkId::Modified)),
-            s if s.starts_with('+') => {
-                let num = s[1..].parse::<usize>()?;
-                Ok(LeadParam::Pint(num))
-            }
-            s if s.starts_with('-') => {
-                let num = s[1..].parse::<usize>()?;
-                Ok(LeadParam::Nint(num))
-            }
+            s if s.starts_with('+') => s[1..].parse().map(LeadParam::Pint).map_err(|_| invalid()),
+            s if s.starts_with('-') => s[1..].parse().map(LeadParam::Nint).map_err(|_| invalid()),
             s if s.chars().all(|ch| ch.is_ascii_digit()) => {
-                let num = s.parse::<usize>()?;
-                Ok(LeadParam::Pint(num))
+                s.parse().map(LeadParam::Pint).map_err(|_| invalid())
             }
-            _ => bail!("Invalid leading parameter: {}", buf),
+            _ => Err(invalid()),
         }
     }
 
     /// Parse a command name (1-3 chars, may start with `*` for prefix commands).
-    fn parse_command(&mut self) -> Result<&'static CmdInfo> {
+    ///
+    /// Returns the command and its name in upper case.
+    fn parse_command(&mut self) -> Result<(&'static CmdInfo, String), CompileError> {
+        let start = self.chars.pos;
         let mut name = String::new();
         // Collect up to 3 alphabetic chars
         while let Some(&ch) = self.chars.peek() {
@@ -248,58 +531,99 @@ impl Compiler<'_> {
                 name.push(ch.to_ascii_lowercase());
                 self.chars.next();
                 // Check if this is a known command name
-                if let Ok(info) = name_to_info(&name) {
+                if let Some(info) = NAME_TO_OP_MAP.get(&name) {
                     // If it's known, we can return it immediately
-                    return Ok(info);
+                    return Ok((info, name.to_uppercase()));
                 }
             } else {
                 break;
             }
         }
         if name.is_empty() {
-            bail!("Syntax error: expected command name.");
+            return Err(self.error_at(
+                CompileErrorKind::ExpectedCommand,
+                start,
+                "expected command name",
+            ));
         }
-        bail!("Syntax error: unknown command '{}'.", name.to_uppercase());
+        Err(self.error(
+            CompileErrorKind::UnknownCommand,
+            start,
+            &format!("unknown command '{}'", name.to_uppercase()),
+        ))
     }
 
     /// Parse a trailing parameter: delimiter-bounded string.
-    fn parse_trailing_param(&mut self) -> Result<TrailParam> {
-        let delim = match self.chars.next() {
-            Some(c) if c.is_ascii_punctuation() => c,
-            _ => bail!("Syntax error: expected trailing parameter delimiter."),
+    fn parse_trailing_param(&mut self) -> Result<TrailParam, CompileError> {
+        let open = self.chars.pos;
+        let delim = match self.chars.peek() {
+            Some(&c) if c.is_ascii_punctuation() => c,
+            _ => {
+                return Err(self.error_at(
+                    CompileErrorKind::ExpectedDelimiter,
+                    open,
+                    "expected trailing parameter delimiter",
+                ));
+            }
         };
-        self.parse_trailing_param_with_delim(delim)
+        self.chars.next();
+        self.parse_trailing_param_with_delim(delim, open)
     }
 
-    /// Parse a trailing parameter using a known delimiter.
-    fn parse_trailing_param_with_delim(&mut self, delim: char) -> Result<TrailParam> {
+    /// Parse a trailing parameter using a known delimiter, blaming an
+    /// unclosed parameter on the delimiter at `open`.
+    fn parse_trailing_param_with_delim(
+        &mut self,
+        delim: char,
+        open: SourcePos,
+    ) -> Result<TrailParam, CompileError> {
         let mut content = String::new();
         loop {
             match self.chars.next() {
                 Some(c) if c == delim => return Ok(TrailParam::new(delim, content)),
                 Some(c) => content.push(c),
-                None => bail!("Syntax error: unclosed trailing parameter."),
+                None => {
+                    return Err(self.error_at(
+                        CompileErrorKind::UnclosedTrailParam,
+                        open,
+                        "unclosed trailing parameter",
+                    ));
+                }
             }
         }
     }
 
     /// Skip whitespace and `!`-to-end-of-line comments.
     fn skip_whitespace_and_comments(&mut self) {
+        // Whatever came before is code, unless this is the start.
+        let mut trailing = self.chars.pos != (SourcePos { line: 1, column: 1 });
         loop {
             match self.chars.peek() {
                 Some(&ch) if ch.is_ascii_whitespace() => {
+                    trailing &= ch != '\n';
                     self.chars.next();
                 }
                 Some(&'!') => {
                     // Skip to end of line
+                    let pos = self.chars.pos;
                     self.chars.next();
-                    while let Some(&ch) = self.chars.peek() {
+                    let mut text = String::new();
+                    for ch in self.chars.by_ref() {
                         if ch == '\n' {
-                            self.chars.next();
                             break;
                         }
-                        self.chars.next();
+                        text.push(ch);
                     }
+                    if text.ends_with('\r') {
+                        text.pop();
+                    }
+                    self.comments.push(Comment {
+                        pos,
+                        text,
+                        depth: self.depth,
+                        trailing,
+                    });
+                    trailing = false;
                 }
                 _ => break,
             }
@@ -338,6 +662,9 @@ fn lead_param_kind(lp: &LeadParam) -> LeadParamKind {
         LeadParam::Pindef => LeadParamKind::Pindef,
         LeadParam::Nindef => LeadParamKind::Nindef,
         LeadParam::Marker(_) => LeadParamKind::Marker,
+        // Checked as a count; whether it is negative is only known when the
+        // command runs.
+        LeadParam::Counter(_) => LeadParamKind::Pint,
     }
 }
 
@@ -402,6 +729,11 @@ const NAME_TO_OP_MAP: Map<&'static str, CmdInfo> = phf_map! {
         allowed_leads: lead_param_mask!(None, Plus, Pint, Pindef),
         tpar_count: 1
     },
+    "ep" => CmdInfo {
+        op: CmdOp::FrameParameters,
+        allowed_leads: lead_param_mask!(None),
+        tpar_count: 1
+    },
     "eol" => CmdInfo {
         op: CmdOp::EqualEol,
         allowed_leads: lead_param_mask!(None, Plus, Minus, Pindef, Nindef),
@@ -432,6 +764,16 @@ const NAME_TO_OP_MAP: Map<&'static str, CmdInfo> = phf_map! {
         allowed_leads: lead_param_mask!(None, Plus, Minus, Pindef, Nindef),
         tpar_count: 1
     },
+    "fp" => CmdInfo {
+        op: CmdOp::Page,
+        allowed_leads: lead_param_mask!(None),
+        tpar_count: 0
+    },
+    "fr" => CmdInfo {
+        op: CmdOp::FileRead,
+        allowed_leads: lead_param_mask!(None, Plus, Pint, Pindef),
+        tpar_count: 0
+    },
     "eqs" => CmdInfo {
         op: CmdOp::EqualString,
         allowed_leads: lead_param_mask!(None, Plus, Minus, Pindef, Nindef),
@@ -487,6 +829,11 @@ const NAME_TO_OP_MAP: Map<&'static str, CmdInfo> = phf_map! {
         allowed_leads: lead_param_mask!(None, Plus, Minus),
         tpar_count: 1
     },
+    "sp" => CmdInfo {
+        op: CmdOp::SpanPrint,
+        allowed_leads: lead_param_mask!(None),
+        tpar_count: 1
+    },
     "sr" => CmdInfo {
         op: CmdOp::SpanCompile,
         allowed_leads: lead_param_mask!(None, Plus),
@@ -657,18 +1004,48 @@ const NAME_TO_OP_MAP: Map<&'static str, CmdInfo> = phf_map! {
         allowed_leads: lead_param_mask!(None, Plus, Minus, Pint, Nint, Pindef, Nindef),
         tpar_count: 0
     },
+    "~s" => CmdInfo {
+        op: CmdOp::CounterSet,
+        allowed_leads: lead_param_mask!(None),
+        tpar_count: 2
+    },
+    "~a" => CmdInfo {
+        op: CmdOp::CounterAdd,
+        allowed_leads: lead_param_mask!(None, Plus, Minus, Pint, Nint),
+        tpar_count: 1
+    },
+    "~e" => CmdInfo {
+        op: CmdOp::CounterEqual,
+        allowed_leads: lead_param_mask!(None, Plus, Minus, Pindef, Nindef),
+        tpar_count: 2
+    },
+    "~i" => CmdInfo {
+        op: CmdOp::CounterInsert,
+        allowed_leads: lead_param_mask!(None, Plus),
+        tpar_count: 1
+    },
 };
 
-/// Map a command name string to its CmdInfo.
-fn name_to_info(name: &str) -> Result<&'static CmdInfo> {
+/// The name of the command that compiles to `op`, in upper case, or `None`
+/// for opcodes no command name produces.
+pub fn command_name(op: CmdOp) -> Option<String> {
     NAME_TO_OP_MAP
-        .get(name)
-        .ok_or_else(|| anyhow::anyhow!("Syntax error: unknown command '{}'.", name.to_uppercase()))
+        .entries()
+        .find(|(_, info)| info.op == op)
+        .map(|(name, _)| name.to_ascii_uppercase())
+}
+
+/// The opcode the command called `name` compiles to, if there is one, and
+/// how many trailing parameters it takes.
+pub(crate) fn command_op(name: &str) -> Option<(CmdOp, usize)> {
+    NAME_TO_OP_MAP
+        .get(&name.to_ascii_lowercase())
+        .map(|info| (info.op, info.tpar_count.into()))
 }
 
 /// Check if a character is valid in a command name.
 fn is_command_char(ch: char) -> bool {
-    matches!(ch, '\\' | '"' | '\'' | '*' | '{' | '}' | '?') || ch.is_ascii_alphabetic()
+    matches!(ch, '\\' | '"' | '\'' | '*' | '{' | '}' | '?' | '~') || ch.is_ascii_alphabetic()
 }
 
 #[cfg(test)]
@@ -696,6 +1073,7 @@ mod tests {
                 lead,
                 tpars,
                 exit_handler,
+                ..
             } => {
                 assert_eq!(*op, CmdOp::Advance);
                 assert_eq!(*lead, LeadParam::None);
@@ -830,6 +1208,7 @@ mod tests {
                 repeat,
                 body,
                 exit_handler,
+                ..
             } => {
                 assert_eq!(*repeat, RepeatCount::Once);
                 assert_eq!(body.instructions().len(), 1);
@@ -929,7 +1308,7 @@ mod tests {
     fn test_exit_success() {
         let instrs = compile_ok("XS");
         match &instrs[0] {
-            Instruction::ExitSuccess(ExitLevels::Count(1)) => {}
+            Instruction::ExitSuccess(ExitLevels::Count(1), _) => {}
             _ => panic!("expected ExitSuccess(Count(1))"),
         }
     }
@@ -938,7 +1317,7 @@ mod tests {
     fn test_exit_success_with_count() {
         let instrs = compile_ok("2XF");
         match &instrs[0] {
-            Instruction::ExitFailure(ExitLevels::Count(2)) => {}
+            Instruction::ExitFailure(ExitLevels::Count(2), _) => {}
             _ => panic!("expected ExitFailure(Count(2))"),
         }
     }
@@ -947,7 +1326,7 @@ mod tests {
     fn test_exit_success_all() {
         let instrs = compile_ok(">XS");
         match &instrs[0] {
-            Instruction::ExitSuccess(ExitLevels::All) => {}
+            Instruction::ExitSuccess(ExitLevels::All, _) => {}
             _ => panic!("expected ExitSuccess(All)"),
         }
     }
@@ -956,7 +1335,7 @@ mod tests {
     fn test_exit_abort() {
         let instrs = compile_ok("XA");
         match &instrs[0] {
-            Instruction::ExitAbort => {}
+            Instruction::ExitAbort(_) => {}
             _ => panic!("expected ExitAbort"),
         }
     }
@@ -981,6 +1360,28 @@ mod tests {
         assert_eq!(instrs.len(), 1);
     }
 
+    #[test]
+    fn test_comments_noted() {
+        let (code, comments) =
+            compile_noting_comments("!top\nA ! one\n  J I/!/ (!two\r\n)").unwrap();
+        assert_eq!(code.instructions().len(), 4);
+        let comment = |line, column, text: &str, depth, trailing| Comment {
+            pos: SourcePos { line, column },
+            text: text.to_string(),
+            depth,
+            trailing,
+        };
+        assert_eq!(
+            comments,
+            [
+                comment(1, 1, "top", 0, false),
+                comment(2, 3, " one", 0, true),
+                comment(3, 11, "two", 1, true),
+            ]
+        );
+        assert_eq!(compile_noting_comments("A").unwrap().1, []);
+    }
+
     // --- Error cases ---
 
     #[test]
@@ -998,7 +1399,11 @@ mod tests {
     #[test]
     fn test_invalid_lead_for_split_line() {
         let msg = compile_err(">SL");
-        assert!(msg.contains("Syntax error."), "got: {}", msg);
+        assert!(
+            msg.contains("SL does not accept this leading parameter"),
+            "got: {}",
+            msg
+        );
     }
 
     #[test]
@@ -1132,4 +1537,179 @@ mod tests {
         let msg = compile_err("*Z");
         assert!(msg.contains("unknown command"), "got: {}", msg);
     }
+
+    // --- Counters ---
+
+    #[test]
+    fn test_counter_commands() {
+        let instrs = compile_ok("~s/a/1/ -3~A/a/ >~e/a/#b/ ~I/a/");
+        let ops: Vec<_> = instrs
+            .iter()
+            .map(|instr| match instr {
+                Instruction::SimpleCmd {
+                    op, lead, tpars, ..
+                } => (*op, *lead, tpars.len()),
+                _ => panic!("expected SimpleCmd"),
+            })
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                (CmdOp::CounterSet, LeadParam::None, 2),
+                (CmdOp::CounterAdd, LeadParam::Nint(3), 1),
+                (CmdOp::CounterEqual, LeadParam::Pindef, 2),
+                (CmdOp::CounterInsert, LeadParam::None, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_counter_lead_and_repeat() {
+        let instrs = compile_ok("#aJ #B(A)");
+        match &instrs[0] {
+            Instruction::SimpleCmd { op, lead, .. } => {
+                assert_eq!(*op, CmdOp::Jump);
+                assert_eq!(*lead, LeadParam::Counter('A'));
+            }
+            _ => panic!("expected SimpleCmd"),
+        }
+        match &instrs[1] {
+            Instruction::CompoundCmd { repeat, .. } => {
+                assert_eq!(*repeat, RepeatCount::Counter('B'));
+            }
+            _ => panic!("expected CompoundCmd"),
+        }
+    }
+
+    #[test]
+    fn test_counter_lead_errors() {
+        let msg = compile_err("#1A");
+        assert!(
+            msg.contains("counter name must be a letter"),
+            "got: {}",
+            msg
+        );
+        let msg = compile_err("#a~E/a/1/");
+        assert!(msg.contains("does not accept"), "got: {}", msg);
+    }
+
+    // --- Error positions ---
+
+    fn error_at(input: &str) -> (CompileErrorKind, SourcePos, SourcePos) {
+        let err = compile(input).unwrap_err();
+        (err.kind, err.span.start, err.span.end)
+    }
+
+    fn pos(line: usize, column: usize) -> SourcePos {
+        SourcePos { line, column }
+    }
+
+    #[test]
+    fn test_error_positions() {
+        use CompileErrorKind::*;
+        assert_eq!(error_at("A\n  Z"), (UnknownCommand, pos(2, 3), pos(2, 4)));
+        // Unclosed constructs are blamed on where they open.
+        assert_eq!(
+            error_at("A\n2(J\n  A\n"),
+            (UnclosedParen, pos(2, 2), pos(2, 3))
+        );
+        assert_eq!(
+            error_at("J I/abc\ndef"),
+            (UnclosedTrailParam, pos(1, 4), pos(1, 5))
+        );
+        assert_eq!(
+            error_at("A [J"),
+            (UnclosedExitHandler, pos(1, 3), pos(1, 4))
+        );
+        assert_eq!(
+            error_at("J\n-3(A)"),
+            (InvalidLeadParam, pos(2, 1), pos(2, 3))
+        );
+        assert_eq!(error_at("@12A"), (InvalidMarker, pos(1, 1), pos(1, 4)));
+        assert_eq!(
+            error_at("A) J"),
+            (UnexpectedCharacters, pos(1, 2), pos(1, 3))
+        );
+        assert_eq!(
+            error_at("! comment\n5"),
+            (ExpectedCommand, pos(2, 2), pos(2, 3))
+        );
+        assert_eq!(error_at("I"), (ExpectedDelimiter, pos(1, 2), pos(1, 3)));
+    }
+
+    #[test]
+    fn test_instruction_spans() {
+        let code = compile("J\n  3A I/x/[:J]\n(@1D)").unwrap();
+        let spans: Vec<_> = code
+            .instructions()
+            .iter()
+            .filter_map(|instr| match instr {
+                Instruction::SimpleCmd { span, .. } => Some((span.start, span.end)),
+                _ => None,
+            })
+            .collect();
+        // The exit handler is not part of its command's span.
+        assert_eq!(
+            spans,
+            [
+                (pos(1, 1), pos(1, 2)),
+                (pos(2, 3), pos(2, 5)),
+                (pos(2, 6), pos(2, 10)),
+            ]
+        );
+        let Instruction::CompoundCmd { body, .. } = &code.instructions()[3] else {
+            panic!("expected CompoundCmd");
+        };
+        let Instruction::SimpleCmd { span, .. } = &body.instructions()[0] else {
+            panic!("expected SimpleCmd");
+        };
+        assert_eq!(span.start, pos(3, 2));
+    }
+
+    #[test]
+    fn test_compound_and_exit_spans() {
+        let code = compile(
+            "2( A )[XS]
+ >XF xa",
+        )
+        .unwrap();
+        let spans: Vec<_> = code
+            .instructions()
+            .iter()
+            .map(|instr| (instr.span().start, instr.span().end))
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                (pos(1, 1), pos(1, 7)),
+                (pos(2, 2), pos(2, 5)),
+                (pos(2, 6), pos(2, 8)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_command_op_inverts_command_name() {
+        for (_, info) in NAME_TO_OP_MAP.entries() {
+            let name = command_name(info.op).unwrap();
+            assert_eq!(
+                command_op(&name),
+                Some((info.op, info.tpar_count.into())),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_error_excerpt() {
+        assert_eq!(
+            compile_err("A\n\tSL >SL J"),
+            "Syntax error at line 2, column 5: SL does not accept this leading parameter. [E02]\n SL >SL J\n    ^^^"
+        );
+        // A span running on past its line is marked to the end of the line.
+        assert_eq!(
+            compile_err("-3  \n(A)"),
+            "Syntax error at line 1, column 1: invalid leading parameter for compound command. [E02]\n-3  \n^^^^"
+        );
+    }
 }
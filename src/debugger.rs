@@ -0,0 +1,375 @@
+//! Stepping through command execution.
+//!
+//! The interpreter offers each instruction to a [`Debugger`] before executing
+//! it, described by a [`Step`]. A debugger may pause there for as long as it
+//! likes, for instance to show the frame and wait for a key, and then let
+//! execution continue or abort it. [`Stepper`] keeps the breakpoints and
+//! decides where a debugger should pause.
+
+use std::fmt;
+use std::io::Write;
+
+use crate::code::{CmdOp, ExecOutcome, Instruction, RepeatCount, SourcePos, SourceSpan};
+use crate::compiler::command_name;
+use crate::editor::Prompter;
+use crate::pretty::{lead_text, levels_text};
+use crate::{CmdFailure, Frame, Position};
+
+/// Watches execution one instruction at a time.
+///
+/// A debugger also answers `&prompt&` dereferences, since it has the user's
+/// attention while execution is paused.
+pub trait Debugger: Prompter {
+    /// Called before `step` is executed.
+    fn before(&mut self, step: &Step<'_>) -> DebugAction;
+
+    /// Called once `step` has been executed, before its exit handler runs,
+    /// with its outcome and, for a command that failed of its own accord,
+    /// the reason. `step.frame` is the frame as the instruction left it.
+    fn after(&mut self, _step: &Step<'_>, _outcome: &ExecOutcome, _reason: Option<&CmdFailure>) {}
+}
+
+/// What to do after [`Debugger::before`] returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugAction {
+    /// Execute the instruction.
+    Continue,
+    /// Abandon execution, as `XA` would.
+    Abort,
+}
+
+/// An instruction about to be executed, and the state around it.
+pub struct Step<'a> {
+    pub instruction: &'a Instruction,
+    /// The span or frame the instruction was compiled from, or `None` for
+    /// top-level code.
+    pub source: Option<&'a str>,
+    /// How many compound commands, spans and exit handlers enclose the
+    /// instruction.
+    pub depth: usize,
+    /// The repetitions of the enclosing compound commands and spans,
+    /// outermost first.
+    pub repeats: &'a [Repeat],
+    /// The current frame, as the instruction will find it.
+    pub frame: &'a Frame,
+}
+
+impl Step<'_> {
+    /// The instruction's place in its source, if it is a simple command.
+    pub fn span(&self) -> Option<SourceSpan> {
+        match self.instruction {
+            Instruction::SimpleCmd { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+
+    /// Where the instruction is, as `MYMACRO:3:12`, or `None` for
+    /// instructions without a source span.
+    pub fn location(&self) -> Option<String> {
+        let start = self.span()?.start;
+        Some(match self.source {
+            Some(source) => format!("{source}:{start}"),
+            None => start.to_string(),
+        })
+    }
+}
+
+/// One repetition of a compound command or span: `iteration` of `count`,
+/// counted from 1, where a count of `None` repeats indefinitely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repeat {
+    pub iteration: usize,
+    pub count: Option<usize>,
+}
+
+impl fmt::Display for Repeat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.count {
+            Some(count) => write!(f, "{}/{}", self.iteration, count),
+            None => write!(f, "{}/>", self.iteration),
+        }
+    }
+}
+
+/// A short description of `instr` in command syntax, such as `3A`,
+/// `I/text/` or `>(...)`. Bodies and exit handlers are elided.
+pub fn describe(instr: &Instruction) -> String {
+    match instr {
+        Instruction::SimpleCmd {
+            op,
+            lead,
+            tpars,
+            exit_handler,
+            ..
+        } => {
+            let mut text = lead_text(*lead);
+            text += &command_name(*op).unwrap_or_else(|| format!("{op:?}"));
+            // Trailing parameters share their delimiters: `R/old/new/`.
+            if let Some(first) = tpars.first() {
+                text.push(first.delim);
+            }
+            for tpar in tpars {
+                // Control characters as ^X, as on screen, keep it to one line.
+                for ch in tpar.content.chars() {
+                    if ch.is_ascii_control() {
+                        text.push('^');
+                        text.push((ch as u8 ^ 0x40) as char);
+                    } else {
+                        text.push(ch);
+                    }
+                }
+                text.push(tpar.delim);
+            }
+            if exit_handler.is_some() {
+                text += "[...]";
+            }
+            text
+        }
+        Instruction::CompoundCmd {
+            repeat,
+            exit_handler,
+            ..
+        } => {
+            let count = match repeat {
+                RepeatCount::Once => String::new(),
+                RepeatCount::Times(n) => n.to_string(),
+                RepeatCount::Indefinite => ">".to_string(),
+                RepeatCount::Counter(name) => format!("#{name}"),
+            };
+            let handler = if exit_handler.is_some() { "[...]" } else { "" };
+            format!("{count}(...){handler}")
+        }
+        Instruction::ExitSuccess(levels, _) => format!("{}XS", levels_text(*levels)),
+        Instruction::ExitFailure(levels, _) => format!("{}XF", levels_text(*levels)),
+        Instruction::ExitAbort(_) => "XA".to_string(),
+    }
+}
+
+/// A breakpoint on the command starting at `pos` in `source`, `None` being
+/// top-level code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub source: Option<String>,
+    pub pos: SourcePos,
+}
+
+impl Breakpoint {
+    fn matches(&self, step: &Step<'_>) -> bool {
+        step.span().is_some_and(|span| span.start == self.pos)
+            && self.source.as_deref() == step.source
+    }
+}
+
+/// How far to run before pausing again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Pause at the next instruction.
+    StepInto,
+    /// Pause at the next instruction no deeper than this.
+    StepOver(usize),
+    /// Pause only at breakpoints.
+    Run,
+}
+
+/// Breakpoints and stepping state for a [`Debugger`].
+#[derive(Debug, Clone)]
+pub struct Stepper {
+    breakpoints: Vec<Breakpoint>,
+    mode: Mode,
+}
+
+impl Default for Stepper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stepper {
+    /// A stepper with no breakpoints that pauses at the first instruction.
+    pub fn new() -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            mode: Mode::StepInto,
+        }
+    }
+
+    /// Whether to pause before executing `step`.
+    pub fn should_pause(&self, step: &Step<'_>) -> bool {
+        match self.mode {
+            Mode::StepInto => true,
+            Mode::StepOver(depth) if step.depth <= depth => true,
+            _ => self.is_breakpoint(step),
+        }
+    }
+
+    /// Pause at the next instruction, inside `step` if it has a body.
+    pub fn step_into(&mut self) {
+        self.mode = Mode::StepInto;
+    }
+
+    /// Pause at the next instruction after `step`, running any compound
+    /// body, span or exit handler it has without pausing, except at
+    /// breakpoints.
+    pub fn step_over(&mut self, step: &Step<'_>) {
+        self.mode = Mode::StepOver(step.depth);
+    }
+
+    /// Run until a breakpoint.
+    pub fn resume(&mut self) {
+        self.mode = Mode::Run;
+    }
+
+    /// Set a breakpoint on `step`, or clear the one there. Returns whether
+    /// there is now a breakpoint, which there can't be on instructions
+    /// without a source span.
+    pub fn toggle_breakpoint(&mut self, step: &Step<'_>) -> bool {
+        if let Some(index) = self.breakpoints.iter().position(|bp| bp.matches(step)) {
+            self.breakpoints.remove(index);
+            return false;
+        }
+        let Some(span) = step.span() else {
+            return false;
+        };
+        self.breakpoints.push(Breakpoint {
+            source: step.source.map(str::to_string),
+            pos: span.start,
+        });
+        true
+    }
+
+    /// Whether there is a breakpoint on `step`.
+    pub fn is_breakpoint(&self, step: &Step<'_>) -> bool {
+        self.breakpoints.iter().any(|bp| bp.matches(step))
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+}
+
+/// A [`Debugger`] that never pauses, but writes a line to `out` for each
+/// instruction executed: where it is, what it is, its outcome and how it
+/// moved dot. Lines are indented by nesting depth, and a compound command
+/// or span execution also gets a line as it starts, so that the lines for
+/// its body appear between the two.
+///
+/// ```text
+/// 2(...)  dot 1:1
+///   1:3 A  ok  dot 1:1 -> 2:1
+///   1:3 A  failed (out of range)  dot 2:1 -> 2:1
+/// 2(...)  failed  dot 1:1 -> 2:1
+/// ```
+///
+/// Write errors are ignored; a trace is not worth failing the edit for.
+pub struct Tracer<'a> {
+    out: &'a mut dyn Write,
+    prompter: &'a mut dyn Prompter,
+    /// The frame and dot before each instruction still executing.
+    before: Vec<(String, Position)>,
+}
+
+impl<'a> Tracer<'a> {
+    /// A tracer writing to `out` that passes `&prompt&` dereferences on to
+    /// `prompter`.
+    pub fn new(out: &'a mut dyn Write, prompter: &'a mut dyn Prompter) -> Self {
+        Self {
+            out,
+            prompter,
+            before: Vec::new(),
+        }
+    }
+
+    /// Where and what `step` is, as `MYMACRO:3:12 I/x/`.
+    fn describe(&self, step: &Step<'_>) -> String {
+        let mut location = match step.span() {
+            Some(span) => match step.source {
+                Some(source) => format!("{source}:{} ", span.start),
+                None => format!("{} ", span.start),
+            },
+            None => String::new(),
+        };
+        location += &describe(step.instruction);
+        format!("{:indent$}{location}", "", indent = 2 * step.depth)
+    }
+}
+
+/// Whether `instr` executes a body of other instructions.
+fn has_body(instr: &Instruction) -> bool {
+    match instr {
+        Instruction::SimpleCmd { op, .. } => {
+            matches!(op, CmdOp::SpanExecute | CmdOp::SpanExecuteNoRecompile)
+        }
+        Instruction::CompoundCmd { .. } => true,
+        _ => false,
+    }
+}
+
+/// `dot` in `frame`, counted from 1, naming the frame only if it differs
+/// from `other`.
+fn dot_text(frame: &str, dot: Position, other: &str) -> String {
+    let pos = SourcePos {
+        line: dot.line + 1,
+        column: dot.column + 1,
+    };
+    if frame == other {
+        pos.to_string()
+    } else {
+        format!("{frame} {pos}")
+    }
+}
+
+impl Prompter for Tracer<'_> {
+    fn prompt(&mut self, prompt: &str) -> Option<String> {
+        self.prompter.prompt(prompt)
+    }
+
+    fn interrupted(&mut self) -> bool {
+        self.prompter.interrupted()
+    }
+}
+
+impl Debugger for Tracer<'_> {
+    fn before(&mut self, step: &Step<'_>) -> DebugAction {
+        let frame = step.frame.name().to_string();
+        let dot = step.frame.dot();
+        if has_body(step.instruction) {
+            let line = format!(
+                "{}  dot {}",
+                self.describe(step),
+                dot_text(&frame, dot, &frame)
+            );
+            let _ = writeln!(self.out, "{line}");
+        }
+        self.before.push((frame, dot));
+        DebugAction::Continue
+    }
+
+    fn after(&mut self, step: &Step<'_>, outcome: &ExecOutcome, reason: Option<&CmdFailure>) {
+        let Some((frame, dot)) = self.before.pop() else {
+            return;
+        };
+        let result = match (outcome, reason) {
+            (ExecOutcome::Success, _) => "ok".to_string(),
+            (ExecOutcome::Failure, Some(reason)) => format!("failed ({reason})"),
+            (ExecOutcome::Failure, None) => "failed".to_string(),
+            (ExecOutcome::ExitSuccess { .. } | ExecOutcome::ExitSuccessAll, _) => {
+                "exit success".to_string()
+            }
+            (ExecOutcome::ExitFailure { .. } | ExecOutcome::ExitFailureAll, _) => {
+                "exit failure".to_string()
+            }
+            (ExecOutcome::Abort, _) => "abort".to_string(),
+        };
+        let line = format!(
+            "{}  {result}  dot {} -> {}",
+            self.describe(step),
+            dot_text(&frame, dot, &frame),
+            dot_text(step.frame.name(), step.frame.dot(), &frame),
+        );
+        let _ = writeln!(self.out, "{line}");
+    }
+}
@@ -2,18 +2,47 @@
 //! compiled Ludwig commands.
 
 use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 
-use crate::Frame;
+use crate::code_file::SavedSpan;
+use crate::debugger::Debugger;
 use crate::exec_context::ExecutionContext;
 use crate::frame_set::FrameSet;
 use crate::interpreter;
+use crate::text_format::{EncodeError, Encoding};
+use crate::{CmdFailure, CmdResult, FailedCommand, Frame, LeadParam, TrailParam};
 use crate::{MarkId, code::*};
 
 const DEFAULT_FRAME_NAME: &str = "LUDWIG";
 
+/// Supplies answers to `&prompt&` dereferences during execution.
+pub trait Prompter {
+    /// Ask the user for a line of text, or `None` if they cancel.
+    fn prompt(&mut self, prompt: &str) -> Option<String>;
+
+    /// Whether the user has asked for execution to stop since this was last
+    /// asked. Polled every so often while commands execute.
+    fn interrupted(&mut self) -> bool {
+        false
+    }
+}
+
 /// An editor instance that wraps a FrameSet and provides command execution.
 pub struct Editor {
     frame_set: FrameSet,
+    /// Why the last execution failed, if there is more to say than that it did.
+    message: Option<String>,
+    /// The command that made the last execution fail.
+    failure: Option<FailedCommand>,
+    /// Set to stop execution; see [`set_interrupt`](Self::set_interrupt).
+    interrupt: Option<Arc<AtomicBool>>,
+    /// How many instructions a command line may take.
+    step_budget: Option<u64>,
+    /// How many instructions the current command line has taken.
+    steps: u64,
 }
 
 impl Default for Editor {
@@ -27,6 +56,11 @@ impl Editor {
     pub fn new() -> Self {
         Editor {
             frame_set: FrameSet::new(Frame::new(DEFAULT_FRAME_NAME)),
+            message: None,
+            failure: None,
+            interrupt: None,
+            step_budget: None,
+            steps: 0,
         }
     }
 
@@ -35,9 +69,84 @@ impl Editor {
     pub fn from_str(s: &str) -> Self {
         Editor {
             frame_set: FrameSet::new(Frame::from_str(DEFAULT_FRAME_NAME, s)),
+            message: None,
+            failure: None,
+            interrupt: None,
+            step_budget: None,
+            steps: 0,
         }
     }
 
+    /// Create an editor from the raw contents of a file, preserving its
+    /// encoding, line endings, BOM and final newline for
+    /// [`file_contents`](Self::file_contents).
+    pub fn from_file_contents(contents: &[u8], encoding: Option<Encoding>) -> Self {
+        Editor {
+            frame_set: FrameSet::new(Frame::from_file_contents(
+                DEFAULT_FRAME_NAME,
+                contents,
+                encoding,
+            )),
+            message: None,
+            failure: None,
+            interrupt: None,
+            step_budget: None,
+            steps: 0,
+        }
+    }
+
+    /// Create an editor that reads the file at `path` incrementally.
+    ///
+    /// See [`Frame::open_paged`].
+    pub fn open_paged(path: &Path, encoding: Option<Encoding>) -> io::Result<Self> {
+        Ok(Editor {
+            frame_set: FrameSet::new(Frame::open_paged(DEFAULT_FRAME_NAME, path, encoding)?),
+            message: None,
+            failure: None,
+            interrupt: None,
+            step_budget: None,
+            steps: 0,
+        })
+    }
+
+    /// True if the current frame is paged from its file.
+    pub fn is_paged(&self) -> bool {
+        self.current_frame().is_paged()
+    }
+
+    /// Save a paged frame back to its file. See [`Frame::save_paged`].
+    pub fn save_paged(&mut self) -> io::Result<()> {
+        self.current_frame_mut().save_paged()
+    }
+
+    /// The current frame's text in its original on-disk format.
+    pub fn file_contents(&self) -> Result<Vec<u8>, EncodeError> {
+        self.current_frame().file_contents()
+    }
+
+    /// True if any frame's crash-recovery journal holds unsaved changes.
+    pub fn has_journalled_changes(&mut self) -> bool {
+        self.frame_set
+            .frames_mut()
+            .any(|frame| frame.journal_mut().is_some_and(|j| j.has_changes()))
+    }
+
+    /// Remove every frame's crash-recovery journal.
+    ///
+    /// Called on a clean exit, once the journals' changes are saved or
+    /// abandoned. Every journal is removed even if removing one fails.
+    pub fn discard_journal(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        for frame in self.frame_set.frames_mut() {
+            if let Some(journal) = frame.take_journal()
+                && let Err(err) = journal.discard()
+            {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
     /// Get a reference to the current frame.
     pub fn current_frame(&self) -> &Frame {
         self.frame_set.current_frame()
@@ -53,13 +162,152 @@ impl Editor {
         self.current_frame().get_mark(MarkId::Modified).is_some()
     }
 
+    /// Take the explanation of why the last execution failed, if any; for
+    /// example, where a pattern's syntax error is.
+    pub fn take_message(&mut self) -> Option<String> {
+        self.message.take()
+    }
+
+    /// Take the command that made the last execution fail, if it failed.
+    pub fn take_failure(&mut self) -> Option<FailedCommand> {
+        self.failure.take()
+    }
+
+    /// Stop any execution, as `XA` would, once `flag` is set, for example by
+    /// a SIGINT handler. The flag is cleared again when that happens.
+    pub fn set_interrupt(&mut self, flag: Arc<AtomicBool>) {
+        self.interrupt = Some(flag);
+    }
+
+    /// Stop any execution, as `XA` would, once its command line has
+    /// executed `budget` instructions; `None` lets it run for as long as it
+    /// takes.
+    pub fn set_step_budget(&mut self, budget: Option<u64>) {
+        self.step_budget = budget;
+    }
+
+    /// Start a new command line, with all of the step budget to spend.
+    ///
+    /// [`execute`](Self::execute) and the like each start one; a command
+    /// line run a few instructions at a time with
+    /// [`execute_instructions_with_prompter`](Self::execute_instructions_with_prompter)
+    /// is to start with this.
+    pub fn start_command_line(&mut self) {
+        self.steps = 0;
+    }
+
+    /// The spans defined so far, each with the code last compiled for it.
+    pub fn saved_spans(&self) -> Vec<SavedSpan> {
+        self.frame_set
+            .sorted_span_names()
+            .into_iter()
+            .filter_map(|name| {
+                Some(SavedSpan {
+                    name: name.to_string(),
+                    text: self.frame_set.span_text(name)?,
+                    code: self
+                        .frame_set
+                        .get_span(name)?
+                        .get_code()
+                        .map(|c| (**c).clone()),
+                })
+            })
+            .collect()
+    }
+
+    /// Define each of `spans` as `SA` would, giving it the code saved with it
+    /// rather than compiling its text again.
+    pub fn load_spans(&mut self, spans: Vec<SavedSpan>) -> Result<(), CmdFailure> {
+        for saved in spans {
+            let tpars = [
+                TrailParam::from_str(&saved.name),
+                TrailParam::from_str(&saved.text),
+            ];
+            let mut ctx = ExecutionContext::new(&mut self.frame_set);
+            if let CmdResult::Failure(failure) = ctx.cmd_span_assign(LeadParam::None, &tpars) {
+                return Err(failure);
+            }
+            let span = self
+                .frame_set
+                .get_span_mut(&saved.name)
+                .expect("span was just assigned");
+            match saved.code {
+                Some(code) => span.set_code(code),
+                None => span.clear_code(),
+            }
+        }
+        Ok(())
+    }
+
     /// Execute compiled code against the frame.
     ///
     /// This delegates to the interpreter module which handles all control flow,
     /// exit handlers, and command dispatch.
     pub fn execute(&mut self, code: &CompiledCode) -> ExecOutcome {
-        let mut ctx = ExecutionContext::new(&mut self.frame_set);
-        interpreter::execute(&mut ctx, code)
+        self.start_command_line();
+        let mut ctx = ExecutionContext::new(&mut self.frame_set).with_limits(
+            self.interrupt.as_deref(),
+            self.step_budget,
+            self.steps,
+        );
+        let outcome = interpreter::execute(&mut ctx, code);
+        self.steps = ctx.steps();
+        self.message = ctx.message.take().filter(|_| !outcome.is_success());
+        self.failure = ctx.failure.take().filter(|_| !outcome.is_success());
+        self.frame_set.current_frame_mut().page_in_near_dot();
+        outcome
+    }
+
+    /// Execute compiled code, answering `&prompt&` dereferences with `prompter`.
+    pub fn execute_with_prompter(
+        &mut self,
+        code: &CompiledCode,
+        prompter: &mut dyn Prompter,
+    ) -> ExecOutcome {
+        self.start_command_line();
+        self.execute_instructions_with_prompter(code.instructions(), prompter)
+    }
+
+    /// Execute some of the instructions of compiled code, as
+    /// [`execute_with_prompter`](Self::execute_with_prompter) does, as part
+    /// of the current command line.
+    pub fn execute_instructions_with_prompter(
+        &mut self,
+        instructions: &[Instruction],
+        prompter: &mut dyn Prompter,
+    ) -> ExecOutcome {
+        let mut ctx = ExecutionContext::with_prompter(&mut self.frame_set, prompter).with_limits(
+            self.interrupt.as_deref(),
+            self.step_budget,
+            self.steps,
+        );
+        let outcome = interpreter::execute_instructions(&mut ctx, instructions);
+        self.steps = ctx.steps();
+        self.message = ctx.message.take().filter(|_| !outcome.is_success());
+        self.failure = ctx.failure.take().filter(|_| !outcome.is_success());
+        self.frame_set.current_frame_mut().page_in_near_dot();
+        outcome
+    }
+
+    /// Execute compiled code, offering each instruction to `debugger` before
+    /// it is executed. The debugger also answers `&prompt&` dereferences.
+    pub fn execute_debugged(
+        &mut self,
+        code: &CompiledCode,
+        debugger: &mut dyn Debugger,
+    ) -> ExecOutcome {
+        self.start_command_line();
+        let mut ctx = ExecutionContext::with_debugger(&mut self.frame_set, debugger).with_limits(
+            self.interrupt.as_deref(),
+            self.step_budget,
+            self.steps,
+        );
+        let outcome = interpreter::execute(&mut ctx, code);
+        self.steps = ctx.steps();
+        self.message = ctx.message.take().filter(|_| !outcome.is_success());
+        self.failure = ctx.failure.take().filter(|_| !outcome.is_success());
+        self.frame_set.current_frame_mut().page_in_near_dot();
+        outcome
     }
 }
 
@@ -72,8 +320,10 @@ impl fmt::Display for Editor {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Position;
     use crate::compiler::compile;
+    use crate::debugger::{DebugAction, Step, Stepper, Tracer, describe};
+    use crate::test_util::ScratchDir;
+    use crate::{CmdFailure, Position};
 
     // Helper: compile and execute, return outcome
     fn exec(content: &str, commands: &str) -> (Editor, ExecOutcome) {
@@ -737,6 +987,42 @@ mod tests {
         assert_eq!(editor.to_string(), "hello \n");
     }
 
+    // --- Counters ---
+
+    #[test]
+    fn test_counters_number_lines() {
+        let (editor, outcome) = exec(
+            "alpha\nbeta\ngamma\n",
+            "~S/n/0/ >(~A/n/ ~I/n/ I/. / A)[] ~E/n/3/",
+        );
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "1. alpha\n2. beta\n3. gamma\n");
+    }
+
+    #[test]
+    fn test_counter_repeat_count() {
+        // The count is read once, so changing it in the body does not
+        // change how many times the body runs.
+        let (editor, outcome) = exec("", "~S/k/3/ #k(I/x/ ~A/k/) #k(I/y/) #z(I/z/)");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "xxxyyyyyy");
+    }
+
+    #[test]
+    fn test_counter_negative_repeat_fails() {
+        let (editor, outcome) = exec("", "-~A/k/ #k(I/x/)");
+        assert_eq!(outcome, ExecOutcome::Failure);
+        assert_eq!(editor.to_string(), "");
+    }
+
+    #[test]
+    fn test_counter_as_lead() {
+        // A negative counter moves the other way, as -2J would.
+        let (editor, outcome) = exec("abcdef\n", "~S/k/4/ #kJ I/|/ ~S/j/-6/ #j~A/k/ #kJ I/|/");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "abc|d|ef\n");
+    }
+
     // --- Span command tests ---
 
     #[test]
@@ -846,6 +1132,73 @@ mod tests {
         assert!(span.get_code().is_some());
     }
 
+    #[test]
+    fn test_span_compile_error_is_explained() {
+        let (mut editor, outcome) = exec("", "SA/cmd/2A(J/ SR/cmd/");
+        assert_eq!(outcome, ExecOutcome::Failure);
+        assert_eq!(
+            editor.take_message().as_deref(),
+            Some("CMD: Syntax error at line 1, column 3: unclosed parenthesis. [E03]\n2A(J\n  ^")
+        );
+        let (mut editor, outcome) = exec("", "SA/cmd/Q/ EX/cmd/");
+        assert_eq!(outcome, ExecOutcome::Failure);
+        assert!(
+            editor
+                .take_message()
+                .unwrap()
+                .contains("unknown command 'Q'")
+        );
+    }
+
+    #[test]
+    fn test_span_print() {
+        let (editor, outcome) = exec("end\n", "SA/cmd/2(a j[:xf])/ SP/cmd/");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "2(\n  A\n  J[:XF]\n)\nend\n");
+        assert_eq!(editor.current_frame().dot(), Position::new(4, 0));
+
+        let (mut editor, outcome) = exec("", "SA/cmd/2A(J/ SP/cmd/");
+        assert_eq!(outcome, ExecOutcome::Failure);
+        assert!(
+            editor
+                .take_message()
+                .unwrap()
+                .contains("unclosed parenthesis")
+        );
+        assert_eq!(editor.to_string(), "");
+        let (_, outcome) = exec("", "SP/nosuch/");
+        assert_eq!(outcome, ExecOutcome::Failure);
+    }
+
+    #[test]
+    fn test_saved_spans_keep_compiled_code() {
+        // The text changes after SR, so EN and EX run different code.
+        let (editor, outcome) = exec("", "SA/m/I'x'/ SR/m/ SA/m/I'y'/ SA/plain/J/");
+        assert_eq!(outcome, ExecOutcome::Success);
+        let saved = editor.saved_spans();
+        let names: Vec<_> = saved.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["M", "PLAIN"]);
+        assert_eq!(saved[0].text, "I'y'");
+        assert!(saved[0].code.is_some());
+        assert!(saved[1].code.is_none());
+
+        let mut editor = Editor::from_str("");
+        editor.load_spans(saved).unwrap();
+        let outcome = editor.execute(&compile("EN/m/ EX/m/").unwrap());
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "xy");
+    }
+
+    #[test]
+    fn test_load_spans_replaces_existing() {
+        let (mut editor, _) = exec("", "SA/m/I'old'/ SR/m/");
+        let (other, _) = exec("", "SA/m/I'new'/");
+        editor.load_spans(other.saved_spans()).unwrap();
+        let outcome = editor.execute(&compile("EN/m/").unwrap());
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "new");
+    }
+
     #[test]
     fn test_span_assign_span_ref() {
         // SA/x/hello/ creates span x; SA$y$x$ sets y to the same content
@@ -1077,6 +1430,203 @@ mod tests {
         assert_eq!(editor.to_string(), "aXbXcX\n");
     }
 
+    #[test]
+    fn test_pattern_r_reorders_fields() {
+        let (editor, outcome) = exec(
+            "alice,30,paris\nbob,25,rome\n",
+            r#">R`(+-D/,/)","(+-D/,/)","(+-D/,/)`\3,\1,\2`"#,
+        );
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "paris,alice,30\nrome,bob,25\n");
+    }
+
+    #[test]
+    fn test_pattern_r_case_escapes() {
+        let (editor, outcome) = exec("hello world\n", r">R`+A`\u\0`");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "Hello World\n");
+    }
+
+    #[test]
+    fn test_pattern_unicode_property_sets() {
+        let (editor, outcome) = exec("alpha αβγ 12 ٣٤\n", r">R`+X/Greek/`greek`>R`+X/Nd/`#`");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "alpha greek # #\n");
+        let (_, outcome) = exec("abc\n", "R`X/Klingon/`x`");
+        assert_eq!(outcome, ExecOutcome::Failure);
+    }
+
+    #[test]
+    fn test_pattern_syntax_error_is_explained() {
+        let code = compile("J G`A (N`").unwrap();
+        let mut editor = Editor::from_str("abc\n");
+        assert_eq!(editor.execute(&code), ExecOutcome::Failure);
+        assert_eq!(
+            editor.take_message().as_deref(),
+            Some("Unclosed group '(' in pattern at character 3\nA (N\n  ^")
+        );
+        assert_eq!(editor.take_message(), None);
+        // Only a failure is explained.
+        let code = compile("G`(N`[:J]").unwrap();
+        assert_eq!(editor.execute(&code), ExecOutcome::Success);
+        assert_eq!(editor.take_message(), None);
+    }
+
+    #[test]
+    fn test_failed_command_is_located() {
+        let code = compile("J\n  @1A").unwrap();
+        let mut editor = Editor::from_str("abc\n");
+        assert_eq!(editor.execute(&code), ExecOutcome::Failure);
+        let failure = editor.take_failure().unwrap();
+        assert_eq!(failure.source, None);
+        assert_eq!(failure.reason, Some(CmdFailure::MarkNotDefined));
+        assert_eq!(failure.to_string(), "at 2:3 (mark not defined)");
+        assert_eq!(editor.take_failure(), None);
+    }
+
+    #[test]
+    fn test_failed_command_in_span_is_located() {
+        let (mut editor, outcome) = exec("abc\n", "SA/MYMACRO/J\n  (A[:J]) A[:] @1A/ EX/MYMACRO/");
+        assert_eq!(outcome, ExecOutcome::Failure);
+        assert_eq!(
+            editor.take_failure().unwrap().to_string(),
+            "at MYMACRO:2:16 (mark not defined)"
+        );
+        // A span that fails without a failing command of its own, here
+        // through XF after a handled failure, is blamed on the EX.
+        let (mut editor, outcome) = exec("abc\n", "SA/M/J A[:] XF/\nEX/M/");
+        assert_eq!(outcome, ExecOutcome::Failure);
+        assert_eq!(editor.take_failure().unwrap().to_string(), "at 2:1");
+        // Only a failure is reported.
+        let (mut editor, outcome) = exec("abc\n", "A[:J]");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.take_failure(), None);
+    }
+
+    #[test]
+    fn test_step_budget_stops_runaway_loops() {
+        let mut editor = Editor::from_str("abc\n");
+        editor.set_step_budget(Some(1000));
+        let code = compile(">(C)").unwrap();
+        assert_eq!(editor.execute(&code), ExecOutcome::Abort);
+        assert_eq!(
+            editor.take_message().as_deref(),
+            Some("Stopped after 1000 steps.")
+        );
+        assert_eq!(editor.take_failure().unwrap().to_string(), "at 1:3");
+        // The budget is for each execution.
+        assert_eq!(editor.execute(&compile("J").unwrap()), ExecOutcome::Success);
+    }
+
+    #[test]
+    fn test_step_budget_stops_empty_loops() {
+        for commands in [">()", ">(>())", "SA/E// >EX/E/", "SA/E// >EN/E/"] {
+            let mut editor = Editor::from_str("abc\n");
+            editor.set_step_budget(Some(1000));
+            let code = compile(commands).unwrap();
+            assert_eq!(editor.execute(&code), ExecOutcome::Abort, "{commands}");
+            assert_eq!(
+                editor.take_message().as_deref(),
+                Some("Stopped after 1000 steps."),
+                "{commands}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_step_budget_counts_instructions_not_iterations() {
+        // The compound command and each J are a step apiece.
+        let mut editor = Editor::from_str("abcdefghijklmnop\n");
+        editor.set_step_budget(Some(10));
+        assert_eq!(
+            editor.execute(&compile("9(J)").unwrap()),
+            ExecOutcome::Success
+        );
+        assert_eq!(
+            editor.execute(&compile("10(J)").unwrap()),
+            ExecOutcome::Abort
+        );
+    }
+
+    #[test]
+    fn test_step_budget_is_for_a_whole_command_line() {
+        let mut editor = Editor::from_str("abcdefghijklmnop\n");
+        editor.set_step_budget(Some(3));
+        let code = compile("J J J J").unwrap();
+        let mut prompter = FixedPrompter("");
+        editor.start_command_line();
+        let outcomes: Vec<_> = code
+            .instructions()
+            .iter()
+            .map(|instr| {
+                editor
+                    .execute_instructions_with_prompter(std::slice::from_ref(instr), &mut prompter)
+            })
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ExecOutcome::Success,
+                ExecOutcome::Success,
+                ExecOutcome::Success,
+                ExecOutcome::Abort
+            ]
+        );
+        editor.start_command_line();
+        let outcome =
+            editor.execute_instructions_with_prompter(&code.instructions()[..1], &mut prompter);
+        assert_eq!(outcome, ExecOutcome::Success);
+    }
+
+    #[test]
+    fn test_interrupt_stops_execution() {
+        let mut editor = Editor::from_str("abc\n");
+        let flag = Arc::new(AtomicBool::new(true));
+        editor.set_interrupt(Arc::clone(&flag));
+        let code = compile("J J").unwrap();
+        assert_eq!(editor.execute(&code), ExecOutcome::Abort);
+        assert_eq!(editor.take_message().as_deref(), Some("Interrupted."));
+        assert_eq!(editor.current_frame().dot(), Position::new(0, 0));
+        // Noticing the interrupt clears it.
+        assert!(!flag.load(std::sync::atomic::Ordering::Relaxed));
+        assert_eq!(editor.execute(&code), ExecOutcome::Success);
+    }
+
+    /// Reports an interrupt the first time it is asked.
+    struct InterruptingPrompter {
+        asked: usize,
+    }
+
+    impl crate::Prompter for InterruptingPrompter {
+        fn prompt(&mut self, _prompt: &str) -> Option<String> {
+            None
+        }
+
+        fn interrupted(&mut self) -> bool {
+            self.asked += 1;
+            true
+        }
+    }
+
+    #[test]
+    fn test_prompter_interrupts_execution() {
+        let mut editor = Editor::from_str("abc\n");
+        let mut prompter = InterruptingPrompter { asked: 0 };
+        let code = compile("2000(J)").unwrap();
+        let outcome = editor.execute_with_prompter(&code, &mut prompter);
+        assert_eq!(outcome, ExecOutcome::Abort);
+        assert_eq!(editor.take_message().as_deref(), Some("Interrupted."));
+        // It is only asked every so often.
+        assert_eq!(prompter.asked, 1);
+    }
+
+    #[test]
+    fn test_pattern_r_unknown_group_fails() {
+        let (editor, outcome) = exec("abc\n", r"R`(A)`\2`");
+        assert_eq!(outcome, ExecOutcome::Failure);
+        assert_eq!(editor.to_string(), "abc\n");
+    }
+
     #[test]
     fn test_pattern_eqs_matches() {
         // EQS`A` — succeeds if dot is at an alpha char
@@ -1106,6 +1656,55 @@ mod tests {
         assert_eq!(outcome, ExecOutcome::Success);
     }
 
+    #[test]
+    fn test_pattern_g_spans_line_break() {
+        // The middle context `>` steps over the break so `<'begin'` can match.
+        let (editor, outcome) = exec("if x\nend\nbegin y\n", "G`'end',>,<'begin'`");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(
+            editor.current_frame().get_mark(MarkId::Equals).unwrap(),
+            Position::new(1, 3)
+        );
+        assert_eq!(editor.current_frame().dot(), Position::new(2, 0));
+    }
+
+    #[test]
+    fn test_pattern_r_joins_lines() {
+        let (editor, outcome) = exec("ab-\ncd\n", "R`\"-\"#``");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "abcd\n");
+    }
+
+    #[test]
+    fn test_pattern_g_backward_spans_line_break() {
+        let (editor, outcome) = exec("x\nab\ncd\n", "2A -G`\"b\"#\"c\"`");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(
+            editor.current_frame().get_mark(MarkId::Equals).unwrap(),
+            Position::new(1, 1)
+        );
+        assert_eq!(editor.current_frame().dot(), Position::new(2, 1));
+    }
+
+    #[test]
+    fn test_pattern_g_over_many_lines() {
+        let mut text: String = (0..50_000).map(|i| format!("line {}\n", i)).collect();
+        text.push_str("needle\n");
+        let (editor, outcome) = exec(&text, "G`\"needle\"` -G`\"line 49999\"`");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.current_frame().dot(), Position::new(49_999, 10));
+    }
+
+    #[test]
+    fn test_pattern_margins_use_frame_margins() {
+        let (editor, outcome) = exec_with_margins("ab  cd ef\n", "G`{A`", 4, 79);
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.current_frame().dot(), Position::new(0, 5));
+        let (editor, outcome) = exec_with_margins("ab  cd ef\n", "G`A}`", 0, 6);
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.current_frame().dot(), Position::new(0, 6));
+    }
+
     #[test]
     fn test_pattern_syntax_error() {
         // G with an invalid pattern (unclosed group)
@@ -1113,6 +1712,133 @@ mod tests {
         assert_eq!(outcome, ExecOutcome::Failure);
     }
 
+    struct FixedPrompter(&'static str);
+
+    impl crate::Prompter for FixedPrompter {
+        fn prompt(&mut self, _prompt: &str) -> Option<String> {
+            Some(self.0.to_string())
+        }
+    }
+
+    #[test]
+    fn test_pattern_g_dereferences_span() {
+        let (editor, outcome) = exec("abc 123 x\n", "SA/num/+N/ G`$num$`");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.current_frame().dot(), Position::new(0, 7));
+    }
+
+    #[test]
+    fn test_pattern_r_dereferences_span_as_string() {
+        let (editor, outcome) = exec("a+b a+b\n", "SA/op/+/ >R`'$op$'`-`");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "a-b a-b\n");
+    }
+
+    #[test]
+    fn test_pattern_undefined_span_fails() {
+        let (_, outcome) = exec("abc\n", "G`$nothing$`");
+        assert_eq!(outcome, ExecOutcome::Failure);
+    }
+
+    #[test]
+    fn test_pattern_eqs_prompts() {
+        let mut editor = Editor::from_str("xyz\n");
+        let code = compile("EQS`D&Letters: &`").unwrap();
+        let outcome = editor.execute_with_prompter(&code, &mut FixedPrompter("w..z"));
+        assert_eq!(outcome, ExecOutcome::Success);
+        // Without a prompter the dereference can't be resolved.
+        assert_eq!(editor.execute(&code), ExecOutcome::Failure);
+    }
+
+    // ── Trailing parameter dereferencing ────────────────────────────────────
+
+    /// Answers prompts in turn, recording what was asked.
+    struct ScriptedPrompter {
+        answers: Vec<&'static str>,
+        asked: Vec<String>,
+    }
+
+    impl crate::Prompter for ScriptedPrompter {
+        fn prompt(&mut self, prompt: &str) -> Option<String> {
+            self.asked.push(prompt.to_string());
+            (!self.answers.is_empty()).then(|| self.answers.remove(0).to_string())
+        }
+    }
+
+    #[test]
+    fn test_tpar_span_dereference() {
+        let (editor, outcome) = exec("one two\n", "SA/w/two/ G$w$");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.current_frame().dot(), Position::new(0, 7));
+        let (editor, outcome) = exec("one two\n", "SA/w/two/ I$w$");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "twoone two\n");
+    }
+
+    #[test]
+    fn test_tpar_other_delimiters_are_literal() {
+        let (editor, outcome) = exec("", "SA/w/two/ I/'hi'/ I/$w$/ I\"&x&\"");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "'hi'$w$&x&");
+    }
+
+    #[test]
+    fn test_tpar_quotes_stop_dereferencing() {
+        let mut editor = Editor::from_str("");
+        let mut prompter = ScriptedPrompter {
+            answers: vec!["w"],
+            asked: vec![],
+        };
+        let code = compile("SA/w/two/ I$'w'$ I$'&w&'$").unwrap();
+        let outcome = editor.execute_with_prompter(&code, &mut prompter);
+        assert_eq!(outcome, ExecOutcome::Failure);
+        assert_eq!(editor.to_string(), "two");
+        assert!(prompter.asked.is_empty());
+    }
+
+    #[test]
+    fn test_tpar_undefined_span_fails() {
+        let (editor, outcome) = exec("abc\n", "I$nothing$");
+        assert_eq!(outcome, ExecOutcome::Failure);
+        assert_eq!(editor.to_string(), "abc\n");
+    }
+
+    #[test]
+    fn test_tpar_multi_line_span_only_where_allowed() {
+        let (editor, outcome) = exec("", "SA/t/a\nb/ I$t$");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "a\nb");
+        let (_, outcome) = exec("a\nb\n", "SA/t/a\nb/ G$t$");
+        assert_eq!(outcome, ExecOutcome::Failure);
+    }
+
+    #[test]
+    fn test_tpar_prompts_use_default_when_empty() {
+        let mut editor = Editor::from_str("cat\n");
+        let mut prompter = ScriptedPrompter {
+            answers: vec!["cat", "dog"],
+            asked: vec![],
+        };
+        let code = compile("R&&With what? &").unwrap();
+        let outcome = editor.execute_with_prompter(&code, &mut prompter);
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "dog\n");
+        assert_eq!(prompter.asked, vec!["Replace:", "With what? "]);
+    }
+
+    #[test]
+    fn test_tpar_prompt_for_span_name() {
+        let mut editor = Editor::from_str("one two\n");
+        let mut prompter = ScriptedPrompter {
+            answers: vec!["w"],
+            asked: vec![],
+        };
+        let code = compile("SA/w/two/ G$&Span: &$").unwrap();
+        let outcome = editor.execute_with_prompter(&code, &mut prompter);
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.current_frame().dot(), Position::new(0, 7));
+    }
+
     // ─── Phase 7: Word formatting commands (YF, YJ, YC, YL, YR) ──────────────
 
     /// Helper: compile+execute with explicit left/right margins.
@@ -1370,4 +2096,420 @@ mod tests {
         // "hello" + " " + "world" should be on one line.
         assert_eq!(editor.to_string(), "hello world\n\n");
     }
+
+    // ── EP / space limit ────────────────────────────────────────────────────
+
+    #[test]
+    fn test_ep_sets_space_limit() {
+        let (editor, outcome) = exec("hello\n", "EP/S=10/");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.current_frame().space_limit(), 10);
+    }
+
+    #[test]
+    fn test_ep_space_limit_below_content_fails() {
+        let (editor, outcome) = exec("hello\n", "EP/S=3/");
+        assert_eq!(outcome, ExecOutcome::Failure);
+        assert_eq!(
+            editor.current_frame().space_limit(),
+            crate::DEFAULT_SPACE_LIMIT
+        );
+    }
+
+    #[test]
+    fn test_insert_beyond_space_limit_fails_without_change() {
+        let (editor, outcome) = exec("hello\n", "EP/S=10/ 3I/ab/");
+        assert_eq!(outcome, ExecOutcome::Failure);
+        assert_eq!(editor.to_string(), "hello\n");
+        assert!(!editor.modified());
+    }
+
+    #[test]
+    fn test_span_copy_loop_stops_at_space_limit() {
+        // A runaway copy loop is stopped by the limit rather than growing forever.
+        let (editor, outcome) = exec("\n", "EP/S=8/ SA/x/abc/ >(SC/x/)");
+        assert_eq!(outcome, ExecOutcome::Failure);
+        assert_eq!(editor.to_string(), "abcabc\n");
+    }
+
+    #[test]
+    fn test_virtual_space_counts_against_space_limit() {
+        let (editor, outcome) = exec("hi\n", "EP/S=8/ 10ZR I/x/");
+        assert_eq!(outcome, ExecOutcome::Failure);
+        assert_eq!(editor.to_string(), "hi\n");
+    }
+
+    #[test]
+    fn test_file_contents_preserves_crlf_and_missing_newline() {
+        let mut editor = Editor::from_file_contents(b"one\r\ntwo", None);
+        let code = compile("A I/2:/").unwrap();
+        assert_eq!(editor.execute(&code), ExecOutcome::Success);
+        assert_eq!(editor.file_contents().unwrap(), b"one\r\n2:two");
+    }
+
+    #[test]
+    fn test_ep_converts_line_endings() {
+        let mut editor = Editor::from_file_contents("\u{feff}a\r\nb\r\n".as_bytes(), None);
+        let code = compile("EP/L=LF/").unwrap();
+        assert_eq!(editor.execute(&code), ExecOutcome::Success);
+        assert_eq!(editor.file_contents().unwrap(), "\u{feff}a\nb\n".as_bytes());
+    }
+
+    #[test]
+    fn test_ep_unknown_line_ending_fails() {
+        let (_, outcome) = exec("a\n", "EP/L=NL/");
+        assert_eq!(outcome, ExecOutcome::Failure);
+    }
+
+    #[test]
+    fn test_ep_converts_encoding() {
+        let mut editor = Editor::from_file_contents(b"caf\xe9\n", None);
+        let code = compile("EP/E=UTF-8/").unwrap();
+        assert_eq!(editor.execute(&code), ExecOutcome::Success);
+        assert_eq!(editor.file_contents().unwrap(), "café\n".as_bytes());
+    }
+
+    #[test]
+    fn test_ep_regex_pattern_syntax() {
+        let (editor, outcome) = exec("abc 123 def\n", r"EP/P=REGEX/ G`\d+`");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(
+            editor.current_frame().get_mark(MarkId::Equals).unwrap(),
+            Position::new(0, 4)
+        );
+        assert_eq!(editor.current_frame().dot(), Position::new(0, 7));
+    }
+
+    #[test]
+    fn test_ep_regex_lookaround_sets_contexts() {
+        let (editor, outcome) = exec("xy xyz\n", "EP/P=REGEX/ G`(?<=x)y(?=z)`");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(
+            editor.current_frame().get_mark(MarkId::Equals).unwrap(),
+            Position::new(0, 4)
+        );
+        assert_eq!(editor.current_frame().dot(), Position::new(0, 5));
+    }
+
+    #[test]
+    fn test_ep_regex_replace_with_groups() {
+        let (editor, outcome) = exec("a=1\nbb=22\n", r"EP/P=REGEX/ >R`(\w+)=(\w+)`\2=\1`");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "1=a\n22=bb\n");
+    }
+
+    #[test]
+    fn test_ep_unknown_pattern_syntax_fails() {
+        let (_, outcome) = exec("a\n", "EP/P=PERL/");
+        assert_eq!(outcome, ExecOutcome::Failure);
+    }
+
+    // ── FR / FP: paged files ────────────────────────────────────────────────
+
+    #[test]
+    fn test_fr_reads_lines_on_demand() {
+        let dir = ScratchDir::new("editor");
+        let path = dir.file("fr", b"one\ntwo\nthree\n");
+        let mut editor = Editor::open_paged(&path, None).unwrap();
+        // Keep the frame small so nothing is paged in behind our back.
+        editor.current_frame_mut().set_space_limit(5).unwrap();
+        assert_eq!(
+            editor.execute(&compile("FR").unwrap()),
+            ExecOutcome::Success
+        );
+        assert_eq!(editor.to_string(), "one\n");
+        assert_eq!(
+            editor.execute(&compile("FR").unwrap()),
+            ExecOutcome::Failure
+        );
+        let code = compile("EP/S=100/ >FR").unwrap();
+        assert_eq!(editor.execute(&code), ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "one\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn test_fr_fails_at_end_of_input() {
+        let dir = ScratchDir::new("editor");
+        let path = dir.file("fr-eof", b"one\n");
+        let mut editor = Editor::open_paged(&path, None).unwrap();
+        assert_eq!(
+            editor.execute(&compile(">FR").unwrap()),
+            ExecOutcome::Success
+        );
+        assert_eq!(
+            editor.execute(&compile("FR").unwrap()),
+            ExecOutcome::Failure
+        );
+        assert_eq!(editor.to_string(), "one\n");
+    }
+
+    #[test]
+    fn test_fp_pages_out_and_save_rebuilds_file() {
+        let dir = ScratchDir::new("editor");
+        let path = dir.file("fp", b"one\r\ntwo\r\nthree\r\n");
+        let mut editor = Editor::open_paged(&path, None).unwrap();
+        editor.current_frame_mut().page_in_near_dot();
+        let code = compile("2A FP I/2:/").unwrap();
+        assert_eq!(editor.execute(&code), ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "2:three\n");
+        assert!(editor.modified());
+
+        editor.save_paged().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"one\r\ntwo\r\n2:three\r\n");
+        let mut backup = path.clone().into_os_string();
+        backup.push("~1");
+        assert_eq!(std::fs::read(backup).unwrap(), b"one\r\ntwo\r\nthree\r\n");
+    }
+
+    /// A paged file of `count` numbered lines, none of which is read yet.
+    fn numbered_paged_file(dir: &ScratchDir, name: &str, count: usize) -> Editor {
+        let contents: String = (0..count).map(|n| format!("line {n:04}\n")).collect();
+        let path = dir.file(name, contents.as_bytes());
+        Editor::open_paged(&path, None).unwrap()
+    }
+
+    #[test]
+    fn test_advance_reads_paged_file_to_its_end() {
+        let dir = ScratchDir::new("editor");
+        let mut editor = numbered_paged_file(&dir, "advance-end", 4000);
+        let code = compile(">A I/END/").unwrap();
+        assert_eq!(editor.execute(&code), ExecOutcome::Success);
+        assert!(editor.to_string().ends_with("line 3999\nEND"));
+        assert_eq!(editor.current_frame().dot().line, 4000);
+    }
+
+    #[test]
+    fn test_search_reads_paged_file_far_ahead() {
+        let dir = ScratchDir::new("editor");
+        let mut editor = numbered_paged_file(&dir, "search-far", 4000);
+        let code = compile("G/line 3999/").unwrap();
+        assert_eq!(editor.execute(&code), ExecOutcome::Success);
+        assert_eq!(editor.current_frame().dot().line, 3999);
+
+        let mut editor = numbered_paged_file(&dir, "pattern-far", 4000);
+        let code = compile("G`\"line 3998\"`").unwrap();
+        assert_eq!(editor.execute(&code), ExecOutcome::Success);
+        assert_eq!(editor.current_frame().dot().line, 3998);
+
+        let mut editor = numbered_paged_file(&dir, "missing", 4000);
+        assert_eq!(
+            editor.execute(&compile("G/missing/").unwrap()),
+            ExecOutcome::Failure
+        );
+        assert_eq!(
+            editor.execute(&compile(">A EOF").unwrap()),
+            ExecOutcome::Success
+        );
+        let mut editor = numbered_paged_file(&dir, "eof", 4000);
+        assert_eq!(
+            editor.execute(&compile("EOF").unwrap()),
+            ExecOutcome::Failure
+        );
+    }
+
+    #[test]
+    fn test_paged_command_aborts_when_input_does_not_fit() {
+        let dir = ScratchDir::new("editor");
+        let mut editor = numbered_paged_file(&dir, "no-room", 4000);
+        editor.current_frame_mut().set_space_limit(20_000).unwrap();
+        assert_eq!(editor.execute(&compile(">A").unwrap()), ExecOutcome::Abort);
+        assert_eq!(
+            editor.execute(&compile("G/line 3999/").unwrap()),
+            ExecOutcome::Abort
+        );
+        assert!(editor.take_message().is_some_and(|m| m.contains("FP")));
+    }
+
+    // ── Crash-recovery journal ──────────────────────────────────────────────
+
+    #[test]
+    fn test_journal_replay_reproduces_edits() {
+        let dir = ScratchDir::new("editor");
+        let path = dir.file("journalled", b"alpha\nbeta\n");
+        let mut editor = Editor::from_file_contents(b"alpha\nbeta\n", None);
+        let journal = crate::journal::Journal::create(&path, b"alpha\nbeta\n").unwrap();
+        editor.current_frame_mut().attach_journal(journal);
+        let code = compile("10J I/!/ A O/XY/ 2*U SW 10ZR 3C 2D").unwrap();
+        editor.execute(&code);
+
+        let ops = crate::journal::Journal::recover(&path, b"alpha\nbeta\n")
+            .unwrap()
+            .unwrap();
+        let mut recovered = Editor::from_file_contents(b"alpha\nbeta\n", None);
+        assert!(recovered.current_frame_mut().replay_journal(&ops));
+        assert_eq!(recovered.to_string(), editor.to_string());
+        assert!(recovered.modified());
+
+        editor.discard_journal().unwrap();
+        assert!(!crate::journal::Journal::path_for(&path).exists());
+    }
+
+    #[test]
+    fn test_journal_replay_respects_space_limit() {
+        let ops = [crate::journal::JournalOp::Insert {
+            at: 0,
+            text: "too long".into(),
+        }];
+        let mut editor = Editor::from_file_contents(b"a\n", None);
+        editor.current_frame_mut().set_space_limit(5).unwrap();
+        assert!(!editor.current_frame_mut().replay_journal(&ops));
+        assert_eq!(editor.to_string(), "a\n");
+    }
+
+    // ── Step debugger ───────────────────────────────────────────────────────
+
+    /// Pauses where its stepper says, recording each pause as
+    /// `location command [repeats] dot-line` and answering it with the next
+    /// of `keys`: step into, step over, continue, toggle breakpoint, abort.
+    struct ScriptedDebugger {
+        stepper: Stepper,
+        keys: Vec<char>,
+        pauses: Vec<String>,
+    }
+
+    impl ScriptedDebugger {
+        fn new(keys: &str) -> Self {
+            Self {
+                stepper: Stepper::new(),
+                keys: keys.chars().collect(),
+                pauses: Vec::new(),
+            }
+        }
+    }
+
+    impl crate::Prompter for ScriptedDebugger {
+        fn prompt(&mut self, _prompt: &str) -> Option<String> {
+            Some("answer".to_string())
+        }
+    }
+
+    impl Debugger for ScriptedDebugger {
+        fn before(&mut self, step: &Step<'_>) -> DebugAction {
+            if !self.stepper.should_pause(step) {
+                return DebugAction::Continue;
+            }
+            let repeats: Vec<_> = step.repeats.iter().map(ToString::to_string).collect();
+            self.pauses.push(format!(
+                "{} {} [{}] {}",
+                step.location().unwrap_or_else(|| "-".to_string()),
+                describe(step.instruction),
+                repeats.join(" "),
+                step.frame.dot().line,
+            ));
+            loop {
+                match self.keys.remove(0) {
+                    's' => self.stepper.step_into(),
+                    'o' => self.stepper.step_over(step),
+                    'c' => self.stepper.resume(),
+                    'b' => {
+                        self.stepper.toggle_breakpoint(step);
+                        continue;
+                    }
+                    _ => return DebugAction::Abort,
+                }
+                return DebugAction::Continue;
+            }
+        }
+    }
+
+    fn debug(content: &str, commands: &str, keys: &str) -> (Editor, ExecOutcome, Vec<String>) {
+        let mut editor = Editor::from_str(content);
+        let code = compile(commands).unwrap();
+        let mut debugger = ScriptedDebugger::new(keys);
+        let outcome = editor.execute_debugged(&code, &mut debugger);
+        (editor, outcome, debugger.pauses)
+    }
+
+    #[test]
+    fn test_debugger_steps_into_and_over() {
+        let (editor, outcome, pauses) = debug("ab\ncd\nef\n", "2(A I/x/) J", "ssoooo");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(editor.to_string(), "ab\nxcd\nxef\n");
+        assert_eq!(
+            pauses,
+            [
+                "- 2(...) [] 0",
+                "1:3 A [1/2] 0",
+                "1:5 I/x/ [1/2] 1",
+                "1:3 A [2/2] 1",
+                "1:5 I/x/ [2/2] 2",
+                "1:11 J [] 2",
+            ]
+        );
+        // Stepping over a compound command runs its body without pausing.
+        let (_, _, pauses) = debug("ab\ncd\nef\n", "2(A I/x/) J", "os");
+        assert_eq!(pauses, ["- 2(...) [] 0", "1:11 J [] 2"]);
+    }
+
+    #[test]
+    fn test_debugger_steps_into_and_over_spans() {
+        let (_, outcome, pauses) = debug("ab\ncd\nef\n", "SA/M/A\nA/ EX/M/ J", "sssss");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(
+            pauses,
+            [
+                "1:1 SA/M/A^JA/ [] 0",
+                "2:4 EX/M/ [] 0",
+                "M:1:1 A [1/1] 0",
+                "M:2:1 A [1/1] 1",
+                "2:10 J [] 2",
+            ]
+        );
+        let (_, _, pauses) = debug("ab\ncd\nef\n", "SA/M/A\nA/ EX/M/ J", "sos");
+        assert_eq!(
+            pauses,
+            ["1:1 SA/M/A^JA/ [] 0", "2:4 EX/M/ [] 0", "2:10 J [] 2"]
+        );
+    }
+
+    #[test]
+    fn test_debugger_breakpoints() {
+        // Break on the loop's A, run to it twice, then clear it and run on.
+        let (_, outcome, pauses) = debug("a\nb\nc\nd\n", "3(A) J", "sbccbc");
+        assert_eq!(outcome, ExecOutcome::Success);
+        assert_eq!(
+            pauses,
+            [
+                "- 3(...) [] 0",
+                "1:3 A [1/3] 0",
+                "1:3 A [2/3] 1",
+                "1:3 A [3/3] 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_debugger_abort_and_prompts() {
+        let (editor, outcome, pauses) = debug("abc\n", "I&Text: & A", "sa");
+        assert_eq!(outcome, ExecOutcome::Abort);
+        assert_eq!(pauses.len(), 2);
+        // The debugger answered the prompt; the A was never executed.
+        assert_eq!(editor.to_string(), "answerabc\n");
+        assert_eq!(editor.current_frame().dot(), Position::new(0, 6));
+    }
+
+    #[test]
+    fn test_tracer_logs_each_instruction() {
+        let mut editor = Editor::from_str("ab\ncd\n");
+        let code = compile("SA/M/A/\n2(EX/M/) I&?&").unwrap();
+        let mut out = Vec::new();
+        let mut prompter = FixedPrompter("x");
+        let outcome = editor.execute_debugged(&code, &mut Tracer::new(&mut out, &mut prompter));
+        assert_eq!(outcome, ExecOutcome::Failure);
+        let trace = String::from_utf8(out).unwrap();
+        assert_eq!(
+            trace.lines().collect::<Vec<_>>(),
+            [
+                "1:1 SA/M/A/  ok  dot 1:1 -> 1:1",
+                "2(...)  dot 1:1",
+                "  2:3 EX/M/  dot 1:1",
+                "    M:1:1 A  ok  dot 1:1 -> 2:1",
+                "  2:3 EX/M/  ok  dot 1:1 -> 2:1",
+                "  2:3 EX/M/  dot 2:1",
+                "    M:1:1 A  failed (out of range)  dot 2:1 -> 2:1",
+                "  2:3 EX/M/  failed  dot 2:1 -> 2:1",
+                "2(...)  failed  dot 1:1 -> 2:1",
+            ]
+        );
+    }
 }
@@ -4,12 +4,21 @@
 //! Using a context type (rather than a bare `&mut Frame`) lets span commands
 //! reach across frames and lets future phases (Phase 7) track recursion depth.
 
-use crate::frame::Frame;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::debugger::{Debugger, Repeat};
+use crate::editor::Prompter;
+use crate::frame::{EditCommands, Frame};
 use crate::frame_set::FrameSet;
 use crate::marks::NUMBERED_MARK_RANGE;
+use crate::pattern::ast::PatternDef;
+use crate::pattern::{self, Dereference, PatternError, PatternSyntax};
+use crate::pretty::format_code;
 use crate::span::Span;
 
-use crate::{CmdFailure, CmdResult, LeadParam, MarkId, Position, TrailParam, compile};
+use crate::{
+    CmdFailure, CmdResult, FailedCommand, LeadParam, MarkId, Position, TrailParam, compile,
+};
 
 /// The execution environment for the Ludwig interpreter.
 pub(crate) struct ExecutionContext<'a> {
@@ -17,19 +26,127 @@ pub(crate) struct ExecutionContext<'a> {
     pub(crate) frame_set: &'a mut FrameSet,
     /// Current EX/EN nesting depth; capped at [`MAX_RECURSION_DEPTH`].
     pub(crate) recursion_depth: u32,
+    /// Source of answers for `&prompt&` dereferences, if any.
+    prompter: Option<&'a mut dyn Prompter>,
+    /// Offered each instruction before it is executed, if debugging.
+    pub(crate) debugger: Option<&'a mut dyn Debugger>,
+    /// How many compound commands, spans and exit handlers are executing.
+    pub(crate) depth: usize,
+    /// The repetitions of the compound commands and spans executing,
+    /// outermost first.
+    pub(crate) repeats: Vec<Repeat>,
+    /// An explanation of why the last command failed, for the user.
+    pub(crate) message: Option<String>,
+    /// The span or frame whose code is executing; `None` at top level.
+    pub(crate) source: Option<String>,
+    /// The innermost command that failed since the last one started.
+    pub(crate) failure: Option<FailedCommand>,
+    /// Set, e.g. by a SIGINT handler, to stop execution.
+    interrupt: Option<&'a AtomicBool>,
+    /// How many instructions may be executed, if limited.
+    step_budget: Option<u64>,
+    /// How many instructions have been executed.
+    steps: u64,
 }
 
+/// How many instructions to execute between asking the prompter whether
+/// the user has interrupted.
+const INTERRUPT_POLL_INTERVAL: u64 = 1024;
+
 /// Maximum allowed EX/EN recursion depth (spec section 9.8).
 pub(crate) const MAX_RECURSION_DEPTH: u32 = 100;
 
+/// Maximum nesting of delimiters inside a trailing parameter, e.g. `$&name&$`.
+const MAX_DEREFERENCE_DEPTH: usize = 16;
+
 impl<'a> ExecutionContext<'a> {
     pub(crate) fn new(frame_set: &'a mut FrameSet) -> Self {
         Self {
             frame_set,
             recursion_depth: 0,
+            prompter: None,
+            debugger: None,
+            depth: 0,
+            repeats: Vec::new(),
+            message: None,
+            source: None,
+            failure: None,
+            interrupt: None,
+            step_budget: None,
+            steps: 0,
+        }
+    }
+
+    /// A context that answers `&prompt&` dereferences using `prompter`.
+    pub(crate) fn with_prompter(
+        frame_set: &'a mut FrameSet,
+        prompter: &'a mut dyn Prompter,
+    ) -> Self {
+        Self {
+            prompter: Some(prompter),
+            ..Self::new(frame_set)
+        }
+    }
+
+    /// A context that offers each instruction to `debugger`, which also
+    /// answers `&prompt&` dereferences.
+    pub(crate) fn with_debugger(
+        frame_set: &'a mut FrameSet,
+        debugger: &'a mut dyn Debugger,
+    ) -> Self {
+        Self {
+            debugger: Some(debugger),
+            ..Self::new(frame_set)
         }
     }
 
+    /// Stop execution once `interrupt` is set or after `step_budget`
+    /// instructions, `steps` of which have already been executed.
+    pub(crate) fn with_limits(
+        self,
+        interrupt: Option<&'a AtomicBool>,
+        step_budget: Option<u64>,
+        steps: u64,
+    ) -> Self {
+        Self {
+            interrupt,
+            step_budget,
+            steps,
+            ..self
+        }
+    }
+
+    /// How many instructions have been executed, counting those before.
+    pub(crate) fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Count an instruction about to be executed, and say why execution
+    /// must stop instead, if it must: the user interrupted it, or it has
+    /// used up its step budget.
+    ///
+    /// The prompter or debugger is asked whether the user has interrupted
+    /// only every [`INTERRUPT_POLL_INTERVAL`] instructions, as that may mean
+    /// a system call.
+    pub(crate) fn check_limits(&mut self) -> Option<String> {
+        self.steps += 1;
+        if let Some(budget) = self.step_budget
+            && self.steps > budget
+        {
+            return Some(format!("Stopped after {budget} steps."));
+        }
+        let flagged = self
+            .interrupt
+            .is_some_and(|flag| flag.swap(false, Ordering::Relaxed));
+        let asked = self.steps.is_multiple_of(INTERRUPT_POLL_INTERVAL)
+            && match (&mut self.prompter, &mut self.debugger) {
+                (Some(prompter), _) => prompter.interrupted(),
+                (None, Some(debugger)) => debugger.interrupted(),
+                (None, None) => false,
+            };
+        (flagged || asked).then(|| "Interrupted.".to_string())
+    }
+
     /// Immutable reference to the current frame.
     pub(crate) fn current_frame(&self) -> &Frame {
         self.frame_set.current_frame()
@@ -134,9 +251,16 @@ impl<'a> ExecutionContext<'a> {
 
         // Extract span text (ends the borrow on ctx.frame_set before the insert).
         if let Some(text) = self.read_span_or_frame_text(&span_or_frame_name) {
+            // Check all copies fit up front so a failure leaves the frame untouched.
+            let needed = text.chars().count().saturating_mul(count);
+            if let Err(failure) = self.current_frame().check_room(needed) {
+                return CmdResult::Failure(failure);
+            }
             for _ in 0..count {
                 let dot = self.current_frame().dot();
-                self.current_frame_mut().insert_at(dot, &text);
+                if let Err(failure) = self.current_frame_mut().insert_at(dot, &text) {
+                    return CmdResult::Failure(failure);
+                }
             }
             CmdResult::Success
         } else {
@@ -192,6 +316,14 @@ impl<'a> ExecutionContext<'a> {
             (txt, frame_name, mark_start, mark_end, from, to)
         };
 
+        // A transfer within one frame never grows it; otherwise the destination
+        // must have room before the source is touched.
+        if src_frame_name != self.frame_set.current_name()
+            && let Err(failure) = self.current_frame().check_room(text.chars().count())
+        {
+            return CmdResult::Failure(failure);
+        }
+
         // Delete from source frame.
         {
             let src_frame = match self.frame_set.get_frame_mut(&src_frame_name) {
@@ -207,7 +339,9 @@ impl<'a> ExecutionContext<'a> {
 
         // Insert at current frame dot.
         let dot = self.current_frame().dot();
-        self.current_frame_mut().insert_at(dot, &text);
+        if let Err(failure) = self.current_frame_mut().insert_at(dot, &text) {
+            return CmdResult::Failure(failure);
+        }
 
         CmdResult::Success
     }
@@ -280,16 +414,8 @@ impl<'a> ExecutionContext<'a> {
             None => return CmdResult::Failure(CmdFailure::SyntaxError),
         };
 
-        // Resolve value: span dereference if delim == '$', otherwise literal.
-        let value = if tpars[1].delim == '$' {
-            let ref_name = tpars[1].content.trim().to_uppercase();
-            match self.read_span_or_frame_text(&ref_name) {
-                Some(t) => t,
-                None => return CmdResult::Failure(CmdFailure::MarkNotDefined),
-            }
-        } else {
-            tpars[1].content.clone()
-        };
+        // `SA$name$refspan$` has already had its value dereferenced.
+        let value = tpars[1].content.clone();
 
         // Check whether the target span already exists.
         if self.frame_set.contains_span(&span_name) {
@@ -315,8 +441,16 @@ impl<'a> ExecutionContext<'a> {
                 let frame = self.frame_set.get_frame_mut(&frame_name).unwrap();
                 // Get fresh to after resolving above
                 let to = frame.get_mark(mark_end).unwrap();
+                let removed = frame.to_char_index(&to) - frame.to_char_index(&from);
+                if let Err(failure) =
+                    frame.check_room(value.chars().count().saturating_sub(removed))
+                {
+                    return CmdResult::Failure(failure);
+                }
                 frame.delete(from, to);
-                frame.insert_at(from, &value);
+                if let Err(failure) = frame.insert_at(from, &value) {
+                    return CmdResult::Failure(failure);
+                }
                 // After delete+insert both marks end up past the new text.
                 // Reset mark_start back to 'from'.
                 frame.set_mark_at(mark_start, from);
@@ -340,7 +474,9 @@ impl<'a> ExecutionContext<'a> {
                 let last_col = hf.line_length_including_newline(last_line);
                 let insert_pos = Position::new(last_line, last_col);
                 // Insert span text followed by a newline separator.
-                hf.insert_at(insert_pos, &format!("{}\n", value));
+                if let Err(failure) = hf.insert_at(insert_pos, &format!("{}\n", value)) {
+                    return CmdResult::Failure(failure);
+                }
                 // mark_end points just before the separator newline.
                 let mark_end_pos = insert_pos.after_text(&value);
                 hf.set_mark_at(id_start, insert_pos);
@@ -400,7 +536,10 @@ impl<'a> ExecutionContext<'a> {
         // Compile it.
         let compiled = match compile(&text) {
             Ok(c) => c,
-            Err(_) => return CmdResult::Failure(CmdFailure::SyntaxError),
+            Err(err) => {
+                self.message = Some(format!("{span_name}: {err}"));
+                return CmdResult::Failure(CmdFailure::SyntaxError);
+            }
         };
 
         // Store the compiled code on the span.
@@ -415,6 +554,132 @@ impl<'a> ExecutionContext<'a> {
         }
     }
 
+    /// SP — Span Print
+    ///
+    /// `SP/name/`
+    /// Inserts the span's code at dot, formatted as canonical source.
+    pub(crate) fn cmd_span_print(&mut self, tpar: &TrailParam) -> CmdResult {
+        let span_name = match parse_span_name(tpar) {
+            Some(n) => n,
+            None => return CmdResult::Failure(CmdFailure::SyntaxError),
+        };
+        let text = match self.read_span_or_frame_text(&span_name) {
+            Some(t) => t,
+            None => return CmdResult::Failure(CmdFailure::OutOfRange),
+        };
+        let compiled = match compile(&text) {
+            Ok(c) => c,
+            Err(err) => {
+                self.message = Some(format!("{span_name}: {err}"));
+                return CmdResult::Failure(CmdFailure::SyntaxError);
+            }
+        };
+        let formatted = TrailParam::new(tpar.delim, format_code(&compiled));
+        self.current_frame_mut()
+            .cmd_insert_text(LeadParam::None, &formatted)
+    }
+
+    /// Resolve a `$name$` dereference to the text of a span or frame, or a
+    /// `&prompt&` dereference by asking the prompter.
+    pub(crate) fn dereference(&mut self, deref: Dereference<'_>) -> Option<String> {
+        match deref {
+            Dereference::Span(name) => self.read_span_or_frame_text(&name.trim().to_uppercase()),
+            Dereference::Prompt(prompt) => match (&mut self.prompter, &mut self.debugger) {
+                (Some(prompter), _) => prompter.prompt(prompt),
+                (None, Some(debugger)) => debugger.prompt(prompt),
+                (None, None) => None,
+            },
+        }
+    }
+
+    /// Resolve a `$span$` or `&prompt&` trailing parameter.
+    ///
+    /// The content of a `$` or `&` parameter may itself be wholly enclosed
+    /// in `$` or `&`, and is dereferenced from the innermost outwards, so
+    /// `G$&Span: &$` prompts for a span name and searches for that span's
+    /// text. Enclosing it in `"`, `'` or `` ` `` stops further dereferencing.
+    /// Parameters with any other delimiter are returned unchanged. An empty
+    /// prompt (`&&`) uses `default_prompt`. Unless `multi_line`, a
+    /// dereference may not supply more than one line.
+    pub(crate) fn dereference_tpar(
+        &mut self,
+        tpar: &TrailParam,
+        default_prompt: &str,
+        multi_line: bool,
+    ) -> Result<TrailParam, CmdFailure> {
+        if !matches!(tpar.delim, '$' | '&') {
+            return Ok(tpar.clone());
+        }
+        let content = self.evaluate_delimited(tpar.delim, &tpar.content, default_prompt, 0)?;
+        if !multi_line && content.contains('\n') && !tpar.content.contains('\n') {
+            return Err(CmdFailure::SyntaxError);
+        }
+        Ok(TrailParam::new('/', content))
+    }
+
+    /// Apply `delim` to `content`: dereference it for `$` and `&`, or take
+    /// it literally for anything else.
+    fn evaluate_delimited(
+        &mut self,
+        delim: char,
+        content: &str,
+        default_prompt: &str,
+        depth: usize,
+    ) -> Result<String, CmdFailure> {
+        if depth > MAX_DEREFERENCE_DEPTH {
+            return Err(CmdFailure::SyntaxError);
+        }
+        match delim {
+            '$' | '&' => {
+                let inner = self.evaluate_content(content, default_prompt, depth)?;
+                let deref = match (delim, inner.as_str()) {
+                    ('$', name) => Dereference::Span(name),
+                    (_, "") => Dereference::Prompt(default_prompt),
+                    (_, prompt) => Dereference::Prompt(prompt),
+                };
+                self.dereference(deref).ok_or(CmdFailure::OutOfRange)
+            }
+            _ => Ok(content.to_string()),
+        }
+    }
+
+    /// Evaluate the content of a `$` or `&` parameter, which is itself
+    /// evaluated if wholly enclosed in special delimiters.
+    fn evaluate_content(
+        &mut self,
+        content: &str,
+        default_prompt: &str,
+        depth: usize,
+    ) -> Result<String, CmdFailure> {
+        let mut chars = content.chars();
+        match (chars.next(), chars.next_back()) {
+            (Some(first), Some(last)) if first == last && is_special_delim(first) => {
+                self.evaluate_delimited(first, chars.as_str(), default_prompt, depth + 1)
+            }
+            _ => Ok(content.to_string()),
+        }
+    }
+
+    /// Parse a backtick-delimited pattern in the current frame's syntax,
+    /// resolving any dereferences.
+    ///
+    /// A syntax error is explained in [`message`](Self::message).
+    pub(crate) fn parse_pattern(&mut self, tpar: &TrailParam) -> Result<PatternDef, CmdFailure> {
+        let parsed = match self.current_frame().pattern_syntax() {
+            PatternSyntax::Ludwig => {
+                pattern::parse_with(&tpar.content, &mut |deref| self.dereference(deref))
+            }
+            PatternSyntax::Regex => pattern::parse_regex(&tpar.content),
+        };
+        parsed.map_err(|diagnostic| match diagnostic.error {
+            PatternError::UnresolvedDereference(_) => CmdFailure::OutOfRange,
+            _ => {
+                self.message = Some(diagnostic.to_string());
+                CmdFailure::SyntaxError
+            }
+        })
+    }
+
     /// Extracts the text of a span or frame by name.
     ///
     /// Returns `None` if neither a frame nor a span with that name exists,
@@ -422,23 +687,17 @@ impl<'a> ExecutionContext<'a> {
     pub(crate) fn read_span_or_frame_text(&self, name: &str) -> Option<String> {
         if let Some(frame) = self.frame_set.get_frame(name) {
             Some(frame.text())
-        } else if let Some(span) = self.frame_set.get_span(name) {
-            let frame = self.frame_set.get_frame(&span.frame_name)?;
-            let start = frame.get_mark(span.mark_start)?;
-            let end = frame.get_mark(span.mark_end)?;
-            let start_idx = frame.to_char_index(&start);
-            let end_idx = frame.to_char_index(&end);
-            if start_idx >= end_idx {
-                Some(String::new())
-            } else {
-                Some(frame.slice(start_idx..end_idx))
-            }
         } else {
-            None
+            self.frame_set.span_text(name)
         }
     }
 }
 
+/// True for delimiters with a special meaning inside trailing parameters.
+pub(crate) fn is_special_delim(c: char) -> bool {
+    matches!(c, '$' | '&' | '"' | '\'' | '`')
+}
+
 pub(crate) const MAX_SPAN_NAME_LEN: usize = 31;
 
 /// Validate and normalise a span name from a trailing param.
@@ -1,26 +1,40 @@
 //! The main Frame type that combines a Rope with marks and handles virtual space.
 
+mod counter;
 mod edit;
+mod file;
 mod motion;
+mod params;
 mod predicate;
 mod search;
 mod word;
 
+pub use counter::CounterCommands;
+use counter::{COUNTER_COUNT, counter_index};
 pub use edit::{CaseMode, EditCommands};
+pub use file::FileCommands;
 pub use motion::MotionCommands;
+pub use params::ParameterCommands;
 pub use predicate::PredicateCommands;
 pub use search::SearchCommands;
 pub use word::WordCommands;
 
 use std::collections::HashMap;
 use std::fmt;
-use std::ops::RangeBounds;
+use std::ops::{Range, RangeBounds};
+use std::sync::Arc;
 
 use ropey::Rope;
 
 use crate::CompiledCode;
+use crate::cmd_result::CmdFailure;
+use crate::journal::{Journal, JournalOp};
+use crate::lead_param::LeadParam;
 use crate::marks::{MarkId, MarkSet};
+use crate::paging::{InputFile, OutputFile};
+use crate::pattern::{PatternDiagnostic, PatternSyntax, ast::PatternDef};
 use crate::position::Position;
+use crate::text_format::{EncodeError, Encoding, TextFormat};
 
 /// An editable text frame with support for virtual space and marks.
 #[derive(Debug)]
@@ -32,13 +46,32 @@ pub struct Frame {
     /// All marks (including dot) in this frame.
     marks: MarkSet,
     /// Compiled code for the frame
-    code: Option<CompiledCode>,
+    code: Option<Arc<CompiledCode>>,
     /// Left margin column (0-based). Text should start at or after this column.
     pub left_margin: usize,
     /// Right margin: maximum line length. Lines should be at most this many characters.
     pub right_margin: usize,
+    /// Maximum number of characters the frame may hold (`-s` / `EP'S=n'`).
+    space_limit: usize,
+    /// Line endings, BOM and final newline to reproduce when saving.
+    text_format: TextFormat,
+    /// Syntax of backtick-delimited patterns (`EP'P=REGEX'`).
+    pattern_syntax: PatternSyntax,
+    /// File read on demand into the end of the frame, if paged.
+    input: Option<InputFile>,
+    /// Temporary file receiving text paged out of the frame by FP.
+    output: Option<OutputFile>,
+    /// Why input a command needed could not be read, until taken.
+    paging_failure: Option<CmdFailure>,
+    /// Crash-recovery journal that every change to the text is recorded in.
+    journal: Option<Journal>,
+    /// Integer counters `A` to `Z` (`~S`, `~A`, `#A`).
+    counters: [i64; COUNTER_COUNT],
 }
 
+/// Default per-frame character limit, as documented for the `-s` option.
+pub const DEFAULT_SPACE_LIMIT: usize = 500_000;
+
 impl Default for Frame {
     fn default() -> Self {
         Self::new("")
@@ -62,6 +95,14 @@ impl Frame {
             code: None,
             left_margin: 0,
             right_margin: 79,
+            space_limit: DEFAULT_SPACE_LIMIT,
+            text_format: TextFormat::default(),
+            pattern_syntax: PatternSyntax::default(),
+            input: None,
+            output: None,
+            paging_failure: None,
+            journal: None,
+            counters: [0; COUNTER_COUNT],
         }
     }
 
@@ -78,8 +119,29 @@ impl Frame {
             code: None,
             left_margin: 0,
             right_margin: 79,
+            space_limit: DEFAULT_SPACE_LIMIT,
+            text_format: TextFormat::default(),
+            pattern_syntax: PatternSyntax::default(),
+            input: None,
+            output: None,
+            paging_failure: None,
+            journal: None,
+            counters: [0; COUNTER_COUNT],
         }
     }
+
+    /// Create a frame from the raw contents of a file.
+    ///
+    /// The contents are decoded using `encoding`, or a detected encoding if
+    /// `None`, and line endings are normalised to `\n`. The resulting
+    /// [`TextFormat`] is kept so that [`file_contents`](Self::file_contents)
+    /// reproduces the file.
+    pub fn from_file_contents(name: &str, contents: &[u8], encoding: Option<Encoding>) -> Self {
+        let (text_format, text) = TextFormat::decode(contents, encoding);
+        let mut frame = Self::from_str(name, &text);
+        frame.text_format = text_format;
+        frame
+    }
 }
 
 // Core Frame methods (used by command implementations)
@@ -117,13 +179,13 @@ impl Frame {
     }
 
     /// Get the compiled code for the frame
-    pub fn get_code(&self) -> Option<&CompiledCode> {
+    pub fn get_code(&self) -> Option<&Arc<CompiledCode>> {
         self.code.as_ref()
     }
 
     /// Set the compiled code for the frame
-    pub fn set_code(&mut self, code: CompiledCode) {
-        self.code = Some(code)
+    pub fn set_code(&mut self, code: impl Into<Arc<CompiledCode>>) {
+        self.code = Some(code.into())
     }
 
     /// Unset the compiled code for the frame
@@ -131,6 +193,129 @@ impl Frame {
         self.code = None
     }
 
+    /// The value of counter `name`, an upper case letter.
+    pub fn counter(&self, name: char) -> i64 {
+        self.counters[counter_index(name)]
+    }
+
+    /// Set counter `name`, an upper case letter, to `value`.
+    pub fn set_counter(&mut self, name: char, value: i64) {
+        self.counters[counter_index(name)] = value;
+    }
+
+    /// `lead_param`, or if it is a counter, the counter's value as a count.
+    pub fn resolve_counter(&self, lead_param: LeadParam) -> LeadParam {
+        match lead_param {
+            LeadParam::Counter(name) => {
+                let value = self.counter(name);
+                // usize is at least as wide as i64 on the targets we build for.
+                let count = value.unsigned_abs() as usize;
+                if value < 0 {
+                    LeadParam::Nint(count)
+                } else {
+                    LeadParam::Pint(count)
+                }
+            }
+            lead_param => lead_param,
+        }
+    }
+
+    /// Maximum number of characters the frame may hold.
+    pub fn space_limit(&self) -> usize {
+        self.space_limit
+    }
+
+    /// Change the frame's space limit.
+    ///
+    /// Fails with [`CmdFailure::NoRoom`] if the frame already holds more than
+    /// `limit` characters; the limit is left unchanged in that case.
+    pub fn set_space_limit(&mut self, limit: usize) -> Result<(), CmdFailure> {
+        if self.rope.len_chars() > limit {
+            return Err(CmdFailure::NoRoom);
+        }
+        self.space_limit = limit;
+        Ok(())
+    }
+
+    /// Check that `additional` more characters fit within the space limit.
+    pub fn check_room(&self, additional: usize) -> Result<(), CmdFailure> {
+        if self.rope.len_chars().saturating_add(additional) > self.space_limit {
+            Err(CmdFailure::NoRoom)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// On-disk format used when saving the frame.
+    pub fn text_format(&self) -> TextFormat {
+        self.text_format
+    }
+
+    /// Change the on-disk format used when saving the frame.
+    pub fn set_text_format(&mut self, text_format: TextFormat) {
+        self.text_format = text_format;
+    }
+
+    /// Syntax in which backtick-delimited patterns are written.
+    pub fn pattern_syntax(&self) -> PatternSyntax {
+        self.pattern_syntax
+    }
+
+    /// Change the syntax in which backtick-delimited patterns are written.
+    pub fn set_pattern_syntax(&mut self, syntax: PatternSyntax) {
+        self.pattern_syntax = syntax;
+    }
+
+    /// The frame's text encoded in its [`TextFormat`], ready to be written out.
+    pub fn file_contents(&self) -> Result<Vec<u8>, EncodeError> {
+        self.text_format.encode(&self.text())
+    }
+
+    /// Record all further changes to the text in `journal`.
+    pub fn attach_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    /// Detach the frame's journal, if it has one.
+    pub fn take_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
+    pub fn journal_mut(&mut self) -> Option<&mut Journal> {
+        self.journal.as_mut()
+    }
+
+    /// Reapply changes recovered from a journal, leaving dot at the last one.
+    ///
+    /// Stops at the first change that does not fit the text or the space
+    /// limit, returning false. The changes are recorded in any attached
+    /// journal like any other edit.
+    pub fn replay_journal(&mut self, ops: &[JournalOp]) -> bool {
+        let mut last = None;
+        for op in ops {
+            match op {
+                JournalOp::Insert { at, text }
+                    if *at <= self.rope.len_chars()
+                        && self.check_room(text.chars().count()).is_ok() =>
+                {
+                    self.rope_insert(*at, text);
+                    last = Some(*at + text.chars().count());
+                }
+                JournalOp::Remove { from, to } if from <= to && *to <= self.rope.len_chars() => {
+                    self.rope_remove(*from..*to);
+                    last = Some(*from);
+                }
+                _ => return false,
+            }
+        }
+        if let Some(idx) = last {
+            let line = self.rope.char_to_line(idx);
+            self.set_dot(Position::new(line, idx - self.rope.line_to_char(line)));
+            self.set_mark(MarkId::Modified);
+        }
+        true
+    }
+
     /// Get the number of lines in the frame
     pub fn line_count(&self) -> usize {
         if self.rope.len_chars() == 0 {
@@ -209,13 +394,12 @@ impl Frame {
             if len > 0 {
                 let last_char = self.rope.char(len - 1);
                 if last_char != '\n' && last_char != '\r' {
-                    self.rope.insert_char(len, '\n');
+                    self.rope_insert(len, "\n");
                 }
             }
 
             // Add the required newlines
-            self.rope
-                .insert(self.rope.len_chars(), &"\n".repeat(lines_to_add));
+            self.rope_insert(self.rope.len_chars(), &"\n".repeat(lines_to_add));
         }
 
         // Now pad the line with spaces if needed
@@ -225,7 +409,21 @@ impl Frame {
             let line_start = self.rope.line_to_char(pos.line);
             let insert_pos = line_start + line_len;
 
-            self.rope.insert(insert_pos, &" ".repeat(spaces_needed));
+            self.rope_insert(insert_pos, &" ".repeat(spaces_needed));
+        }
+    }
+
+    /// Number of characters [`materialize_virtual_space`](Self::materialize_virtual_space)
+    /// would add to make `pos` real.
+    fn virtual_space_cost(&self, pos: Position) -> usize {
+        let total_lines = self.rope.len_lines();
+        if pos.line >= total_lines {
+            let len = self.rope.len_chars();
+            let needs_eol = len > 0 && !matches!(self.rope.char(len - 1), '\n' | '\r');
+            usize::from(needs_eol) + (pos.line - total_lines + 1) + pos.column
+        } else {
+            pos.column
+                .saturating_sub(self.line_length_excluding_newline(pos.line))
         }
     }
 
@@ -233,10 +431,14 @@ impl Frame {
     ///
     /// If the position is in virtual space, materializes the space first.
     /// Updates all marks appropriately.
-    pub fn insert_at(&mut self, pos: Position, text: &str) {
+    ///
+    /// Fails with [`CmdFailure::NoRoom`], leaving the frame untouched, if the
+    /// text and any virtual space padding would exceed the space limit.
+    pub fn insert_at(&mut self, pos: Position, text: &str) -> Result<(), CmdFailure> {
         if text.is_empty() {
-            return;
+            return Ok(());
         }
+        self.check_room(self.virtual_space_cost(pos) + text.chars().count())?;
 
         // Materialize virtual space if needed
         self.materialize_virtual_space(pos);
@@ -245,13 +447,14 @@ impl Frame {
         let char_idx = self.to_char_index(&pos);
 
         // Insert the text
-        self.rope.insert(char_idx, text);
+        self.rope_insert(char_idx, text);
 
         // Calculate how the insertion affects positions
         let (lines_added, end_column) = calculate_insert_effect(text);
 
         // Update all marks
         self.marks.update_after_insert(pos, lines_added, end_column);
+        Ok(())
     }
 
     /// Insert text at the current dot position.
@@ -259,8 +462,8 @@ impl Frame {
     /// If dot is in virtual space, materializes the space first.
     /// Updates all marks appropriately.
     /// Dot ends up at the end of the inserted text.
-    fn insert(&mut self, text: &str) {
-        self.insert_at(self.dot(), text);
+    fn insert(&mut self, text: &str) -> Result<(), CmdFailure> {
+        self.insert_at(self.dot(), text)
     }
 
     /// Overtype (replace) text at the current dot position.
@@ -268,16 +471,13 @@ impl Frame {
     /// This replaces existing characters with the new text.
     /// If dot is in virtual space, materializes the space first.
     /// If the text extends beyond the line, the extra characters are inserted.
-    fn overtype(&mut self, text: &str) {
+    fn overtype(&mut self, text: &str) -> Result<(), CmdFailure> {
         if text.is_empty() {
-            return;
+            return Ok(());
         }
 
         let pos = self.dot();
 
-        // Materialize virtual space if needed
-        self.materialize_virtual_space(pos);
-
         // Figure out how many characters we can replace on this line
         let line_len = self.line_length_excluding_newline(pos.line);
         let chars_after_cursor = line_len.saturating_sub(pos.column);
@@ -285,13 +485,17 @@ impl Frame {
         // Count chars in text (handling multi-line text)
         let first_line_chars = Self::first_line_length(text);
         let chars_to_replace = first_line_chars.min(chars_after_cursor);
+        self.check_room(
+            self.virtual_space_cost(pos) + text.chars().count().saturating_sub(chars_to_replace),
+        )?;
+
+        // Materialize virtual space if needed
+        self.materialize_virtual_space(pos);
 
         let (pos, to_insert) = if chars_to_replace > 0 {
             let overwrite_position = self.to_char_index(&pos);
-            self.rope
-                .remove(overwrite_position..(overwrite_position + chars_to_replace));
-            self.rope
-                .insert(overwrite_position, &text[..chars_to_replace]);
+            self.rope_remove(overwrite_position..(overwrite_position + chars_to_replace));
+            self.rope_insert(overwrite_position, &text[..chars_to_replace]);
             // Dot moves to the end of the overwritten part
             let new_dot = Position::new(pos.line, pos.column + chars_to_replace);
             self.set_dot(new_dot);
@@ -301,7 +505,7 @@ impl Frame {
         };
 
         // Now insert the text
-        self.insert_at(pos, to_insert);
+        self.insert_at(pos, to_insert)
     }
 
     /// Delete text from `from` to `to` (exclusive).
@@ -323,13 +527,31 @@ impl Frame {
         let to_idx = self.to_char_index(&clamp_to);
 
         // Delete from the rope
-        self.rope.remove(from_idx..to_idx);
+        self.rope_remove(from_idx..to_idx);
 
         // Update all marks
         self.marks.update_after_delete(from, clamp_to);
         true
     }
 
+    /// Insert into the rope, recording the change in the journal.
+    ///
+    /// All changes to the text go through this or [`rope_remove`](Self::rope_remove).
+    fn rope_insert(&mut self, char_idx: usize, text: &str) {
+        self.rope.insert(char_idx, text);
+        if let Some(journal) = self.journal.as_mut() {
+            journal.record_insert(char_idx, text);
+        }
+    }
+
+    /// Remove from the rope, recording the change in the journal.
+    fn rope_remove(&mut self, char_range: Range<usize>) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.record_remove(char_range.start, char_range.end);
+        }
+        self.rope.remove(char_range);
+    }
+
     fn first_line_length(text: &str) -> usize {
         text.find(['\r', '\n']).unwrap_or(text.len())
     }
@@ -352,27 +574,26 @@ impl Frame {
         line_start + column
     }
 
-    /// Build a [`crate::pattern::MatchCtx`] for the given line.
+    /// Parse a backtick-delimited pattern in the frame's [`PatternSyntax`].
     ///
-    /// Returns `None` if `line_idx` is out of range.
-    pub fn make_match_ctx(&self, line_idx: usize) -> Option<crate::pattern::MatchCtx> {
-        if line_idx >= self.line_count() {
-            return None;
+    /// Dereferences are not resolved; the interpreter parses patterns that
+    /// may contain them itself.
+    pub fn parse_pattern(&self, text: &str) -> Result<PatternDef, PatternDiagnostic> {
+        match self.pattern_syntax {
+            PatternSyntax::Ludwig => crate::pattern::parse(text),
+            PatternSyntax::Regex => crate::pattern::parse_regex(text),
         }
-        let line_chars: Vec<char> = self
-            .rope
-            .line(line_idx)
-            .chars()
-            .filter(|&c| c != '\n' && c != '\r')
-            .collect();
-        Some(crate::pattern::MatchCtx {
-            line: line_chars,
+    }
+
+    /// Build a [`crate::pattern::MatchCtx`] borrowing the frame's text and marks.
+    pub fn make_match_ctx(&self) -> crate::pattern::MatchCtx<'_> {
+        crate::pattern::MatchCtx {
+            text: self.rope.slice(..),
             dot_col: self.dot().column,
             left_margin: self.left_margin,
             right_margin: self.right_margin,
-            line_idx,
-            marks: self.marks.clone(),
-        })
+            marks: &self.marks,
+        }
     }
 
     /// Clamp this position to be within the actual text (no virtual space).
@@ -457,6 +678,11 @@ impl FrameRegistry {
     pub fn contains(&self, name: &str) -> bool {
         self.frames.contains_key(name)
     }
+
+    /// Every frame, in no particular order.
+    pub fn frames_mut(&mut self) -> impl Iterator<Item = &mut Frame> {
+        self.frames.values_mut()
+    }
 }
 
 #[cfg(test)]
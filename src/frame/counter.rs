@@ -0,0 +1,136 @@
+//! Counter commands: ~S, ~A, ~E and ~I.
+//!
+//! Each frame has an integer counter for each letter, `A` to `Z`, that starts
+//! at zero. A counter's value can also be used as a leading parameter, as in
+//! `#A J`, or as the repeat count of a compound command, as in `#A(...)`.
+
+use crate::cmd_result::{CmdFailure, CmdResult};
+use crate::lead_param::LeadParam;
+use crate::trail_param::TrailParam;
+
+use super::Frame;
+use super::edit::EditCommands;
+use super::predicate::bool_result;
+
+/// Number of counters in each frame, one for each letter.
+pub(super) const COUNTER_COUNT: usize = 26;
+
+/// Commands that set, change, test and insert a frame's counters.
+pub trait CounterCommands {
+    /// ~S: Set a counter.
+    /// `~S/X/N/` sets counter X to N, which may be negative.
+    /// `~S/X/#Y/` sets counter X to the value of counter Y.
+    fn cmd_counter_set(&mut self, name: &TrailParam, value: &TrailParam) -> CmdResult;
+
+    /// ~A: Add to a counter.
+    /// `~A/X/` adds 1 to counter X, `N~A/X/` adds N, and `-~A/X/` and
+    /// `-N~A/X/` subtract 1 and N. Fails if the result would not fit.
+    fn cmd_counter_add(&mut self, lead_param: LeadParam, name: &TrailParam) -> CmdResult;
+
+    /// ~E: Test a counter against a number, or `#Y` for counter Y.
+    /// `~E/X/N/` succeeds if counter X == N.
+    /// `-~E/X/N/` succeeds if counter X != N.
+    /// `>~E/X/N/` succeeds if counter X >= N.
+    /// `<~E/X/N/` succeeds if counter X <= N.
+    fn cmd_counter_equal(
+        &mut self,
+        lead_param: LeadParam,
+        name: &TrailParam,
+        value: &TrailParam,
+    ) -> CmdResult;
+
+    /// ~I: Insert the value of a counter at dot, in decimal, as `I` would.
+    fn cmd_counter_insert(&mut self, lead_param: LeadParam, name: &TrailParam) -> CmdResult;
+}
+
+impl CounterCommands for Frame {
+    fn cmd_counter_set(&mut self, name: &TrailParam, value: &TrailParam) -> CmdResult {
+        match (parse_counter_name(name), self.parse_counter_value(value)) {
+            (Some(name), Some(value)) => {
+                self.set_counter(name, value);
+                CmdResult::Success
+            }
+            _ => CmdResult::Failure(CmdFailure::SyntaxError),
+        }
+    }
+
+    fn cmd_counter_add(&mut self, lead_param: LeadParam, name: &TrailParam) -> CmdResult {
+        let Some(name) = parse_counter_name(name) else {
+            return CmdResult::Failure(CmdFailure::SyntaxError);
+        };
+        let amount = match lead_param {
+            LeadParam::None | LeadParam::Plus => Some(1),
+            LeadParam::Minus => Some(-1),
+            LeadParam::Pint(n) => i64::try_from(n).ok(),
+            LeadParam::Nint(n) => i64::try_from(n).ok().map(|n| -n),
+            _ => return CmdResult::Failure(CmdFailure::SyntaxError),
+        };
+        match amount.and_then(|amount| self.counter(name).checked_add(amount)) {
+            Some(value) => {
+                self.set_counter(name, value);
+                CmdResult::Success
+            }
+            None => CmdResult::Failure(CmdFailure::OutOfRange),
+        }
+    }
+
+    fn cmd_counter_equal(
+        &mut self,
+        lead_param: LeadParam,
+        name: &TrailParam,
+        value: &TrailParam,
+    ) -> CmdResult {
+        let (Some(name), Some(value)) = (parse_counter_name(name), self.parse_counter_value(value))
+        else {
+            return CmdResult::Failure(CmdFailure::SyntaxError);
+        };
+        let counter = self.counter(name);
+        let result = match lead_param {
+            LeadParam::None | LeadParam::Plus => counter == value,
+            LeadParam::Minus => counter != value,
+            LeadParam::Pindef => counter >= value,
+            LeadParam::Nindef => counter <= value,
+            _ => return CmdResult::Failure(CmdFailure::SyntaxError),
+        };
+        bool_result(result)
+    }
+
+    fn cmd_counter_insert(&mut self, lead_param: LeadParam, name: &TrailParam) -> CmdResult {
+        let Some(name) = parse_counter_name(name) else {
+            return CmdResult::Failure(CmdFailure::SyntaxError);
+        };
+        let text = TrailParam::from_str(&self.counter(name).to_string());
+        self.cmd_insert_text(lead_param, &text)
+    }
+}
+
+impl Frame {
+    /// A counter's value, or `None` if `value` is neither a number nor `#`
+    /// and a counter name.
+    fn parse_counter_value(&self, value: &TrailParam) -> Option<i64> {
+        let value = value.content.trim();
+        match value.strip_prefix('#') {
+            Some(name) => Some(self.counter(counter_name(name)?)),
+            None => value.parse().ok(),
+        }
+    }
+}
+
+/// Parse the trailing parameter naming a counter: a single letter.
+fn parse_counter_name(tpar: &TrailParam) -> Option<char> {
+    counter_name(tpar.content.trim())
+}
+
+fn counter_name(name: &str) -> Option<char> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) if ch.is_ascii_alphabetic() => Some(ch.to_ascii_uppercase()),
+        _ => None,
+    }
+}
+
+/// Where counter `name`, an upper case letter, is kept.
+pub(super) fn counter_index(name: char) -> usize {
+    debug_assert!(name.is_ascii_uppercase());
+    usize::from(name as u8 - b'A')
+}
@@ -89,6 +89,7 @@ impl EditCommands for Frame {
             LeadParam::Nint(n) => self.cmd_del_backward(n),
             LeadParam::Nindef => self.cmd_del_backward(self.dot().column),
             LeadParam::Marker(id) => self.cmd_del_to_mark(id),
+            LeadParam::Counter(_) => CmdResult::Failure(CmdFailure::SyntaxError),
         }
     }
 
@@ -106,7 +107,12 @@ impl EditCommands for Frame {
             // We are not going to extend the line, so just clamp the dot to the actual text.
             let clamped_dot = self.clamp_to_text(&original_dot);
             self.set_dot(clamped_dot);
-            self.insert_at(Position::new(clamped_dot.line, clamped_dot.column), "\n");
+            if let Err(failure) =
+                self.insert_at(Position::new(clamped_dot.line, clamped_dot.column), "\n")
+            {
+                self.set_dot(original_dot);
+                return CmdResult::Failure(failure);
+            }
             self.set_mark(MarkId::Modified);
             self.set_mark_at(MarkId::Equals, original_dot);
             CmdResult::Success
@@ -146,6 +152,7 @@ impl EditCommands for Frame {
     }
 
     fn cmd_delete_line(&mut self, lead_param: LeadParam) -> CmdResult {
+        self.page_in_for(lead_param);
         let num_lines = self.line_count();
         let dot = self.dot();
         match lead_param {
@@ -210,10 +217,12 @@ impl EditCommands for Frame {
                     CmdResult::Failure(CmdFailure::MarkNotDefined)
                 }
             }
+            LeadParam::Counter(_) => CmdResult::Failure(CmdFailure::SyntaxError),
         }
     }
 
     fn cmd_swap_line(&mut self, lead_param: LeadParam) -> CmdResult {
+        self.page_in_for(lead_param);
         let dot = self.dot();
         let num_lines = self.line_count();
         let (source_line, dest_line) = match lead_param {
@@ -261,6 +270,7 @@ impl EditCommands for Frame {
                     return CmdResult::Failure(CmdFailure::MarkNotDefined);
                 }
             }
+            LeadParam::Counter(_) => return CmdResult::Failure(CmdFailure::SyntaxError),
         };
         if source_line == dest_line {
             // No-op if trying to swap the same line
@@ -283,9 +293,14 @@ impl Frame {
         if count == 0 {
             return CmdResult::Success;
         }
+        if let Err(failure) = self.check_room(count) {
+            return CmdResult::Failure(failure);
+        }
         let original_dot = self.dot();
         let insert_pos = Position::new(original_dot.line, 0);
-        self.insert_at(insert_pos, &"\n".repeat(count));
+        if let Err(failure) = self.insert_at(insert_pos, &"\n".repeat(count)) {
+            return CmdResult::Failure(failure);
+        }
         self.set_mark(MarkId::Modified);
         self.set_mark_at(MarkId::Equals, original_dot);
         if !move_dot {
@@ -300,8 +315,13 @@ impl Frame {
         if count == 0 {
             return CmdResult::Success;
         }
+        if let Err(failure) = self.check_room(count) {
+            return CmdResult::Failure(failure);
+        }
         let original_dot = self.dot();
-        self.insert(&" ".repeat(count));
+        if let Err(failure) = self.insert(&" ".repeat(count)) {
+            return CmdResult::Failure(failure);
+        }
         self.set_mark(MarkId::Modified);
         self.set_mark_at(MarkId::Equals, original_dot);
         if !move_dot {
@@ -315,8 +335,13 @@ impl Frame {
         if count == 0 {
             return CmdResult::Success;
         }
+        if let Err(failure) = self.check_room(text.chars().count().saturating_mul(count)) {
+            return CmdResult::Failure(failure);
+        }
         let last = self.dot();
-        self.insert(&text.repeat(count));
+        if let Err(failure) = self.insert(&text.repeat(count)) {
+            return CmdResult::Failure(failure);
+        }
         self.set_mark(MarkId::Modified);
         self.set_mark_at(MarkId::Equals, last);
         CmdResult::Success
@@ -384,7 +409,9 @@ impl Frame {
             return CmdResult::Success;
         }
         let last = self.dot();
-        self.overtype(&text.repeat(count));
+        if let Err(failure) = self.overtype(&text.repeat(count)) {
+            return CmdResult::Failure(failure);
+        }
         self.set_mark(MarkId::Modified);
         self.set_mark_at(MarkId::Equals, last);
         CmdResult::Success
@@ -467,11 +494,11 @@ impl Frame {
         let source_text = self.rope.slice(start..end).to_string();
 
         // Delete the source line
-        self.rope.remove(start..end);
+        self.rope_remove(start..end);
 
         // Insert the source text at the destination line
         let dest_start = self.rope.line_to_char(dest_line);
-        self.rope.insert(dest_start, &source_text);
+        self.rope_insert(dest_start, &source_text);
     }
 }
 
@@ -570,7 +597,7 @@ impl Frame {
 
         // Replace in the rope character by character
         let new_str: String = new_chars.into_iter().collect();
-        self.rope.remove(start_idx..start_idx + count);
-        self.rope.insert(start_idx, &new_str);
+        self.rope_remove(start_idx..start_idx + count);
+        self.rope_insert(start_idx, &new_str);
     }
 }
@@ -0,0 +1,282 @@
+//! File paging commands (FR, FP) and saving of paged frames.
+
+use std::io;
+use std::path::Path;
+
+use crate::cmd_result::{CmdFailure, CmdResult};
+use crate::lead_param::LeadParam;
+use crate::marks::MarkId;
+use crate::paging::{self, InputFile, OutputFile, PAGE_LINES};
+use crate::position::Position;
+use crate::text_format::{Encoding, TextFormat};
+
+use super::Frame;
+
+/// Commands that move text between a frame and its input and output files.
+pub trait FileCommands {
+    /// FR: Read lines from the frame's input file onto the end of the frame.
+    /// `FR` / `nFR` read one or `n` lines; `>FR` reads the rest of the file.
+    /// Fails if there is no input left, or no room for the next line.
+    fn cmd_file_read(&mut self, lead_param: LeadParam) -> CmdResult;
+
+    /// FP: Move all text above the line containing dot to the output file,
+    /// then read another page from the input file.
+    fn cmd_page(&mut self, lead_param: LeadParam) -> CmdResult;
+}
+
+impl FileCommands for Frame {
+    fn cmd_file_read(&mut self, lead_param: LeadParam) -> CmdResult {
+        let count = match lead_param {
+            LeadParam::None | LeadParam::Plus => 1,
+            LeadParam::Pint(n) => n,
+            LeadParam::Pindef => usize::MAX,
+            _ => return CmdResult::Failure(CmdFailure::SyntaxError),
+        };
+        if self.input.as_ref().is_none_or(|input| input.is_eof()) {
+            return CmdResult::Failure(CmdFailure::OutOfRange);
+        }
+        match self.read_input(count) {
+            Ok(0) => CmdResult::Failure(CmdFailure::NoRoom),
+            Ok(_) => CmdResult::Success,
+            Err(failure) => CmdResult::Failure(failure),
+        }
+    }
+
+    fn cmd_page(&mut self, lead_param: LeadParam) -> CmdResult {
+        if lead_param != LeadParam::None {
+            return CmdResult::Failure(CmdFailure::SyntaxError);
+        }
+        let dot_line = self.dot().line.min(self.line_count());
+        if dot_line > 0
+            && let Some(output) = self.output.as_mut()
+        {
+            let end = self.rope.line_to_char(dot_line);
+            let text = self.rope.slice(..end).to_string();
+            let first = output.is_empty();
+            let written = self
+                .text_format
+                .encode_chunk(&text, first)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+                .and_then(|bytes| output.write(&bytes));
+            if written.is_err() {
+                return CmdResult::Failure(CmdFailure::FileError);
+            }
+            self.delete(Position::zero(), Position::new(dot_line, 0));
+            self.set_mark(MarkId::Modified);
+        }
+        if self.input.as_ref().is_some_and(|input| !input.is_eof())
+            && let Err(failure) = self.read_input(PAGE_LINES)
+        {
+            return CmdResult::Failure(failure);
+        }
+        CmdResult::Success
+    }
+}
+
+impl Frame {
+    /// Create a frame that reads `path` incrementally and pages out to a
+    /// temporary file alongside it.
+    ///
+    /// Nothing is read until [`page_in_near_dot`](Self::page_in_near_dot) or FR.
+    pub fn open_paged(name: &str, path: &Path, encoding: Option<Encoding>) -> io::Result<Self> {
+        let input = InputFile::open(path, encoding)?;
+        let mut frame = Self::new(name);
+        frame.text_format.encoding = input.encoding();
+        frame.input = Some(input);
+        frame.output = Some(OutputFile::create_for(path)?);
+        Ok(frame)
+    }
+
+    /// True if the frame is attached to an input file that is read on demand.
+    pub fn is_paged(&self) -> bool {
+        self.input.is_some()
+    }
+
+    /// True if the frame is paged and there is input it has not read yet.
+    pub(crate) fn has_more_input(&self) -> bool {
+        self.input.as_ref().is_some_and(|input| !input.is_eof())
+    }
+
+    /// Read input until `line` is a line of text rather than the null line
+    /// after it, or the input runs out. Commands call this before going
+    /// forward to `line`, so that they see the file as it is rather than
+    /// the part of it read so far.
+    ///
+    /// If the lines do not fit, the failure is kept for
+    /// [`take_paging_failure`](Self::take_paging_failure).
+    pub(crate) fn page_in_through(&mut self, line: usize) {
+        let wanted = line.saturating_add(2);
+        if self.line_count() >= wanted || !self.has_more_input() {
+            return;
+        }
+        let count = (wanted - self.line_count()).max(PAGE_LINES);
+        match self.read_input(count) {
+            Ok(_) if self.line_count() >= wanted || !self.has_more_input() => {}
+            Ok(_) => self.paging_failure = Some(CmdFailure::NoRoom),
+            Err(failure) => self.paging_failure = Some(failure),
+        }
+    }
+
+    /// Read input as far as `lead_param` says a command may go forward in
+    /// lines: the next `n` lines, or all of it for `>`.
+    pub(crate) fn page_in_for(&mut self, lead_param: LeadParam) {
+        let count = match lead_param {
+            LeadParam::None | LeadParam::Plus => 1,
+            LeadParam::Pint(n) => n,
+            LeadParam::Pindef => usize::MAX,
+            _ => return,
+        };
+        self.page_in_through(self.dot().line.saturating_add(count));
+    }
+
+    /// Run a forward `search` over the text, reading more input and running
+    /// it again for as long as it says more text could change its result.
+    ///
+    /// Each read is twice as large as the last, so a search that runs far
+    /// into the file goes over the text already read only a few times.
+    pub(crate) fn search_paged<T>(
+        &mut self,
+        search: impl Fn(&Self) -> (Option<T>, bool),
+    ) -> Option<T> {
+        let mut count = PAGE_LINES;
+        loop {
+            let (found, unsettled) = search(self);
+            if !unsettled || !self.has_more_input() {
+                return found;
+            }
+            match self.read_input(count) {
+                Ok(0) => {
+                    self.paging_failure = Some(CmdFailure::NoRoom);
+                    return found;
+                }
+                Ok(_) => count = count.saturating_mul(2),
+                Err(failure) => {
+                    self.paging_failure = Some(failure);
+                    return found;
+                }
+            }
+        }
+    }
+
+    /// Why the last command could not read input it needed, if it could
+    /// not. What the command did may then be wrong, so it is to be stopped.
+    pub fn take_paging_failure(&mut self) -> Option<CmdFailure> {
+        self.paging_failure.take()
+    }
+
+    /// Read more input once dot is within [`PAGE_LINES`] of the end of the text.
+    pub fn page_in_near_dot(&mut self) {
+        let wanted = self.dot().line.saturating_add(PAGE_LINES);
+        if self.line_count() < wanted && self.has_more_input() {
+            let _ = self.read_input(wanted - self.line_count());
+        }
+    }
+
+    /// Write the paged-out text, the frame and the unread input back to the
+    /// input file, keeping a `~1` backup.
+    ///
+    /// The frame is left as it was: text already paged out stays on disk and
+    /// the rest of the file is still read on demand.
+    pub fn save_paged(&mut self) -> io::Result<()> {
+        let (Some(mut input), Some(mut output)) = (self.input.take(), self.output.take()) else {
+            return Err(io::Error::other("frame is not paged"));
+        };
+        let keep = output.len();
+        let offset = input.offset();
+        if let Err(e) = self.write_remaining(&mut input, &mut output) {
+            // The original file is untouched; rewind both files and carry on paging.
+            output.truncate(keep)?;
+            self.input = Some(input.reopen_at(offset)?);
+            self.output = Some(output);
+            return Err(e);
+        }
+        let resume_at = output.len();
+        let (input, output) = paging::commit(
+            output,
+            input.path(),
+            self.text_format.encoding,
+            keep,
+            resume_at,
+        )?;
+        self.input = Some(input);
+        self.output = Some(output);
+        Ok(())
+    }
+
+    /// Append the frame text and the rest of `input` to `output`.
+    fn write_remaining(&self, input: &mut InputFile, output: &mut OutputFile) -> io::Result<()> {
+        let first = output.is_empty();
+        let mut text = self.text();
+        let bytes = if input.is_eof() {
+            let format = TextFormat {
+                bom: self.text_format.bom && first,
+                ..self.text_format
+            };
+            format.encode(&text)
+        } else {
+            if !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+            self.text_format.encode_chunk(&text, first)
+        };
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        output.write(&bytes.map_err(invalid)?)?;
+        while !input.is_eof() {
+            let chunk = normalise(&input.read_lines(PAGE_LINES, usize::MAX)?);
+            let bytes = self.text_format.encode_chunk(&chunk, false);
+            output.write(&bytes.map_err(invalid)?)?;
+        }
+        Ok(())
+    }
+
+    /// Read up to `count` lines from the input file onto the end of the frame.
+    ///
+    /// Marks are not moved, so a mark on the null line after the text ends up
+    /// on the first line read. Returns the number of lines read, which is
+    /// zero if the next line would exceed the space limit.
+    fn read_input(&mut self, count: usize) -> Result<usize, CmdFailure> {
+        let Some(input) = self.input.as_mut() else {
+            return Ok(0);
+        };
+        let first = input.at_start();
+        // Leave room for a line terminator added at the end of the file.
+        let room = self
+            .space_limit
+            .saturating_sub(self.rope.len_chars())
+            .saturating_sub(1);
+        let raw = input
+            .read_lines(count, room)
+            .map_err(|_| CmdFailure::FileError)?;
+        let eof = input.is_eof();
+
+        let mut text = if first {
+            let (format, text) = TextFormat::detect(&raw);
+            self.text_format.line_ending = format.line_ending;
+            self.text_format.bom = format.bom;
+            text
+        } else {
+            normalise(&raw)
+        };
+        if eof {
+            self.text_format.final_newline = raw.is_empty() || raw.ends_with(['\r', '\n']);
+        }
+        if text.is_empty() {
+            return Ok(0);
+        }
+        let len = self.rope.len_chars();
+        if len > 0 && self.rope.char(len - 1) != '\n' {
+            text.insert(0, '\n');
+        }
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        let lines = text.matches('\n').count();
+        self.rope_insert(self.rope.len_chars(), &text);
+        Ok(lines)
+    }
+}
+
+/// Convert `\r\n` and `\r` line endings to `\n`.
+fn normalise(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
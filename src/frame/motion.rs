@@ -33,6 +33,7 @@ pub trait MotionCommands {
 
 impl MotionCommands for Frame {
     fn cmd_advance(&mut self, lead_param: LeadParam) -> CmdResult {
+        self.page_in_for(lead_param);
         match lead_param {
             LeadParam::None | LeadParam::Plus => self.advance_fwd(1, false),
             LeadParam::Pint(n) => self.advance_fwd(n, false),
@@ -41,6 +42,7 @@ impl MotionCommands for Frame {
             LeadParam::Nint(n) => self.advance_back(n),
             LeadParam::Nindef => self.advance_begin(),
             LeadParam::Marker(id) => self.advance_to(self.get_mark(id)),
+            LeadParam::Counter(_) => CmdResult::Failure(CmdFailure::SyntaxError),
         }
     }
 
@@ -53,6 +55,7 @@ impl MotionCommands for Frame {
             LeadParam::Nint(n) => self.jump_back(n),
             LeadParam::Nindef => self.jump_begin(),
             LeadParam::Marker(id) => self.jump_to(self.get_mark(id)),
+            LeadParam::Counter(_) => CmdResult::Failure(CmdFailure::SyntaxError),
         }
     }
 
@@ -139,6 +142,7 @@ impl MotionCommands for Frame {
     }
 
     fn cmd_down(&mut self, lead_param: LeadParam) -> CmdResult {
+        self.page_in_for(lead_param);
         let dot = self.dot();
         let num_lines = self.line_count();
         match lead_param {
@@ -172,6 +176,7 @@ impl MotionCommands for Frame {
     fn cmd_return(&mut self, lead_param: LeadParam) -> CmdResult {
         // ZC: Advance n lines, go to left margin (column 0).
         // When on the last line, inserts a newline to extend the buffer.
+        self.page_in_for(lead_param);
         match lead_param {
             LeadParam::None | LeadParam::Plus => self.return_fwd(1),
             LeadParam::Pint(n) => self.return_fwd(n),
@@ -205,10 +210,12 @@ impl Frame {
             let lines_to_add = new_line - num_lines + 1;
             let last_line = num_lines.saturating_sub(1);
             let last_line_len = self.line_length_excluding_newline(last_line);
-            self.insert_at(
+            if let Err(failure) = self.insert_at(
                 Position::new(last_line, last_line_len),
                 &"\n".repeat(lines_to_add),
-            );
+            ) {
+                return CmdResult::Failure(failure);
+            }
         }
 
         self.set_mark_at(MarkId::Equals, old_pos);
@@ -0,0 +1,109 @@
+//! Frame parameter command (EP).
+
+use crate::cmd_result::{CmdFailure, CmdResult};
+use crate::pattern::PatternSyntax;
+use crate::text_format::{Encoding, LineEnding};
+use crate::trail_param::TrailParam;
+
+use super::Frame;
+
+/// Commands that change per-frame editor parameters.
+pub trait ParameterCommands {
+    /// EP: Set frame parameters from a comma-separated list of assignments.
+    /// `EP'S=n'` sets the frame's space limit to `n` characters.
+    /// Fails if the frame already holds more than `n` characters.
+    /// `EP'L=CRLF'` converts the line endings used on save (`LF`, `CRLF` or `CR`).
+    /// `EP'E=LATIN1'` converts the encoding used on save.
+    /// `EP'P=REGEX'` reads backtick-delimited patterns as regular expressions
+    /// (`P=LUDWIG` restores Ludwig's pattern syntax).
+    fn cmd_frame_parameters(&mut self, tpar: &TrailParam) -> CmdResult;
+}
+
+impl ParameterCommands for Frame {
+    fn cmd_frame_parameters(&mut self, tpar: &TrailParam) -> CmdResult {
+        let assignments = match parse_assignments(&tpar.content) {
+            Some(a) if !a.is_empty() => a,
+            // An empty parameter displays the current settings (not yet supported).
+            Some(_) => return CmdResult::Failure(CmdFailure::NotImplemented),
+            None => return CmdResult::Failure(CmdFailure::SyntaxError),
+        };
+
+        // Validate everything before applying anything.
+        let mut space_limit = None;
+        let mut line_ending = None;
+        let mut encoding = None;
+        let mut pattern_syntax = None;
+        for (key, value) in assignments {
+            match key {
+                'S' => match value.parse::<usize>() {
+                    Ok(n) => space_limit = Some(n),
+                    Err(_) => return CmdResult::Failure(CmdFailure::SyntaxError),
+                },
+                'L' => match value.parse::<LineEnding>() {
+                    Ok(ending) => line_ending = Some(ending),
+                    Err(_) => return CmdResult::Failure(CmdFailure::SyntaxError),
+                },
+                'E' => match value.parse::<Encoding>() {
+                    Ok(e) => encoding = Some(e),
+                    Err(_) => return CmdResult::Failure(CmdFailure::SyntaxError),
+                },
+                'P' => match value.parse::<PatternSyntax>() {
+                    Ok(syntax) => pattern_syntax = Some(syntax),
+                    Err(_) => return CmdResult::Failure(CmdFailure::SyntaxError),
+                },
+                'K' | 'C' | 'H' | 'W' | 'O' | 'M' | 'V' | 'T' => {
+                    return CmdResult::Failure(CmdFailure::NotImplemented);
+                }
+                _ => return CmdResult::Failure(CmdFailure::SyntaxError),
+            }
+        }
+
+        if let Some(limit) = space_limit
+            && let Err(failure) = self.set_space_limit(limit)
+        {
+            return CmdResult::Failure(failure);
+        }
+        let mut text_format = self.text_format();
+        if let Some(ending) = line_ending {
+            text_format.line_ending = ending;
+        }
+        if let Some(encoding) = encoding {
+            text_format.encoding = encoding;
+        }
+        self.set_text_format(text_format);
+        if let Some(syntax) = pattern_syntax {
+            self.set_pattern_syntax(syntax);
+        }
+        CmdResult::Success
+    }
+}
+
+/// Split `K=value, K=value` into `(key, value)` pairs.
+///
+/// Keys are returned in upper case. Parenthesised values may contain commas.
+/// A `$` (global default) prefix is not supported and yields `None`.
+fn parse_assignments(s: &str) -> Option<Vec<(char, &str)>> {
+    let mut result = Vec::new();
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        let mut chars = rest.chars();
+        let key = chars.next()?.to_ascii_uppercase();
+        if !key.is_ascii_alphabetic() {
+            return None;
+        }
+        rest = chars.as_str().trim_start().strip_prefix('=')?.trim_start();
+        let end = if rest.starts_with('(') {
+            rest.find(')')? + 1
+        } else {
+            rest.find(',').unwrap_or(rest.len())
+        };
+        result.push((key, rest[..end].trim()));
+        rest = rest[end..].trim_start();
+        if let Some(r) = rest.strip_prefix(',') {
+            rest = r.trim_start();
+        } else if !rest.is_empty() {
+            return None;
+        }
+    }
+    Some(result)
+}
@@ -5,6 +5,7 @@ use std::cmp::Ordering;
 use crate::cmd_result::{CmdFailure, CmdResult};
 use crate::lead_param::LeadParam;
 use crate::marks::MarkId;
+use crate::pattern::ast::PatternDef;
 use crate::position::Position;
 use crate::trail_param::TrailParam;
 
@@ -45,6 +46,9 @@ pub trait PredicateCommands {
     /// Leading `-` inverts.
     fn cmd_eqs(&mut self, lead_param: LeadParam, tpar: &TrailParam) -> CmdResult;
 
+    /// EQS with an already parsed pattern: succeeds if it matches at dot.
+    fn cmd_eqs_pattern(&mut self, lead_param: LeadParam, pattern: &PatternDef) -> CmdResult;
+
     /// M: Set/unset marks.
     /// `M` sets mark 1 at dot. `NM` sets mark N at dot.
     /// `-M` unsets mark 1. `-NM` unsets mark N.
@@ -80,8 +84,14 @@ impl PredicateCommands for Frame {
     }
 
     fn cmd_eof(&mut self, lead_param: LeadParam) -> CmdResult {
-        // EOF is the same as EOP in Ludwig-rs as the file is always fully loaded in memory.
-        self.cmd_eop(lead_param)
+        // EOF is EOP once all of a paged file has been read.
+        let invert = match lead_param {
+            LeadParam::None | LeadParam::Plus => false,
+            LeadParam::Minus => true,
+            _ => return CmdResult::Failure(CmdFailure::SyntaxError),
+        };
+        let at_eof = self.is_at_eop() && !self.has_more_input();
+        bool_result(at_eof ^ invert)
     }
 
     fn cmd_eqc(&mut self, lead_param: LeadParam, tpar: &TrailParam) -> CmdResult {
@@ -122,7 +132,10 @@ impl PredicateCommands for Frame {
 
     fn cmd_eqs(&mut self, lead_param: LeadParam, tpar: &TrailParam) -> CmdResult {
         if tpar.delim == '`' {
-            return self.cmd_eqs_pattern(lead_param, tpar);
+            return match self.parse_pattern(&tpar.content) {
+                Ok(pattern) => self.cmd_eqs_pattern(lead_param, &pattern),
+                Err(_) => CmdResult::Failure(CmdFailure::SyntaxError),
+            };
         }
 
         let case_sensitive = tpar.delim == '"';
@@ -171,23 +184,19 @@ impl PredicateCommands for Frame {
             _ => CmdResult::Failure(CmdFailure::SyntaxError),
         }
     }
-}
-
-impl Frame {
-    /// EQS command via pattern (backtick delimiter).
-    fn cmd_eqs_pattern(&mut self, lead_param: LeadParam, tpar: &TrailParam) -> CmdResult {
-        let pattern = match crate::pattern::parse(&tpar.content) {
-            Ok(p) => p,
-            Err(_) => return CmdResult::Failure(CmdFailure::SyntaxError),
-        };
 
+    fn cmd_eqs_pattern(&mut self, lead_param: LeadParam, pattern: &PatternDef) -> CmdResult {
         let dot = self.dot();
-        let ctx = match self.make_match_ctx(dot.line) {
-            Some(c) => c,
-            None => return CmdResult::Failure(CmdFailure::OutOfRange),
-        };
+        if dot.line >= self.line_count() {
+            return CmdResult::Failure(CmdFailure::OutOfRange);
+        }
 
-        let matches = crate::pattern::match_at(&pattern, &ctx, dot.column).is_some();
+        let program = crate::pattern::compile(pattern);
+        let matches = self
+            .search_paged(|frame| {
+                crate::pattern::match_at_partial(&program, &frame.make_match_ctx(), dot)
+            })
+            .is_some();
         let result = match lead_param {
             LeadParam::None | LeadParam::Plus => matches,
             LeadParam::Minus => !matches,
@@ -195,7 +204,9 @@ impl Frame {
         };
         bool_result(result)
     }
+}
 
+impl Frame {
     /// Check if dot is at end-of-page (last null line).
     fn is_at_eop(&self) -> bool {
         let num_lines = self.line_count();
@@ -240,7 +251,7 @@ impl Frame {
 }
 
 /// Convert a boolean condition to a CmdResult.
-fn bool_result(condition: bool) -> CmdResult {
+pub(super) fn bool_result(condition: bool) -> CmdResult {
     if condition {
         CmdResult::Success
     } else {
@@ -4,7 +4,10 @@ use std::collections::HashSet;
 
 use crate::cmd_result::{CmdFailure, CmdResult};
 use crate::lead_param::LeadParam;
+use crate::literal::LiteralSearcher;
 use crate::marks::MarkId;
+use crate::pattern::ast::PatternDef;
+use crate::pattern::{MatchResult, Template};
 use crate::position::Position;
 use crate::trail_param::TrailParam;
 
@@ -29,6 +32,17 @@ pub trait SearchCommands {
     /// Get (Search) command (G): search for literal text.
     /// On success: dot → after match, Equals → start of match.
     fn cmd_get(&mut self, lead_param: LeadParam, tpar: &TrailParam) -> CmdResult;
+
+    /// G with an already parsed pattern, e.g. one with dereferences resolved.
+    fn cmd_get_pattern(&mut self, lead_param: LeadParam, pattern: &PatternDef) -> CmdResult;
+
+    /// R with an already parsed search pattern.
+    fn cmd_replace_pattern(
+        &mut self,
+        lead_param: LeadParam,
+        pattern: &PatternDef,
+        replace: &TrailParam,
+    ) -> CmdResult;
 }
 
 impl SearchCommands for Frame {
@@ -59,7 +73,10 @@ impl SearchCommands for Frame {
         replace: &TrailParam,
     ) -> CmdResult {
         if search.delim == '`' {
-            return self.cmd_replace_pattern(lead_param, search, replace);
+            return match self.parse_pattern(&search.content) {
+                Ok(pattern) => self.cmd_replace_pattern(lead_param, &pattern, replace),
+                Err(_) => CmdResult::Failure(CmdFailure::SyntaxError),
+            };
         }
 
         // Determine count and direction
@@ -77,8 +94,9 @@ impl SearchCommands for Frame {
             return CmdResult::Failure(CmdFailure::OutOfRange);
         }
 
-        // Case sensitivity: / = insensitive, " = exact, others = insensitive
-        let case_sensitive = search.delim == '"';
+        let Some(searcher) = Self::literal_searcher(search) else {
+            return CmdResult::Failure(CmdFailure::OutOfRange);
+        };
 
         let original_dot = self.dot();
         let mut replacements = 0usize;
@@ -87,13 +105,18 @@ impl SearchCommands for Frame {
             // Replace all occurrences
             loop {
                 let found = if count > 0 {
-                    self.find_literal_forward(&search.content, case_sensitive)
+                    self.find_literal_forward(&searcher)
                 } else {
-                    self.find_literal_backward(&search.content, case_sensitive)
+                    self.find_literal_backward(&searcher)
                 };
                 match found {
                     Some((start, end)) => {
-                        self.do_replace(start, end, &replace.content);
+                        if let Err(failure) = self.do_replace(start, end, &replace.content) {
+                            if replacements > 0 {
+                                self.set_mark(MarkId::Modified);
+                            }
+                            return CmdResult::Failure(failure);
+                        }
                         replacements += 1;
                     }
                     None => break,
@@ -104,13 +127,18 @@ impl SearchCommands for Frame {
             let abs_count = count.unsigned_abs();
             for _ in 0..abs_count {
                 let found = if count > 0 {
-                    self.find_literal_forward(&search.content, case_sensitive)
+                    self.find_literal_forward(&searcher)
                 } else {
-                    self.find_literal_backward(&search.content, case_sensitive)
+                    self.find_literal_backward(&searcher)
                 };
                 match found {
                     Some((start, end)) => {
-                        self.do_replace(start, end, &replace.content);
+                        if let Err(failure) = self.do_replace(start, end, &replace.content) {
+                            if replacements > 0 {
+                                self.set_mark(MarkId::Modified);
+                            }
+                            return CmdResult::Failure(failure);
+                        }
                         replacements += 1;
                     }
                     None => {
@@ -134,7 +162,10 @@ impl SearchCommands for Frame {
 
     fn cmd_get(&mut self, lead_param: LeadParam, tpar: &TrailParam) -> CmdResult {
         if tpar.delim == '`' {
-            return self.cmd_get_pattern(lead_param, tpar);
+            return match self.parse_pattern(&tpar.content) {
+                Ok(pattern) => self.cmd_get_pattern(lead_param, &pattern),
+                Err(_) => CmdResult::Failure(CmdFailure::SyntaxError),
+            };
         }
 
         let (count, forward) = match lead_param {
@@ -149,13 +180,15 @@ impl SearchCommands for Frame {
             return CmdResult::Failure(CmdFailure::OutOfRange);
         }
 
-        let case_sensitive = tpar.delim == '"';
+        let Some(searcher) = Self::literal_searcher(tpar) else {
+            return CmdResult::Failure(CmdFailure::OutOfRange);
+        };
 
         for _ in 0..count {
             let found = if forward {
-                self.find_literal_forward(&tpar.content, case_sensitive)
+                self.find_literal_forward(&searcher)
             } else {
-                self.find_literal_backward(&tpar.content, case_sensitive)
+                self.find_literal_backward(&searcher)
             };
             match found {
                 Some((new_equals, new_dot)) => {
@@ -170,6 +203,127 @@ impl SearchCommands for Frame {
 
         CmdResult::Success
     }
+
+    fn cmd_get_pattern(&mut self, lead_param: LeadParam, pattern: &PatternDef) -> CmdResult {
+        let (count, forward) = match lead_param {
+            LeadParam::None | LeadParam::Plus => (1usize, true),
+            LeadParam::Pint(n) => (n, true),
+            LeadParam::Minus => (1, false),
+            LeadParam::Nint(n) => (n, false),
+            _ => return CmdResult::Failure(CmdFailure::SyntaxError),
+        };
+
+        if pattern.is_empty() {
+            return CmdResult::Failure(CmdFailure::OutOfRange);
+        }
+
+        let program = crate::pattern::compile(pattern);
+        for _ in 0..count {
+            let found = if forward {
+                self.find_pattern_forward(&program)
+            } else {
+                self.find_pattern_backward(&program)
+            };
+            match found {
+                Some(found) => {
+                    self.set_mark_at(MarkId::Equals, found.middle_start);
+                    self.set_dot(found.middle_end);
+                }
+                None => return CmdResult::Failure(CmdFailure::OutOfRange),
+            }
+        }
+        CmdResult::Success
+    }
+
+    fn cmd_replace_pattern(
+        &mut self,
+        lead_param: LeadParam,
+        pattern: &PatternDef,
+        replace: &TrailParam,
+    ) -> CmdResult {
+        let (count, replace_all) = match lead_param {
+            LeadParam::None | LeadParam::Plus => (1isize, false),
+            LeadParam::Pint(n) => (n as isize, false),
+            LeadParam::Minus => (-1, false),
+            LeadParam::Nint(n) => (-(n as isize), false),
+            LeadParam::Pindef => (1, true),
+            LeadParam::Nindef => (-1, true),
+            _ => return CmdResult::Failure(CmdFailure::SyntaxError),
+        };
+
+        if pattern.is_empty() {
+            return CmdResult::Failure(CmdFailure::OutOfRange);
+        }
+
+        let program = crate::pattern::compile(pattern);
+        let Ok(template) = Template::parse(&replace.content, program.group_count()) else {
+            return CmdResult::Failure(CmdFailure::SyntaxError);
+        };
+        let original_dot = self.dot();
+        let mut replacements = 0usize;
+
+        if replace_all {
+            loop {
+                let found = if count > 0 {
+                    self.find_pattern_forward(&program)
+                } else {
+                    self.find_pattern_backward(&program)
+                };
+                match found {
+                    Some(found) => {
+                        let replacement = self.expand_template(&template, &found);
+                        if let Err(failure) =
+                            self.do_replace(found.middle_start, found.middle_end, &replacement)
+                        {
+                            if replacements > 0 {
+                                self.set_mark(MarkId::Modified);
+                            }
+                            return CmdResult::Failure(failure);
+                        }
+                        replacements += 1;
+                    }
+                    None => break,
+                }
+            }
+        } else {
+            let abs_count = count.unsigned_abs();
+            for _ in 0..abs_count {
+                let found = if count > 0 {
+                    self.find_pattern_forward(&program)
+                } else {
+                    self.find_pattern_backward(&program)
+                };
+                match found {
+                    Some(found) => {
+                        let replacement = self.expand_template(&template, &found);
+                        if let Err(failure) =
+                            self.do_replace(found.middle_start, found.middle_end, &replacement)
+                        {
+                            if replacements > 0 {
+                                self.set_mark(MarkId::Modified);
+                            }
+                            return CmdResult::Failure(failure);
+                        }
+                        replacements += 1;
+                    }
+                    None => {
+                        if replacements == 0 {
+                            return CmdResult::Failure(CmdFailure::OutOfRange);
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+
+        if replacements > 0 {
+            self.set_mark(MarkId::Modified);
+            self.set_mark_at(MarkId::Equals, original_dot);
+            CmdResult::Success
+        } else {
+            CmdResult::Failure(CmdFailure::OutOfRange)
+        }
+    }
 }
 
 /// Parse the trailing parameter into a character set.
@@ -218,10 +372,19 @@ impl Frame {
 
         let chars = parse_char_set(tpar);
         let original_dot = self.dot();
-        let num_lines = self.line_count();
 
         if count > 0 {
-            match self.nextbridge_forward(count as usize, &chars, bridge, num_lines) {
+            // A match on the null line at the end may only be the end of the
+            // text read so far.
+            let found = self.search_paged(|frame| {
+                let found =
+                    frame.nextbridge_forward(count as usize, &chars, bridge, frame.line_count());
+                (
+                    found,
+                    found.is_none_or(|pos| pos.line + 1 >= frame.line_count()),
+                )
+            });
+            match found {
                 Some(pos) => {
                     self.set_mark_at(MarkId::Equals, original_dot);
                     self.set_dot(pos);
@@ -230,7 +393,7 @@ impl Frame {
                 None => CmdResult::Failure(CmdFailure::OutOfRange),
             }
         } else {
-            match self.nextbridge_backward((-count) as usize, &chars, bridge, num_lines) {
+            match self.nextbridge_backward((-count) as usize, &chars, bridge, self.line_count()) {
                 Some(pos) => {
                     self.set_mark_at(MarkId::Equals, original_dot);
                     self.set_dot(pos);
@@ -404,272 +567,101 @@ impl Frame {
     /// Search forward from dot for a literal string.
     /// Returns (start_position, end_position) of match.
     /// Dot is NOT moved by this method — the caller handles positioning.
-    fn find_literal_forward(
-        &self,
-        pattern: &str,
-        case_sensitive: bool,
-    ) -> Option<(Position, Position)> {
-        let dot = self.dot();
-        let num_lines = self.line_count();
-        let pat_chars: Vec<char> = pattern.chars().collect();
-        let pat_len = pat_chars.len();
-
-        if pat_len == 0 {
-            return None;
-        }
-
-        let mut line = dot.line;
-        let mut col = dot.column;
-
-        while line < num_lines {
-            let line_len = self.line_length_excluding_newline(line);
-            let line_start = self.rope.line_to_char(line);
-
-            while col + pat_len <= line_len {
-                if self.matches_at_pos(line_start + col, &pat_chars, case_sensitive) {
-                    return Some((Position::new(line, col), Position::new(line, col + pat_len)));
-                }
-                col += 1;
-            }
-            line += 1;
-            col = 0;
-        }
-        None
+    fn find_literal_forward(&mut self, searcher: &LiteralSearcher) -> Option<(Position, Position)> {
+        let from = self.to_char_index(&self.dot());
+        // Matches never span lines, so the first one found stays the first.
+        let start = self.search_paged(|frame| {
+            let start = searcher.find_forward(frame.rope.slice(..), from);
+            (start, start.is_none())
+        })?;
+        Some((
+            self.char_index_to_position(start),
+            self.char_index_to_position(start + searcher.len()),
+        ))
     }
 
     /// Search backward from dot for a literal string.
     /// Returns (end_position, start_position) of match.
-    fn find_literal_backward(
-        &self,
-        pattern: &str,
-        case_sensitive: bool,
-    ) -> Option<(Position, Position)> {
-        let dot = self.dot();
-        let pat_chars: Vec<char> = pattern.chars().collect();
-        let pat_len = pat_chars.len();
-
-        if pat_len == 0 {
-            return None;
-        }
-
-        let mut line = dot.line;
-        let line_len = self.line_length_excluding_newline(line);
-        // Start searching from one position before dot (or end of line if dot is beyond)
-        let effective_col = dot.column.min(line_len);
-        let mut col = effective_col.saturating_sub(1) as isize;
-
-        loop {
-            let line_len = self.line_length_excluding_newline(line);
-            let line_start = self.rope.line_to_char(line);
-
-            while col >= 0 {
-                let c = col as usize;
-                if c + pat_len <= line_len
-                    && self.matches_at_pos(line_start + c, &pat_chars, case_sensitive)
-                {
-                    return Some((Position::new(line, c + pat_len), Position::new(line, c)));
-                }
-                col -= 1;
-            }
+    fn find_literal_backward(&self, searcher: &LiteralSearcher) -> Option<(Position, Position)> {
+        // Only find matches that start before dot (or end of line if dot is beyond)
+        let before = self.to_char_index(&self.dot());
+        let start = searcher.find_backward(self.rope.slice(..), before)?;
+        Some((
+            self.char_index_to_position(start + searcher.len()),
+            self.char_index_to_position(start),
+        ))
+    }
 
-            if line == 0 {
-                return None;
-            }
-            line -= 1;
-            let prev_line_len = self.line_length_excluding_newline(line);
-            col = prev_line_len.saturating_sub(1) as isize;
-        }
+    fn char_index_to_position(&self, idx: usize) -> Position {
+        let line = self.rope.char_to_line(idx);
+        Position::new(line, idx - self.rope.line_to_char(line))
     }
 
-    /// Check if pattern matches at a specific char index in the rope.
-    fn matches_at_pos(&self, char_idx: usize, pat_chars: &[char], case_sensitive: bool) -> bool {
-        for (i, &pc) in pat_chars.iter().enumerate() {
-            let rc = self.rope.char(char_idx + i);
-            if case_sensitive {
-                if rc != pc {
-                    return false;
-                }
-            } else if !unicode_case_eq_char(rc, pc) {
-                return false;
-            }
+    /// Build a searcher for the literal text of a trailing parameter.
+    ///
+    /// Literal searches never span lines, so text containing a line break
+    /// can never be found.
+    fn literal_searcher(tpar: &TrailParam) -> Option<LiteralSearcher> {
+        if tpar.content.contains(['\n', '\r']) {
+            return None;
         }
-        true
+        // Case sensitivity: / = insensitive, " = exact, others = insensitive
+        Some(LiteralSearcher::new(&tpar.content, tpar.delim == '"'))
     }
 
     /// Perform a replacement: delete from start to end, insert replacement text.
     /// Dot ends up after the replacement text.
-    fn do_replace(&mut self, start: Position, end: Position, replacement: &str) {
+    /// Fails without modifying the frame if the result would not fit.
+    fn do_replace(
+        &mut self,
+        start: Position,
+        end: Position,
+        replacement: &str,
+    ) -> Result<(), CmdFailure> {
+        let removed = self.to_char_index(&end) - self.to_char_index(&start);
+        self.check_room(replacement.chars().count().saturating_sub(removed))?;
         // Move dot to start, delete the matched text, insert replacement
         self.set_dot(start);
         self.delete(start, end);
-        self.insert(replacement);
+        self.insert(replacement)
     }
 }
 
 // ─── Pattern search helpers ───────────────────────────────────────────────────
 
 impl Frame {
-    /// G command via pattern (backtick delimiter).
-    fn cmd_get_pattern(&mut self, lead_param: LeadParam, tpar: &TrailParam) -> CmdResult {
-        let (count, forward) = match lead_param {
-            LeadParam::None | LeadParam::Plus => (1usize, true),
-            LeadParam::Pint(n) => (n, true),
-            LeadParam::Minus => (1, false),
-            LeadParam::Nint(n) => (n, false),
-            _ => return CmdResult::Failure(CmdFailure::SyntaxError),
-        };
-
-        if tpar.content.is_empty() {
-            return CmdResult::Failure(CmdFailure::OutOfRange);
-        }
-
-        let pattern = match crate::pattern::parse(&tpar.content) {
-            Ok(p) => p,
-            Err(_) => return CmdResult::Failure(CmdFailure::SyntaxError),
-        };
-
-        for _ in 0..count {
-            let found = if forward {
-                self.find_pattern_forward(&pattern)
-            } else {
-                self.find_pattern_backward(&pattern)
-            };
-            match found {
-                Some((line, mid_start, mid_end)) => {
-                    self.set_mark_at(MarkId::Equals, Position::new(line, mid_start));
-                    self.set_dot(Position::new(line, mid_end));
-                }
-                None => return CmdResult::Failure(CmdFailure::OutOfRange),
-            }
-        }
-        CmdResult::Success
-    }
-
-    /// R command via pattern (backtick delimiter on search tpar).
-    fn cmd_replace_pattern(
-        &mut self,
-        lead_param: LeadParam,
-        search: &TrailParam,
-        replace: &TrailParam,
-    ) -> CmdResult {
-        let (count, replace_all) = match lead_param {
-            LeadParam::None | LeadParam::Plus => (1isize, false),
-            LeadParam::Pint(n) => (n as isize, false),
-            LeadParam::Minus => (-1, false),
-            LeadParam::Nint(n) => (-(n as isize), false),
-            LeadParam::Pindef => (1, true),
-            LeadParam::Nindef => (-1, true),
-            _ => return CmdResult::Failure(CmdFailure::SyntaxError),
-        };
-
-        if search.content.is_empty() {
-            return CmdResult::Failure(CmdFailure::OutOfRange);
-        }
-
-        let pattern = match crate::pattern::parse(&search.content) {
-            Ok(p) => p,
-            Err(_) => return CmdResult::Failure(CmdFailure::SyntaxError),
-        };
-
-        let original_dot = self.dot();
-        let mut replacements = 0usize;
-
-        if replace_all {
-            loop {
-                let found = if count > 0 {
-                    self.find_pattern_forward(&pattern)
-                } else {
-                    self.find_pattern_backward(&pattern)
-                };
-                match found {
-                    Some((line, start_col, end_col)) => {
-                        let start = Position::new(line, start_col);
-                        let end = Position::new(line, end_col);
-                        self.do_replace(start, end, &replace.content);
-                        replacements += 1;
-                    }
-                    None => break,
-                }
-            }
-        } else {
-            let abs_count = count.unsigned_abs();
-            for _ in 0..abs_count {
-                let found = if count > 0 {
-                    self.find_pattern_forward(&pattern)
-                } else {
-                    self.find_pattern_backward(&pattern)
-                };
-                match found {
-                    Some((line, start_col, end_col)) => {
-                        let start = Position::new(line, start_col);
-                        let end = Position::new(line, end_col);
-                        self.do_replace(start, end, &replace.content);
-                        replacements += 1;
-                    }
-                    None => {
-                        if replacements == 0 {
-                            return CmdResult::Failure(CmdFailure::OutOfRange);
-                        }
-                        break;
-                    }
-                }
-            }
-        }
-
-        if replacements > 0 {
-            self.set_mark(MarkId::Modified);
-            self.set_mark_at(MarkId::Equals, original_dot);
-            CmdResult::Success
-        } else {
-            CmdResult::Failure(CmdFailure::OutOfRange)
-        }
-    }
-
-    /// Search forward from dot for a pattern, line by line.
-    /// Returns `(line_idx, middle_start, middle_end)`.
-    fn find_pattern_forward(
-        &self,
-        pattern: &crate::pattern::ast::PatternDef,
-    ) -> Option<(usize, usize, usize)> {
+    /// Search forward from dot for a pattern.
+    fn find_pattern_forward(&mut self, program: &crate::pattern::Program) -> Option<MatchResult> {
         let dot = self.dot();
-        let num_lines = self.line_count();
-
-        for line_idx in dot.line..num_lines {
-            if let Some(ctx) = self.make_match_ctx(line_idx) {
-                let start_col = if line_idx == dot.line { dot.column } else { 0 };
-                if let Some(result) = crate::pattern::find_forward(pattern, &ctx, start_col) {
-                    return Some((line_idx, result.middle_start, result.middle_end));
-                }
-            }
-        }
-        None
+        self.search_paged(|frame| {
+            crate::pattern::find_forward_partial(program, &frame.make_match_ctx(), dot)
+        })
     }
 
-    /// Search backward from dot for a pattern, line by line.
-    /// Returns `(line_idx, middle_start, middle_end)`.
-    fn find_pattern_backward(
-        &self,
-        pattern: &crate::pattern::ast::PatternDef,
-    ) -> Option<(usize, usize, usize)> {
+    /// Search backward from dot for a pattern.
+    fn find_pattern_backward(&self, program: &crate::pattern::Program) -> Option<MatchResult> {
         let dot = self.dot();
+        // Only find matches that start before dot
+        let start = if dot.column > 0 {
+            Position::new(dot.line, dot.column - 1)
+        } else if dot.line > 0 {
+            Position::new(
+                dot.line - 1,
+                self.line_length_excluding_newline(dot.line - 1),
+            )
+        } else {
+            return None;
+        };
+        let ctx = self.make_match_ctx();
+        crate::pattern::find_backward(program, &ctx, start)
+    }
 
-        for line_idx in (0..=dot.line).rev() {
-            if let Some(ctx) = self.make_match_ctx(line_idx) {
-                let start_col = if line_idx == dot.line {
-                    // Only find matches that start before dot
-                    if dot.column == 0 {
-                        continue;
-                    }
-                    dot.column - 1
-                } else {
-                    ctx.line.len()
-                };
-                if let Some(result) = crate::pattern::find_backward(pattern, &ctx, start_col) {
-                    return Some((line_idx, result.middle_start, result.middle_end));
-                }
-            }
-        }
-        None
+    /// Expand a replacement template with the text of a match's groups.
+    fn expand_template(&self, template: &Template, found: &MatchResult) -> String {
+        template.expand(|n| match found.group(n) {
+            Some((start, end)) => self.slice(self.to_char_index(&start)..self.to_char_index(&end)),
+            None => String::new(),
+        })
     }
 }
 
@@ -681,14 +673,6 @@ fn char_matches(ch: char, chars: &HashSet<char>, bridge: bool) -> bool {
     chars.contains(&ch) ^ bridge
 }
 
-/// Unicode-aware, case-insensitive equality for single scalar values.
-///
-/// This compares lowercase expansions, so it handles mappings where
-/// a single character lowercases to multiple code points.
-fn unicode_case_eq_char(a: char, b: char) -> bool {
-    a.to_lowercase().eq(b.to_lowercase())
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
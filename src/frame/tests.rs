@@ -66,7 +66,7 @@ fn test_new_frame() {
 #[test]
 fn test_insert_at_beginning() {
     let mut frame = Frame::new(TEST_FRAME_NAME);
-    frame.insert("hello");
+    frame.insert("hello").unwrap();
     assert_eq!(frame.to_string(), "hello");
     assert_eq!(frame.dot(), Position::new(0, 5));
 }
@@ -74,7 +74,7 @@ fn test_insert_at_beginning() {
 #[test]
 fn test_insert_with_newlines() {
     let mut frame = Frame::new(TEST_FRAME_NAME);
-    frame.insert("hello\nworld");
+    frame.insert("hello\nworld").unwrap();
     assert_eq!(frame.to_string(), "hello\nworld");
     assert_eq!(frame.dot(), Position::new(1, 5));
 }
@@ -83,7 +83,7 @@ fn test_insert_with_newlines() {
 fn test_insert_in_middle() {
     let mut frame: Frame = Frame::from_str(TEST_FRAME_NAME, "helloworld\n");
     frame.set_dot(Position::new(0, 5));
-    frame.insert(" ");
+    frame.insert(" ").unwrap();
     assert_eq!(frame.to_string(), "hello world\n");
     assert_eq!(frame.dot(), Position::new(0, 6));
 }
@@ -97,7 +97,7 @@ fn test_virtual_space_insert() {
     assert!(frame.dot().column > frame.line_length_excluding_newline(0));
 
     // Insert text - should pad with spaces first
-    frame.insert("world");
+    frame.insert("world").unwrap();
 
     assert_eq!(frame.to_string(), "hello     world\n");
     // dot started at column 10 (virtual), then "world" (5 chars) was inserted
@@ -113,7 +113,7 @@ fn test_dot_clamped_to_lines() {
     frame.set_dot(Position::new(5, 3));
     assert_eq!(frame.dot().line, 1);
 
-    frame.insert("x");
+    frame.insert("x").unwrap();
 
     // Should have padded with spaces
     let content = frame.to_string();
@@ -159,7 +159,7 @@ fn test_delete_across_lines() {
 fn test_overtype() {
     let mut frame: Frame = Frame::from_str(TEST_FRAME_NAME, "hello world\n");
     frame.set_dot(Position::new(0, 6));
-    frame.overtype("there");
+    frame.overtype("there").unwrap();
     assert_eq!(frame.to_string(), "hello there\n");
 }
 
@@ -167,7 +167,7 @@ fn test_overtype() {
 fn test_overtype_extends_line() {
     let mut frame: Frame = Frame::from_str(TEST_FRAME_NAME, "hello\n");
     frame.set_dot(Position::new(0, 3));
-    frame.overtype("ping world");
+    frame.overtype("ping world").unwrap();
     assert_eq!(frame.to_string(), "helping world\n");
 }
 
@@ -181,7 +181,7 @@ fn test_marks_update_on_insert() {
     frame.set_mark(end_mark);
 
     // Insert at position 5
-    frame.insert_at(Position::new(0, 5), " beautiful");
+    frame.insert_at(Position::new(0, 5), " beautiful").unwrap();
 
     // Mark should have moved
     assert_eq!(frame.get_mark(end_mark), Some(Position::new(0, 21)));
@@ -1722,3 +1722,114 @@ fn zz_rejects_minus() {
     let result = f.cmd_rubout(LeadParam::Minus);
     assert!(!result.is_success());
 }
+
+// --- Counter tests ---
+
+fn tp(s: &str) -> TrailParam {
+    TrailParam::from_str(s)
+}
+
+#[test]
+fn counters_start_at_zero() {
+    let f = Frame::new(TEST_FRAME_NAME);
+    assert_eq!(f.counter('A'), 0);
+    assert_eq!(f.counter('Z'), 0);
+}
+
+#[test]
+fn counter_set_number_and_counter() {
+    let mut f = Frame::new(TEST_FRAME_NAME);
+    assert!(f.cmd_counter_set(&tp("a"), &tp("-7")).is_success());
+    assert_eq!(f.counter('A'), -7);
+    assert!(f.cmd_counter_set(&tp("B"), &tp("#a")).is_success());
+    assert_eq!(f.counter('B'), -7);
+}
+
+#[test]
+fn counter_set_rejects_bad_names_and_values() {
+    let mut f = Frame::new(TEST_FRAME_NAME);
+    assert!(!f.cmd_counter_set(&tp("ab"), &tp("1")).is_success());
+    assert!(!f.cmd_counter_set(&tp("1"), &tp("1")).is_success());
+    assert!(!f.cmd_counter_set(&tp("a"), &tp("x")).is_success());
+    assert!(!f.cmd_counter_set(&tp("a"), &tp("#1")).is_success());
+    assert_eq!(f.counter('A'), 0);
+}
+
+#[test]
+fn counter_add_with_leads() {
+    let mut f = Frame::new(TEST_FRAME_NAME);
+    assert!(f.cmd_counter_add(LeadParam::None, &tp("n")).is_success());
+    assert!(f.cmd_counter_add(LeadParam::Pint(5), &tp("n")).is_success());
+    assert_eq!(f.counter('N'), 6);
+    assert!(f.cmd_counter_add(LeadParam::Minus, &tp("n")).is_success());
+    assert!(f.cmd_counter_add(LeadParam::Nint(8), &tp("n")).is_success());
+    assert_eq!(f.counter('N'), -3);
+}
+
+#[test]
+fn counter_add_fails_on_overflow() {
+    let mut f = Frame::new(TEST_FRAME_NAME);
+    f.set_counter('N', i64::MAX);
+    let result = f.cmd_counter_add(LeadParam::None, &tp("n"));
+    assert!(matches!(
+        result,
+        crate::cmd_result::CmdResult::Failure(CmdFailure::OutOfRange)
+    ));
+    assert_eq!(f.counter('N'), i64::MAX);
+}
+
+#[test]
+fn counter_equal_comparisons() {
+    let mut f = Frame::new(TEST_FRAME_NAME);
+    f.set_counter('A', 3);
+    f.set_counter('B', 4);
+    assert!(
+        f.cmd_counter_equal(LeadParam::None, &tp("a"), &tp("3"))
+            .is_success()
+    );
+    assert!(
+        !f.cmd_counter_equal(LeadParam::None, &tp("a"), &tp("#b"))
+            .is_success()
+    );
+    assert!(
+        f.cmd_counter_equal(LeadParam::Minus, &tp("a"), &tp("#b"))
+            .is_success()
+    );
+    assert!(
+        f.cmd_counter_equal(LeadParam::Pindef, &tp("b"), &tp("#a"))
+            .is_success()
+    );
+    assert!(
+        !f.cmd_counter_equal(LeadParam::Pindef, &tp("a"), &tp("#b"))
+            .is_success()
+    );
+    assert!(
+        f.cmd_counter_equal(LeadParam::Nindef, &tp("a"), &tp("3"))
+            .is_success()
+    );
+}
+
+#[test]
+fn counter_insert_at_dot() {
+    let mut f = Frame::from_str(TEST_FRAME_NAME, "line");
+    f.set_counter('A', -12);
+    assert!(f.cmd_counter_insert(LeadParam::None, &tp("a")).is_success());
+    assert_eq!(f.to_string(), "-12line\n");
+    assert_eq!(f.dot(), Position::new(0, 3));
+}
+
+#[test]
+fn resolve_counter_gives_integer_leads() {
+    let mut f = Frame::new(TEST_FRAME_NAME);
+    f.set_counter('A', 4);
+    f.set_counter('B', -2);
+    assert_eq!(
+        f.resolve_counter(LeadParam::Counter('A')),
+        LeadParam::Pint(4)
+    );
+    assert_eq!(
+        f.resolve_counter(LeadParam::Counter('B')),
+        LeadParam::Nint(2)
+    );
+    assert_eq!(f.resolve_counter(LeadParam::Minus), LeadParam::Minus);
+}
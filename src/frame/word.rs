@@ -100,6 +100,14 @@ impl WordCommands for Frame {
         let start_line = del_start.line;
         let end_line = del_end.line;
 
+        // The re-inserted line break must fit once the range has been deleted.
+        if start_line != end_line {
+            let removed = self.to_char_index(&del_end) - self.to_char_index(&del_start);
+            if let Err(failure) = self.check_room((1 + advance_end_col).saturating_sub(removed)) {
+                return CmdResult::Failure(failure);
+            }
+        }
+
         // Step 4: Delete text. If nothing is deleted (same position) treat as failure.
         if !self.delete(del_start, del_end) {
             return CmdResult::Failure(CmdFailure::OutOfRange);
@@ -110,7 +118,9 @@ impl WordCommands for Frame {
         // The indent reproduces the original indentation of the advance-end position.
         if start_line != end_line {
             let indent = " ".repeat(advance_end_col);
-            self.insert(&format!("\n{indent}"));
+            if let Err(failure) = self.insert(&format!("\n{indent}")) {
+                return CmdResult::Failure(failure);
+            }
         }
 
         // Step 6: Update marks.
@@ -257,8 +267,11 @@ impl WordCommands for Frame {
             let right = self.right_margin;
 
             // Adjust to left margin if not the first line of a paragraph.
-            if line > 0 && !self.is_blank_line(line - 1) {
-                self.fill_adjust_margin(line);
+            if line > 0
+                && !self.is_blank_line(line - 1)
+                && let Err(failure) = self.fill_adjust_margin(line)
+            {
+                return CmdResult::Failure(failure);
             }
 
             let line_len = self.line_length_excluding_newline(line);
@@ -266,7 +279,11 @@ impl WordCommands for Frame {
 
             if line_len > right {
                 // Line is too long — split at the last space within the margin.
-                if !self.fill_split_at_margin(line) {
+                let split = match self.fill_split_at_margin(line) {
+                    Ok(split) => split,
+                    Err(failure) => return CmdResult::Failure(failure),
+                };
+                if !split {
                     if is_pindef {
                         break;
                     }
@@ -297,8 +314,10 @@ impl WordCommands for Frame {
                         break;
                     }
 
-                    if !self.fill_pull_one_chunk(line) {
-                        break;
+                    match self.fill_pull_one_chunk(line) {
+                        Ok(true) => {}
+                        Ok(false) => break,
+                        Err(failure) => return CmdResult::Failure(failure),
                     }
 
                     // Check if the next line is now empty.
@@ -317,7 +336,9 @@ impl WordCommands for Frame {
                         // Continue pulling from what is now the new next line.
                     } else {
                         // Normalize the next line's start to left_margin.
-                        self.fill_normalize_line_start(line + 1);
+                        if let Err(failure) = self.fill_normalize_line_start(line + 1) {
+                            return CmdResult::Failure(failure);
+                        }
                         break;
                     }
                 }
@@ -388,6 +409,9 @@ impl WordCommands for Frame {
                 }
 
                 let space_to_add = right - line_len;
+                if let Err(failure) = self.check_room(space_to_add) {
+                    return CmdResult::Failure(failure);
+                }
                 if space_to_add > 0 {
                     let lsc = self.rope.line_to_char(line);
 
@@ -431,7 +455,11 @@ impl WordCommands for Frame {
                             debit += fill_ratio;
                             let n = (debit + 0.5) as i32;
                             if n > 0 {
-                                self.insert_at(Position::new(line, pos), &" ".repeat(n as usize));
+                                if let Err(failure) = self
+                                    .insert_at(Position::new(line, pos), &" ".repeat(n as usize))
+                                {
+                                    return CmdResult::Failure(failure);
+                                }
                                 debit -= n as f64;
                             }
 
@@ -527,10 +555,12 @@ impl WordCommands for Frame {
                     - (first_ns as isize - left as isize);
 
             if space_to_add > 0 {
-                self.insert_at(
+                if let Err(failure) = self.insert_at(
                     Position::new(line, left),
                     &" ".repeat(space_to_add as usize),
-                );
+                ) {
+                    return CmdResult::Failure(failure);
+                }
             } else if space_to_add < 0 {
                 let to_remove = (-space_to_add) as usize;
                 self.delete(
@@ -669,7 +699,11 @@ impl WordCommands for Frame {
 
             if line_len < right {
                 let space_to_add = right - line_len;
-                self.insert_at(Position::new(line, 0), &" ".repeat(space_to_add));
+                if let Err(failure) =
+                    self.insert_at(Position::new(line, 0), &" ".repeat(space_to_add))
+                {
+                    return CmdResult::Failure(failure);
+                }
             } else if line_len > right {
                 // Line too long to right-align.
                 if is_pindef {
@@ -1065,7 +1099,7 @@ impl Frame {
 
     // ── YF helper: adjust a non-first-paragraph line's leading spaces to left_margin ──
 
-    fn fill_adjust_margin(&mut self, line: usize) {
+    fn fill_adjust_margin(&mut self, line: usize) -> Result<(), CmdFailure> {
         let lsc = self.rope.line_to_char(line);
         let line_len = self.line_length_excluding_newline(line);
         let left = self.left_margin;
@@ -1076,7 +1110,7 @@ impl Frame {
 
         if first_ns < left && first_ns < line_len {
             // Insert spaces to push text to left_margin.
-            self.insert_at(Position::new(line, first_ns), &" ".repeat(left - first_ns));
+            self.insert_at(Position::new(line, first_ns), &" ".repeat(left - first_ns))?;
         } else {
             // Remove any excess spaces that lie between left_margin and the text.
             let start = left;
@@ -1092,12 +1126,13 @@ impl Frame {
                 }
             }
         }
+        Ok(())
     }
 
     // ── YF helper: split a too-long line at the last word boundary within the margin ──
 
     /// Returns true on success, false if no valid split point exists.
-    fn fill_split_at_margin(&mut self, line: usize) -> bool {
+    fn fill_split_at_margin(&mut self, line: usize) -> Result<bool, CmdFailure> {
         let lsc = self.rope.line_to_char(line);
         let line_len = self.line_length_excluding_newline(line);
         let left = self.left_margin;
@@ -1112,7 +1147,7 @@ impl Frame {
                 break;
             }
             if end_col == left {
-                return false; // No split point found.
+                return Ok(false); // No split point found.
             }
             end_col -= 1;
         }
@@ -1124,7 +1159,7 @@ impl Frame {
             end_col -= 1;
         }
         if end_col == left {
-            return false; // Nothing to keep.
+            return Ok(false); // Nothing to keep.
         }
 
         // Scan forward from overflow_start to find first char of overflow.
@@ -1132,7 +1167,7 @@ impl Frame {
             overflow_start += 1;
         }
         if overflow_start >= line_len {
-            return false; // No overflow text (line has only trailing spaces).
+            return Ok(false); // No overflow text (line has only trailing spaces).
         }
 
         // Keep dot on the current line if it would end up in the overflow.
@@ -1150,6 +1185,9 @@ impl Frame {
             .take_while(|&c| c != '\n')
             .collect();
 
+        // The new line break and indentation must fit once the overflow is moved.
+        self.check_room(1 + self.left_margin)?;
+
         // Delete overflow from current line.
         self.delete(
             Position::new(line, overflow_start),
@@ -1158,29 +1196,29 @@ impl Frame {
 
         // Insert a new line with left_margin indentation and overflow text.
         let insert_text = format!("\n{}{}", " ".repeat(self.left_margin), overflow);
-        self.insert_at(Position::new(line, overflow_start), &insert_text);
+        self.insert_at(Position::new(line, overflow_start), &insert_text)?;
 
-        true
+        Ok(true)
     }
 
     // ── YF helper: pull one "chunk" of fitting words from the next line ──
 
     /// Returns true if any words were moved.
-    fn fill_pull_one_chunk(&mut self, line: usize) -> bool {
+    fn fill_pull_one_chunk(&mut self, line: usize) -> Result<bool, CmdFailure> {
         let line_len = self.line_length_excluding_newline(line);
         let right = self.right_margin;
 
         if line_len >= right {
-            return false;
+            return Ok(false);
         }
         let space_available = right - line_len - 1;
         if space_available == 0 {
-            return false;
+            return Ok(false);
         }
 
         let next_line = line + 1;
         if next_line >= self.line_count() {
-            return false;
+            return Ok(false);
         }
 
         let next_len = self.line_length_excluding_newline(next_line);
@@ -1192,7 +1230,7 @@ impl Frame {
             start += 1;
         }
         if start >= next_len {
-            return false; // Next line is all spaces.
+            return Ok(false); // Next line is all spaces.
         }
 
         // Scan words on next line to find how many fit.
@@ -1224,7 +1262,7 @@ impl Frame {
         }
 
         if old_end == start {
-            return false; // Nothing fits.
+            return Ok(false); // Nothing fits.
         }
 
         let actual_end = old_end.min(next_len);
@@ -1244,7 +1282,7 @@ impl Frame {
         self.insert_at(
             Position::new(line, cur_len),
             &format!("{}{}", sep, text_to_move),
-        );
+        )?;
 
         // Remove [0, actual_end) from next line (leading spaces + moved words).
         self.delete(
@@ -1252,12 +1290,12 @@ impl Frame {
             Position::new(next_line, actual_end),
         );
 
-        true
+        Ok(true)
     }
 
     // ── YF helper: normalize a line's leading spaces to left_margin ──
 
-    fn fill_normalize_line_start(&mut self, line: usize) {
+    fn fill_normalize_line_start(&mut self, line: usize) -> Result<(), CmdFailure> {
         let lsc = self.rope.line_to_char(line);
         let line_len = self.line_length_excluding_newline(line);
         let left = self.left_margin;
@@ -1267,10 +1305,11 @@ impl Frame {
             .unwrap_or(line_len);
 
         if first_ns < left {
-            self.insert_at(Position::new(line, first_ns), &" ".repeat(left - first_ns));
+            self.insert_at(Position::new(line, first_ns), &" ".repeat(left - first_ns))?;
         } else if first_ns > left {
             self.delete(Position::new(line, left), Position::new(line, first_ns));
         }
+        Ok(())
     }
 
     /// Ditto: copy character(s) from line above (direction=-1) or below (direction=1).
@@ -1303,7 +1342,9 @@ impl Frame {
         }
 
         // Insert at dot
-        self.insert(&chars_to_insert);
+        if let Err(failure) = self.insert(&chars_to_insert) {
+            return CmdResult::Failure(failure);
+        }
         self.set_mark(MarkId::Modified);
         self.set_mark_at(MarkId::Equals, original_dot);
         CmdResult::Success
@@ -7,7 +7,8 @@ use crate::span::{Span, SpanRegistry};
 const COMMAND_FRAME_NAME: &str = "COMMAND";
 const HEAP_FRAME_NAME: &str = "HEAP";
 const OOPS_FRAME_NAME: &str = "OOPS";
-const SPECIAL_FRAME_NAMES: &[&str] = &[COMMAND_FRAME_NAME, HEAP_FRAME_NAME, OOPS_FRAME_NAME];
+pub(crate) const SPECIAL_FRAME_NAMES: &[&str] =
+    &[COMMAND_FRAME_NAME, HEAP_FRAME_NAME, OOPS_FRAME_NAME];
 
 /// A collection of named [`Frame`]s plus the global [`SpanRegistry`].
 pub struct FrameSet {
@@ -78,6 +79,11 @@ impl FrameSet {
         self.frames.get_mut(&normalise(name))
     }
 
+    /// Every frame, in no particular order.
+    pub fn frames_mut(&mut self) -> impl Iterator<Item = &mut Frame> {
+        self.frames.frames_mut()
+    }
+
     /// Test whether a frame exists.
     pub fn contains_frame(&self, name: &str) -> bool {
         self.frames.contains(&normalise(name))
@@ -93,6 +99,20 @@ impl FrameSet {
         self.spans.get_mut(&normalise(name))
     }
 
+    /// The text of a span, or `None` if there is no such span or its marks
+    /// are gone.
+    pub fn span_text(&self, name: &str) -> Option<String> {
+        let span = self.get_span(name)?;
+        let frame = self.get_frame(&span.frame_name)?;
+        let start = frame.to_char_index(&frame.get_mark(span.mark_start)?);
+        let end = frame.to_char_index(&frame.get_mark(span.mark_end)?);
+        if start >= end {
+            Some(String::new())
+        } else {
+            Some(frame.slice(start..end))
+        }
+    }
+
     /// Insert or replace a span by name. Name is normalised to UPPERCASE.
     pub fn insert_span(&mut self, name: &str, span: Span) {
         self.spans.insert(normalise(name), span);
@@ -4,106 +4,202 @@
 //! them against an [`ExecutionContext`]. It handles control flow including compound commands
 //! with repetition, exit handlers, and exit level unwinding (XS/XF/XA).
 
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use crate::bytecode;
 use crate::code::*;
+use crate::debugger::Repeat;
 use crate::exec_context::{ExecutionContext, MAX_RECURSION_DEPTH, parse_span_name};
 use crate::frame::{
-    CaseMode, EditCommands, MotionCommands, PredicateCommands, SearchCommands, WordCommands,
+    CaseMode, CounterCommands, EditCommands, FileCommands, MotionCommands, ParameterCommands,
+    PredicateCommands, SearchCommands, WordCommands,
 };
-use crate::{CmdFailure, CmdResult, LeadParam, TrailParam, compile};
+use crate::{CmdFailure, CmdResult, FailedCommand, LeadParam, TrailParam, compile};
 
 /// Execute compiled code against an execution context. Top-level entry point.
 ///
 /// Executes each instruction sequentially until completion or until
 /// a failure/exit occurs.
 pub fn execute(ctx: &mut ExecutionContext, code: &CompiledCode) -> ExecOutcome {
-    for instr in code.instructions() {
-        let outcome = execute_instruction(ctx, instr);
-        match outcome {
-            ExecOutcome::Success => continue,
-            _ => return outcome,
-        }
-    }
-    ExecOutcome::Success
+    execute_instructions(ctx, code.instructions())
 }
 
-/// Execute a single instruction.
-fn execute_instruction(ctx: &mut ExecutionContext, instr: &Instruction) -> ExecOutcome {
-    match instr {
-        Instruction::SimpleCmd {
-            op,
-            lead,
-            tpars,
-            exit_handler,
-        } => {
-            let outcome = match op {
-                CmdOp::SpanExecute => execute_span(ctx, *lead, tpars, true),
-                CmdOp::SpanExecuteNoRecompile => execute_span(ctx, *lead, tpars, false),
-                _ => {
-                    let result = dispatch_cmd(ctx, *op, *lead, tpars);
-                    if result.is_success() {
-                        ExecOutcome::Success
-                    } else {
-                        ExecOutcome::Failure
+/// Execute `instructions` as [`execute`] does, for callers that hold only
+/// part of some compiled code.
+pub(crate) fn execute_instructions(
+    ctx: &mut ExecutionContext,
+    instructions: &[Instruction],
+) -> ExecOutcome {
+    bytecode::run(ctx, &bytecode::lower(instructions, ctx.debugger.is_some()))
+}
+
+/// Execute a simple command, returning its outcome and, if it failed of its
+/// own accord, why. Its exit handler is left to the caller.
+pub(crate) fn execute_command(
+    ctx: &mut ExecutionContext,
+    instr: &Instruction,
+) -> (ExecOutcome, Option<CmdFailure>) {
+    let Instruction::SimpleCmd {
+        op,
+        lead,
+        tpars,
+        span,
+        ..
+    } = instr
+    else {
+        unreachable!("only simple commands are dispatched");
+    };
+    ctx.message = None;
+    ctx.failure = None;
+    // `#X` stands for the value counter X has now.
+    let lead = ctx.current_frame().resolve_counter(*lead);
+    let (outcome, reason) = match dereference_tpars(ctx, *op, tpars) {
+        Err(failure) => (ExecOutcome::Failure, Some(failure)),
+        Ok(tpars) => match op {
+            CmdOp::SpanExecute => (execute_span(ctx, lead, &tpars, true), None),
+            CmdOp::SpanExecuteNoRecompile => (execute_span(ctx, lead, &tpars, false), None),
+            _ => match dispatch_cmd(ctx, *op, lead, &tpars) {
+                CmdResult::Success => (ExecOutcome::Success, None),
+                CmdResult::Failure(failure) => (ExecOutcome::Failure, Some(failure)),
+            },
+        },
+    };
+    // A command that could not read as much of a paged file as it needed
+    // may have gone wrong without failing, so execution stops here.
+    let (outcome, reason) = match ctx.current_frame_mut().take_paging_failure() {
+        Some(failure) => {
+            ctx.message = Some(
+                match failure {
+                    CmdFailure::NoRoom => {
+                        "No room to read more of the file; FP pages out the text above dot."
                     }
+                    _ => "Could not read more of the file.",
                 }
-            };
-            apply_exit_handler(ctx, outcome, exit_handler.as_ref())
+                .to_string(),
+            );
+            ctx.failure = Some(FailedCommand {
+                source: ctx.source.clone(),
+                span: *span,
+                reason: Some(failure.clone()),
+            });
+            (ExecOutcome::Abort, Some(failure))
         }
-        Instruction::CompoundCmd {
-            repeat,
-            body,
-            exit_handler,
-        } => {
-            let outcome = execute_compound(ctx, *repeat, body);
-            apply_exit_handler(ctx, outcome, exit_handler.as_ref())
+        None => (outcome, reason),
+    };
+    // Blame the innermost command: an EX whose span failed keeps the
+    // failure recorded by the span's own command.
+    if outcome == ExecOutcome::Failure && ctx.failure.is_none() {
+        ctx.failure = Some(FailedCommand {
+            source: ctx.source.clone(),
+            span: *span,
+            reason: reason.clone(),
+        });
+    }
+    (outcome, reason)
+}
+
+/// Resolve `$span$` and `&prompt&` dereferences in a command's trailing
+/// parameters before it is dispatched.
+fn dereference_tpars<'t>(
+    ctx: &mut ExecutionContext,
+    op: CmdOp,
+    tpars: &'t [TrailParam],
+) -> Result<Cow<'t, [TrailParam]>, CmdFailure> {
+    let needs_work = |tpar: &TrailParam| matches!(tpar.delim, '$' | '&');
+    if !tpars.iter().any(needs_work) {
+        return Ok(Cow::Borrowed(tpars));
+    }
+    let mut resolved = Vec::with_capacity(tpars.len());
+    for (index, tpar) in tpars.iter().enumerate() {
+        // SA$name$refspan$ names the span being assigned literally.
+        if op == CmdOp::SpanAssign && index == 0 && tpar.delim == '$' {
+            resolved.push(tpar.clone());
+            continue;
         }
-        Instruction::ExitSuccess(levels) => match levels {
-            ExitLevels::Count(n) => ExecOutcome::ExitSuccess { remaining: *n },
-            ExitLevels::All => ExecOutcome::ExitSuccessAll,
-        },
-        Instruction::ExitFailure(levels) => match levels {
-            ExitLevels::Count(n) => ExecOutcome::ExitFailure { remaining: *n },
-            ExitLevels::All => ExecOutcome::ExitFailureAll,
-        },
-        Instruction::ExitAbort => ExecOutcome::Abort,
+        let (prompt, multi_line) = tpar_prompt(op, index);
+        resolved.push(ctx.dereference_tpar(tpar, prompt, multi_line)?);
     }
+    Ok(Cow::Owned(resolved))
 }
 
-/// Execute a compound command body based on RepeatCount.
-fn execute_compound(
+/// The default `&&` prompt for trailing parameter `index` of `op`, and
+/// whether a dereference may supply more than one line for it.
+fn tpar_prompt(op: CmdOp, index: usize) -> (&'static str, bool) {
+    match (op, index) {
+        (CmdOp::Get, _) => ("Get    :", false),
+        (CmdOp::Replace, 0) => ("Replace:", false),
+        (CmdOp::Replace, _) => ("With   :", true),
+        (CmdOp::InsertText, _) => ("Insert :", true),
+        (CmdOp::OvertypeText, _) => ("Overtype:", false),
+        (CmdOp::EqualString, _) => ("Equal  :", false),
+        (CmdOp::Next, _) => ("Next   :", false),
+        (CmdOp::Bridge, _) => ("Bridge :", false),
+        (CmdOp::SpanAssign, 1) => ("Text   :", true),
+        (CmdOp::ExecuteString, _) => ("Execute:", true),
+        (
+            CmdOp::SpanDefine
+            | CmdOp::SpanCopy
+            | CmdOp::SpanTransfer
+            | CmdOp::SpanJump
+            | CmdOp::SpanAssign
+            | CmdOp::SpanCompile
+            | CmdOp::SpanPrint
+            | CmdOp::SpanExecute
+            | CmdOp::SpanExecuteNoRecompile,
+            _,
+        ) => ("Span   :", false),
+        (CmdOp::CounterSet | CmdOp::CounterAdd | CmdOp::CounterEqual | CmdOp::CounterInsert, 0) => {
+            ("Counter:", false)
+        }
+        (CmdOp::CounterSet | CmdOp::CounterEqual, _) => ("Value  :", false),
+        _ => ("Parameter:", false),
+    }
+}
+
+/// Execute `body` `count` times, or until it fails if `count` is `None`,
+/// stopping early on any outcome but success, or with
+/// [`ExecOutcome::Abort`] if a limit is reached.
+fn execute_repeated(
     ctx: &mut ExecutionContext,
-    repeat: RepeatCount,
+    count: Option<usize>,
     body: &CompiledCode,
 ) -> ExecOutcome {
-    match repeat {
-        RepeatCount::Once => {
-            let outcome = execute(ctx, body);
-            unwrap_exit_level(outcome)
+    let program = bytecode::lower(body.instructions(), ctx.debugger.is_some());
+    ctx.depth += 1;
+    ctx.repeats.push(Repeat {
+        iteration: 0,
+        count,
+    });
+    let mut outcome = ExecOutcome::Success;
+    for iteration in 1.. {
+        if count.is_some_and(|n| iteration > n) {
+            break;
         }
-        RepeatCount::Times(n) => {
-            for _ in 0..n {
-                let outcome = execute(ctx, body);
-                let outcome = unwrap_exit_level(outcome);
-                match outcome {
-                    ExecOutcome::Success => continue,
-                    _ => return outcome,
-                }
-            }
-            ExecOutcome::Success
+        // An empty body, as in `>EX/E/` for an empty span, would otherwise
+        // never stop.
+        if body.instructions().is_empty()
+            && let Some(message) = ctx.check_limits()
+        {
+            ctx.message = Some(message);
+            outcome = ExecOutcome::Abort;
+            break;
+        }
+        if let Some(repeat) = ctx.repeats.last_mut() {
+            repeat.iteration = iteration;
+        }
+        outcome = unwrap_exit_level(bytecode::run(ctx, &program));
+        if outcome != ExecOutcome::Success {
+            break;
         }
-        RepeatCount::Indefinite => loop {
-            let outcome = execute(ctx, body);
-            let outcome = unwrap_exit_level(outcome);
-            match outcome {
-                ExecOutcome::Success => continue,
-                other => return other,
-            }
-        },
     }
+    ctx.repeats.pop();
+    ctx.depth -= 1;
+    outcome
 }
 
 /// Decrement exit level counters at a compound command boundary.
-fn unwrap_exit_level(outcome: ExecOutcome) -> ExecOutcome {
+pub(crate) fn unwrap_exit_level(outcome: ExecOutcome) -> ExecOutcome {
     match outcome {
         ExecOutcome::ExitSuccess { remaining } => {
             if remaining <= 1 {
@@ -127,37 +223,6 @@ fn unwrap_exit_level(outcome: ExecOutcome) -> ExecOutcome {
     }
 }
 
-/// Apply an exit handler to an outcome, running success/failure code as appropriate.
-fn apply_exit_handler(
-    ctx: &mut ExecutionContext,
-    outcome: ExecOutcome,
-    handler: Option<&ExitHandler>,
-) -> ExecOutcome {
-    let handler = match handler {
-        Some(h) => h,
-        None => return outcome,
-    };
-
-    match &outcome {
-        ExecOutcome::Success => {
-            if let Some(code) = &handler.on_success {
-                execute(ctx, code)
-            } else {
-                ExecOutcome::Success
-            }
-        }
-        ExecOutcome::Failure => {
-            if let Some(code) = &handler.on_failure {
-                execute(ctx, code)
-            } else {
-                ExecOutcome::Success
-            }
-        }
-        // XS/XF/XA/Abort propagate through handlers without triggering them
-        _ => outcome,
-    }
-}
-
 /// Execute a span procedure (EX / EN).
 ///
 /// `recompile`:
@@ -203,14 +268,17 @@ fn execute_span(
             None => return ExecOutcome::Failure,
         };
         let code = match compile(&text) {
-            Ok(c) => c,
-            Err(_) => return ExecOutcome::Failure,
+            Ok(c) => Arc::new(c),
+            Err(err) => {
+                ctx.message = Some(format!("{span_name}: {err}"));
+                return ExecOutcome::Failure;
+            }
         };
         // Cache it.
         if let Some(span) = ctx.frame_set.get_span_mut(&span_name) {
-            span.set_code(code.clone());
+            span.set_code(Arc::clone(&code));
         } else if let Some(frame) = ctx.frame_set.get_frame_mut(&span_name) {
-            frame.set_code(code.clone());
+            frame.set_code(Arc::clone(&code));
         }
         code
     } else {
@@ -232,13 +300,16 @@ fn execute_span(
                 None => return ExecOutcome::Failure,
             };
             let code = match compile(&text) {
-                Ok(c) => c,
-                Err(_) => return ExecOutcome::Failure,
+                Ok(c) => Arc::new(c),
+                Err(err) => {
+                    ctx.message = Some(format!("{span_name}: {err}"));
+                    return ExecOutcome::Failure;
+                }
             };
             if let Some(span) = ctx.frame_set.get_span_mut(&span_name) {
-                span.set_code(code.clone());
+                span.set_code(Arc::clone(&code));
             } else if let Some(frame) = ctx.frame_set.get_frame_mut(&span_name) {
-                frame.set_code(code.clone());
+                frame.set_code(Arc::clone(&code));
             }
             code
         }
@@ -246,28 +317,9 @@ fn execute_span(
 
     // Execute the compiled code, respecting the repeat count.
     ctx.recursion_depth += 1;
-    let outcome = match count {
-        Some(n) => {
-            let mut outcome = ExecOutcome::Success;
-            for _ in 0..n {
-                outcome = execute(ctx, &compiled);
-                outcome = unwrap_exit_level(outcome);
-                match outcome {
-                    ExecOutcome::Success => continue,
-                    _ => break,
-                }
-            }
-            outcome
-        }
-        None => loop {
-            let outcome = execute(ctx, &compiled);
-            let outcome = unwrap_exit_level(outcome);
-            match outcome {
-                ExecOutcome::Success => continue,
-                other => break other,
-            }
-        },
-    };
+    let caller = ctx.source.replace(span_name);
+    let outcome = execute_repeated(ctx, count, &compiled);
+    ctx.source = caller;
     ctx.recursion_depth -= 1;
     outcome
 }
@@ -280,6 +332,9 @@ fn dispatch_cmd(
     tpars: &[TrailParam],
 ) -> CmdResult {
     match op {
+        CmdOp::Get | CmdOp::Replace | CmdOp::EqualString if tpars[0].delim == '`' => {
+            dispatch_pattern_cmd(ctx, op, lead, tpars)
+        }
         CmdOp::Advance => ctx.current_frame_mut().cmd_advance(lead),
         CmdOp::Jump => ctx.current_frame_mut().cmd_jump(lead),
         CmdOp::DeleteChar => ctx.current_frame_mut().cmd_delete_char(lead),
@@ -328,6 +383,17 @@ fn dispatch_cmd(
         CmdOp::LineRight => ctx.current_frame_mut().cmd_line_right(lead),
         CmdOp::DittoUp => ctx.current_frame_mut().cmd_ditto_up(lead),
         CmdOp::DittoDown => ctx.current_frame_mut().cmd_ditto_down(lead),
+        CmdOp::FrameParameters => ctx.current_frame_mut().cmd_frame_parameters(&tpars[0]),
+        CmdOp::FileRead => ctx.current_frame_mut().cmd_file_read(lead),
+        CmdOp::Page => ctx.current_frame_mut().cmd_page(lead),
+        CmdOp::CounterSet => ctx
+            .current_frame_mut()
+            .cmd_counter_set(&tpars[0], &tpars[1]),
+        CmdOp::CounterAdd => ctx.current_frame_mut().cmd_counter_add(lead, &tpars[0]),
+        CmdOp::CounterEqual => ctx
+            .current_frame_mut()
+            .cmd_counter_equal(lead, &tpars[0], &tpars[1]),
+        CmdOp::CounterInsert => ctx.current_frame_mut().cmd_counter_insert(lead, &tpars[0]),
         // Window commands are no-ops in batch mode; handled by App in interactive mode.
         CmdOp::WindowForward
         | CmdOp::WindowBackward
@@ -345,11 +411,33 @@ fn dispatch_cmd(
         CmdOp::SpanAssign => ctx.cmd_span_assign(lead, tpars),
         CmdOp::SpanIndex => ctx.cmd_span_index(),
         CmdOp::SpanCompile => ctx.cmd_span_compile(lead, tpars),
+        CmdOp::SpanPrint => ctx.cmd_span_print(&tpars[0]),
         // FIXME: remove this when everything is implemented
         _ => CmdResult::Failure(CmdFailure::NotImplemented),
     }
 }
 
+/// Dispatch G, R or EQS with a backtick-delimited pattern, which may
+/// dereference spans and prompts and so is parsed here rather than by the frame.
+fn dispatch_pattern_cmd(
+    ctx: &mut ExecutionContext,
+    op: CmdOp,
+    lead: LeadParam,
+    tpars: &[TrailParam],
+) -> CmdResult {
+    let pattern = match ctx.parse_pattern(&tpars[0]) {
+        Ok(pattern) => pattern,
+        Err(failure) => return CmdResult::Failure(failure),
+    };
+    let frame = ctx.current_frame_mut();
+    match op {
+        CmdOp::Get => frame.cmd_get_pattern(lead, &pattern),
+        CmdOp::Replace => frame.cmd_replace_pattern(lead, &pattern, &tpars[1]),
+        CmdOp::EqualString => frame.cmd_eqs_pattern(lead, &pattern),
+        _ => CmdResult::Failure(CmdFailure::NotImplemented),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,278 @@
+//! Crash-recovery journal for frames.
+//!
+//! A frame with a journal attached records every change to its text in a
+//! hidden swap file next to the file being edited. The journal starts with a
+//! header identifying the file contents it applies to, followed by one record
+//! per change:
+//!
+//! ```text
+//! ludwig-journal 1 <base length> <base hash>
+//! I <char index> <byte length>
+//! <inserted text>
+//! R <from char index> <to char index>
+//! ```
+//!
+//! Records are written as they happen, so a journal left behind by a crash
+//! can be replayed over the unchanged file to recover the lost edits. A
+//! partially written final record is ignored.
+
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &str = "ludwig-journal 1";
+
+/// How many `~N` names [`Journal::set_aside`] tries before giving up.
+const SET_ASIDE_ATTEMPTS: usize = 100;
+
+/// A single recorded change, in rope character indices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalOp {
+    Insert { at: usize, text: String },
+    Remove { from: usize, to: usize },
+}
+
+/// An open journal file that changes are appended to.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    /// Set once a write fails; later records are dropped so that the journal
+    /// still holds a consistent prefix of the changes.
+    failed: bool,
+    /// Whether any change has been recorded since the journal was started
+    /// or last rebased.
+    changed: bool,
+}
+
+impl Journal {
+    /// The hidden journal path used for `file`: `.name.lswp` in the same directory.
+    pub fn path_for(file: &Path) -> PathBuf {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        file.with_file_name(format!(".{}.lswp", name))
+    }
+
+    /// Start a new journal for `file`, whose current contents are `base`.
+    ///
+    /// Any existing journal is overwritten.
+    pub fn create(file: &Path, base: &[u8]) -> io::Result<Journal> {
+        let path = Self::path_for(file);
+        let mut journal = Journal {
+            file: File::create(&path)?,
+            path,
+            failed: false,
+            changed: false,
+        };
+        journal.file.write_all(header(base).as_bytes())?;
+        Ok(journal)
+    }
+
+    /// Move the journal left behind for `file` out of the way, to the first
+    /// free name of the form `.name.lswp~N`, and return where it went.
+    ///
+    /// A new journal can then be started without losing the old one's
+    /// changes.
+    pub fn set_aside(file: &Path) -> io::Result<PathBuf> {
+        let path = Self::path_for(file);
+        for n in 1..=SET_ASIDE_ATTEMPTS {
+            let mut name = path.clone().into_os_string();
+            name.push(format!("~{}", n));
+            let aside = PathBuf::from(name);
+            if !aside.exists() {
+                fs::rename(&path, &aside)?;
+                return Ok(aside);
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "too many old journals",
+        ))
+    }
+
+    /// Read the journal left behind for `file`, if there is one.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if the journal was not made
+    /// against `base`, the file's current contents.
+    pub fn recover(file: &Path, base: &[u8]) -> io::Result<Option<Vec<JournalOp>>> {
+        let bytes = match fs::read(Self::path_for(file)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let header = header(base);
+        let Some(mut rest) = bytes.strip_prefix(header.as_bytes()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "journal does not match the file",
+            ));
+        };
+        let mut ops = Vec::new();
+        while let Some((op, tail)) = parse_record(rest) {
+            ops.push(op);
+            rest = tail;
+        }
+        Ok(Some(ops))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// True if the journal holds changes that have not been saved.
+    pub fn has_changes(&self) -> bool {
+        self.changed
+    }
+
+    /// Record that `text` was inserted at character index `at`.
+    pub fn record_insert(&mut self, at: usize, text: &str) {
+        let mut record = format!("I {} {}\n", at, text.len()).into_bytes();
+        record.extend_from_slice(text.as_bytes());
+        record.push(b'\n');
+        self.write(&record);
+    }
+
+    /// Record that the characters `from..to` were removed.
+    pub fn record_remove(&mut self, from: usize, to: usize) {
+        self.write(format!("R {} {}\n", from, to).as_bytes());
+    }
+
+    /// Start again from `base`, e.g. after the file has been saved.
+    pub fn rebase(&mut self, base: &[u8]) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(header(base).as_bytes())?;
+        self.failed = false;
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Remove the journal file once its changes are no longer needed.
+    pub fn discard(self) -> io::Result<()> {
+        fs::remove_file(&self.path)
+    }
+
+    fn write(&mut self, record: &[u8]) {
+        self.changed = true;
+        if !self.failed && self.file.write_all(record).is_err() {
+            self.failed = true;
+        }
+    }
+}
+
+fn header(base: &[u8]) -> String {
+    format!("{} {} {:016x}\n", MAGIC, base.len(), fnv1a(base))
+}
+
+/// 64-bit FNV-1a hash, used to check a journal belongs to a file.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Parse one complete record from the start of `bytes`.
+fn parse_record(bytes: &[u8]) -> Option<(JournalOp, &[u8])> {
+    let eol = bytes.iter().position(|&b| b == b'\n')?;
+    let line = std::str::from_utf8(&bytes[..eol]).ok()?;
+    let rest = &bytes[eol + 1..];
+    let mut fields = line.split(' ');
+    let kind = fields.next()?;
+    let a = fields.next()?.parse().ok()?;
+    let b = fields.next()?.parse().ok()?;
+    match kind {
+        "I" => {
+            let text = rest.get(..b)?;
+            if rest.get(b) != Some(&b'\n') {
+                return None;
+            }
+            let text = std::str::from_utf8(text).ok()?.to_string();
+            Some((JournalOp::Insert { at: a, text }, &rest[b + 1..]))
+        }
+        "R" => Some((JournalOp::Remove { from: a, to: b }, rest)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::ScratchDir;
+    use std::fs::OpenOptions;
+
+    #[test]
+    fn test_records_round_trip() {
+        let dir = ScratchDir::new("journal");
+        let path = dir.file("round-trip", b"abc\n");
+        let mut journal = Journal::create(&path, b"abc\n").unwrap();
+        journal.record_insert(1, "x\ny");
+        journal.record_remove(0, 2);
+        assert_eq!(
+            Journal::recover(&path, b"abc\n").unwrap().unwrap(),
+            vec![
+                JournalOp::Insert {
+                    at: 1,
+                    text: "x\ny".into()
+                },
+                JournalOp::Remove { from: 0, to: 2 },
+            ]
+        );
+        journal.discard().unwrap();
+        assert_eq!(Journal::recover(&path, b"abc\n").unwrap(), None);
+    }
+
+    #[test]
+    fn test_truncated_record_is_ignored() {
+        let dir = ScratchDir::new("journal");
+        let path = dir.file("truncated", b"");
+        let mut journal = Journal::create(&path, b"").unwrap();
+        journal.record_insert(0, "one");
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(journal.path())
+            .unwrap();
+        file.write_all(b"I 3 10\npar").unwrap();
+        assert_eq!(
+            Journal::recover(&path, b"").unwrap().unwrap(),
+            vec![JournalOp::Insert {
+                at: 0,
+                text: "one".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_journal_for_changed_file_is_rejected() {
+        let dir = ScratchDir::new("journal");
+        let path = dir.file("changed", b"old\n");
+        Journal::create(&path, b"old\n").unwrap();
+        let err = Journal::recover(&path, b"new\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_rebase_clears_records() {
+        let dir = ScratchDir::new("journal");
+        let path = dir.file("rebase", b"a\n");
+        let mut journal = Journal::create(&path, b"a\n").unwrap();
+        assert!(!journal.has_changes());
+        journal.record_insert(0, "b");
+        assert!(journal.has_changes());
+        journal.rebase(b"ba\n").unwrap();
+        assert!(!journal.has_changes());
+        assert_eq!(Journal::recover(&path, b"ba\n").unwrap().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_set_aside_keeps_old_journals() {
+        let dir = ScratchDir::new("journal");
+        let path = dir.file("aside", b"a\n");
+        let mut journal = Journal::create(&path, b"a\n").unwrap();
+        journal.record_insert(0, "b");
+        let first = Journal::set_aside(&path).unwrap();
+        assert!(!Journal::path_for(&path).exists());
+        Journal::create(&path, b"a\n").unwrap();
+        let second = Journal::set_aside(&path).unwrap();
+        assert_ne!(first, second);
+        assert!(fs::read(&first).unwrap().ends_with(b"I 0 1\nb\n"));
+    }
+}
@@ -17,6 +17,8 @@ pub enum KeyAction {
     Quit,
     /// Save the file.
     Save,
+    /// Enter a command to run in the step debugger.
+    Debug,
     /// Toggle insert/overtype mode.
     ToggleMode,
     /// Terminal was resized.
@@ -37,6 +39,7 @@ pub fn resolve_key(key: KeyEvent) -> KeyAction {
         return match key.code {
             KeyCode::Char('q') => KeyAction::Quit,
             KeyCode::Char('s') => KeyAction::Save,
+            KeyCode::Char('d') => KeyAction::Debug,
             _ => KeyAction::Ignore,
         };
     }
@@ -117,6 +120,11 @@ mod tests {
         assert_eq!(resolve_key(ctrl_key('q')), KeyAction::Quit);
     }
 
+    #[test]
+    fn test_ctrl_d_debug() {
+        assert_eq!(resolve_key(ctrl_key('d')), KeyAction::Debug);
+    }
+
     #[test]
     fn test_escape_command_introducer() {
         assert_eq!(resolve_key(key(KeyCode::Esc)), KeyAction::CommandIntroducer);
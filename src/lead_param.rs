@@ -18,4 +18,7 @@ pub enum LeadParam {
     Nindef,
     /// Marker (e.g. `@n`, `=`, or `%`)
     Marker(MarkId),
+    /// The value of a counter in the current frame (e.g. `#A`), resolved to
+    /// `Pint` or `Nint` when the command runs
+    Counter(char),
 }
@@ -24,33 +24,45 @@
 //! ```
 
 pub mod app;
+mod bytecode;
 pub mod cell_buffer;
 mod cmd_result;
 pub mod code;
+pub mod code_file;
 pub mod compiler;
+pub mod debugger;
 pub mod edit_mode;
 mod editor;
 mod exec_context;
 mod frame;
 pub mod frame_set;
 mod interpreter;
+pub mod journal;
 pub mod keybind;
 mod lead_param;
+pub mod lint;
+pub mod literal;
 mod marks;
+pub mod paging;
 pub mod pattern;
 mod position;
+pub mod pretty;
 pub mod screen;
 mod span;
 pub mod terminal;
+#[cfg(test)]
+mod test_util;
+pub mod text_format;
 mod trail_param;
 pub mod viewport;
 
-pub use cmd_result::{CmdFailure, CmdResult};
+pub use cmd_result::{CmdFailure, CmdResult, FailedCommand};
 pub use code::{CompiledCode, ExecOutcome};
 pub use compiler::compile;
-pub use editor::Editor;
+pub use editor::{Editor, Prompter};
 pub use frame::{
-    CaseMode, EditCommands, Frame, MotionCommands, PredicateCommands, SearchCommands, WordCommands,
+    CaseMode, CounterCommands, DEFAULT_SPACE_LIMIT, EditCommands, FileCommands, Frame,
+    MotionCommands, ParameterCommands, PredicateCommands, SearchCommands, WordCommands,
 };
 pub use lead_param::LeadParam;
 pub use marks::{MarkId, MarkSet};
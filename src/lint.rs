@@ -0,0 +1,433 @@
+//! Checking command procedures for likely mistakes.
+//!
+//! [`check`] compiles a command file and looks over the result for code that
+//! is well formed but almost certainly not what its author meant: commands
+//! that can never run, exits that leave more levels than there are, loops
+//! that cannot end, spans used but never defined, and commands that do
+//! nothing. The checks are conservative, since the file cannot be run:
+//! anything that might be right is let through.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::code::{
+    CmdOp, CompiledCode, ExitHandler, ExitLevels, Instruction, RepeatCount, SourceSpan,
+};
+use crate::compiler::{CompileError, write_excerpt};
+use crate::debugger::describe;
+use crate::exec_context::{is_special_delim, parse_span_name};
+use crate::frame_set::SPECIAL_FRAME_NAMES;
+use crate::{LeadParam, compile};
+
+/// The kinds of mistake [`check`] looks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningKind {
+    /// A command after `XS`, `XF` or `XA` in the same sequence.
+    Unreachable,
+    /// `nXS` or `nXF` exiting more levels than enclose it.
+    ExitTooDeep,
+    /// A `>` loop whose body can neither fail nor exit.
+    EndlessLoop,
+    /// A span used, but defined nowhere in the file.
+    UndefinedSpan,
+    /// A command that does nothing, such as `0D` or `()`.
+    NoEffect,
+}
+
+impl WarningKind {
+    /// A short code identifying the kind of warning, for looking it up.
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::Unreachable => "W01",
+            Self::ExitTooDeep => "W02",
+            Self::EndlessLoop => "W03",
+            Self::UndefinedSpan => "W04",
+            Self::NoEffect => "W05",
+        }
+    }
+}
+
+/// A likely mistake in Ludwig commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    /// The command at fault.
+    pub span: SourceSpan,
+    pub message: String,
+    /// The source line on which `span` starts.
+    pub line_text: String,
+}
+
+impl fmt::Display for Warning {
+    /// The warning and its position on one line, then the source line with
+    /// carets under the command at fault.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Warning at line {}, column {}: {}. [{}]",
+            self.span.start.line,
+            self.span.start.column,
+            self.message,
+            self.kind.code()
+        )?;
+        write_excerpt(f, self.span, &self.line_text)
+    }
+}
+
+/// Compile `source` and check it, returning its warnings in source order.
+pub fn check(source: &str) -> Result<Vec<Warning>, CompileError> {
+    let code = compile(source)?;
+    let mut checker = Checker::default();
+    // The procedure itself is one level for XS and XF to leave.
+    checker.check_sequence(&code, 1);
+
+    let mut found = checker.found;
+    let mut reported = HashSet::new();
+    for (name, span) in checker.references {
+        if !checker.defined.contains(&name)
+            && !SPECIAL_FRAME_NAMES.contains(&name.as_str())
+            && reported.insert(name.clone())
+        {
+            found.push((
+                WarningKind::UndefinedSpan,
+                span,
+                format!("span {name} is not defined in this file"),
+            ));
+        }
+    }
+
+    found.sort_by_key(|(_, span, _)| span.start);
+    let lines: Vec<&str> = source.lines().collect();
+    Ok(found
+        .into_iter()
+        .map(|(kind, span, message)| Warning {
+            kind,
+            span,
+            message,
+            line_text: lines
+                .get(span.start.line - 1)
+                .copied()
+                .unwrap_or_default()
+                .to_string(),
+        })
+        .collect())
+}
+
+#[derive(Default)]
+struct Checker {
+    found: Vec<(WarningKind, SourceSpan, String)>,
+    /// Spans defined by `SD` or `SA`.
+    defined: HashSet<String>,
+    /// Spans used, and where.
+    references: Vec<(String, SourceSpan)>,
+}
+
+impl Checker {
+    fn warn(&mut self, kind: WarningKind, span: SourceSpan, message: String) {
+        self.found.push((kind, span, message));
+    }
+
+    /// Check `code`, with `levels` compound commands and procedures around it.
+    fn check_sequence(&mut self, code: &CompiledCode, levels: usize) {
+        let instructions = code.instructions();
+        for (index, instr) in instructions.iter().enumerate() {
+            self.check_instruction(instr, levels);
+            if is_exit(instr)
+                && let Some(next) = instructions.get(index + 1)
+            {
+                let span = SourceSpan {
+                    start: next.span().start,
+                    end: instructions
+                        .last()
+                        .map_or(next.span().end, |last| last.span().end),
+                };
+                self.warn(
+                    WarningKind::Unreachable,
+                    span,
+                    format!("unreachable code after {}", describe(instr)),
+                );
+                break;
+            }
+        }
+    }
+
+    fn check_instruction(&mut self, instr: &Instruction, levels: usize) {
+        match instr {
+            Instruction::SimpleCmd {
+                op,
+                lead,
+                tpars,
+                exit_handler,
+                span,
+            } => {
+                if *lead == LeadParam::Pint(0) && does_nothing_zero_times(*op) {
+                    self.warn(
+                        WarningKind::NoEffect,
+                        *span,
+                        format!("{} has no effect", describe(instr)),
+                    );
+                }
+                for (index, tpar) in tpars.iter().enumerate() {
+                    // A name that is itself dereferenced is not known until run.
+                    if matches!(tpar.delim, '$' | '&') && tpar.content.starts_with(is_special_delim)
+                    {
+                        continue;
+                    }
+                    let Some(name) = parse_span_name(tpar) else {
+                        continue;
+                    };
+                    match (op, index, tpar.delim) {
+                        // SA$name$refspan$ names the span being assigned literally.
+                        (CmdOp::SpanDefine | CmdOp::SpanAssign, 0, _) => {
+                            self.defined.insert(name);
+                        }
+                        (_, _, '$') => self.references.push((name, *span)),
+                        (_, _, '&') => {}
+                        (op, 0, _) if uses_span(*op) => self.references.push((name, *span)),
+                        _ => {}
+                    }
+                }
+                if let Some(handler) = exit_handler {
+                    self.check_handler(handler, levels);
+                }
+            }
+            Instruction::CompoundCmd {
+                repeat,
+                body,
+                exit_handler,
+                span,
+            } => {
+                match repeat {
+                    RepeatCount::Times(0) => self.warn(
+                        WarningKind::NoEffect,
+                        *span,
+                        "0(...) has no effect".to_string(),
+                    ),
+                    RepeatCount::Indefinite if !can_stop(body, 0) => self.warn(
+                        WarningKind::EndlessLoop,
+                        *span,
+                        "loop never ends, as nothing in it can fail or leave it".to_string(),
+                    ),
+                    _ if body.instructions().is_empty() => self.warn(
+                        WarningKind::NoEffect,
+                        *span,
+                        "empty compound command has no effect".to_string(),
+                    ),
+                    _ => {}
+                }
+                self.check_sequence(body, levels + 1);
+                if let Some(handler) = exit_handler {
+                    self.check_handler(handler, levels);
+                }
+            }
+            Instruction::ExitSuccess(ExitLevels::Count(n), span)
+            | Instruction::ExitFailure(ExitLevels::Count(n), span)
+                if *n > levels =>
+            {
+                self.warn(
+                    WarningKind::ExitTooDeep,
+                    *span,
+                    format!(
+                        "{} exits {n} levels, but only {levels} enclose it",
+                        describe(instr)
+                    ),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Exit handlers run at the level of their command, so an exit in one
+    /// leaves the same levels as it would beside the command.
+    fn check_handler(&mut self, handler: &ExitHandler, levels: usize) {
+        for code in [&handler.on_success, &handler.on_failure]
+            .into_iter()
+            .flatten()
+        {
+            self.check_sequence(code, levels);
+        }
+    }
+}
+
+fn is_exit(instr: &Instruction) -> bool {
+    matches!(
+        instr,
+        Instruction::ExitSuccess(..) | Instruction::ExitFailure(..) | Instruction::ExitAbort(_)
+    )
+}
+
+/// Commands that read the span named by their first trailing parameter.
+fn uses_span(op: CmdOp) -> bool {
+    matches!(
+        op,
+        CmdOp::SpanExecute
+            | CmdOp::SpanExecuteNoRecompile
+            | CmdOp::SpanCopy
+            | CmdOp::SpanTransfer
+            | CmdOp::SpanJump
+            | CmdOp::SpanCompile
+            | CmdOp::SpanPrint
+    )
+}
+
+/// Commands for which a leading parameter of 0 means doing nothing.
+fn does_nothing_zero_times(op: CmdOp) -> bool {
+    matches!(
+        op,
+        CmdOp::InsertChar
+            | CmdOp::DeleteChar
+            | CmdOp::SwapLine
+            | CmdOp::CaseUp
+            | CmdOp::CaseLow
+            | CmdOp::CaseEdit
+            | CmdOp::InsertText
+            | CmdOp::OvertypeText
+            | CmdOp::SpanCopy
+            | CmdOp::SpanExecute
+            | CmdOp::SpanExecuteNoRecompile
+    )
+}
+
+/// Whether running `code`, `depth` compound commands inside the body of a
+/// `>` loop, might fail or exit, and so end the loop.
+fn can_stop(code: &CompiledCode, depth: usize) -> bool {
+    code.instructions().iter().any(|instr| match instr {
+        Instruction::SimpleCmd {
+            op,
+            lead,
+            exit_handler,
+            ..
+        } => match exit_handler {
+            Some(handler) => handler_can_stop(handler, depth),
+            None => !(*lead == LeadParam::Pint(0) && does_nothing_zero_times(*op)),
+        },
+        Instruction::CompoundCmd {
+            repeat,
+            body,
+            exit_handler,
+            ..
+        } => match exit_handler {
+            Some(handler) => handler_can_stop(handler, depth),
+            // A `>` loop only ever ends by failing, and a `#X` one fails
+            // if counter X is negative.
+            None => match repeat {
+                RepeatCount::Indefinite | RepeatCount::Counter(_) => true,
+                RepeatCount::Times(0) => false,
+                _ => can_stop(body, depth + 1),
+            },
+        },
+        // `XS` leaving no more than the loop's body only ends an iteration,
+        // and the loop goes on.
+        Instruction::ExitSuccess(ExitLevels::Count(n), _) => *n > depth + 1,
+        _ => true,
+    })
+}
+
+/// An exit handler turns failure into success unless its code can fail.
+fn handler_can_stop(handler: &ExitHandler, depth: usize) -> bool {
+    [&handler.on_success, &handler.on_failure]
+        .into_iter()
+        .flatten()
+        .any(|code| can_stop(code, depth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::SourcePos;
+
+    /// The kind, line and column of each warning for `source`.
+    fn warnings(source: &str) -> Vec<(WarningKind, usize, usize)> {
+        check(source)
+            .unwrap()
+            .iter()
+            .map(|w| (w.kind, w.span.start.line, w.span.start.column))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_procedure() {
+        let source = "SA/word/x/\n>(G$word$[:XF] 2D)\nA[XS:XA]\nEX/word/";
+        assert_eq!(warnings(source), []);
+    }
+
+    #[test]
+    fn test_unreachable_after_exit() {
+        assert_eq!(warnings("A XS J\nD"), [(WarningKind::Unreachable, 1, 6)]);
+        assert_eq!(
+            warnings("(A XF (J)) XA"),
+            [(WarningKind::Unreachable, 1, 7)]
+        );
+        // An exit in an exit handler only ends the handler.
+        assert_eq!(warnings("A[XS] J"), []);
+    }
+
+    #[test]
+    fn test_exit_too_deep() {
+        assert_eq!(warnings("XS 2XF"), [(WarningKind::Unreachable, 1, 4)]);
+        assert_eq!(warnings("2XF"), [(WarningKind::ExitTooDeep, 1, 1)]);
+        assert_eq!(warnings("(A[:2XF]) >XS"), []);
+        assert_eq!(warnings("(2(A[:4XS]))"), [(WarningKind::ExitTooDeep, 1, 7)]);
+    }
+
+    #[test]
+    fn test_endless_loop() {
+        assert_eq!(warnings(">(A[])"), [(WarningKind::EndlessLoop, 1, 1)]);
+        assert_eq!(warnings(">()"), [(WarningKind::EndlessLoop, 1, 1)]);
+        assert_eq!(
+            warnings("A >(A[] 2(D[]))"),
+            [(WarningKind::EndlessLoop, 1, 3)]
+        );
+        // Any command that might fail or exit ends the loop.
+        assert_eq!(warnings(">(A[J:XF]) >(D) >(J[] >(A)) >((XF))"), []);
+        // Exiting with success only ends an iteration, unless it leaves the
+        // loop as well.
+        assert_eq!(warnings(">(XS)"), [(WarningKind::EndlessLoop, 1, 1)]);
+        assert_eq!(warnings(">(A[:XS])"), [(WarningKind::EndlessLoop, 1, 1)]);
+        assert_eq!(warnings(">((2XS))"), [(WarningKind::EndlessLoop, 1, 1)]);
+        assert_eq!(warnings("(>(2XS)) (>((A[:3XS])))"), []);
+        assert_eq!(warnings(">(>XS)"), []);
+    }
+
+    #[test]
+    fn test_undefined_span() {
+        assert_eq!(
+            warnings("EX/mac/ SC/x/\nEN/MAC/"),
+            [
+                (WarningKind::UndefinedSpan, 1, 1),
+                (WarningKind::UndefinedSpan, 1, 9),
+            ]
+        );
+        assert_eq!(
+            warnings("SA$y$x$ SD/z/ ST/Z/ I$y$"),
+            [(WarningKind::UndefinedSpan, 1, 1)]
+        );
+        // Frames, and names that are only known when run, are not spans.
+        assert_eq!(warnings("EX/heap/ EX&Span?&"), []);
+    }
+
+    #[test]
+    fn test_no_effect() {
+        assert_eq!(
+            warnings("0D 0I/x/ 0A\n0(J) () 2()"),
+            [
+                (WarningKind::NoEffect, 1, 1),
+                (WarningKind::NoEffect, 1, 4),
+                (WarningKind::NoEffect, 2, 1),
+                (WarningKind::NoEffect, 2, 6),
+                (WarningKind::NoEffect, 2, 9),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_warning_display() {
+        let warning = &check("A\n  XS J\n  D").unwrap()[0];
+        assert_eq!(
+            warning.to_string(),
+            "Warning at line 2, column 6: unreachable code after XS. [W01]\n  XS J\n     ^"
+        );
+        assert_eq!(warning.span.end, SourcePos { line: 3, column: 4 });
+        assert!(check("2(A").is_err());
+    }
+}
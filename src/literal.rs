@@ -0,0 +1,254 @@
+//! Literal string search over a rope.
+//!
+//! [`LiteralSearcher`] is a Boyer–Moore–Horspool search on characters. After
+//! a mismatch it skips ahead by as much as the character under the end of
+//! the window allows, so most of the text is never compared. Characters are
+//! read a chunk at a time straight from the rope, and a match may straddle
+//! chunk boundaries.
+//!
+//! The case-insensitive search compares characters by their lowercase forms,
+//! as the `/`-delimited search commands do. Its skip table is keyed on the
+//! first character of each lowercase form, so characters that compare equal
+//! always share a skip distance.
+
+use ropey::RopeSlice;
+
+/// The skip table is indexed by the low byte of a character's key. Characters
+/// that share an entry get the smaller of their skip distances, which is
+/// always safe.
+const TABLE_SIZE: usize = 256;
+
+/// A literal pattern prepared for repeated searching.
+#[derive(Debug, Clone)]
+pub struct LiteralSearcher {
+    pattern: Vec<char>,
+    case_sensitive: bool,
+    /// Forward skip for the character at the end of the window.
+    skip_forward: [usize; TABLE_SIZE],
+    /// Backward skip for the character at the start of the window.
+    skip_backward: [usize; TABLE_SIZE],
+}
+
+impl LiteralSearcher {
+    pub fn new(pattern: &str, case_sensitive: bool) -> Self {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let len = pattern.len();
+        let mut searcher = Self {
+            skip_forward: [len.max(1); TABLE_SIZE],
+            skip_backward: [len.max(1); TABLE_SIZE],
+            pattern,
+            case_sensitive,
+        };
+        for i in 0..len.saturating_sub(1) {
+            let slot = searcher.slot(searcher.pattern[i]);
+            searcher.skip_forward[slot] = len - 1 - i;
+        }
+        for i in (1..len).rev() {
+            let slot = searcher.slot(searcher.pattern[i]);
+            searcher.skip_backward[slot] = i;
+        }
+        searcher
+    }
+
+    /// Length of the pattern in characters.
+    pub fn len(&self) -> usize {
+        self.pattern.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pattern.is_empty()
+    }
+
+    /// Find the first match starting at or after char index `from`.
+    ///
+    /// Returns the char index of the start of the match.
+    pub fn find_forward(&self, text: RopeSlice, from: usize) -> Option<usize> {
+        let len = self.pattern.len();
+        if len == 0 {
+            return None;
+        }
+        let mut text = ChunkCursor::new(text);
+        let mut start = from;
+        while start + len <= text.len {
+            let last = text.char_at(start + len - 1);
+            if self.eq(last, self.pattern[len - 1])
+                && (0..len - 1)
+                    .rev()
+                    .all(|i| self.eq(text.char_at(start + i), self.pattern[i]))
+            {
+                return Some(start);
+            }
+            start += self.skip_forward[self.slot(last)];
+        }
+        None
+    }
+
+    /// Find the last match starting before char index `before`.
+    ///
+    /// Returns the char index of the start of the match.
+    pub fn find_backward(&self, text: RopeSlice, before: usize) -> Option<usize> {
+        let len = self.pattern.len();
+        if len == 0 || before == 0 || text.len_chars() < len {
+            return None;
+        }
+        let mut text = ChunkCursor::new(text);
+        let mut start = (before - 1).min(text.len - len);
+        loop {
+            let first = text.char_at(start);
+            if self.eq(first, self.pattern[0])
+                && (1..len).all(|i| self.eq(text.char_at(start + i), self.pattern[i]))
+            {
+                return Some(start);
+            }
+            start = start.checked_sub(self.skip_backward[self.slot(first)])?;
+        }
+    }
+
+    fn eq(&self, text_ch: char, pat_ch: char) -> bool {
+        if self.case_sensitive {
+            text_ch == pat_ch
+        } else {
+            unicode_case_eq_char(text_ch, pat_ch)
+        }
+    }
+
+    fn slot(&self, ch: char) -> usize {
+        let key = if self.case_sensitive {
+            ch
+        } else {
+            ch.to_lowercase().next().unwrap_or(ch)
+        };
+        key as usize % TABLE_SIZE
+    }
+}
+
+/// Unicode-aware, case-insensitive equality for single scalar values.
+///
+/// This compares lowercase expansions, so it handles mappings where
+/// a single character lowercases to multiple code points.
+pub(crate) fn unicode_case_eq_char(a: char, b: char) -> bool {
+    a.to_lowercase().eq(b.to_lowercase())
+}
+
+/// Random access to the characters of a rope, decoding one chunk at a time.
+struct ChunkCursor<'a> {
+    text: RopeSlice<'a>,
+    len: usize,
+    /// Char index of the first character in `chars`.
+    chunk_start: usize,
+    chars: Vec<char>,
+}
+
+impl<'a> ChunkCursor<'a> {
+    fn new(text: RopeSlice<'a>) -> Self {
+        Self {
+            text,
+            len: text.len_chars(),
+            chunk_start: 0,
+            chars: Vec::new(),
+        }
+    }
+
+    fn char_at(&mut self, idx: usize) -> char {
+        if idx < self.chunk_start || idx >= self.chunk_start + self.chars.len() {
+            let (chunk, _, chunk_start, _) = self.text.chunk_at_char(idx);
+            self.chunk_start = chunk_start;
+            self.chars.clear();
+            self.chars.extend(chunk.chars());
+        }
+        self.chars[idx - self.chunk_start]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ropey::Rope;
+
+    /// Every match start, by brute force.
+    fn naive(text: &str, pattern: &str, case_sensitive: bool) -> Vec<usize> {
+        let text: Vec<char> = text.chars().collect();
+        let pattern: Vec<char> = pattern.chars().collect();
+        (0..=text.len().saturating_sub(pattern.len()))
+            .filter(|&s| {
+                s + pattern.len() <= text.len()
+                    && pattern.iter().enumerate().all(|(i, &p)| {
+                        if case_sensitive {
+                            text[s + i] == p
+                        } else {
+                            unicode_case_eq_char(text[s + i], p)
+                        }
+                    })
+            })
+            .collect()
+    }
+
+    fn all_forward(searcher: &LiteralSearcher, rope: &Rope) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut from = 0;
+        while let Some(start) = searcher.find_forward(rope.slice(..), from) {
+            found.push(start);
+            from = start + 1;
+        }
+        found
+    }
+
+    fn all_backward(searcher: &LiteralSearcher, rope: &Rope) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut before = rope.len_chars();
+        while let Some(start) = searcher.find_backward(rope.slice(..), before) {
+            found.push(start);
+            before = start;
+        }
+        found.reverse();
+        found
+    }
+
+    #[test]
+    fn test_matches_agree_with_brute_force() {
+        let text = "abracadabra ABRACADABRA abrAcadAbra\n".repeat(200);
+        let rope = Rope::from_str(&text);
+        assert!(rope.chunks().count() > 2, "text should span several chunks");
+        for (pattern, case_sensitive) in [
+            ("abra", true),
+            ("abra", false),
+            ("a", true),
+            ("ra\nab", true),
+            ("cadAbra", false),
+            ("zzz", false),
+        ] {
+            let searcher = LiteralSearcher::new(pattern, case_sensitive);
+            let expected = naive(&text, pattern, case_sensitive);
+            assert_eq!(all_forward(&searcher, &rope), expected, "{pattern:?}");
+            assert_eq!(all_backward(&searcher, &rope), expected, "{pattern:?}");
+        }
+    }
+
+    #[test]
+    fn test_case_insensitive_non_ascii() {
+        let rope = Rope::from_str("straße ÉCOLE école");
+        let searcher = LiteralSearcher::new("école", false);
+        assert_eq!(searcher.find_forward(rope.slice(..), 0), Some(7));
+        assert_eq!(searcher.find_backward(rope.slice(..), 18), Some(13));
+        // The Kelvin sign lowercases to an ASCII `k`.
+        let rope = Rope::from_str("5 \u{212A}g");
+        assert_eq!(
+            LiteralSearcher::new("kg", false).find_forward(rope.slice(..), 0),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_bounds() {
+        let rope = Rope::from_str("abcabc");
+        let searcher = LiteralSearcher::new("abc", true);
+        assert_eq!(searcher.find_forward(rope.slice(..), 1), Some(3));
+        assert_eq!(searcher.find_forward(rope.slice(..), 4), None);
+        assert_eq!(searcher.find_backward(rope.slice(..), 3), Some(0));
+        assert_eq!(searcher.find_backward(rope.slice(..), 0), None);
+        assert_eq!(
+            LiteralSearcher::new("", true).find_forward(rope.slice(..), 0),
+            None
+        );
+    }
+}
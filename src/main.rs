@@ -1,11 +1,22 @@
 use clap::Parser;
 use std::fs;
-use std::io::{self, IsTerminal, Read};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 
 use ludwig::app::App;
+use ludwig::code_file::{read_library, write_library};
+use ludwig::compiler::compile_noting_comments;
+use ludwig::debugger::Tracer;
+use ludwig::journal::Journal;
+use ludwig::lint;
+use ludwig::paging;
+use ludwig::pretty::format_code_with_comments;
 use ludwig::screen::Screen;
 use ludwig::terminal::{CrosstermTerminal, Terminal};
-use ludwig::{Editor, ExecOutcome, compile};
+use ludwig::text_format::Encoding;
+use ludwig::{DEFAULT_SPACE_LIMIT, Editor, ExecOutcome, Prompter, compile};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -37,16 +48,68 @@ struct Args {
     /// Open in read-only mode
     #[arg(short = 'r', long)]
     read_only: bool,
+
+    /// Number of characters of text allowed in the default frame
+    #[arg(short = 's', long, value_name = "VALUE", default_value_t = DEFAULT_SPACE_LIMIT)]
+    space: usize,
+
+    /// File encoding (UTF-8, Latin-1, UTF-16LE or UTF-16BE); detected if omitted
+    #[arg(short = 'e', long, value_name = "NAME")]
+    encoding: Option<Encoding>,
+
+    /// Stop a command line after it has executed this many instructions
+    #[arg(long, value_name = "COUNT")]
+    max_steps: Option<u64>,
+
+    /// In batch mode, log each command executed to FILE (--trace=FILE), or to stderr
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "-"
+    )]
+    trace: Option<String>,
+
+    /// Print the commands in FILE, or standard input, in canonical form and exit
+    #[arg(long)]
+    format: bool,
+
+    /// Check the commands in FILE, or standard input, for likely mistakes and exit
+    #[arg(long, conflicts_with = "format")]
+    check: bool,
+
+    /// Run the commands in FILE, or standard input, and print the spans they
+    /// define, with any code compiled for them, as a span library
+    #[arg(long, conflicts_with_all = ["format", "check"])]
+    compile: bool,
+
+    /// Define the spans in a span library written by --compile before editing
+    #[arg(long, value_name = "FILE")]
+    library: Vec<String>,
 }
 
 fn main() {
     let args = Args::parse();
 
+    if args.format {
+        run_format(args.file.as_deref());
+        return;
+    }
+    if args.check {
+        run_check(args.file.as_deref());
+        return;
+    }
+    if args.compile {
+        run_compile(args.file.as_deref());
+        return;
+    }
+
     // Determine whether to run in interactive or batch mode.
     // Interactive mode: stdin is a terminal AND -M (batch) was not specified.
     let interactive = io::stdin().is_terminal() && !args.batch;
 
-    let maybe_path = args.file.map(|s| {
+    let maybe_path = args.file.clone().map(|s| {
         if std::path::Path::new(&s).exists() {
             fs::canonicalize(&s).unwrap().to_string_lossy().to_string()
         } else {
@@ -55,27 +118,260 @@ fn main() {
     });
 
     if interactive {
-        run_interactive(maybe_path);
+        run_interactive(maybe_path, &args);
     } else {
-        run_batch(maybe_path);
+        run_batch(maybe_path, &args);
+    }
+}
+
+/// Read the commands in `path`, or standard input.
+fn read_commands(path: Option<&str>) -> String {
+    match path {
+        Some(path) => fs::read_to_string(path),
+        None => io::read_to_string(io::stdin()),
+    }
+    .unwrap_or_else(|err| {
+        eprintln!("Failed to read {}: {}", path.unwrap_or("stdin"), err);
+        std::process::exit(1);
+    })
+}
+
+/// Print the commands in `path`, or standard input, in canonical form,
+/// keeping their comments.
+fn run_format(path: Option<&str>) {
+    let source = read_commands(path);
+    match compile_noting_comments(&source) {
+        Ok((code, comments)) => print!("{}", format_code_with_comments(&code, &comments)),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Print warnings about the commands in `path`, or standard input, exiting
+/// with status 1 if there are any.
+fn run_check(path: Option<&str>) {
+    let source = read_commands(path);
+    match lint::check(&source) {
+        Ok(warnings) if warnings.is_empty() => {}
+        Ok(warnings) => {
+            for warning in &warnings {
+                println!("{}", warning);
+            }
+            std::process::exit(1);
+        }
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(1);
+        }
     }
 }
 
-fn run_interactive(maybe_path: Option<String>) {
-    let file_contents = if let Some(path) = maybe_path.as_ref() {
-        if std::path::Path::new(path).exists() {
-            fs::read_to_string(path).unwrap_or_else(|err| {
+/// Print the spans defined by running the commands in `path`, or standard
+/// input, as a span library.
+fn run_compile(path: Option<&str>) {
+    let source = read_commands(path);
+    let code = compile(&source).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    let mut editor = Editor::new();
+    if !editor.execute(&code).is_success() {
+        match editor.take_failure() {
+            Some(failure) => eprintln!("COMMAND FAILED {}", failure),
+            None => eprintln!("COMMAND FAILED"),
+        }
+        if let Some(message) = editor.take_message() {
+            eprintln!("{}", message);
+        }
+        std::process::exit(1);
+    }
+    print!("{}", write_library(&editor.saved_spans()));
+}
+
+/// Define the spans in each `--library` file.
+fn load_libraries(editor: &mut Editor, args: &Args) {
+    for path in &args.library {
+        let spans = fs::read_to_string(path).and_then(|library| read_library(&library));
+        let loaded = match spans {
+            Ok(spans) => editor
+                .load_spans(spans)
+                .map_err(|failure| failure.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        if let Err(err) = loaded {
+            eprintln!("Failed to load {}: {}", path, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Apply `--max-steps` to the editor, and let SIGINT stop a command. A second
+/// SIGINT before the first has been noticed ends the process as usual.
+fn set_limits(editor: &mut Editor, args: &Args) {
+    editor.set_step_budget(args.max_steps);
+    let interrupt = Arc::new(AtomicBool::new(false));
+    // Conditional shutdown first, so that it sees the flag as it was.
+    let registered = signal_hook::flag::register_conditional_shutdown(
+        signal_hook::consts::SIGINT,
+        1,
+        Arc::clone(&interrupt),
+    )
+    .and_then(|_| signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&interrupt)));
+    if registered.is_ok() {
+        editor.set_interrupt(interrupt);
+    }
+}
+
+/// Create an editor for raw file `contents`, applying `-e` and the `-s` space limit.
+fn load_editor(contents: &[u8], args: &Args, maybe_path: Option<&String>) -> Editor {
+    let mut editor = Editor::from_file_contents(contents, args.encoding);
+    if editor
+        .current_frame_mut()
+        .set_space_limit(args.space)
+        .is_err()
+    {
+        eprintln!(
+            "{} does not fit in {} characters of space.",
+            maybe_path.map_or("Input", |p| p.as_str()),
+            args.space
+        );
+        std::process::exit(1);
+    }
+    editor
+}
+
+/// Open the file at `path`, applying `-e` and the `-s` space limit.
+///
+/// Files larger than the space limit are paged in as needed rather than
+/// read all at once.
+fn open_editor(path: &str, args: &Args) -> Editor {
+    let size = fs::metadata(path).map_or(0, |m| m.len());
+    if size > args.space as u64 {
+        match Editor::open_paged(Path::new(path), args.encoding) {
+            Ok(mut editor) => {
+                let frame = editor.current_frame_mut();
+                // The frame is still empty, so this cannot fail.
+                let _ = frame.set_space_limit(args.space);
+                frame.page_in_near_dot();
+                return editor;
+            }
+            // UTF-16 can't be paged; read it whole and let the limit decide.
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => {}
+            Err(err) => {
                 eprintln!("Failed to read {}: {}", path, err);
                 std::process::exit(1);
-            })
-        } else {
-            String::new()
+            }
+        }
+    }
+    let file_contents = fs::read(path).unwrap_or_else(|err| {
+        eprintln!("Failed to read {}: {}", path, err);
+        std::process::exit(1);
+    });
+    load_editor(&file_contents, args, Some(&path.to_string()))
+}
+
+/// Attach a crash-recovery journal for `path` to the editor's frame.
+///
+/// The frame the editor starts in is the only one backed by a file, so it is
+/// the only one journalled. A journal left behind by an earlier session is
+/// replayed if the user agrees. Unless the user discards it, it is set aside
+/// rather than overwritten, and kept if its changes were not all recovered.
+/// Batch runs keep no journal, and only report one that is found.
+fn start_journal(editor: &mut Editor, path: &Path, interactive: bool) {
+    if editor.is_paged() {
+        return;
+    }
+    let base = fs::read(path).unwrap_or_default();
+    let journal_path = Journal::path_for(path);
+    let (ops, keep_old) = match Journal::recover(path, &base) {
+        Ok(None) => (None, false),
+        Ok(Some(ops)) if ops.is_empty() => (None, false),
+        Ok(Some(ops)) if interactive => {
+            eprint!(
+                "{} has unsaved changes from an earlier session. Recover them? [y/N/d(iscard)] ",
+                path.display()
+            );
+            let mut answer = String::new();
+            let _ = io::stdin().read_line(&mut answer);
+            match answer.trim().to_ascii_lowercase().as_str() {
+                "y" => (Some(ops), true),
+                "d" => (None, false),
+                _ => (None, true),
+            }
+        }
+        Ok(Some(_)) => {
+            eprintln!(
+                "{} exists; edit {} interactively to recover it.",
+                journal_path.display(),
+                path.display()
+            );
+            return;
+        }
+        Err(err) if interactive => {
+            eprintln!("{} not used: {}.", journal_path.display(), err);
+            (None, true)
+        }
+        Err(err) => {
+            eprintln!("{} not used: {}.", journal_path.display(), err);
+            return;
+        }
+    };
+    if !interactive {
+        return;
+    }
+    let aside = if keep_old {
+        match Journal::set_aside(path) {
+            Ok(aside) => Some(aside),
+            Err(err) => {
+                eprintln!(
+                    "{} could not be set aside, so changes are not journalled: {}.",
+                    journal_path.display(),
+                    err
+                );
+                return;
+            }
         }
     } else {
-        String::new()
+        None
+    };
+    let journal = match Journal::create(path, &base) {
+        Ok(journal) => journal,
+        Err(err) => {
+            eprintln!("{} could not be created: {}.", journal_path.display(), err);
+            return;
+        }
     };
+    let frame = editor.current_frame_mut();
+    frame.attach_journal(journal);
+    let recovered = ops.map(|ops| frame.replay_journal(&ops));
+    match (aside, recovered) {
+        // The new journal now holds all of the old one's changes.
+        (Some(aside), Some(true)) => {
+            let _ = fs::remove_file(aside);
+        }
+        (Some(aside), Some(false)) => eprintln!(
+            "Some changes could not be recovered; the old journal is kept as {}.",
+            aside.display()
+        ),
+        (Some(aside), None) => {
+            eprintln!("The old journal is kept as {}.", aside.display())
+        }
+        (None, _) => {}
+    }
+}
 
-    let editor = Editor::from_str(&file_contents);
+fn run_interactive(maybe_path: Option<String>, args: &Args) {
+    let mut editor = match maybe_path.as_ref() {
+        Some(path) if Path::new(path).exists() => open_editor(path, args),
+        _ => load_editor(&[], args, None),
+    };
+    if let Some(path) = maybe_path.as_ref() {
+        start_journal(&mut editor, Path::new(path), true);
+    }
+    set_limits(&mut editor, args);
+    load_libraries(&mut editor, args);
     let mut terminal = CrosstermTerminal::new();
     let screen = Screen::new(terminal.size());
     let mut app = App::new(editor, screen, maybe_path);
@@ -84,58 +380,93 @@ fn run_interactive(maybe_path: Option<String>) {
         // Make sure terminal is cleaned up even on error
         let _ = terminal.cleanup();
         eprintln!("Error: {}", e);
+        // Unsaved changes are what the journal is for; otherwise tidy up.
+        if app.editor.has_journalled_changes() {
+            eprintln!("Unsaved changes can be recovered by editing the file again.");
+        } else {
+            let _ = app.editor.discard_journal();
+        }
         std::process::exit(1);
     }
+    let _ = app.editor.discard_journal();
 }
 
-fn run_batch(maybe_path: Option<String>) {
+fn run_batch(maybe_path: Option<String>, args: &Args) {
     let mut output = Vec::<String>::new();
 
-    let file_contents = if let Some(path) = maybe_path.as_ref() {
-        let file_contents = fs::read_to_string(path).unwrap_or_else(|err| {
-            eprintln!("Failed to read {}: {}", path, err);
-            std::process::exit(1);
-        });
+    let mut editor = if let Some(path) = maybe_path.as_ref() {
+        let editor = open_editor(path, args);
+        // A paged frame holds only the start of the file so far.
+        let line_count = if editor.is_paged() {
+            paging::count_lines(Path::new(path)).unwrap_or_default()
+        } else {
+            editor.to_string().lines().count()
+        };
         output.push(format!(
             "{} closed ({} line{} read).",
             path,
-            file_contents.lines().count(),
-            if file_contents.lines().count() == 1 {
-                ""
-            } else {
-                "s"
-            }
+            line_count,
+            if line_count == 1 { "" } else { "s" }
         ));
-        file_contents
+        editor
     } else {
-        String::new()
+        load_editor(&[], args, None)
     };
 
-    let mut stdin_contents = String::new();
-    io::stdin()
-        .read_to_string(&mut stdin_contents)
-        .unwrap_or_else(|err| {
-            eprintln!("Failed to read stdin: {}", err);
-            std::process::exit(1);
-        });
+    if let Some(path) = maybe_path.as_ref() {
+        start_journal(&mut editor, Path::new(path), false);
+    }
+    set_limits(&mut editor, args);
+    load_libraries(&mut editor, args);
 
-    let code = compile(&stdin_contents).unwrap_or_else(|err| {
+    let mut trace: Option<Box<dyn Write>> = match args.trace.as_deref() {
+        None => None,
+        Some("-") => Some(Box::new(io::stderr())),
+        Some(trace_path) => match fs::File::create(trace_path) {
+            Ok(file) => Some(Box::new(io::BufWriter::new(file))),
+            Err(err) => {
+                eprintln!("Failed to create {}: {}", trace_path, err);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    let mut input = io::stdin().lock();
+    let source = read_script(&mut input).unwrap_or_else(|err| {
+        eprintln!("Failed to read stdin: {}", err);
+        std::process::exit(1);
+    });
+    let code = compile(&source).unwrap_or_else(|err| {
         println!("{}", err);
-        for line in output.clone() {
+        for line in &output {
             println!("{}", line);
         }
         std::process::exit(0);
     });
 
-    let mut editor = Editor::from_str(&file_contents);
-    let outcome = editor.execute(&code);
+    // Whatever follows the script answers `&prompt&` dereferences.
+    let mut prompter = LinePrompter(&mut input);
+    let outcome = match trace.as_mut() {
+        Some(out) => editor.execute_debugged(&code, &mut Tracer::new(out.as_mut(), &mut prompter)),
+        None => editor.execute_with_prompter(&code, &mut prompter),
+    };
+
+    if let Some(out) = trace.as_mut() {
+        let _ = out.flush();
+    }
 
     let failed = !matches!(
         outcome,
         ExecOutcome::Success | ExecOutcome::ExitSuccess { .. } | ExecOutcome::ExitSuccessAll
     );
     if failed {
-        println!("\x07COMMAND FAILED");
+        match editor.take_failure() {
+            Some(failure) => println!("\x07COMMAND FAILED {}", failure),
+            None => println!("\x07COMMAND FAILED"),
+        }
+        if let Some(message) = editor.take_message() {
+            println!("{}", message);
+        }
     }
 
     for line in output {
@@ -145,21 +476,58 @@ fn run_batch(maybe_path: Option<String>) {
         && editor.modified()
         && let Some(path) = maybe_path.as_ref()
     {
-        fs::rename(path, format!("{}~1", path)).unwrap();
-        let mut contents = editor.to_string();
-        if !contents.is_empty() && !contents.ends_with('\n') {
-            contents.push('\n');
+        if editor.is_paged() {
+            editor.save_paged().unwrap_or_else(|err| {
+                println!("{} not written: {}.", path, err);
+                std::process::exit(1);
+            });
+            println!("{} created.", path);
+            return;
         }
+        let contents = editor.file_contents().unwrap_or_else(|err| {
+            println!("{} not written: {}.", path, err);
+            std::process::exit(1);
+        });
+        fs::rename(path, format!("{}~1", path)).unwrap();
+        let line_count = editor.to_string().lines().count();
         println!(
             "{} created ({} line{} written).",
             path,
-            contents.lines().count(),
-            if contents.lines().count() == 1 {
-                ""
-            } else {
-                "s"
-            }
+            line_count,
+            if line_count == 1 { "" } else { "s" }
         );
         fs::write(path, contents).unwrap();
     }
 }
+
+/// The line that ends the commands in standard input in batch mode. It is a
+/// comment, so the commands before it can be compiled on their own too.
+const SCRIPT_END: &str = "!&";
+
+/// Read batch commands from `input` up to a line holding only
+/// [`SCRIPT_END`], or to the end, leaving the lines after it unread.
+fn read_script(input: &mut impl BufRead) -> io::Result<String> {
+    let mut source = String::new();
+    let mut line = String::new();
+    while input.read_line(&mut line)? > 0 {
+        if line.trim_end_matches(['\r', '\n']) == SCRIPT_END {
+            break;
+        }
+        source.push_str(&line);
+        line.clear();
+    }
+    Ok(source)
+}
+
+/// Answers `&prompt&` dereferences in batch mode with the next line of input.
+struct LinePrompter<'a, R: BufRead>(&'a mut R);
+
+impl<R: BufRead> Prompter for LinePrompter<'_, R> {
+    fn prompt(&mut self, _prompt: &str) -> Option<String> {
+        let mut line = String::new();
+        match self.0.read_line(&mut line) {
+            Ok(n) if n > 0 => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! Incremental file input and output for frames too large to hold in memory.
+//!
+//! A paged frame reads its input file a few lines at a time as dot approaches
+//! the end of the text in memory, and `FP` moves text above dot out to a
+//! temporary output file. Saving copies the frame and the unread remainder of
+//! the input to the output, which then replaces the original file.
+//!
+//! Input is split into lines on `\n` bytes, so paging is only available for
+//! byte-oriented encodings (UTF-8 and Latin-1).
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::text_format::Encoding;
+
+/// Number of lines kept in memory beyond dot, and read by each `FP`.
+pub const PAGE_LINES: usize = 1000;
+
+/// A file being read into a frame line by line.
+#[derive(Debug)]
+pub struct InputFile {
+    path: PathBuf,
+    reader: BufReader<File>,
+    encoding: Encoding,
+    /// Bytes of text handed out from the start of the file.
+    offset: u64,
+    /// A line read from the file but not yet handed out.
+    pending: Option<Vec<u8>>,
+    eof: bool,
+}
+
+impl InputFile {
+    /// Open `path` for paged reading.
+    ///
+    /// The encoding is guessed from the start of the file if not given.
+    /// Fails with [`io::ErrorKind::Unsupported`] for UTF-16 files.
+    pub fn open(path: &Path, encoding: Option<Encoding>) -> io::Result<InputFile> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let encoding = match encoding {
+            Some(e) => e,
+            None => detect_prefix(reader.fill_buf()?),
+        };
+        if matches!(encoding, Encoding::Utf16Le | Encoding::Utf16Be) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "UTF-16 files cannot be paged",
+            ));
+        }
+        Ok(InputFile {
+            path: path.to_path_buf(),
+            reader,
+            encoding,
+            offset: 0,
+            pending: None,
+            eof: false,
+        })
+    }
+
+    /// Reopen the same file and continue reading from byte `offset`.
+    pub fn reopen_at(&self, offset: u64) -> io::Result<InputFile> {
+        Self::open_at(&self.path, self.encoding, offset)
+    }
+
+    fn open_at(path: &Path, encoding: Encoding, offset: u64) -> io::Result<InputFile> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(InputFile {
+            path: path.to_path_buf(),
+            reader: BufReader::new(file),
+            encoding,
+            offset,
+            pending: None,
+            eof: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// True once the whole file has been read.
+    pub fn is_eof(&self) -> bool {
+        self.eof
+    }
+
+    /// True if nothing has been read from the file yet.
+    pub fn at_start(&self) -> bool {
+        self.offset == 0
+    }
+
+    /// Bytes of text read from the start of the file.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Read up to `count` lines, decoded but with their original terminators.
+    ///
+    /// Stops early if the next line would take the text past `max_chars`
+    /// characters; that line is held back for the next call.
+    pub fn read_lines(&mut self, count: usize, max_chars: usize) -> io::Result<String> {
+        let mut text = String::new();
+        let mut chars = 0;
+        for _ in 0..count {
+            let bytes = match self.pending.take() {
+                Some(bytes) => bytes,
+                None => {
+                    let mut bytes = Vec::new();
+                    self.reader.read_until(b'\n', &mut bytes)?;
+                    bytes
+                }
+            };
+            if bytes.is_empty() {
+                break;
+            }
+            let decoded = self.encoding.decode(&bytes);
+            let len = decoded.chars().count();
+            if chars + len > max_chars {
+                self.pending = Some(bytes);
+                break;
+            }
+            self.offset += bytes.len() as u64;
+            chars += len;
+            text.push_str(&decoded);
+        }
+        self.eof = self.pending.is_none() && self.reader.fill_buf()?.is_empty();
+        Ok(text)
+    }
+}
+
+/// The temporary file that paged-out text is written to.
+#[derive(Debug)]
+pub struct OutputFile {
+    /// Temporary path, renamed over the original on save.
+    path: PathBuf,
+    writer: BufWriter<File>,
+    /// Bytes written so far.
+    len: u64,
+}
+
+impl OutputFile {
+    /// Create a fresh temporary output file next to `target`.
+    ///
+    /// An existing file is never reused, as it may belong to another session
+    /// or to the user: `name~tmp` is tried first, then `name~tmp1` and so on.
+    pub fn create_for(target: &Path) -> io::Result<OutputFile> {
+        for attempt in 0..TEMP_ATTEMPTS {
+            let path = temp_path(target, attempt);
+            match File::create_new(&path) {
+                Ok(file) => {
+                    return Ok(OutputFile {
+                        writer: BufWriter::new(file),
+                        path,
+                        len: 0,
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("no free temporary file name for {}", target.display()),
+        ))
+    }
+
+    /// Bytes written so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.len += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Discard everything written after the first `len` bytes.
+    pub fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.set_len(len)?;
+        file.seek(SeekFrom::Start(len))?;
+        self.len = len;
+        Ok(())
+    }
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Replace `target` with the completed `output`, keeping a `~1` backup.
+///
+/// Returns fresh input and output files that continue from byte `resume_at`
+/// of the new file, with the first `keep` bytes already copied to the output.
+pub fn commit(
+    output: OutputFile,
+    target: &Path,
+    encoding: Encoding,
+    keep: u64,
+    resume_at: u64,
+) -> io::Result<(InputFile, OutputFile)> {
+    let mut output = output;
+    output.writer.flush()?;
+    let mut backup = target.as_os_str().to_owned();
+    backup.push("~1");
+    if target.exists() {
+        fs::rename(target, &backup)?;
+    }
+    fs::rename(&output.path, target)?;
+    // The temporary file has moved; make sure dropping `output` doesn't touch it.
+    output.path = PathBuf::new();
+
+    let mut next = OutputFile::create_for(target)?;
+    let mut head = File::open(target)?.take(keep);
+    next.len = io::copy(&mut head, &mut next.writer)?;
+    let input = InputFile::open_at(target, encoding, resume_at)?;
+    Ok((input, next))
+}
+
+/// The number of lines in the file at `path`, counted as [`InputFile`]
+/// splits them, without holding the file in memory.
+pub fn count_lines(path: &Path) -> io::Result<usize> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut lines = 0;
+    let mut last = b'\n';
+    loop {
+        let buf = reader.fill_buf()?;
+        let Some(&end) = buf.last() else {
+            break;
+        };
+        lines += buf.iter().filter(|&&b| b == b'\n').count();
+        last = end;
+        let len = buf.len();
+        reader.consume(len);
+    }
+    Ok(lines + usize::from(last != b'\n'))
+}
+
+/// How many temporary file names [`OutputFile::create_for`] tries.
+const TEMP_ATTEMPTS: usize = 100;
+
+/// The `attempt`th candidate name for a temporary file next to `target`.
+fn temp_path(target: &Path, attempt: usize) -> PathBuf {
+    let mut name = target.as_os_str().to_owned();
+    name.push("~tmp");
+    if attempt > 0 {
+        name.push(attempt.to_string());
+    }
+    PathBuf::from(name)
+}
+
+/// Like [`Encoding::detect`], but tolerates a multi-byte UTF-8 sequence cut
+/// off at the end of the sample.
+fn detect_prefix(sample: &[u8]) -> Encoding {
+    match std::str::from_utf8(sample) {
+        Err(e) if e.error_len().is_none() => Encoding::Utf8,
+        _ => Encoding::detect(sample),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::ScratchDir;
+
+    #[test]
+    fn test_read_lines_incrementally() {
+        let dir = ScratchDir::new("paging");
+        let path = dir.file("incremental", b"one\ntwo\r\nthree");
+        let mut input = InputFile::open(&path, None).unwrap();
+        assert_eq!(input.read_lines(1, usize::MAX).unwrap(), "one\n");
+        assert!(!input.is_eof());
+        assert_eq!(input.read_lines(5, usize::MAX).unwrap(), "two\r\nthree");
+        assert!(input.is_eof());
+    }
+
+    #[test]
+    fn test_read_lines_respects_char_limit() {
+        let dir = ScratchDir::new("paging");
+        let path = dir.file("limit", b"abc\ndef\n");
+        let mut input = InputFile::open(&path, None).unwrap();
+        assert_eq!(input.read_lines(2, 6).unwrap(), "abc\n");
+        assert_eq!(input.read_lines(2, usize::MAX).unwrap(), "def\n");
+    }
+
+    #[test]
+    fn test_utf16_is_not_paged() {
+        let dir = ScratchDir::new("paging");
+        let path = dir.file("utf16", b"\xff\xfea\x00");
+        let err = InputFile::open(&path, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_count_lines() {
+        let dir = ScratchDir::new("paging");
+        assert_eq!(count_lines(&dir.file("count0", b"")).unwrap(), 0);
+        assert_eq!(count_lines(&dir.file("count1", b"a\r\nb\n")).unwrap(), 2);
+        assert_eq!(count_lines(&dir.file("count2", b"a\nb")).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_output_file_keeps_existing_temp_file() {
+        let dir = ScratchDir::new("paging");
+        let target = dir.file("busy", b"text\n");
+        let taken = dir.file("busy~tmp", b"someone else's\n");
+        let output = OutputFile::create_for(&target).unwrap();
+        assert_eq!(output.path, temp_path(&target, 1));
+        drop(output);
+        assert_eq!(fs::read(&taken).unwrap(), b"someone else's\n");
+        assert!(!temp_path(&target, 1).exists());
+    }
+
+    #[test]
+    fn test_detect_prefix_allows_truncated_utf8() {
+        assert_eq!(detect_prefix(b"caf\xc3"), Encoding::Utf8);
+        assert_eq!(detect_prefix(b"caf\xe9!"), Encoding::Latin1);
+    }
+}
@@ -2,6 +2,8 @@
 
 use crate::marks::MarkId;
 
+use super::unicode::UnicodeClass;
+
 /// A fully parsed Ludwig pattern with optional left/middle/right contexts.
 ///
 /// A pattern like `A,N,S` has left=Alpha, middle=Numeric, right=Space.
@@ -13,6 +15,15 @@ pub struct PatternDef {
     pub right: Compound,
 }
 
+impl PatternDef {
+    /// True if every context is empty, as for a blank pattern string.
+    pub fn is_empty(&self) -> bool {
+        self.left.is_empty_pattern()
+            && self.middle.is_empty_pattern()
+            && self.right.is_empty_pattern()
+    }
+}
+
 /// An alternation of one or more sequences (`|`-separated).
 ///
 /// An empty `alternatives` vec is the canonical empty compound; it matches
@@ -67,16 +78,18 @@ pub enum Element {
         case_fold: bool,
     },
     Positional(Positional),
+    /// `#` — the break between a line and the next.
+    LineBreak,
     MarkCheck(MarkId),
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct CharSet {
     pub negated: bool,
     pub kind: CharSetKind,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum CharSetKind {
     Alpha,     // A — alphabetic
     Upper,     // U — uppercase
@@ -86,9 +99,10 @@ pub enum CharSetKind {
     Punct,     // P — (),.;:"'!?-`
     Printable, // C — 0x20..0x7E
     Custom(Vec<CharClass>),
+    Unicode(UnicodeClass), // X/name/ — a Unicode property
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum CharClass {
     Single(char),
     Range(char, char),
@@ -97,7 +111,7 @@ pub enum CharClass {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Positional {
     BegLine,     // < — pos == 0
-    EndLine,     // > — pos == line length
+    EndLine,     // > — pos == line length (may step to the next line)
     LeftMargin,  // { — pos == ctx.left_margin
     RightMargin, // } — pos == ctx.right_margin
     DotCol,      // ^ — pos == ctx.dot_col
@@ -20,6 +20,7 @@ fn kind_matches(kind: &CharSetKind, ch: char) -> bool {
         CharSetKind::Punct => "(),.;:\"'!?-`".contains(ch),
         CharSetKind::Printable => ('\x20'..='\x7e').contains(&ch),
         CharSetKind::Custom(classes) => classes.iter().any(|c| char_in_class(c, ch)),
+        CharSetKind::Unicode(class) => class.contains(ch),
     }
 }
 
@@ -35,6 +36,7 @@ pub fn char_in_class(class: &CharClass, ch: char) -> bool {
 mod tests {
     use super::*;
     use crate::pattern::ast::{CharClass, CharSet, CharSetKind};
+    use crate::pattern::unicode::UnicodeClass;
 
     fn cs(kind: CharSetKind) -> CharSet {
         CharSet {
@@ -101,6 +103,16 @@ mod tests {
         assert!(!charset_matches(&cs, '0'));
     }
 
+    #[test]
+    fn unicode_sets_reach_beyond_ascii() {
+        let zs = cs(CharSetKind::Unicode(UnicodeClass::lookup("Zs").unwrap()));
+        assert!(charset_matches(&zs, '\u{3000}'));
+        assert!(!charset_matches(&cs(CharSetKind::Space), '\u{3000}'));
+        let nd = cs(CharSetKind::Unicode(UnicodeClass::lookup("Nd").unwrap()));
+        assert!(charset_matches(&nd, '७'));
+        assert!(!charset_matches(&cs(CharSetKind::Numeric), '७'));
+    }
+
     #[test]
     fn custom_single() {
         let kind = CharSetKind::Custom(vec![CharClass::Single('x')]);
@@ -1,370 +1,715 @@
-//! Pattern matcher: find matches of a [`PatternDef`] in a line of text.
+//! Pattern matcher: run a compiled [`Program`] over the lines of a frame.
 //!
-//! All positions are **character** (not byte) indices into the line.
+//! Positions are line/column [`Position`]s, with columns counted in
+//! **characters** (not bytes). A match may span line boundaries, either with
+//! an explicit `#` line-break element or by stepping over the break after `>`.
+//!
+//! The program is simulated as an NFA: every live thread advances one
+//! position at a time, at most one thread per instruction, so a search takes
+//! time linear in the length of the text searched. Threads are kept in
+//! priority order so the match found is the one a backtracking matcher would
+//! have preferred: the first alternative, and the most repetitions, first.
+//!
+//! The text is read straight from the frame's rope, one chunk at a time,
+//! without copying lines or marks.
+//!
+//! Past the end of a line the text continues as virtual space. A thread that
+//! enters virtual space cannot reach the next line, and virtual space only
+//! extends as far as the pattern could need: one column, or up to the
+//! margins, dot column or marks the pattern tests.
+
+use std::mem;
+use std::rc::Rc;
+
+use ropey::RopeSlice;
 
 use crate::marks::MarkSet;
+use crate::position::Position;
 
 use super::ast::*;
 use super::char_class::charset_matches;
+use super::program::{Inst, MIDDLE_END, MIDDLE_START, Program, group_start};
 
-/// Context provided to the matcher for a single line.
-#[derive(Debug, Clone)]
-pub struct MatchCtx {
-    /// Line content as a character vector (no trailing newline).
-    pub line: Vec<char>,
+/// Context provided to the matcher: the text of a frame and the state the
+/// pattern's positionals test, borrowed for the duration of a search.
+#[derive(Debug, Clone, Copy)]
+pub struct MatchCtx<'a> {
+    /// The frame's text. Lines end at `\n`, `\r\n` or `\r`.
+    pub text: RopeSlice<'a>,
     /// Original dot column (used for the `^` positional).
     pub dot_col: usize,
-    /// Left margin column (`{`). Currently always 0.
+    /// Left margin column (`{`).
     pub left_margin: usize,
-    /// Right margin column (`}`). Currently always `line.len()`.
+    /// Right margin column (`}`).
     pub right_margin: usize,
-    /// 0-based index of this line in the frame (for `@N` mark checks).
-    pub line_idx: usize,
-    /// All marks in the frame (cloned for immutable access during matching).
-    pub marks: MarkSet,
+    /// All marks in the frame.
+    pub marks: &'a MarkSet,
+}
+
+impl MatchCtx<'_> {
+    fn contains_line(&self, line: usize) -> bool {
+        line < self.text.len_lines()
+    }
+
+    /// Length of `line` in characters, excluding the line break.
+    fn line_len(&self, line: usize) -> usize {
+        let slice = self.text.line(line);
+        let mut len = slice.len_chars();
+        if len > 0 && slice.char(len - 1) == '\n' {
+            len -= 1;
+        }
+        if len > 0 && slice.char(len - 1) == '\r' {
+            len -= 1;
+        }
+        len
+    }
 }
 
 /// The result of a successful pattern match.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct MatchResult {
-    /// Column of the start of the middle context. Becomes the new Equals mark.
-    pub middle_start: usize,
-    /// Column of the end of the middle context. Becomes the new Dot.
-    pub middle_end: usize,
+    /// Start of the middle context. Becomes the new Equals mark.
+    pub middle_start: Position,
+    /// End of the middle context. Becomes the new Dot.
+    pub middle_end: Position,
+    /// Start and end of each group, indexed by group number less one.
+    /// `None` for a group that took no part in the match.
+    pub groups: Vec<Option<(Position, Position)>>,
 }
 
-/// Maximum number of backtracking steps before giving up.
-const MAX_STEPS: usize = 100_000;
+impl MatchResult {
+    /// Start and end of group `n`, where group 0 is the middle context.
+    pub fn group(&self, n: usize) -> Option<(Position, Position)> {
+        match n {
+            0 => Some((self.middle_start, self.middle_end)),
+            n => self.groups.get(n - 1).copied().flatten(),
+        }
+    }
+}
 
 // ─── Public API ─────────────────────────────────────────────────────────────
 
-/// Find the leftmost match at or after `start_col`.
+/// Find the leftmost match whose middle context starts at or after `start`.
 ///
 /// Used by G (forward search).
-pub fn find_forward(pattern: &PatternDef, ctx: &MatchCtx, start_col: usize) -> Option<MatchResult> {
-    let len = ctx.line.len();
-    let mut steps = 0usize;
-
-    if pattern.left.is_empty_pattern() {
-        // Optimisation: iterate mid_start directly from start_col
-        for mid_start in start_col..=len {
-            if let Some(mid_end) = match_compound(&pattern.middle, ctx, mid_start, &mut steps)
-                && match_compound(&pattern.right, ctx, mid_end, &mut steps).is_some()
-            {
-                return Some(MatchResult {
-                    middle_start: mid_start,
-                    middle_end: mid_end,
-                });
-            }
-            if steps > MAX_STEPS {
-                return None;
-            }
+pub fn find_forward(program: &Program, ctx: &MatchCtx, start: Position) -> Option<MatchResult> {
+    find_forward_partial(program, ctx, start).0
+}
+
+/// [`find_forward`] over text that may go on beyond the end of `ctx.text`,
+/// as a paged frame's does. Also says whether the search ran into the end
+/// of the text, in which case more text could change what it finds.
+pub fn find_forward_partial(
+    program: &Program,
+    ctx: &MatchCtx,
+    start: Position,
+) -> (Option<MatchResult>, bool) {
+    if !ctx.contains_line(start.line) {
+        return (None, true);
+    }
+    // Matches never start in virtual space.
+    let from = if start.column > ctx.line_len(start.line) {
+        if !ctx.contains_line(start.line + 1) {
+            return (None, true);
         }
+        Position::new(start.line + 1, 0)
     } else {
-        for outer_start in 0..=len {
-            if let Some(mid_start) = match_compound(&pattern.left, ctx, outer_start, &mut steps)
-                && mid_start >= start_col
-                && let Some(mid_end) = match_compound(&pattern.middle, ctx, mid_start, &mut steps)
-                && match_compound(&pattern.right, ctx, mid_end, &mut steps).is_some()
-            {
-                return Some(MatchResult {
-                    middle_start: mid_start,
-                    middle_end: mid_end,
-                });
-            }
-            if steps > MAX_STEPS {
-                return None;
-            }
-        }
-    }
-    None
+        start
+    };
+    let seed_from = if program.unanchored_left {
+        from
+    } else {
+        Position::new(from.line, 0)
+    };
+    let mut vm = new_vm(program, ctx, Some(start), None);
+    let found = vm.run(seed_from, Position::new(usize::MAX, usize::MAX), false);
+    (found, vm.reached_end())
 }
 
-/// Find the rightmost match where mid_start ≤ `start_col`.
+/// Find the rightmost match whose middle context starts at or before `start`.
 ///
 /// Used by G (backward search).
-pub fn find_backward(
-    pattern: &PatternDef,
-    ctx: &MatchCtx,
-    start_col: usize,
-) -> Option<MatchResult> {
-    let len = ctx.line.len();
-    let upper = start_col.min(len);
-    let mut last_match = None;
-    let mut steps = 0usize;
-
-    if pattern.left.is_empty_pattern() {
-        for mid_start in 0..=upper {
-            if let Some(mid_end) = match_compound(&pattern.middle, ctx, mid_start, &mut steps)
-                && match_compound(&pattern.right, ctx, mid_end, &mut steps).is_some()
-            {
-                last_match = Some(MatchResult {
-                    middle_start: mid_start,
-                    middle_end: mid_end,
-                });
-            }
-            if steps > MAX_STEPS {
-                break;
-            }
-        }
+pub fn find_backward(program: &Program, ctx: &MatchCtx, start: Position) -> Option<MatchResult> {
+    let line = start.line.min(ctx.text.len_lines() - 1);
+    let upper = if line == start.line {
+        Position::new(line, start.column.min(ctx.line_len(line)))
     } else {
-        for outer_start in 0..=upper {
-            if let Some(mid_start) = match_compound(&pattern.left, ctx, outer_start, &mut steps)
-                && mid_start <= upper
-                && let Some(mid_end) = match_compound(&pattern.middle, ctx, mid_start, &mut steps)
-                && match_compound(&pattern.right, ctx, mid_end, &mut steps).is_some()
-            {
-                last_match = Some(MatchResult {
-                    middle_start: mid_start,
-                    middle_end: mid_end,
-                });
-            }
-            if steps > MAX_STEPS {
-                break;
-            }
-        }
-    }
-    last_match
+        Position::new(line, ctx.line_len(line))
+    };
+    let mut vm = new_vm(program, ctx, None, Some(upper));
+    search_back(vm.as_mut(), upper)
 }
 
-/// Test whether a match begins exactly at `col`.
+/// Test whether a match begins exactly at `at`.
 ///
 /// Used by EQS (equality predicate).
-pub fn match_at(pattern: &PatternDef, ctx: &MatchCtx, col: usize) -> Option<MatchResult> {
-    let mut steps = 0usize;
-
-    // Left context must end at col
-    if !pattern.left.is_empty_pattern() {
-        let left_ok = (0..=col).any(|start| {
-            match_compound(&pattern.left, ctx, start, &mut steps)
-                .map(|end| end == col)
-                .unwrap_or(false)
-        });
-        if !left_ok {
+pub fn match_at(program: &Program, ctx: &MatchCtx, at: Position) -> Option<MatchResult> {
+    match_at_partial(program, ctx, at).0
+}
+
+/// [`match_at`] over text that may go on beyond the end of `ctx.text`, also
+/// saying whether the match ran into the end of the text.
+pub fn match_at_partial(
+    program: &Program,
+    ctx: &MatchCtx,
+    at: Position,
+) -> (Option<MatchResult>, bool) {
+    if !ctx.contains_line(at.line) {
+        return (None, true);
+    }
+    let mut vm = new_vm(program, ctx, Some(at), Some(at));
+    let found = if program.unanchored_left {
+        vm.run(at, at, true)
+    } else {
+        // The left context must end at `at`; it may start on an earlier line.
+        search_back(vm.as_mut(), at)
+    };
+    (found, vm.reached_end())
+}
+
+/// Find the match with the latest starting point at or before `upper`.
+///
+/// Starting points are tried a window of lines at a time, nearest first, so
+/// that a match near `upper` is found without running over the whole text
+/// before it. Each window is twice the size of the last, so a match running
+/// on over many lines is only scanned again a logarithmic number of times.
+fn search_back(vm: &mut dyn Run, upper: Position) -> Option<MatchResult> {
+    let mut last = upper.line;
+    let mut size = 1;
+    loop {
+        let first = last.saturating_sub(size - 1);
+        let seed_to = if last == upper.line {
+            upper
+        } else {
+            Position::new(last, usize::MAX)
+        };
+        if let Some(found) = vm.run(Position::new(first, 0), seed_to, true) {
+            return Some(found);
+        }
+        if first == 0 {
             return None;
         }
+        last = first - 1;
+        size = size.saturating_mul(2);
     }
+}
+
+// ─── NFA simulation ──────────────────────────────────────────────────────────
+
+/// A [`Vm`] whose slot type has been chosen to suit its program.
+trait Run {
+    fn run(&mut self, from: Position, seed_to: Position, latest_first: bool)
+    -> Option<MatchResult>;
 
-    let mid_end = match_compound(&pattern.middle, ctx, col, &mut steps)?;
-    match_compound(&pattern.right, ctx, mid_end, &mut steps)?;
-    Some(MatchResult {
-        middle_start: col,
-        middle_end: mid_end,
-    })
+    /// Whether any run so far had a thread still going at the end of the text.
+    fn reached_end(&self) -> bool;
 }
 
-// ─── Core matching functions ─────────────────────────────────────────────────
+impl<S: Slots> Run for Vm<'_, '_, S> {
+    fn run(
+        &mut self,
+        from: Position,
+        seed_to: Position,
+        latest_first: bool,
+    ) -> Option<MatchResult> {
+        Vm::run(self, from, seed_to, latest_first)
+    }
 
-/// Match a compound (alternation) starting at `pos`.  Returns the end position
-/// of the first successful alternative, or `None`.
-///
-/// An empty compound (no alternatives) always succeeds, returning `Some(pos)`.
-fn match_compound(
-    compound: &Compound,
-    ctx: &MatchCtx,
-    pos: usize,
-    steps: &mut usize,
-) -> Option<usize> {
-    if compound.alternatives.is_empty() {
-        return Some(pos); // empty compound = always match
-    }
-    for seq in &compound.alternatives {
-        if let Some(end) = match_items_backtrack(&seq.items, ctx, pos, steps) {
-            return Some(end);
-        }
+    fn reached_end(&self) -> bool {
+        self.reached_end
     }
-    None
 }
 
-/// Backtracking sequence matcher.
-///
-/// Tries each item in order, using greedy-first positions for quantified items.
-/// Backtracks to earlier items when a later item fails to match.
-fn match_items_backtrack(
-    items: &[Item],
-    ctx: &MatchCtx,
-    pos: usize,
-    steps: &mut usize,
-) -> Option<usize> {
-    *steps += 1;
-    if *steps > MAX_STEPS {
-        return None;
-    }
-    if items.is_empty() {
-        return Some(pos);
-    }
-    let item = &items[0];
-    let rest = &items[1..];
-    for end in item_positions(item, ctx, pos, steps) {
-        if let Some(result) = match_items_backtrack(rest, ctx, end, steps) {
-            return Some(result);
-        }
+/// Make a [`Vm`] for `program`. Only a program with groups pays for
+/// recording them.
+fn new_vm<'a>(
+    program: &'a Program,
+    ctx: &'a MatchCtx<'a>,
+    lo: Option<Position>,
+    hi: Option<Position>,
+) -> Box<dyn Run + 'a> {
+    if program.group_count() == 0 {
+        Box::new(Vm::<MiddleSlots>::new(program, ctx, lo, hi))
+    } else {
+        Box::new(Vm::<GroupSlots>::new(program, ctx, lo, hi))
     }
-    None
 }
 
-enum ItemIter {
-    Single(std::option::IntoIter<usize>),
-    Greedy(std::iter::Rev<std::vec::IntoIter<usize>>),
+/// The positions a thread has saved.
+trait Slots: Clone + Default {
+    fn save(&mut self, slot: usize, pos: Position);
+    fn middle(&self) -> MiddleSlots;
+    /// The group saves, newest first.
+    fn groups(&self) -> Option<&Save>;
 }
 
-impl Iterator for ItemIter {
-    type Item = usize;
+/// The slots of a pattern without groups, which are cheap to copy.
+type MiddleSlots = [Option<Position>; 2];
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match self {
-            ItemIter::Single(iter) => iter.next(),
-            ItemIter::Greedy(iter) => iter.next(),
-        }
+impl Slots for MiddleSlots {
+    fn save(&mut self, slot: usize, pos: Position) {
+        self[slot] = Some(pos);
+    }
+
+    fn middle(&self) -> MiddleSlots {
+        *self
+    }
+
+    fn groups(&self) -> Option<&Save> {
+        None
     }
 }
 
-/// Return candidate end positions for `item` starting at `pos`, in greedy-first
-/// order (most characters consumed first).
-fn item_positions(item: &Item, ctx: &MatchCtx, pos: usize, steps: &mut usize) -> ItemIter {
-    match &item.quantifier {
-        Quantifier::Once => {
-            ItemIter::Single(match_element_once(&item.element, ctx, pos, steps).into_iter())
-        }
-        Quantifier::ZeroOrMore => ItemIter::Greedy(greedy_positions(
-            &item.element,
-            ctx,
-            pos,
-            0,
-            ctx.line.len() + 2,
-            steps,
-        )),
-        Quantifier::OneOrMore => ItemIter::Greedy(greedy_positions(
-            &item.element,
-            ctx,
-            pos,
-            1,
-            ctx.line.len() + 2,
-            steps,
-        )),
-        Quantifier::Exactly(n) => {
-            ItemIter::Single(exact_matches(&item.element, ctx, pos, *n, steps).into_iter())
+/// The slots of a pattern with groups. Group saves are kept in a list shared
+/// between threads, so splitting a thread never copies them.
+#[derive(Debug, Clone, Default)]
+struct GroupSlots {
+    middle: MiddleSlots,
+    groups: Option<Rc<Save>>,
+}
+
+#[derive(Debug)]
+struct Save {
+    slot: usize,
+    pos: Position,
+    prev: Option<Rc<Save>>,
+}
+
+impl Drop for Save {
+    // Dropping the list recursively would overflow the stack for a group
+    // repeated many thousands of times, so unlink it one save at a time.
+    fn drop(&mut self) {
+        let mut prev = self.prev.take();
+        while let Some(save) = prev {
+            prev = match Rc::try_unwrap(save) {
+                Ok(mut save) => save.prev.take(),
+                Err(_) => None,
+            };
         }
-        Quantifier::AtLeast(n) => ItemIter::Greedy(greedy_positions(
-            &item.element,
-            ctx,
-            pos,
-            *n,
-            ctx.line.len() + 2,
-            steps,
-        )),
-        Quantifier::Between(lo, hi) => {
-            ItemIter::Greedy(greedy_positions(&item.element, ctx, pos, *lo, *hi, steps))
+    }
+}
+
+impl Slots for GroupSlots {
+    fn save(&mut self, slot: usize, pos: Position) {
+        if slot < self.middle.len() {
+            self.middle[slot] = Some(pos);
+        } else {
+            let prev = self.groups.take();
+            self.groups = Some(Rc::new(Save { slot, pos, prev }));
         }
     }
+
+    fn middle(&self) -> MiddleSlots {
+        self.middle
+    }
+
+    fn groups(&self) -> Option<&Save> {
+        self.groups.as_deref()
+    }
 }
 
-/// Collect end positions for `min..max` repetitions of `element` starting at
-/// `pos`, then reverse to yield the greediest (most chars) first.
-fn greedy_positions(
-    element: &Element,
-    ctx: &MatchCtx,
-    start: usize,
-    min: usize,
-    max: usize,
-    steps: &mut usize,
-) -> std::iter::Rev<std::vec::IntoIter<usize>> {
-    let mut positions = Vec::new();
-    let mut cur = start;
-    let mut count = 0usize;
+#[derive(Debug, Clone)]
+struct Thread<S> {
+    pc: usize,
+    slots: S,
+    /// Priority, at the end of a line, of the thread this one descends from.
+    /// Used to rank matches found in virtual space against threads that
+    /// carried on to the next line.
+    rank: usize,
+}
 
-    loop {
-        if count >= min {
-            positions.push(cur);
+/// The threads at one position, in priority order, at most one per instruction.
+struct ThreadList<S> {
+    threads: Vec<Thread<S>>,
+    seen: Vec<bool>,
+    visited: Vec<usize>,
+}
+
+impl<S: Slots> ThreadList<S> {
+    fn new(size: usize) -> Self {
+        Self {
+            threads: Vec::new(),
+            seen: vec![false; size],
+            visited: Vec::new(),
         }
-        if count >= max {
-            break;
+    }
+
+    /// Mark `pc` as reached. Returns false if it already was.
+    fn visit(&mut self, pc: usize) -> bool {
+        if mem::replace(&mut self.seen[pc], true) {
+            return false;
         }
-        match match_element_once(element, ctx, cur, steps) {
-            Some(next) if next != cur => {
-                cur = next;
-                count += 1;
-            }
-            _ => break, // no progress (zero-width infinite loop guard) or no match
+        self.visited.push(pc);
+        true
+    }
+
+    fn clear(&mut self) {
+        for pc in self.visited.drain(..) {
+            self.seen[pc] = false;
         }
+        self.threads.clear();
+    }
+
+    /// Drop threads that have not yet reached the middle context.
+    fn retain_started(&mut self) {
+        self.threads
+            .retain(|t| t.slots.middle()[MIDDLE_START].is_some());
     }
-    positions.into_iter().rev() // greedy-first = most-first
 }
 
-fn exact_matches(
-    element: &Element,
-    ctx: &MatchCtx,
-    start: usize,
-    n: usize,
-    steps: &mut usize,
-) -> Option<usize> {
-    let mut cur = start;
-    for _ in 0..n {
-        cur = match_element_once(element, ctx, cur, steps)?;
-    }
-    Some(cur)
+/// A position in the text, and whether it is at the end of its line.
+#[derive(Debug, Clone, Copy)]
+struct Here {
+    pos: Position,
+    at_eol: bool,
 }
 
-// ─── Element matching ────────────────────────────────────────────────────────
+fn is_line_break(ch: Option<char>) -> bool {
+    matches!(ch, None | Some('\n' | '\r'))
+}
 
-/// Try to match `element` at position `pos` (in chars).
-/// Returns the new position if successful, or `None`.
-fn match_element_once(
-    element: &Element,
-    ctx: &MatchCtx,
-    pos: usize,
-    steps: &mut usize,
-) -> Option<usize> {
-    match element {
-        Element::CharSet(cs) => {
-            let ch = char_at(&ctx.line, pos);
-            if charset_matches(cs, ch) {
-                Some(pos + 1)
-            } else {
-                None
+struct Vm<'a, 'p, S> {
+    program: &'a Program<'p>,
+    ctx: &'a MatchCtx<'a>,
+    /// Bounds on where the middle context may start.
+    lo: Option<Position>,
+    hi: Option<Position>,
+    /// Thread lists reused from run to run.
+    clist: ThreadList<S>,
+    nlist: ThreadList<S>,
+    vlist: ThreadList<S>,
+    vnext: ThreadList<S>,
+    stack: Vec<(usize, S)>,
+    /// Set once a thread is still going at the end of the text.
+    reached_end: bool,
+}
+
+impl<'a, 'p, S: Slots> Vm<'a, 'p, S> {
+    fn new(
+        program: &'a Program<'p>,
+        ctx: &'a MatchCtx<'a>,
+        lo: Option<Position>,
+        hi: Option<Position>,
+    ) -> Self {
+        let size = program.insts.len();
+        Self {
+            program,
+            ctx,
+            lo,
+            hi,
+            clist: ThreadList::new(size),
+            nlist: ThreadList::new(size),
+            vlist: ThreadList::new(size),
+            vnext: ThreadList::new(size),
+            stack: Vec::new(),
+            reached_end: false,
+        }
+    }
+
+    /// Run the program from `from`, starting a new thread at each real
+    /// position up to `seed_to`.
+    ///
+    /// With `latest_first`, later starting points take priority over earlier
+    /// ones, giving the rightmost match; otherwise the leftmost match wins
+    /// and no new threads are started once one is found.
+    fn run(
+        &mut self,
+        from: Position,
+        seed_to: Position,
+        latest_first: bool,
+    ) -> Option<MatchResult> {
+        let text = self.ctx.text;
+        let mut clist = mem::replace(&mut self.clist, ThreadList::new(0));
+        let mut nlist = mem::replace(&mut self.nlist, ThreadList::new(0));
+        clist.clear();
+        let mut best: Option<S> = None;
+        let mut pos = from;
+
+        let line_start = text.line_to_char(from.line);
+        let len = self.ctx.line_len(from.line);
+        if pos.column > len {
+            // Starting in virtual space, as EQS may with dot past the end of a line.
+            if from.line + 1 >= text.len_lines() {
+                self.reached_end = true;
             }
+            let mut vlist = mem::replace(&mut self.vlist, ThreadList::new(0));
+            vlist.clear();
+            let here = Here { pos, at_eol: false };
+            self.add(&mut vlist, 0, here, S::default(), 0);
+            let found = self.run_virtual(&mut vlist, pos, len);
+            self.vlist = vlist;
+            self.clist = clist;
+            self.nlist = nlist;
+            return found.and_then(|(slots, _)| self.result(slots));
         }
-        Element::Literal { text, case_fold } => {
-            let mut cur = pos;
-            for pat_ch in text.chars() {
-                let line_ch = char_at(&ctx.line, cur);
-                let matched = if *case_fold {
-                    line_ch.eq_ignore_ascii_case(&pat_ch)
+
+        let mut chars = text.chars_at(line_start + pos.column);
+        let mut cur = chars.next();
+        let found = loop {
+            let here = Here {
+                pos,
+                at_eol: is_line_break(cur),
+            };
+
+            if pos <= seed_to && (latest_first || best.is_none()) {
+                if latest_first {
+                    nlist.clear();
+                    self.add(&mut nlist, 0, here, S::default(), 0);
+                    for t in &clist.threads {
+                        if nlist.visit(t.pc) {
+                            nlist.threads.push(t.clone());
+                        }
+                    }
+                    mem::swap(&mut clist, &mut nlist);
                 } else {
-                    line_ch == pat_ch
+                    self.add(&mut clist, 0, here, S::default(), 0);
+                }
+            }
+            if self.hi.is_some_and(|hi| pos > hi) {
+                clist.retain_started();
+            }
+            if cur.is_none() && !clist.threads.is_empty() {
+                self.reached_end = true;
+            }
+            if clist.threads.is_empty() && (pos >= seed_to || best.is_some() && !latest_first) {
+                break best;
+            }
+
+            nlist.clear();
+            if !here.at_eol {
+                let ch = cur.unwrap_or(' ');
+                cur = chars.next();
+                let next = Here {
+                    pos: Position::new(pos.line, pos.column + 1),
+                    at_eol: is_line_break(cur),
                 };
-                if !matched {
-                    return None;
+                for t in &clist.threads {
+                    match &self.program.insts[t.pc] {
+                        Inst::Match => {
+                            // Lower priority threads can't give a better match.
+                            best = Some(t.slots.clone());
+                            break;
+                        }
+                        inst if consumes(inst, ch) => {
+                            self.add(&mut nlist, t.pc + 1, next, t.slots.clone(), 0)
+                        }
+                        _ => {}
+                    }
                 }
-                cur += 1;
+                mem::swap(&mut clist, &mut nlist);
+                pos = next.pos;
+                continue;
             }
-            Some(cur)
-        }
-        Element::Group(compound) => match_compound(compound, ctx, pos, steps),
-        Element::Positional(p) => {
-            let ok = match p {
-                Positional::BegLine => pos == 0,
-                Positional::EndLine => pos == ctx.line.len(),
-                Positional::LeftMargin => pos == ctx.left_margin,
-                Positional::RightMargin => pos == ctx.right_margin,
-                Positional::DotCol => pos == ctx.dot_col,
+
+            // End of line: threads either go on into virtual space or cross
+            // the line break.
+            let mut vlist = mem::replace(&mut self.vlist, ThreadList::new(0));
+            vlist.clear();
+            let mut breaks = Vec::new();
+            let virtual_pos = Position::new(pos.line, pos.column + 1);
+            let virtual_here = Here {
+                pos: virtual_pos,
+                at_eol: false,
+            };
+            for (rank, t) in clist.threads.iter().enumerate() {
+                match &self.program.insts[t.pc] {
+                    Inst::Match => {
+                        best = Some(t.slots.clone());
+                        break;
+                    }
+                    Inst::LineBreak => breaks.push(Thread { rank, ..t.clone() }),
+                    inst if consumes(inst, ' ') => {
+                        self.add(&mut vlist, t.pc + 1, virtual_here, t.slots.clone(), rank)
+                    }
+                    _ => {}
+                }
+            }
+            if let Some((slots, rank)) = self.run_virtual(&mut vlist, virtual_pos, pos.column) {
+                best = Some(slots);
+                breaks.retain(|t| t.rank < rank);
+            }
+            self.vlist = vlist;
+
+            let Some(line_break) = cur else {
+                break best;
+            };
+            cur = chars.next();
+            if line_break == '\r' && cur == Some('\n') {
+                cur = chars.next();
+            }
+            let next = Here {
+                pos: Position::new(pos.line + 1, 0),
+                at_eol: is_line_break(cur),
+            };
+            for t in breaks {
+                self.add(&mut nlist, t.pc + 1, next, t.slots, 0);
+            }
+            mem::swap(&mut clist, &mut nlist);
+            pos = next.pos;
+        };
+        self.clist = clist;
+        self.nlist = nlist;
+        found.and_then(|slots| self.result(slots))
+    }
+
+    /// Run the threads in `list`, which are in virtual space at `pos` on a
+    /// line of length `len`, to the end of the virtual space on that line.
+    ///
+    /// Returns the best match found and the rank of the thread it came from.
+    fn run_virtual(
+        &mut self,
+        list: &mut ThreadList<S>,
+        mut pos: Position,
+        len: usize,
+    ) -> Option<(S, usize)> {
+        let end = self.virtual_end(pos.line, len);
+        let mut next_list = mem::replace(&mut self.vnext, ThreadList::new(0));
+        let mut best = None;
+        loop {
+            if self.hi.is_some_and(|hi| pos > hi) {
+                list.retain_started();
+            }
+            if list.threads.is_empty() {
+                break;
+            }
+            next_list.clear();
+            let next = Here {
+                pos: Position::new(pos.line, pos.column + 1),
+                at_eol: false,
             };
-            if ok { Some(pos) } else { None }
+            for t in &list.threads {
+                match &self.program.insts[t.pc] {
+                    Inst::Match => {
+                        best = Some((t.slots.clone(), t.rank));
+                        break;
+                    }
+                    inst if pos.column < end && consumes(inst, ' ') => {
+                        self.add(&mut next_list, t.pc + 1, next, t.slots.clone(), t.rank)
+                    }
+                    _ => {}
+                }
+            }
+            mem::swap(list, &mut next_list);
+            pos = next.pos;
         }
-        Element::MarkCheck(id) => {
-            let mark_pos = ctx.marks.get(*id)?;
-            if mark_pos.line == ctx.line_idx && mark_pos.column == pos {
-                Some(pos)
-            } else {
-                None
+        self.vnext = next_list;
+        best
+    }
+
+    /// The last column of virtual space on a `line` of length `len` that the
+    /// pattern could need.
+    fn virtual_end(&self, line: usize, len: usize) -> usize {
+        let (program, ctx) = (self.program, self.ctx);
+        let mut end = len + 1;
+        if program.uses_dot_col {
+            end = end.max(ctx.dot_col);
+        }
+        if program.uses_left_margin {
+            end = end.max(ctx.left_margin);
+        }
+        if program.uses_right_margin {
+            end = end.max(ctx.right_margin);
+        }
+        let marks = program.marks.iter().filter_map(|id| ctx.marks.get(*id));
+        let bounds = [self.lo, self.hi].into_iter().flatten();
+        for pos in marks.chain(bounds.map(|b| Position::new(b.line, b.column + 1))) {
+            if pos.line == line {
+                end = end.max(pos.column);
             }
         }
+        end
+    }
+
+    /// Follow the zero-width instructions from `pc` at `here`, adding a thread
+    /// to `list` for each instruction reached that consumes input or matches.
+    fn add(&mut self, list: &mut ThreadList<S>, pc: usize, here: Here, slots: S, rank: usize) {
+        let pos = here.pos;
+        self.stack.push((pc, slots));
+        while let Some((pc, mut slots)) = self.stack.pop() {
+            if !list.visit(pc) {
+                continue;
+            }
+            match &self.program.insts[pc] {
+                Inst::Jmp(target) => self.stack.push((*target, slots)),
+                Inst::Split(first, second) => {
+                    // Pushed in reverse so the preferred branch is followed first.
+                    self.stack.push((*second, slots.clone()));
+                    self.stack.push((*first, slots));
+                }
+                Inst::Assert(positional) => {
+                    if self.assert(positional, here) {
+                        self.stack.push((pc + 1, slots));
+                    }
+                }
+                Inst::Mark(id) => {
+                    if self.ctx.marks.get(*id) == Some(pos) {
+                        self.stack.push((pc + 1, slots));
+                    }
+                }
+                Inst::Save(slot) => {
+                    if *slot == MIDDLE_START
+                        && (self.lo.is_some_and(|lo| pos < lo)
+                            || self.hi.is_some_and(|hi| pos > hi))
+                    {
+                        continue;
+                    }
+                    slots.save(*slot, pos);
+                    self.stack.push((pc + 1, slots));
+                }
+                Inst::Set(_) | Inst::Char { .. } | Inst::LineBreak | Inst::Match => {
+                    list.threads.push(Thread { pc, slots, rank });
+                }
+            }
+        }
+    }
+
+    fn result(&self, slots: S) -> Option<MatchResult> {
+        let mut saved = vec![None; group_start(self.program.group_count() + 1)];
+        let mut save = slots.groups();
+        while let Some(Save { slot, pos, prev }) = save {
+            // The newest save to a slot is the one that counts.
+            saved[*slot].get_or_insert(*pos);
+            save = prev.as_deref();
+        }
+        let groups = (1..=self.program.group_count())
+            .map(|n| Some((saved[group_start(n)]?, saved[group_start(n) + 1]?)))
+            .collect();
+        let middle = slots.middle();
+        Some(MatchResult {
+            middle_start: middle[MIDDLE_START]?,
+            middle_end: middle[MIDDLE_END]?,
+            groups,
+        })
+    }
+
+    fn assert(&self, positional: &Positional, here: Here) -> bool {
+        let (ctx, column) = (self.ctx, here.pos.column);
+        match positional {
+            Positional::BegLine => column == 0,
+            Positional::EndLine => here.at_eol,
+            Positional::LeftMargin => column == ctx.left_margin,
+            Positional::RightMargin => column == ctx.right_margin,
+            Positional::DotCol => column == ctx.dot_col,
+        }
     }
 }
 
-/// Get the character at position `pos`, returning virtual space `' '` at EOL.
-fn char_at(line: &[char], pos: usize) -> char {
-    line.get(pos).copied().unwrap_or(' ')
+/// True if `inst` consumes the character `ch`.
+fn consumes(inst: &Inst, ch: char) -> bool {
+    match inst {
+        Inst::Set(cs) => charset_matches(cs, ch),
+        Inst::Char {
+            ch: pat_ch,
+            case_fold,
+        } => {
+            if *case_fold {
+                ch.eq_ignore_ascii_case(pat_ch)
+            } else {
+                ch == *pat_ch
+            }
+        }
+        _ => false,
+    }
 }
 
 // ─── Tests ───────────────────────────────────────────────────────────────────
@@ -372,38 +717,74 @@ fn char_at(line: &[char], pos: usize) -> char {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::marks::MarkSet;
     use crate::pattern::parser::parse;
+    use crate::pattern::program::compile;
+    use ropey::Rope;
+
+    /// Text and marks for a [`MatchCtx`] to borrow.
+    struct Text {
+        rope: Rope,
+        marks: MarkSet,
+    }
 
-    fn ctx(line: &str) -> MatchCtx {
-        let chars: Vec<char> = line.chars().collect();
-        let len = chars.len();
-        MatchCtx {
-            line: chars,
-            dot_col: 0,
-            left_margin: 0,
-            right_margin: len,
-            line_idx: 0,
-            marks: MarkSet::new(),
+    impl Text {
+        fn new(text: &str) -> Self {
+            Text {
+                rope: Rope::from_str(text),
+                marks: MarkSet::new(),
+            }
+        }
+
+        fn ctx(&self) -> MatchCtx<'_> {
+            MatchCtx {
+                text: self.rope.slice(..),
+                dot_col: 0,
+                left_margin: 0,
+                right_margin: self.rope.line(0).len_chars(),
+                marks: &self.marks,
+            }
         }
     }
 
     fn fwd(pattern_str: &str, line: &str, start: usize) -> Option<(usize, usize)> {
         let p = parse(pattern_str).unwrap();
-        let c = ctx(line);
-        find_forward(&p, &c, start).map(|m| (m.middle_start, m.middle_end))
+        let p = compile(&p);
+        let t = Text::new(line);
+        let c = t.ctx();
+        find_forward(&p, &c, Position::new(0, start))
+            .map(|m| (m.middle_start.column, m.middle_end.column))
     }
 
     fn bwd(pattern_str: &str, line: &str, start: usize) -> Option<(usize, usize)> {
         let p = parse(pattern_str).unwrap();
-        let c = ctx(line);
-        find_backward(&p, &c, start).map(|m| (m.middle_start, m.middle_end))
+        let p = compile(&p);
+        let t = Text::new(line);
+        let c = t.ctx();
+        find_backward(&p, &c, Position::new(0, start))
+            .map(|m| (m.middle_start.column, m.middle_end.column))
     }
 
     fn mat(pattern_str: &str, line: &str, col: usize) -> Option<(usize, usize)> {
         let p = parse(pattern_str).unwrap();
-        let c = ctx(line);
-        match_at(&p, &c, col).map(|m| (m.middle_start, m.middle_end))
+        let p = compile(&p);
+        let t = Text::new(line);
+        let c = t.ctx();
+        match_at(&p, &c, Position::new(0, col))
+            .map(|m| (m.middle_start.column, m.middle_end.column))
+    }
+
+    /// Forward search over several lines, returning `((line, col), (line, col))`.
+    fn fwd_lines(pattern_str: &str, text: &str) -> Option<((usize, usize), (usize, usize))> {
+        let p = parse(pattern_str).unwrap();
+        let p = compile(&p);
+        let t = Text::new(text);
+        let c = t.ctx();
+        find_forward(&p, &c, Position::zero()).map(|m| {
+            (
+                (m.middle_start.line, m.middle_start.column),
+                (m.middle_end.line, m.middle_end.column),
+            )
+        })
     }
 
     // --- Literals ---
@@ -504,8 +885,13 @@ mod tests {
     fn end_line_positional() {
         // >  matches only at end of line
         let p = parse(">").unwrap();
-        let c = ctx("ab");
-        assert_eq!(find_forward(&p, &c, 0).map(|m| m.middle_start), Some(2));
+        let p = compile(&p);
+        let t = Text::new("ab");
+        let c = t.ctx();
+        assert_eq!(
+            find_forward(&p, &c, Position::zero()).map(|m| m.middle_start.column),
+            Some(2)
+        );
     }
 
     // --- Context patterns ---
@@ -571,9 +957,182 @@ mod tests {
     fn virtual_space_at_eol() {
         // S should match the virtual space at end of "abc"
         let p = parse("S").unwrap();
-        let c = ctx("abc");
+        let p = compile(&p);
+        let t = Text::new("abc");
+        let c = t.ctx();
         // Position 3 is end-of-line (virtual space)
-        let result = find_forward(&p, &c, 3).map(|m| (m.middle_start, m.middle_end));
+        let result = find_forward(&p, &c, Position::new(0, 3))
+            .map(|m| (m.middle_start.column, m.middle_end.column));
         assert_eq!(result, Some((3, 4)));
     }
+
+    // --- Multi-line matching ---
+
+    #[test]
+    fn line_break_element() {
+        assert_eq!(
+            fwd_lines("'end'#'begin'", "x end\nbegin"),
+            Some(((0, 2), (1, 5)))
+        );
+    }
+
+    #[test]
+    fn end_line_steps_to_next_line() {
+        // The middle is the line break between the two lines.
+        assert_eq!(
+            fwd_lines("'end',>,<'begin'", "if end\nbegin x"),
+            Some(((0, 6), (1, 0)))
+        );
+    }
+
+    #[test]
+    fn end_line_alone_does_not_step() {
+        assert_eq!(fwd_lines("'end'>", "end\nbegin"), Some(((0, 0), (0, 3))));
+    }
+
+    #[test]
+    fn match_needs_following_line() {
+        assert_eq!(fwd_lines("'end',>,<'begin'", "end\nx begin"), None);
+    }
+
+    #[test]
+    fn backward_across_lines() {
+        let p = parse("'a'#'b'").unwrap();
+        let p = compile(&p);
+        let t = Text::new("a\nb\na\nb");
+        let c = t.ctx();
+        let m = find_backward(&p, &c, Position::new(3, 0)).unwrap();
+        assert_eq!(m.middle_start, Position::new(2, 0));
+        assert_eq!(m.middle_end, Position::new(3, 1));
+    }
+
+    #[test]
+    fn partial_search_says_when_it_reached_the_end() {
+        let t = Text::new("ab\nc\n");
+        let c = t.ctx();
+        let reached = |pattern: &str| {
+            let p = parse(pattern).unwrap();
+            let p = compile(&p);
+            let (found, reached) = find_forward_partial(&p, &c, Position::zero());
+            (found.map(|m| m.middle_start.column), reached)
+        };
+        assert_eq!(reached("'b'"), (Some(1), false));
+        assert_eq!(reached("'x'"), (None, true));
+        // A match starting earlier might yet be found further on.
+        assert_eq!(reached("'a'*(C|#)'z'|'b'"), (Some(1), true));
+        // An empty line at the end of the text may be the start of one more.
+        assert_eq!(reached("<>"), (Some(0), true));
+    }
+
+    #[test]
+    fn backward_finds_long_match_far_behind() {
+        // Scanning the match again from every line in between would take
+        // far too long.
+        let p = parse("'s'*(#|C)'e'").unwrap();
+        let p = compile(&p);
+        let t = Text::new(&format!("s\n{}e", "x\n".repeat(20_000)));
+        let c = t.ctx();
+        let m = find_backward(&p, &c, Position::new(20_001, 1)).unwrap();
+        assert_eq!(m.middle_start, Position::new(0, 0));
+        assert_eq!(m.middle_end, Position::new(20_001, 1));
+    }
+
+    #[test]
+    fn margins_are_positionals() {
+        let p = parse("{A").unwrap();
+        let p = compile(&p);
+        let t = Text::new("ab  cd");
+        let mut c = t.ctx();
+        c.left_margin = 4;
+        c.right_margin = 5;
+        let m = find_forward(&p, &c, Position::zero()).unwrap();
+        assert_eq!(m.middle_start.column, 4);
+        let p = parse("A}").unwrap();
+        let p = compile(&p);
+        let m = find_forward(&p, &c, Position::zero()).unwrap();
+        assert_eq!(m.middle_start.column, 4);
+    }
+
+    // --- Automaton ---
+
+    #[test]
+    fn long_line_is_searched_to_the_end() {
+        // Far more starting points than the old backtracking step limit allowed.
+        let line = format!("{}y", "x".repeat(300_000));
+        assert_eq!(fwd("\"y\"", &line, 0), Some((300_000, 300_001)));
+        assert_eq!(fwd("*\"x\"\"z\"", &line, 0), None);
+    }
+
+    #[test]
+    fn group_alternatives_are_retried() {
+        // The first alternative of the group matches, but only the second
+        // lets the rest of the pattern match.
+        assert_eq!(fwd("(\"ab\"|\"a\")\"bc\"", "abc", 0), Some((0, 3)));
+    }
+
+    #[test]
+    fn mark_check_matches_mark_position() {
+        let p = parse("@1A").unwrap();
+        let p = compile(&p);
+        let mut t = Text::new("abc\ndef");
+        t.marks
+            .set(crate::marks::MarkId::Numbered(1), Position::new(1, 1));
+        let c = t.ctx();
+        let m = find_forward(&p, &c, Position::zero()).unwrap();
+        assert_eq!(m.middle_start, Position::new(1, 1));
+        assert_eq!(m.middle_end, Position::new(1, 2));
+    }
+
+    #[test]
+    fn virtual_space_reaches_right_margin() {
+        let p = parse("\"c\"*S}").unwrap();
+        let p = compile(&p);
+        let t = Text::new("abc");
+        let mut c = t.ctx();
+        c.right_margin = 10;
+        let m = find_forward(&p, &c, Position::zero()).unwrap();
+        assert_eq!(m.middle_end, Position::new(0, 10));
+    }
+
+    // --- Groups ---
+
+    fn groups(pattern_str: &str, line: &str) -> Vec<Option<(usize, usize)>> {
+        let p = parse(pattern_str).unwrap();
+        let p = compile(&p);
+        let t = Text::new(line);
+        let c = t.ctx();
+        let m = find_forward(&p, &c, Position::zero()).unwrap();
+        (0..=p.group_count())
+            .map(|n| m.group(n).map(|(s, e)| (s.column, e.column)))
+            .collect()
+    }
+
+    #[test]
+    fn groups_record_their_text() {
+        assert_eq!(
+            groups("(+A)\",\"(+N)", "id abc,42"),
+            vec![Some((3, 9)), Some((3, 6)), Some((7, 9))]
+        );
+    }
+
+    #[test]
+    fn groups_in_contexts_are_recorded() {
+        assert_eq!(
+            groups("(A),N,(A)", "a1b"),
+            vec![Some((1, 2)), Some((0, 1)), Some((2, 3))]
+        );
+    }
+
+    #[test]
+    fn repeated_group_records_last_repetition() {
+        assert_eq!(groups("+(N)", "123"), vec![Some((0, 3)), Some((2, 3))]);
+    }
+
+    #[test]
+    fn unmatched_group_is_none() {
+        assert_eq!(
+            groups("(\"x\")|(\"y\")", "y"),
+            vec![Some((0, 1)), None, Some((0, 1))]
+        );
+    }
 }
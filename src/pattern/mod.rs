@@ -15,10 +15,11 @@
 //! | `P`           | One punctuation character                      |
 //! | `C`           | One printable character (0x20–0x7E)            |
 //! | `D/…/`        | Custom character set                           |
+//! | `X/name/`     | Unicode property set, e.g. `X/Lu/`, `X/Greek/` |
 //! | `-X`          | Negated character set                          |
 //! | `"text"`      | Literal (exact case)                           |
 //! | `'text'`      | Literal (case-folded)                          |
-//! | `(…)`         | Grouping                                       |
+//! | `(…)`         | Grouping; numbered for use in replacements     |
 //! | `X\|Y`        | Alternation                                    |
 //! | `*X`          | Zero or more                                   |
 //! | `+X`          | One or more                                    |
@@ -27,18 +28,44 @@
 //! | `[n,]X`       | At least n                                     |
 //! | `[n,m]X`      | Between n and m                                |
 //! | `<`           | Beginning of line positional                   |
-//! | `>`           | End of line positional                         |
+//! | `>`           | End of line positional; may step to next line  |
 //! | `{`           | Left-margin positional                         |
 //! | `}`           | Right-margin positional                        |
 //! | `^`           | Dot-column positional                          |
 //! | `@N`          | Mark-check positional                          |
+//! | `#`           | Line break                                     |
+//! | `$name$`      | Sub-pattern taken from a span or frame         |
+//! | `&prompt&`    | Sub-pattern entered by the user                |
 //! | `A,B`         | Context separator (left=A, middle=B)           |
 //! | `A,B,C`       | Full context (left=A, middle=B, right=C)       |
+//!
+//! The one-letter sets keep their traditional, mostly ASCII meanings; `X`
+//! names a Unicode general category, script or property instead. See
+//! [`unicode`] for the names it accepts.
+//!
+//! A frame set to `EP'P=REGEX'` takes familiar regular expressions instead;
+//! see [`regex`].
+//!
+//! In a pattern-mode `R` command, the replacement text may refer to the
+//! groups matched; see [`template`].
 
 pub mod ast;
 pub mod char_class;
 pub mod matcher;
 pub mod parser;
+pub mod program;
+pub mod regex;
+pub mod template;
+pub mod unicode;
+#[rustfmt::skip]
+mod unicode_tables;
 
-pub use matcher::{MatchCtx, MatchResult, find_backward, find_forward, match_at};
-pub use parser::{PatternError, parse};
+pub use matcher::{
+    MatchCtx, MatchResult, find_backward, find_forward, find_forward_partial, match_at,
+    match_at_partial,
+};
+pub use parser::{Dereference, PatternDiagnostic, PatternError, Resolver, parse, parse_with};
+pub use program::{Program, compile};
+pub use regex::{PatternSyntax, parse_regex};
+pub use template::Template;
+pub use unicode::UnicodeClass;
@@ -6,6 +6,8 @@ use std::str::Chars;
 use crate::marks::MarkId;
 
 use super::ast::*;
+use super::program::{MAX_PROGRAM_LEN, MAX_REPEAT, program_len};
+use super::unicode::UnicodeClass;
 
 /// Errors that can occur while parsing a Ludwig pattern.
 #[derive(Debug, Clone, PartialEq)]
@@ -18,6 +20,24 @@ pub enum PatternError {
     UnclosedCustomSet,
     InvalidQuantifierRange,
     DereferenceNotSupported,
+    UnclosedDereference,
+    /// A `$span$` or `&prompt&` that could not be resolved, as written.
+    UnresolvedDereference(String),
+    /// Dereferenced sub-patterns nested too deeply (probably self-referential).
+    DereferenceTooDeep,
+    /// An unknown `\x` escape in replacement text.
+    InvalidEscape(char),
+    /// A replacement refers to a group the pattern does not have.
+    NoSuchGroup(usize),
+    /// A regular expression construct with no Ludwig equivalent.
+    UnsupportedRegex(&'static str),
+    /// An `X/name/` naming no known Unicode property.
+    UnknownProperty(String),
+    /// A repeat count larger than [`MAX_REPEAT`].
+    RepeatTooLarge,
+    /// A pattern whose repeats expand to more than [`MAX_PROGRAM_LEN`]
+    /// instructions.
+    PatternTooLarge,
 }
 
 impl std::fmt::Display for PatternError {
@@ -31,25 +51,193 @@ impl std::fmt::Display for PatternError {
             Self::UnclosedCustomSet => write!(f, "Unclosed custom character set"),
             Self::InvalidQuantifierRange => write!(f, "Invalid quantifier range [n,m]"),
             Self::DereferenceNotSupported => {
-                write!(f, "Dereference ($...$, &...&) not yet supported")
+                write!(f, "Dereference ($...$, &...&) not supported here")
             }
+            Self::UnclosedDereference => write!(f, "Unclosed dereference in pattern"),
+            Self::UnresolvedDereference(d) => write!(f, "Could not dereference {d} in pattern"),
+            Self::DereferenceTooDeep => write!(f, "Dereferenced patterns nested too deeply"),
+            Self::InvalidEscape(c) => write!(f, "Invalid escape \\{c} in replacement"),
+            Self::NoSuchGroup(n) => write!(f, "Pattern has no group {n}"),
+            Self::UnsupportedRegex(what) => write!(f, "Not supported in a regex: {what}"),
+            Self::UnknownProperty(name) => write!(f, "Unknown Unicode property {name:?}"),
+            Self::RepeatTooLarge => write!(f, "Repeat count larger than {MAX_REPEAT}"),
+            Self::PatternTooLarge => write!(f, "Pattern too large once its repeats are expanded"),
         }
     }
 }
 
-/// Parse a Ludwig pattern string into a [`PatternDef`].
-pub fn parse(input: &str) -> Result<PatternDef, PatternError> {
-    Parser {
-        chars: input.chars().peekable(),
+/// Fail with [`PatternError::RepeatTooLarge`] if `quantifier` gives a count
+/// larger than [`MAX_REPEAT`].
+pub(crate) fn check_repeat(quantifier: Quantifier) -> Result<Quantifier, PatternError> {
+    match quantifier {
+        Quantifier::Exactly(n) | Quantifier::AtLeast(n) | Quantifier::Between(_, n)
+            if n > MAX_REPEAT =>
+        {
+            Err(PatternError::RepeatTooLarge)
+        }
+        _ => Ok(quantifier),
+    }
+}
+
+/// Fail with [`PatternError::PatternTooLarge`] if `pattern` would compile to
+/// more than [`MAX_PROGRAM_LEN`] instructions.
+pub(crate) fn check_program_len(pattern: PatternDef) -> Result<PatternDef, PatternError> {
+    if program_len(&pattern) > MAX_PROGRAM_LEN {
+        return Err(PatternError::PatternTooLarge);
+    }
+    Ok(pattern)
+}
+
+impl PatternError {
+    /// True for errors blamed on where their construct starts: one with no
+    /// closing delimiter, or a repeat count that is too large.
+    pub(crate) fn blames_start(&self) -> bool {
+        matches!(
+            self,
+            Self::UnclosedGroup
+                | Self::UnclosedString
+                | Self::UnclosedCustomSet
+                | Self::UnclosedDereference
+                | Self::RepeatTooLarge
+        )
     }
-    .parse_pattern_def()
 }
 
-struct Parser<'a> {
+/// Longest excerpt of the pattern shown by [`PatternDiagnostic`].
+const EXCERPT_WIDTH: usize = 60;
+
+/// A [`PatternError`] and where in the pattern it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternDiagnostic {
+    pub error: PatternError,
+    /// Character offset into `pattern` where the error was found. An
+    /// unclosed construct is blamed on its opening delimiter.
+    pub offset: usize,
+    /// The pattern text, without its delimiters.
+    pub pattern: String,
+}
+
+impl std::fmt::Display for PatternDiagnostic {
+    /// The error and its position on one line, then an excerpt of the
+    /// pattern with a caret under the offending character.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let chars: Vec<char> = self.pattern.chars().collect();
+        let start = (self.offset.saturating_sub(EXCERPT_WIDTH / 2))
+            .min(chars.len().saturating_sub(EXCERPT_WIDTH));
+        let end = chars.len().min(start + EXCERPT_WIDTH);
+        let mut excerpt = String::new();
+        let mut caret = 0;
+        if start > 0 {
+            excerpt.push_str("...");
+            caret += 3;
+        }
+        // Controls would upset the alignment of the caret.
+        excerpt.extend(
+            chars[start..end]
+                .iter()
+                .map(|&c| if c.is_control() { ' ' } else { c }),
+        );
+        if end < chars.len() {
+            excerpt.push_str("...");
+        }
+        caret += self.offset - start;
+        writeln!(f, "{} at character {}", self.error, self.offset + 1)?;
+        writeln!(f, "{excerpt}")?;
+        write!(f, "{:>width$}", "^", width = caret + 1)
+    }
+}
+
+/// A `$span$` or `&prompt&` reference in a pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dereference<'a> {
+    /// `$name$`: the text of the named span or frame.
+    Span(&'a str),
+    /// `&prompt&`: a line entered by the user in response to `prompt`.
+    Prompt(&'a str),
+}
+
+impl std::fmt::Display for Dereference<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Span(name) => write!(f, "${name}$"),
+            Self::Prompt(prompt) => write!(f, "&{prompt}&"),
+        }
+    }
+}
+
+/// Callback that supplies the text for a [`Dereference`], or `None` if it
+/// can't be resolved.
+pub type Resolver<'r> = dyn FnMut(Dereference<'_>) -> Option<String> + 'r;
+
+/// Maximum nesting of dereferenced sub-patterns.
+const MAX_DEREFERENCE_DEPTH: usize = 16;
+
+/// Parse a Ludwig pattern string into a [`PatternDef`].
+///
+/// Dereferences fail with [`PatternError::DereferenceNotSupported`]; use
+/// [`parse_with`] to resolve them.
+pub fn parse(input: &str) -> Result<PatternDef, PatternDiagnostic> {
+    Parser::new(input, None, 0).parse_top()
+}
+
+/// Parse a Ludwig pattern string, resolving `$span$` and `&prompt&`
+/// dereferences with `resolve`.
+///
+/// A dereference in a string (`'$x$'`) is used as literal text, after `D`
+/// (`D$x$`) as the contents of a set, and elsewhere as a sub-pattern, which
+/// may not contain context commas.
+pub fn parse_with(
+    input: &str,
+    resolve: &mut Resolver<'_>,
+) -> Result<PatternDef, PatternDiagnostic> {
+    Parser::new(input, Some(resolve), 0).parse_top()
+}
+
+struct Parser<'a, 'r> {
+    input: &'a str,
     chars: Peekable<Chars<'a>>,
+    resolve: Option<&'r mut Resolver<'r>>,
+    /// Nesting depth of dereferenced sub-patterns.
+    depth: usize,
+    /// Where the construct blamed for the error being returned began.
+    opened_at: Option<usize>,
 }
 
-impl Parser<'_> {
+impl<'a, 'r> Parser<'a, 'r> {
+    fn new(input: &'a str, resolve: Option<&'r mut Resolver<'r>>, depth: usize) -> Self {
+        Self {
+            input,
+            chars: input.chars().peekable(),
+            resolve,
+            depth,
+            opened_at: None,
+        }
+    }
+
+    fn parse_top(mut self) -> Result<PatternDef, PatternDiagnostic> {
+        let parsed = self.parse_pattern_def().and_then(check_program_len);
+        parsed.map_err(|error| PatternDiagnostic {
+            offset: self.opened_at.unwrap_or_else(|| self.offset()),
+            error,
+            pattern: self.input.to_string(),
+        })
+    }
+
+    /// Character offset of the next character to be read.
+    fn offset(&self) -> usize {
+        self.input.chars().count() - self.chars.clone().count()
+    }
+
+    /// Blame `error` on its construct starting at `start`, if it is one that
+    /// [blames the start](PatternError::blames_start), unless a construct
+    /// nested inside it is already blamed.
+    fn blame_start(&mut self, start: usize, error: PatternError) -> PatternError {
+        if self.opened_at.is_none() && error.blames_start() {
+            self.opened_at = Some(start);
+        }
+        error
+    }
+
     fn parse_pattern_def(&mut self) -> Result<PatternDef, PatternError> {
         let first = self.parse_compound()?;
 
@@ -164,10 +352,22 @@ impl Parser<'_> {
                 Ok(Some(Quantifier::OneOrMore))
             }
             Some('[') => {
+                let start = self.offset();
                 self.chars.next();
-                Ok(Some(self.parse_bracket_quantifier()?))
+                let quantifier = self
+                    .parse_bracket_quantifier()
+                    .and_then(check_repeat)
+                    .map_err(|e| self.blame_start(start, e))?;
+                Ok(Some(quantifier))
+            }
+            Some(&c) if c.is_ascii_digit() => {
+                let start = self.offset();
+                let quantifier = self
+                    .parse_number()
+                    .and_then(|n| check_repeat(Quantifier::Exactly(n)))
+                    .map_err(|e| self.blame_start(start, e))?;
+                Ok(Some(quantifier))
             }
-            Some(&c) if c.is_ascii_digit() => Ok(Some(Quantifier::Exactly(self.parse_number()?))),
             _ => Ok(None),
         }
     }
@@ -234,16 +434,20 @@ impl Parser<'_> {
         match self.chars.peek() {
             None => Err(PatternError::UnexpectedEnd),
             Some(&'(') => {
+                let start = self.offset();
                 self.chars.next();
                 let compound = self.parse_compound()?;
                 match self.chars.next() {
                     Some(')') => {}
-                    _ => return Err(PatternError::UnclosedGroup),
+                    _ => return Err(self.blame_start(start, PatternError::UnclosedGroup)),
                 }
                 Ok(Element::Group(Box::new(compound)))
             }
             Some(&'\'') | Some(&'"') => {
-                let (text, case_fold) = self.parse_string()?;
+                let start = self.offset();
+                let (text, case_fold) = self
+                    .parse_string()
+                    .map_err(|e| self.blame_start(start, e))?;
                 Ok(Element::Literal { text, case_fold })
             }
             Some(&'<') => {
@@ -266,6 +470,10 @@ impl Parser<'_> {
                 self.chars.next();
                 Ok(Element::Positional(Positional::DotCol))
             }
+            Some(&'#') => {
+                self.chars.next();
+                Ok(Element::LineBreak)
+            }
             Some(&'@') => {
                 self.chars.next();
                 let digit = match self.chars.next() {
@@ -275,17 +483,30 @@ impl Parser<'_> {
                 };
                 Ok(Element::MarkCheck(MarkId::Numbered(digit)))
             }
-            Some(&'$') | Some(&'&') => Err(PatternError::DereferenceNotSupported),
+            Some(&'$') | Some(&'&') => {
+                let start = self.offset();
+                let delim = self.chars.next().unwrap_or_default();
+                let text = self
+                    .read_dereference(delim)
+                    .map_err(|e| self.blame_start(start, e))?;
+                Ok(Element::Group(Box::new(self.parse_sub_pattern(&text)?)))
+            }
             Some(&'-') => {
+                let start = self.offset();
                 self.chars.next();
-                let kind = self.parse_charset_kind()?;
+                let kind = self
+                    .parse_charset_kind()
+                    .map_err(|e| self.blame_start(start, e))?;
                 Ok(Element::CharSet(CharSet {
                     negated: true,
                     kind,
                 }))
             }
             Some(&c) if is_charset_letter(c) => {
-                let kind = self.parse_charset_kind()?;
+                let start = self.offset();
+                let kind = self
+                    .parse_charset_kind()
+                    .map_err(|e| self.blame_start(start, e))?;
                 Ok(Element::CharSet(CharSet {
                     negated: false,
                     kind,
@@ -306,61 +527,43 @@ impl Parser<'_> {
                 'S' => Ok(CharSetKind::Space),
                 'P' => Ok(CharSetKind::Punct),
                 'C' => Ok(CharSetKind::Printable),
-                'D' => Ok(CharSetKind::Custom(self.parse_custom_set()?)),
-                other => Err(PatternError::UnexpectedChar(other)),
-            },
-        }
-    }
-
-    /// Parse a custom set `D<dlm>...<dlm>` (the `D` has already been consumed).
-    fn parse_custom_set(&mut self) -> Result<Vec<CharClass>, PatternError> {
-        let dlm = self.chars.next().ok_or(PatternError::UnexpectedEnd)?;
-        let mut classes = Vec::new();
-        let mut pending: Option<char> = None;
-
-        loop {
-            match self.chars.next() {
-                None => return Err(PatternError::UnclosedCustomSet),
-                Some(c) if c == dlm => {
-                    if let Some(p) = pending.take() {
-                        classes.push(CharClass::Single(p));
+                'D' => match self.chars.next() {
+                    None => Err(PatternError::UnexpectedEnd),
+                    Some(dlm @ ('$' | '&')) => {
+                        let text = self.read_dereference(dlm)?;
+                        Ok(CharSetKind::Custom(parse_set_spec(
+                            &mut text.chars().peekable(),
+                            None,
+                        )?))
                     }
-                    break;
-                }
-                Some('.') => {
-                    if self.chars.peek() == Some(&'.') {
-                        // '..' range notation
-                        self.chars.next(); // consume second '.'
-                        match self.chars.next() {
-                            Some(c) if c != dlm => {
-                                if let Some(lo) = pending.take() {
-                                    classes.push(CharClass::Range(lo, c));
-                                } else {
-                                    // No preceding char — treat all three as singles
-                                    classes.push(CharClass::Single('.'));
-                                    classes.push(CharClass::Single('.'));
-                                    classes.push(CharClass::Single(c));
+                    Some(dlm) => Ok(CharSetKind::Custom(parse_set_spec(
+                        &mut self.chars,
+                        Some(dlm),
+                    )?)),
+                },
+                'X' => {
+                    let name = match self.chars.next() {
+                        None => return Err(PatternError::UnexpectedEnd),
+                        Some(dlm @ ('$' | '&')) => self.read_dereference(dlm)?,
+                        Some(dlm) => {
+                            let mut name = String::new();
+                            loop {
+                                match self.chars.next() {
+                                    Some(c) if c == dlm => break,
+                                    Some(c) => name.push(c),
+                                    None => return Err(PatternError::UnclosedCustomSet),
                                 }
                             }
-                            _ => return Err(PatternError::UnclosedCustomSet),
-                        }
-                    } else {
-                        // single '.'
-                        if let Some(p) = pending.take() {
-                            classes.push(CharClass::Single(p));
+                            name
                         }
-                        pending = Some('.');
-                    }
+                    };
+                    UnicodeClass::lookup(name.trim())
+                        .map(CharSetKind::Unicode)
+                        .ok_or(PatternError::UnknownProperty(name))
                 }
-                Some(c) => {
-                    if let Some(p) = pending.take() {
-                        classes.push(CharClass::Single(p));
-                    }
-                    pending = Some(c);
-                }
-            }
+                other => Err(PatternError::UnexpectedChar(other)),
+            },
         }
-        Ok(classes)
     }
 
     /// Parse a `'...'` or `"..."` string literal.
@@ -377,9 +580,66 @@ impl Parser<'_> {
                 None => return Err(PatternError::UnclosedString),
             }
         }
+        // A string consisting only of a dereference is replaced by its value.
+        let deref = match (text.chars().next(), text.chars().last()) {
+            (Some('$'), Some('$')) if text.len() > 1 => {
+                Some(Dereference::Span(&text[1..text.len() - 1]))
+            }
+            (Some('&'), Some('&')) if text.len() > 1 => {
+                Some(Dereference::Prompt(&text[1..text.len() - 1]))
+            }
+            _ => None,
+        };
+        if let Some(deref) = deref {
+            return Ok((self.resolve(deref)?, case_fold));
+        }
         Ok((text, case_fold))
     }
 
+    /// Read a dereference up to the closing `delim` (the opening one has
+    /// already been consumed) and resolve it.
+    fn read_dereference(&mut self, delim: char) -> Result<String, PatternError> {
+        let mut name = String::new();
+        loop {
+            match self.chars.next() {
+                Some(c) if c == delim => break,
+                Some(c) => name.push(c),
+                None => return Err(PatternError::UnclosedDereference),
+            }
+        }
+        let deref = if delim == '$' {
+            Dereference::Span(&name)
+        } else {
+            Dereference::Prompt(&name)
+        };
+        self.resolve(deref)
+    }
+
+    fn resolve(&mut self, deref: Dereference<'_>) -> Result<String, PatternError> {
+        let resolve = self
+            .resolve
+            .as_mut()
+            .ok_or(PatternError::DereferenceNotSupported)?;
+        resolve(deref).ok_or_else(|| PatternError::UnresolvedDereference(deref.to_string()))
+    }
+
+    /// Parse dereferenced text as a context-free sub-pattern.
+    fn parse_sub_pattern(&mut self, text: &str) -> Result<Compound, PatternError> {
+        if self.depth >= MAX_DEREFERENCE_DEPTH {
+            return Err(PatternError::DereferenceTooDeep);
+        }
+        let mut sub = Parser::new(
+            text,
+            self.resolve.as_mut().map(|r| &mut **r as &mut Resolver<'_>),
+            self.depth + 1,
+        );
+        let compound = sub.parse_compound()?;
+        match sub.chars.next() {
+            None => Ok(compound),
+            Some(c) => Err(PatternError::UnexpectedChar(c)),
+        }
+    }
+
     fn skip_space(&mut self) {
         while matches!(self.chars.peek(), Some(' ') | Some('\t')) {
             self.chars.next();
@@ -387,11 +647,71 @@ impl Parser<'_> {
     }
 }
 
-/// True for characters that can begin a named charset (`A`, `U`, `L`, `N`, `S`, `P`, `C`, `D`).
+/// Parse the contents of a custom set up to and including the closing `dlm`,
+/// or to the end of `chars` if `dlm` is `None`.
+fn parse_set_spec(
+    chars: &mut Peekable<Chars<'_>>,
+    dlm: Option<char>,
+) -> Result<Vec<CharClass>, PatternError> {
+    let mut classes = Vec::new();
+    let mut pending: Option<char> = None;
+
+    loop {
+        match chars.next() {
+            None if dlm.is_none() => {
+                if let Some(p) = pending.take() {
+                    classes.push(CharClass::Single(p));
+                }
+                break;
+            }
+            None => return Err(PatternError::UnclosedCustomSet),
+            Some(c) if Some(c) == dlm => {
+                if let Some(p) = pending.take() {
+                    classes.push(CharClass::Single(p));
+                }
+                break;
+            }
+            Some('.') => {
+                if chars.peek() == Some(&'.') {
+                    // '..' range notation
+                    chars.next(); // consume second '.'
+                    match chars.next() {
+                        Some(c) if Some(c) != dlm => {
+                            if let Some(lo) = pending.take() {
+                                classes.push(CharClass::Range(lo, c));
+                            } else {
+                                // No preceding char — treat all three as singles
+                                classes.push(CharClass::Single('.'));
+                                classes.push(CharClass::Single('.'));
+                                classes.push(CharClass::Single(c));
+                            }
+                        }
+                        _ => return Err(PatternError::UnclosedCustomSet),
+                    }
+                } else {
+                    // single '.'
+                    if let Some(p) = pending.take() {
+                        classes.push(CharClass::Single(p));
+                    }
+                    pending = Some('.');
+                }
+            }
+            Some(c) => {
+                if let Some(p) = pending.take() {
+                    classes.push(CharClass::Single(p));
+                }
+                pending = Some(c);
+            }
+        }
+    }
+    Ok(classes)
+}
+
+/// True for characters that can begin a named charset (`A`, `U`, `L`, `N`, `S`, `P`, `C`, `D`, `X`).
 fn is_charset_letter(c: char) -> bool {
     matches!(
         c.to_ascii_uppercase(),
-        'A' | 'U' | 'L' | 'N' | 'S' | 'P' | 'C' | 'D'
+        'A' | 'U' | 'L' | 'N' | 'S' | 'P' | 'C' | 'D' | 'X'
     )
 }
 
@@ -403,7 +723,7 @@ mod tests {
         parse(s).expect("parse should succeed")
     }
     fn parse_err(s: &str) -> PatternError {
-        parse(s).expect_err("parse should fail")
+        parse(s).expect_err("parse should fail").error
     }
 
     fn middle_items(p: PatternDef) -> Vec<Item> {
@@ -515,6 +835,33 @@ mod tests {
         }
     }
 
+    // --- Unicode property sets ---
+
+    #[test]
+    fn test_unicode_property_set() {
+        let p = parse_ok("-x:Greek:");
+        let items = middle_items(p);
+        match &items[0].element {
+            Element::CharSet(cs) => {
+                assert!(cs.negated);
+                assert_eq!(
+                    cs.kind,
+                    CharSetKind::Unicode(UnicodeClass::lookup("Grek").unwrap())
+                );
+            }
+            _ => panic!("expected CharSet"),
+        }
+    }
+
+    #[test]
+    fn test_unknown_unicode_property() {
+        assert_eq!(
+            parse_err("X/Klingon/"),
+            PatternError::UnknownProperty("Klingon".into())
+        );
+        assert_eq!(parse_err("X/Lu"), PatternError::UnclosedCustomSet);
+    }
+
     // --- Quantifiers ---
 
     #[test]
@@ -614,6 +961,14 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_line_break() {
+        let p = parse_ok("'end'#'begin'");
+        let items = middle_items(p);
+        assert_eq!(items.len(), 3);
+        assert!(matches!(&items[1].element, Element::LineBreak));
+    }
+
     #[test]
     fn test_dot_col() {
         let p = parse_ok("^");
@@ -680,6 +1035,85 @@ mod tests {
         assert_eq!(items.len(), 2);
     }
 
+    // --- Dereferences ---
+
+    fn parse_deref(s: &str) -> Result<PatternDef, PatternError> {
+        parse_with(s, &mut |deref| match deref {
+            Dereference::Span("num") => Some("+N".into()),
+            Dereference::Span("set") => Some("a..c".into()),
+            Dereference::Span("ctx") => Some("A,N".into()),
+            Dereference::Span("loop") => Some("$loop$".into()),
+            Dereference::Prompt("Word: ") => Some("Hi".into()),
+            _ => None,
+        })
+        .map_err(|d| d.error)
+    }
+
+    #[test]
+    fn test_span_dereference_is_sub_pattern() {
+        let items = middle_items(parse_deref("'x' $num$").unwrap());
+        assert_eq!(items.len(), 2);
+        match &items[1].element {
+            Element::Group(compound) => {
+                let inner = &compound.alternatives[0].items;
+                assert_eq!(inner[0].quantifier, Quantifier::OneOrMore);
+            }
+            other => panic!("expected Group, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_string_dereference_is_literal() {
+        let items = middle_items(parse_deref("\"&Word: &\"").unwrap());
+        match &items[0].element {
+            Element::Literal { text, case_fold } => {
+                assert_eq!(text, "Hi");
+                assert!(!case_fold);
+            }
+            other => panic!("expected Literal, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_set_dereference_is_set_contents() {
+        let items = middle_items(parse_deref("D$set$").unwrap());
+        match &items[0].element {
+            Element::CharSet(CharSet {
+                kind: CharSetKind::Custom(classes),
+                ..
+            }) => assert!(matches!(classes[..], [CharClass::Range('a', 'c')])),
+            other => panic!("expected custom set, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_dereferenced_pattern_may_not_change_context() {
+        assert_eq!(
+            parse_deref("$ctx$").unwrap_err(),
+            PatternError::UnexpectedChar(',')
+        );
+    }
+
+    #[test]
+    fn test_unresolved_dereference() {
+        assert_eq!(
+            parse_deref("'$nope$'").unwrap_err(),
+            PatternError::UnresolvedDereference("$nope$".into())
+        );
+        assert_eq!(
+            parse_deref("$num").unwrap_err(),
+            PatternError::UnclosedDereference
+        );
+    }
+
+    #[test]
+    fn test_self_referential_dereference() {
+        assert_eq!(
+            parse_deref("$loop$").unwrap_err(),
+            PatternError::DereferenceTooDeep
+        );
+    }
+
     // --- Errors ---
 
     #[test]
@@ -707,4 +1141,54 @@ mod tests {
             PatternError::InvalidQuantifierRange
         ));
     }
+
+    #[test]
+    fn test_repeat_limits() {
+        assert!(parse("[1,1000]A 1000N").is_ok());
+        for pattern in ["[1,9999999999]A", "1001A", "[1001,]A", "[,1001]A"] {
+            assert_eq!(
+                parse_err(pattern),
+                PatternError::RepeatTooLarge,
+                "{pattern:?}"
+            );
+        }
+        // Nested repeats multiply.
+        assert_eq!(parse_err("1000(1000A)"), PatternError::PatternTooLarge);
+        assert_eq!(parse("A [1,5000]N").unwrap_err().offset, 2);
+        assert_eq!(parse("A 5000N").unwrap_err().offset, 2);
+    }
+
+    // --- Diagnostics ---
+
+    #[test]
+    fn test_error_offsets() {
+        let offset = |s| parse(s).expect_err("parse should fail").offset;
+        assert_eq!(offset("A ! N"), 2);
+        assert_eq!(offset("A+"), 2);
+        // Unclosed constructs are blamed on where they were opened.
+        assert_eq!(offset("A (N | 'x' "), 2);
+        assert_eq!(offset("(A 'xy"), 3);
+        assert_eq!(offset("N -D/ab"), 2);
+        assert_eq!(offset("[2,3 A"), 0);
+    }
+
+    #[test]
+    fn test_diagnostic_excerpt() {
+        let diagnostic = parse("A (N | 'x' ").unwrap_err();
+        assert_eq!(
+            diagnostic.to_string(),
+            "Unclosed group '(' in pattern at character 3\nA (N | 'x' \n  ^"
+        );
+        let long = format!("{}!{}", "A".repeat(80), "N".repeat(80));
+        let diagnostic = parse(&long).unwrap_err();
+        assert_eq!(diagnostic.offset, 80);
+        let excerpt = format!("...{}!{}...", "A".repeat(30), "N".repeat(29));
+        assert_eq!(
+            diagnostic.to_string(),
+            format!(
+                "Unexpected character in pattern: '!' at character 81\n{excerpt}\n{:>34}",
+                "^"
+            )
+        );
+    }
 }
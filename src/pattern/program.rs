@@ -0,0 +1,386 @@
+//! Compile a [`PatternDef`] into a program for the matcher's automaton.
+//!
+//! The three contexts are laid out one after another, with `Save` instructions
+//! recording where the middle context starts and ends:
+//!
+//! ```text
+//! left  Save(MIDDLE_START)  middle  Save(MIDDLE_END)  right  Match
+//! ```
+//!
+//! Each group `(…)` is numbered from 1 in order of its opening parenthesis,
+//! across all three contexts, and records its start and end in save slots
+//! `2n` and `2n + 1`. A dereferenced `$name$` or `&prompt&` sub-pattern is a
+//! group too. Inside a repetition the last repetition's text is recorded.
+//!
+//! The matcher runs the program as a Thompson NFA, so alternatives and
+//! repetitions are tried in parallel rather than by backtracking.
+
+use crate::marks::MarkId;
+
+use super::ast::*;
+
+/// Save slot holding the start of the middle context.
+pub(crate) const MIDDLE_START: usize = 0;
+/// Save slot holding the end of the middle context.
+pub(crate) const MIDDLE_END: usize = 1;
+
+/// Save slot holding the start of group `n`; the end is in the slot after.
+pub(crate) const fn group_start(n: usize) -> usize {
+    2 * n
+}
+
+/// Largest count a repeat may give, as in `[2,1000]A`.
+pub const MAX_REPEAT: usize = 1000;
+
+/// Most instructions a pattern may compile to. A repeat is laid out once for
+/// each repetition, so repeats nested inside one another multiply.
+pub const MAX_PROGRAM_LEN: usize = 100_000;
+
+/// One instruction of a compiled pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Inst<'p> {
+    /// Consume one character in the set.
+    Set(&'p CharSet),
+    /// Consume one character equal to `ch`.
+    Char {
+        ch: char,
+        case_fold: bool,
+    },
+    /// Consume the break at the end of a line.
+    LineBreak,
+    /// Continue at both targets, preferring the first.
+    Split(usize, usize),
+    Jmp(usize),
+    /// Zero-width test of the current column.
+    Assert(Positional),
+    /// Zero-width test that a mark is at the current position.
+    Mark(MarkId),
+    /// Record the current position in a save slot.
+    Save(usize),
+    Match,
+}
+
+/// A compiled pattern, ready to run with the functions in [`super::matcher`].
+#[derive(Debug)]
+pub struct Program<'p> {
+    pub(crate) insts: Vec<Inst<'p>>,
+    /// True if the pattern has no left context.
+    pub(crate) unanchored_left: bool,
+    /// The column tests and marks the pattern uses, which decide how far
+    /// into virtual space a match may need to reach.
+    pub(crate) uses_dot_col: bool,
+    pub(crate) uses_left_margin: bool,
+    pub(crate) uses_right_margin: bool,
+    pub(crate) marks: Vec<MarkId>,
+    /// The groups in the pattern, in the order they are numbered.
+    groups: Vec<&'p Compound>,
+}
+
+/// Compile `pattern` into a [`Program`].
+pub fn compile(pattern: &PatternDef) -> Program<'_> {
+    let mut program = Program {
+        insts: Vec::new(),
+        unanchored_left: pattern.left.is_empty_pattern(),
+        uses_dot_col: false,
+        uses_left_margin: false,
+        uses_right_margin: false,
+        marks: Vec::new(),
+        groups: Vec::new(),
+    };
+    program.emit_compound(&pattern.left);
+    program.push(Inst::Save(MIDDLE_START));
+    program.emit_compound(&pattern.middle);
+    program.push(Inst::Save(MIDDLE_END));
+    program.emit_compound(&pattern.right);
+    program.push(Inst::Match);
+    program
+}
+
+/// The number of instructions [`compile`] would lay `pattern` out in, or
+/// `usize::MAX` if there are too many to count.
+pub(crate) fn program_len(pattern: &PatternDef) -> usize {
+    [&pattern.left, &pattern.middle, &pattern.right]
+        .into_iter()
+        .fold(3, |len, compound| {
+            len.saturating_add(compound_len(compound))
+        })
+}
+
+fn compound_len(compound: &Compound) -> usize {
+    let branches = 2 * compound.alternatives.len().saturating_sub(1);
+    compound.alternatives.iter().fold(branches, |len, seq| {
+        seq.items
+            .iter()
+            .fold(len, |len, item| len.saturating_add(item_len(item)))
+    })
+}
+
+/// Mirrors [`Program::emit_item`].
+fn item_len(item: &Item) -> usize {
+    let element = element_len(&item.element);
+    match item.quantifier {
+        Quantifier::Once => match item.element {
+            Element::Positional(Positional::EndLine) => element + 2,
+            _ => element,
+        },
+        Quantifier::ZeroOrMore => element.saturating_add(2),
+        Quantifier::OneOrMore => element.saturating_mul(2).saturating_add(2),
+        Quantifier::Exactly(n) => element.saturating_mul(n),
+        Quantifier::AtLeast(n) => element
+            .saturating_mul(n.saturating_add(1))
+            .saturating_add(2),
+        Quantifier::Between(lo, hi) => element
+            .saturating_mul(hi)
+            .saturating_add(hi.saturating_sub(lo)),
+    }
+}
+
+fn element_len(element: &Element) -> usize {
+    match element {
+        Element::Literal { text, .. } => text.chars().count(),
+        Element::Group(compound) => compound_len(compound).saturating_add(2),
+        Element::CharSet(_)
+        | Element::Positional(_)
+        | Element::LineBreak
+        | Element::MarkCheck(_) => 1,
+    }
+}
+
+impl<'p> Program<'p> {
+    /// Number of groups in the pattern. Groups are numbered from 1.
+    pub fn group_count(&self) -> usize {
+        self.groups.len()
+    }
+
+    /// The number of `group`, allocating the next one on first sight.
+    /// Repetitions emit a group more than once but it keeps one number.
+    fn group_number(&mut self, group: &'p Compound) -> usize {
+        match self.groups.iter().position(|g| std::ptr::eq(*g, group)) {
+            Some(i) => i + 1,
+            None => {
+                self.groups.push(group);
+                self.groups.len()
+            }
+        }
+    }
+
+    fn push(&mut self, inst: Inst<'p>) -> usize {
+        self.insts.push(inst);
+        self.insts.len() - 1
+    }
+
+    fn emit_compound(&mut self, compound: &'p Compound) {
+        let Some((last, rest)) = compound.alternatives.split_last() else {
+            return; // empty compound = always match
+        };
+        let mut jumps = Vec::new();
+        for seq in rest {
+            let split = self.push(Inst::Split(0, 0));
+            self.emit_sequence(seq);
+            jumps.push(self.push(Inst::Jmp(0)));
+            self.insts[split] = Inst::Split(split + 1, self.insts.len());
+        }
+        self.emit_sequence(last);
+        let end = self.insts.len();
+        for jump in jumps {
+            self.insts[jump] = Inst::Jmp(end);
+        }
+    }
+
+    fn emit_sequence(&mut self, seq: &'p Sequence) {
+        for item in &seq.items {
+            self.emit_item(item);
+        }
+    }
+
+    fn emit_item(&mut self, item: &'p Item) {
+        let element = &item.element;
+        match &item.quantifier {
+            Quantifier::Once => match element {
+                // A lone `>` may also step over the line break after it, so
+                // that `>,<` can straddle two lines. Not stepping is preferred.
+                Element::Positional(Positional::EndLine) => {
+                    self.emit_element(element);
+                    let split = self.push(Inst::Split(0, 0));
+                    self.push(Inst::LineBreak);
+                    self.insts[split] = Inst::Split(split + 2, split + 1);
+                }
+                _ => self.emit_element(element),
+            },
+            Quantifier::ZeroOrMore => self.emit_star(element),
+            Quantifier::OneOrMore => {
+                self.emit_element(element);
+                self.emit_star(element);
+            }
+            Quantifier::Exactly(n) => {
+                for _ in 0..*n {
+                    self.emit_element(element);
+                }
+            }
+            Quantifier::AtLeast(n) => {
+                for _ in 0..*n {
+                    self.emit_element(element);
+                }
+                self.emit_star(element);
+            }
+            Quantifier::Between(lo, hi) => {
+                for _ in 0..*lo {
+                    self.emit_element(element);
+                }
+                // Each optional repetition is only tried if the previous one matched.
+                let mut splits = Vec::new();
+                for _ in *lo..*hi {
+                    splits.push(self.push(Inst::Split(0, 0)));
+                    self.emit_element(element);
+                }
+                let end = self.insts.len();
+                for split in splits {
+                    self.insts[split] = Inst::Split(split + 1, end);
+                }
+            }
+        }
+    }
+
+    /// Greedy zero-or-more repetition of `element`.
+    fn emit_star(&mut self, element: &'p Element) {
+        let split = self.push(Inst::Split(0, 0));
+        self.emit_element(element);
+        self.push(Inst::Jmp(split));
+        self.insts[split] = Inst::Split(split + 1, self.insts.len());
+    }
+
+    fn emit_element(&mut self, element: &'p Element) {
+        match element {
+            Element::CharSet(cs) => {
+                self.push(Inst::Set(cs));
+            }
+            Element::Literal { text, case_fold } => {
+                for ch in text.chars() {
+                    self.push(Inst::Char {
+                        ch,
+                        case_fold: *case_fold,
+                    });
+                }
+            }
+            Element::Group(compound) => {
+                let start = group_start(self.group_number(compound));
+                self.push(Inst::Save(start));
+                self.emit_compound(compound);
+                self.push(Inst::Save(start + 1));
+            }
+            Element::Positional(p) => {
+                match p {
+                    Positional::DotCol => self.uses_dot_col = true,
+                    Positional::LeftMargin => self.uses_left_margin = true,
+                    Positional::RightMargin => self.uses_right_margin = true,
+                    Positional::BegLine | Positional::EndLine => {}
+                }
+                self.push(Inst::Assert(p.clone()));
+            }
+            Element::LineBreak => {
+                self.push(Inst::LineBreak);
+            }
+            Element::MarkCheck(id) => {
+                if !self.marks.contains(id) {
+                    self.marks.push(*id);
+                }
+                self.push(Inst::Mark(*id));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::parser::parse;
+
+    #[test]
+    fn test_program_len_matches_compile() {
+        for pattern in [
+            "",
+            "'abc'",
+            "A|N|'xy'",
+            ">",
+            "2>",
+            "*A +N 3'ab' [2,]S [1,4](A|#) [,3]@1",
+            "'a',(N|<)^,{}",
+        ] {
+            let p = parse(pattern).unwrap();
+            assert_eq!(program_len(&p), compile(&p).insts.len(), "{pattern:?}");
+        }
+    }
+
+    #[test]
+    fn test_contexts_are_saved_in_order() {
+        let p = parse("\"a\",\"b\",\"c\"").unwrap();
+        let program = compile(&p);
+        let chars = |ch| Inst::Char {
+            ch,
+            case_fold: false,
+        };
+        assert_eq!(
+            program.insts,
+            vec![
+                chars('a'),
+                Inst::Save(MIDDLE_START),
+                chars('b'),
+                Inst::Save(MIDDLE_END),
+                chars('c'),
+                Inst::Match,
+            ]
+        );
+        assert!(!program.unanchored_left);
+    }
+
+    #[test]
+    fn test_alternation_and_star() {
+        let p = parse("*(\"a\"|\"b\")").unwrap();
+        let program = compile(&p);
+        let chars = |ch| Inst::Char {
+            ch,
+            case_fold: false,
+        };
+        assert_eq!(
+            program.insts,
+            vec![
+                Inst::Save(MIDDLE_START),
+                Inst::Split(2, 9),
+                Inst::Save(group_start(1)),
+                Inst::Split(4, 6),
+                chars('a'),
+                Inst::Jmp(7),
+                chars('b'),
+                Inst::Save(group_start(1) + 1),
+                Inst::Jmp(1),
+                Inst::Save(MIDDLE_END),
+                Inst::Match,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_between_skips_remaining_repetitions() {
+        let p = parse("[1,3]N").unwrap();
+        let program = compile(&p);
+        assert_eq!(program.insts[2], Inst::Split(3, 6));
+        assert_eq!(program.insts[4], Inst::Split(5, 6));
+    }
+
+    #[test]
+    fn test_groups_are_numbered_by_opening() {
+        let p = parse("(A),((N)|+(S)),(P)").unwrap();
+        let program = compile(&p);
+        assert_eq!(program.group_count(), 5);
+        let saves: Vec<usize> = program
+            .insts
+            .iter()
+            .filter_map(|inst| match inst {
+                Inst::Save(slot) if *slot >= group_start(1) => Some(*slot),
+                _ => None,
+            })
+            .collect();
+        // `+(S)` emits its group twice, under the same number.
+        assert_eq!(saves, vec![2, 3, 4, 6, 7, 8, 9, 8, 9, 5, 10, 11]);
+    }
+}
@@ -0,0 +1,594 @@
+//! Familiar regular expression syntax, translated into a [`PatternDef`].
+//!
+//! A frame set to `EP'P=REGEX'` reads backtick-delimited patterns in this
+//! syntax instead of Ludwig's. The translation produces the same AST, so a
+//! match leaves Dot and Equals exactly where the equivalent Ludwig pattern
+//! would.
+//!
+//! | Regex                     | Ludwig equivalent                     |
+//! |---------------------------|---------------------------------------|
+//! | `abc`, `\.`               | `"abc"`, `"."`                        |
+//! | `.`                       | `-D//` (any character)                |
+//! | `[a-z_]`, `[^0-9]`        | `D/a..z_/`, `-D/0..9/`                |
+//! | `[[:alpha:]]` etc.        | POSIX classes, ASCII only             |
+//! | `\d` `\w` `\s` (`\D` …)   | ASCII digit, word and white space sets |
+//! | `\p{Greek}`, `\P{Lu}`     | `X/Greek/`, `-X/Lu/`                  |
+//! | `\n`, `\t`                | `#`, a tab                            |
+//! | `^`, `$`                  | `<`, `>`                              |
+//! | `(…)`, `\|`               | `(…)`, `\|`                           |
+//! | `*` `+` `?` `{n}` `{n,m}` | `*` `+` `[0,1]` `[n]` `[n,m]`         |
+//! | `(?<=L)M(?=R)`            | `L,M,R`                               |
+//! | `(?i)` at the start       | `'…'` for every literal and set       |
+//!
+//! A lookbehind may only begin the pattern and a lookahead may only end it,
+//! since they become the left and right contexts. Lazy quantifiers,
+//! non-capturing groups, negative lookaround, word boundaries and
+//! back-references have no Ludwig equivalent and are rejected. `(?i)` does
+//! not widen Unicode property sets.
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+use super::ast::*;
+use super::parser::{PatternDiagnostic, PatternError, check_program_len, check_repeat};
+use super::unicode::UnicodeClass;
+
+/// The syntax in which a frame's backtick-delimited patterns are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PatternSyntax {
+    /// Ludwig's own pattern syntax.
+    #[default]
+    Ludwig,
+    /// Regular expressions, translated by [`parse_regex`].
+    Regex,
+}
+
+impl fmt::Display for PatternSyntax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PatternSyntax::Ludwig => "LUDWIG",
+            PatternSyntax::Regex => "REGEX",
+        })
+    }
+}
+
+impl FromStr for PatternSyntax {
+    type Err = ();
+
+    /// Parse `LUDWIG` or `REGEX`, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "LUDWIG" => Ok(PatternSyntax::Ludwig),
+            "REGEX" => Ok(PatternSyntax::Regex),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Translate a regular expression into a [`PatternDef`].
+pub fn parse_regex(input: &str) -> Result<PatternDef, PatternDiagnostic> {
+    let mut parser = RegexParser {
+        input,
+        chars: input.chars().peekable(),
+        case_fold: false,
+        depth: 0,
+        opened_at: None,
+    };
+    let parsed = parser.parse_def().and_then(check_program_len);
+    parsed.map_err(|error| PatternDiagnostic {
+        offset: parser.opened_at.unwrap_or_else(|| parser.offset()),
+        error,
+        pattern: input.to_string(),
+    })
+}
+
+struct RegexParser<'a> {
+    input: &'a str,
+    chars: Peekable<Chars<'a>>,
+    /// Set by a leading `(?i)`.
+    case_fold: bool,
+    /// Nesting depth of groups.
+    depth: usize,
+    /// Where the construct blamed for the error being returned began.
+    opened_at: Option<usize>,
+}
+
+impl RegexParser<'_> {
+    fn parse_def(&mut self) -> Result<PatternDef, PatternError> {
+        self.case_fold = self.eat("(?i)");
+        let start = self.offset();
+        let left = if self.eat("(?<=") {
+            self.parse_group_body(start)?
+        } else {
+            Compound::empty()
+        };
+        let middle = self.parse_compound()?;
+        let start = self.offset();
+        let right = if self.eat("(?=") {
+            self.parse_group_body(start)?
+        } else {
+            Compound::empty()
+        };
+        if self.chars.peek().is_some() {
+            return Err(PatternError::UnsupportedRegex(
+                "a lookahead must end the pattern",
+            ));
+        }
+        // The contexts apply to the whole middle, where in a regex they would
+        // only apply to the alternative next to them.
+        if middle.alternatives.len() > 1 && !(left.is_empty_pattern() && right.is_empty_pattern()) {
+            return Err(PatternError::UnsupportedRegex(
+                "lookaround next to an alternation; put the alternation in a group",
+            ));
+        }
+        Ok(PatternDef {
+            left,
+            middle,
+            right,
+        })
+    }
+
+    /// Character offset of the next character to be read.
+    fn offset(&self) -> usize {
+        self.input.chars().count() - self.chars.clone().count()
+    }
+
+    /// Blame `error` on its construct starting at `start`, if it is one that
+    /// [blames the start](PatternError::blames_start), unless a construct
+    /// nested inside it is already blamed.
+    fn blame_start(&mut self, start: usize, error: PatternError) -> PatternError {
+        if self.opened_at.is_none() && error.blames_start() {
+            self.opened_at = Some(start);
+        }
+        error
+    }
+
+    /// Consume `s` if the input continues with it.
+    fn eat(&mut self, s: &str) -> bool {
+        let mut ahead = self.chars.clone();
+        if s.chars().all(|c| ahead.next() == Some(c)) {
+            self.chars = ahead;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn at(&self, s: &str) -> bool {
+        let mut ahead = self.chars.clone();
+        s.chars().all(|c| ahead.next() == Some(c))
+    }
+
+    /// Parse the rest of a group opened at `start`, whose opening has been
+    /// consumed.
+    fn parse_group_body(&mut self, start: usize) -> Result<Compound, PatternError> {
+        self.depth += 1;
+        let compound = self.parse_compound()?;
+        self.depth -= 1;
+        match self.chars.next() {
+            Some(')') => Ok(compound),
+            _ => Err(self.blame_start(start, PatternError::UnclosedGroup)),
+        }
+    }
+
+    fn parse_compound(&mut self) -> Result<Compound, PatternError> {
+        let mut alternatives = vec![self.parse_sequence()?];
+        while self.chars.peek() == Some(&'|') {
+            self.chars.next();
+            alternatives.push(self.parse_sequence()?);
+        }
+        if alternatives.len() == 1 && alternatives[0].items.is_empty() {
+            return Ok(Compound::empty());
+        }
+        Ok(Compound { alternatives })
+    }
+
+    /// Parse items up to `|`, the end of the group, or a lookahead that
+    /// ends the pattern.
+    fn parse_sequence(&mut self) -> Result<Sequence, PatternError> {
+        let mut items: Vec<Item> = Vec::new();
+        loop {
+            let lookahead = self.depth == 0 && self.at("(?=");
+            match self.chars.peek() {
+                None | Some('|') => break,
+                Some(')') if self.depth > 0 => break,
+                Some('(') if lookahead => break,
+                _ => {}
+            }
+            let element = self.parse_atom()?;
+            let quantifier = self.parse_quantifier()?;
+            // Adjacent characters make up one literal.
+            if let (
+                Quantifier::Once,
+                Element::Literal { text, case_fold },
+                Some(Item {
+                    quantifier: Quantifier::Once,
+                    element:
+                        Element::Literal {
+                            text: prev,
+                            case_fold: prev_fold,
+                        },
+                }),
+            ) = (&quantifier, &element, items.last_mut())
+                && case_fold == prev_fold
+            {
+                prev.push_str(text);
+                continue;
+            }
+            items.push(Item {
+                quantifier,
+                element,
+            });
+        }
+        Ok(Sequence { items })
+    }
+
+    fn parse_atom(&mut self) -> Result<Element, PatternError> {
+        let c = self.chars.next().ok_or(PatternError::UnexpectedEnd)?;
+        match c {
+            '(' => {
+                if self.chars.peek() == Some(&'?') {
+                    return Err(PatternError::UnsupportedRegex(
+                        if self.at("?=") || self.at("?<=") {
+                            "lookaround inside a pattern"
+                        } else if self.at("?!") || self.at("?<!") {
+                            "negative lookaround"
+                        } else if self.at("?:") {
+                            "non-capturing group"
+                        } else {
+                            "inline flags after the start of the pattern"
+                        },
+                    ));
+                }
+                let start = self.offset() - 1;
+                Ok(Element::Group(Box::new(self.parse_group_body(start)?)))
+            }
+            '[' => {
+                let start = self.offset() - 1;
+                self.parse_bracket().map_err(|e| self.blame_start(start, e))
+            }
+            '.' => Ok(Element::CharSet(CharSet {
+                negated: true,
+                kind: CharSetKind::Custom(Vec::new()),
+            })),
+            '^' => Ok(Element::Positional(Positional::BegLine)),
+            '$' => Ok(Element::Positional(Positional::EndLine)),
+            '\\' => self.parse_escape(),
+            '*' | '+' | '?' | '{' | ')' => Err(PatternError::UnexpectedChar(c)),
+            c => Ok(self.literal(c)),
+        }
+    }
+
+    fn literal(&self, c: char) -> Element {
+        Element::Literal {
+            text: c.to_string(),
+            case_fold: self.case_fold,
+        }
+    }
+
+    fn set(&self, negated: bool, mut classes: Vec<CharClass>) -> Element {
+        if self.case_fold {
+            fold_classes(&mut classes);
+        }
+        Element::CharSet(CharSet {
+            negated,
+            kind: CharSetKind::Custom(classes),
+        })
+    }
+
+    /// Parse an escape outside brackets; the `\` has been consumed.
+    fn parse_escape(&mut self) -> Result<Element, PatternError> {
+        let c = self.chars.next().ok_or(PatternError::UnexpectedEnd)?;
+        if let Some(classes) = escape_class(c.to_ascii_lowercase()) {
+            return Ok(self.set(c.is_ascii_uppercase(), classes));
+        }
+        match c {
+            'n' => Ok(Element::LineBreak),
+            'p' | 'P' => {
+                let name = if self.chars.next_if_eq(&'{').is_some() {
+                    let name: String = self.chars.by_ref().take_while(|&c| c != '}').collect();
+                    name
+                } else {
+                    self.chars
+                        .next()
+                        .ok_or(PatternError::UnexpectedEnd)?
+                        .to_string()
+                };
+                let class =
+                    UnicodeClass::lookup(&name).ok_or(PatternError::UnknownProperty(name))?;
+                Ok(Element::CharSet(CharSet {
+                    negated: c == 'P',
+                    kind: CharSetKind::Unicode(class),
+                }))
+            }
+            'b' | 'B' | 'A' | 'z' | 'Z' => Err(PatternError::UnsupportedRegex("anchor escape")),
+            '1'..='9' => Err(PatternError::UnsupportedRegex("back-reference")),
+            c => Ok(self.literal(escaped_char(c)?)),
+        }
+    }
+
+    /// Parse a bracket expression; the `[` has been consumed.
+    fn parse_bracket(&mut self) -> Result<Element, PatternError> {
+        let negated = self.chars.next_if_eq(&'^').is_some();
+        let mut classes = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.chars.next().ok_or(PatternError::UnclosedCustomSet)?;
+            let lo = match c {
+                ']' if !first => break,
+                '[' if self.chars.peek() == Some(&':') => {
+                    self.chars.next();
+                    let name: String = self.chars.by_ref().take_while(|&c| c != ':').collect();
+                    if self.chars.next() != Some(']') {
+                        return Err(PatternError::UnclosedCustomSet);
+                    }
+                    classes.extend(posix_class(&name)?);
+                    first = false;
+                    continue;
+                }
+                '\\' => {
+                    let e = self.chars.next().ok_or(PatternError::UnclosedCustomSet)?;
+                    if let Some(escaped) = escape_class(e) {
+                        classes.extend(escaped);
+                        first = false;
+                        continue;
+                    }
+                    escaped_char(e)?
+                }
+                c => c,
+            };
+            first = false;
+            let mut ahead = self.chars.clone();
+            if ahead.next() == Some('-') && ahead.peek().is_some_and(|&c| c != ']') {
+                self.chars.next();
+                let hi = match self.chars.next().ok_or(PatternError::UnclosedCustomSet)? {
+                    '\\' => escaped_char(self.chars.next().ok_or(PatternError::UnexpectedEnd)?)?,
+                    c => c,
+                };
+                if hi < lo {
+                    return Err(PatternError::InvalidQuantifierRange);
+                }
+                classes.push(CharClass::Range(lo, hi));
+            } else {
+                classes.push(CharClass::Single(lo));
+            }
+        }
+        Ok(self.set(negated, classes))
+    }
+
+    fn parse_quantifier(&mut self) -> Result<Quantifier, PatternError> {
+        let quantifier = match self.chars.peek() {
+            Some('*') => Quantifier::ZeroOrMore,
+            Some('+') => Quantifier::OneOrMore,
+            Some('?') => Quantifier::Between(0, 1),
+            Some('{') => {
+                let start = self.offset();
+                self.chars.next();
+                let lo = self.parse_number();
+                let quantifier = if self.chars.next_if_eq(&',').is_some() {
+                    match (lo, self.parse_number()) {
+                        (lo, None) => Quantifier::AtLeast(lo.unwrap_or(0)),
+                        (lo, Some(hi)) if lo.unwrap_or(0) <= hi => {
+                            Quantifier::Between(lo.unwrap_or(0), hi)
+                        }
+                        _ => return Err(PatternError::InvalidQuantifierRange),
+                    }
+                } else {
+                    Quantifier::Exactly(lo.ok_or(PatternError::InvalidNumber)?)
+                };
+                if self.chars.next() != Some('}') {
+                    return Err(PatternError::InvalidQuantifierRange);
+                }
+                self.reject_lazy()?;
+                return check_repeat(quantifier).map_err(|e| self.blame_start(start, e));
+            }
+            _ => return Ok(Quantifier::Once),
+        };
+        self.chars.next();
+        self.reject_lazy()?;
+        Ok(quantifier)
+    }
+
+    fn reject_lazy(&mut self) -> Result<(), PatternError> {
+        match self.chars.peek() {
+            Some('?') => Err(PatternError::UnsupportedRegex("lazy quantifier")),
+            Some('+') => Err(PatternError::UnsupportedRegex("possessive quantifier")),
+            _ => Ok(()),
+        }
+    }
+
+    fn parse_number(&mut self) -> Option<usize> {
+        let mut digits = String::new();
+        while let Some(c) = self.chars.next_if(char::is_ascii_digit) {
+            digits.push(c);
+        }
+        digits.parse().ok()
+    }
+}
+
+/// The classes for `\d`, `\w` or `\s`.
+fn escape_class(c: char) -> Option<Vec<CharClass>> {
+    match c {
+        'd' => posix_class("digit").ok(),
+        'w' => {
+            let mut classes = posix_class("alnum").ok()?;
+            classes.push(CharClass::Single('_'));
+            Some(classes)
+        }
+        's' => posix_class("space").ok(),
+        _ => None,
+    }
+}
+
+/// The character written as `\c`.
+fn escaped_char(c: char) -> Result<char, PatternError> {
+    match c {
+        't' => Ok('\t'),
+        'r' => Ok('\r'),
+        'f' => Ok('\x0c'),
+        'v' => Ok('\x0b'),
+        '0' => Ok('\0'),
+        c if c.is_ascii_punctuation() || c == ' ' => Ok(c),
+        c => Err(PatternError::InvalidEscape(c)),
+    }
+}
+
+/// The classes making up POSIX character class `[:name:]`, as in the C locale.
+fn posix_class(name: &str) -> Result<Vec<CharClass>, PatternError> {
+    use CharClass::{Range, Single};
+    Ok(match name {
+        "alpha" => vec![Range('a', 'z'), Range('A', 'Z')],
+        "digit" => vec![Range('0', '9')],
+        "alnum" => vec![Range('a', 'z'), Range('A', 'Z'), Range('0', '9')],
+        "upper" => vec![Range('A', 'Z')],
+        "lower" => vec![Range('a', 'z')],
+        "space" => vec![Single(' '), Range('\t', '\r')],
+        "blank" => vec![Single(' '), Single('\t')],
+        "punct" => vec![
+            Range('!', '/'),
+            Range(':', '@'),
+            Range('[', '`'),
+            Range('{', '~'),
+        ],
+        "print" => vec![Range(' ', '~')],
+        "graph" => vec![Range('!', '~')],
+        "cntrl" => vec![Range('\0', '\x1f'), Single('\x7f')],
+        "xdigit" => vec![Range('0', '9'), Range('a', 'f'), Range('A', 'F')],
+        _ => return Err(PatternError::UnsupportedRegex("unknown POSIX class")),
+    })
+}
+
+/// Add the other case of every ASCII letter in `classes`.
+fn fold_classes(classes: &mut Vec<CharClass>) {
+    let mut folded = Vec::new();
+    for class in classes.iter() {
+        let (lo, hi) = match *class {
+            CharClass::Single(c) => (c, c),
+            CharClass::Range(lo, hi) => (lo, hi),
+        };
+        for (from, to) in [('a', 'A'), ('A', 'a')] {
+            let start = lo.max(from);
+            let end = hi.min((from as u8 + 25) as char);
+            if start <= end {
+                let shift = |c: char| (c as u8 - from as u8 + to as u8) as char;
+                folded.push(CharClass::Range(shift(start), shift(end)));
+            }
+        }
+    }
+    classes.extend(folded);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::parser::parse;
+
+    /// The regex and the Ludwig pattern translate to the same AST.
+    fn same(regex: &str, ludwig: &str) {
+        let r = parse_regex(regex).unwrap();
+        let l = parse(ludwig).unwrap();
+        assert_eq!(format!("{r:?}"), format!("{l:?}"), "{regex} vs {ludwig}");
+    }
+
+    #[test]
+    fn test_literals_and_sets() {
+        same("abc", "\"abc\"");
+        same("ab*c", "\"a\"*\"b\"\"c\"");
+        same("a\\.b", "\"a.b\"");
+        same("[a-z_]+", "+D/a..z_/");
+        same("[^0-9]", "-D/0..9/");
+        same(".", "-D//");
+        same("\\D", "-D/0..9/");
+        same("\\n", "#");
+    }
+
+    #[test]
+    fn test_structure() {
+        same("^(a|bc)?$", "<[0,1](\"a\"|\"bc\")>");
+        same("x{2}y{1,}z{,3}", "[2]\"x\"[1,]\"y\"[0,3]\"z\"");
+        same("(?<=a)b(?=c)", "\"a\",\"b\",\"c\"");
+        same("(?<=a)b", "\"a\",\"b\"");
+        same("b(?=c)", ",\"b\",\"c\"");
+    }
+
+    #[test]
+    fn test_case_fold() {
+        same("(?i)ab", "'ab'");
+        let p = parse_regex("(?i)[b-d]").unwrap();
+        let Element::CharSet(cs) = &p.middle.alternatives[0].items[0].element else {
+            panic!("expected a set");
+        };
+        assert_eq!(
+            cs.kind,
+            CharSetKind::Custom(vec![CharClass::Range('b', 'd'), CharClass::Range('B', 'D')])
+        );
+    }
+
+    #[test]
+    fn test_unicode_properties() {
+        same("\\p{Greek}+", "+X/Greek/");
+        same("\\PL", "-X/L/");
+        assert_eq!(
+            parse_regex("\\p{Nope}").err().map(|d| d.error),
+            Some(PatternError::UnknownProperty("Nope".into()))
+        );
+    }
+
+    #[test]
+    fn test_error_offsets() {
+        let offset = |s| parse_regex(s).expect_err("parse should fail").offset;
+        assert_eq!(offset("ab(c[d-f]"), 2);
+        assert_eq!(offset("ab[c(d"), 2);
+        assert_eq!(offset("(?<=ab"), 0);
+        assert_eq!(offset("a{2,1}"), 5);
+    }
+
+    #[test]
+    fn test_repeat_limits() {
+        let error = |s| parse_regex(s).err().map(|d| d.error);
+        assert_eq!(error("a{1,9999999999}"), Some(PatternError::RepeatTooLarge));
+        assert_eq!(parse_regex("ab{5000}").unwrap_err().offset, 2);
+        assert_eq!(
+            error("(a{1000}){1000}"),
+            Some(PatternError::PatternTooLarge)
+        );
+    }
+
+    #[test]
+    fn test_posix_classes() {
+        same("[[:digit:][:upper:]]", "D/0..9A..Z/");
+        assert!(parse_regex("[[:nope:]]").is_err());
+    }
+
+    #[test]
+    fn test_unsupported() {
+        for regex in [
+            "a*?", "(?:a)", "a(?=b)c", "a(?!b)", "\\bx", "(a)\\1", "a|b(?=c)", "x(?i)",
+        ] {
+            assert!(
+                matches!(
+                    parse_regex(regex).map_err(|d| d.error),
+                    Err(PatternError::UnsupportedRegex(_))
+                ),
+                "{regex}"
+            );
+        }
+        assert_eq!(
+            parse_regex("(a").err().map(|d| d.error),
+            Some(PatternError::UnclosedGroup)
+        );
+        assert_eq!(
+            parse_regex("a)").err().map(|d| d.error),
+            Some(PatternError::UnexpectedChar(')'))
+        );
+        assert_eq!(
+            parse_regex("*a").err().map(|d| d.error),
+            Some(PatternError::UnexpectedChar('*'))
+        );
+        assert_eq!(
+            parse_regex("[ab").err().map(|d| d.error),
+            Some(PatternError::UnclosedCustomSet)
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! Replacement templates for the `R` command in pattern mode.
+//!
+//! The replacement text is copied literally, except for these escapes:
+//!
+//! | Escape    | Meaning                                             |
+//! |-----------|-----------------------------------------------------|
+//! | `\0`      | The text matched by the middle context              |
+//! | `\1`–`\9` | The text matched by group 1–9, empty if unmatched   |
+//! | `\U`      | Uppercase what follows, up to `\E` or `\L`          |
+//! | `\L`      | Lowercase what follows, up to `\E` or `\U`          |
+//! | `\E`      | End a `\U` or `\L`                                  |
+//! | `\u`      | Uppercase the next character                        |
+//! | `\l`      | Lowercase the next character                        |
+//! | `\\`      | A backslash                                         |
+//!
+//! Case escapes apply to group text as well as literal text, so `\u\1`
+//! capitalises group 1.
+
+use std::borrow::Cow;
+use std::mem;
+
+use super::parser::PatternError;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Text(String),
+    Group(usize),
+    Upper,
+    Lower,
+    End,
+    UpperNext,
+    LowerNext,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Case {
+    Keep,
+    Upper,
+    Lower,
+}
+
+/// A parsed replacement template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+impl Template {
+    /// Parse `text` for a pattern with `group_count` groups.
+    ///
+    /// Fails on an unknown escape, a trailing `\`, or a reference to a group
+    /// the pattern does not have.
+    pub fn parse(text: &str, group_count: usize) -> Result<Self, PatternError> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '\\' {
+                literal.push(ch);
+                continue;
+            }
+            let piece = match chars.next().ok_or(PatternError::UnexpectedEnd)? {
+                '\\' => {
+                    literal.push('\\');
+                    continue;
+                }
+                digit @ '0'..='9' => {
+                    let n = digit as usize - '0' as usize;
+                    if n > group_count {
+                        return Err(PatternError::NoSuchGroup(n));
+                    }
+                    Piece::Group(n)
+                }
+                'U' => Piece::Upper,
+                'L' => Piece::Lower,
+                'E' => Piece::End,
+                'u' => Piece::UpperNext,
+                'l' => Piece::LowerNext,
+                other => return Err(PatternError::InvalidEscape(other)),
+            };
+            if !literal.is_empty() {
+                pieces.push(Piece::Text(mem::take(&mut literal)));
+            }
+            pieces.push(piece);
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Text(literal));
+        }
+        Ok(Self { pieces })
+    }
+
+    /// Expand the template, taking the text of group `n` from `group(n)`.
+    /// Group 0 is the middle context.
+    pub fn expand(&self, mut group: impl FnMut(usize) -> String) -> String {
+        let mut out = String::new();
+        let mut case = Case::Keep;
+        let mut next = Case::Keep;
+        for piece in &self.pieces {
+            let text = match piece {
+                Piece::Text(text) => Cow::Borrowed(text.as_str()),
+                Piece::Group(n) => Cow::Owned(group(*n)),
+                Piece::Upper => {
+                    case = Case::Upper;
+                    continue;
+                }
+                Piece::Lower => {
+                    case = Case::Lower;
+                    continue;
+                }
+                Piece::End => {
+                    case = Case::Keep;
+                    continue;
+                }
+                Piece::UpperNext => {
+                    next = Case::Upper;
+                    continue;
+                }
+                Piece::LowerNext => {
+                    next = Case::Lower;
+                    continue;
+                }
+            };
+            for ch in text.chars() {
+                match mem::replace(&mut next, Case::Keep) {
+                    Case::Keep => case.push(ch, &mut out),
+                    once => once.push(ch, &mut out),
+                }
+            }
+        }
+        out
+    }
+}
+
+impl Case {
+    fn push(self, ch: char, out: &mut String) {
+        match self {
+            Case::Keep => out.push(ch),
+            Case::Upper => out.extend(ch.to_uppercase()),
+            Case::Lower => out.extend(ch.to_lowercase()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(template: &str, groups: &[&str]) -> String {
+        Template::parse(template, groups.len() - 1)
+            .unwrap()
+            .expand(|n| groups[n].to_string())
+    }
+
+    #[test]
+    fn test_plain_text_is_copied() {
+        let template = Template::parse("a,b", 0).unwrap();
+        assert_eq!(template.expand(|_| unreachable!()), "a,b");
+    }
+
+    #[test]
+    fn test_groups_are_substituted() {
+        assert_eq!(expand("\\2,\\1 (\\0)", &["x,y", "x", "y"]), "y,x (x,y)");
+        assert_eq!(expand("a\\\\b", &[""]), "a\\b");
+    }
+
+    #[test]
+    fn test_case_escapes() {
+        let groups = ["", "hello", "WORLD"];
+        assert_eq!(expand("\\U\\1\\E \\2", &groups), "HELLO WORLD");
+        assert_eq!(expand("\\u\\1 \\L\\2!", &groups), "Hello world!");
+        assert_eq!(expand("\\L\\uwORLD", &groups), "World");
+        assert_eq!(expand("\\l\\2", &groups), "wORLD");
+    }
+
+    #[test]
+    fn test_bad_templates() {
+        assert_eq!(Template::parse("\\2", 1), Err(PatternError::NoSuchGroup(2)));
+        assert_eq!(
+            Template::parse("\\q", 1),
+            Err(PatternError::InvalidEscape('q'))
+        );
+        assert_eq!(Template::parse("x\\", 1), Err(PatternError::UnexpectedEnd));
+    }
+}
@@ -0,0 +1,232 @@
+//! Unicode property sets for `X/name/` in patterns and `\p{name}` in regexes.
+//!
+//! The legacy sets `N`, `S`, `P` and `C` are ASCII-only; these are their
+//! Unicode-aware counterparts. A name is one of:
+//!
+//! | Name                                     | Members                          |
+//! |------------------------------------------|----------------------------------|
+//! | `Lu`, `Uppercase_Letter`, `Nd`, `Zs`, …  | One general category             |
+//! | `L`, `M`, `N`, `P`, `S`, `Z`, `C`, `LC`  | A major class of categories      |
+//! | `Cn`, `Unassigned`                       | Code points not yet assigned     |
+//! | `Greek`, `Grek`, `Latin`, …              | One script                       |
+//! | `White_Space`, `Alphabetic`              | The binary properties            |
+//! | `Uppercase`, `Lowercase`                 | The binary properties            |
+//! | `Graph`, `Print`                         | As defined by UTS #18            |
+//!
+//! Names are matched loosely, as Unicode recommends: case, spaces, `-` and
+//! `_` are ignored, so `uppercase letter` is `Lu`. A `gc=` or `sc=` prefix
+//! (or `General_Category=`, `Script=`) restricts the lookup to categories or
+//! scripts. The tables are Unicode 14.0.
+
+use super::unicode_tables::{CATEGORIES, SCRIPTS};
+
+/// Bit for unassigned code points in a [`UnicodeClass::Category`] mask.
+const UNASSIGNED: u32 = 1 << 31;
+
+/// The major classes: their one-letter and long names and the prefix shared
+/// by their categories.
+const MAJOR_CLASSES: &[(&str, &str)] = &[
+    ("L", "Letter"),
+    ("M", "Mark"),
+    ("N", "Number"),
+    ("P", "Punctuation"),
+    ("S", "Symbol"),
+    ("Z", "Separator"),
+    ("C", "Other"),
+];
+
+/// A set of characters defined by a Unicode property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeClass {
+    /// One or more general categories, one bit per entry of the category
+    /// table plus a bit for unassigned code points.
+    Category(u32),
+    /// An entry of the script table.
+    Script(usize),
+    WhiteSpace,
+    Alphabetic,
+    Uppercase,
+    Lowercase,
+    /// Visible characters: anything assigned but white space and controls.
+    Graph,
+    /// `Graph` and the space separators.
+    Print,
+}
+
+impl UnicodeClass {
+    /// Look up a property by name, or `None` if there is no such property.
+    pub fn lookup(name: &str) -> Option<Self> {
+        let name = loose(name);
+        if let Some(value) = name
+            .strip_prefix("gc=")
+            .or_else(|| name.strip_prefix("generalcategory="))
+        {
+            return category(value);
+        }
+        if let Some(value) = name
+            .strip_prefix("sc=")
+            .or_else(|| name.strip_prefix("script="))
+        {
+            return script(value);
+        }
+        let property = match name.as_str() {
+            "whitespace" | "space" | "wspace" => Self::WhiteSpace,
+            "alphabetic" | "alpha" => Self::Alphabetic,
+            "uppercase" | "upper" => Self::Uppercase,
+            "lowercase" | "lower" => Self::Lowercase,
+            "graph" => Self::Graph,
+            "print" => Self::Print,
+            _ => return category(&name).or_else(|| script(&name)),
+        };
+        Some(property)
+    }
+
+    /// Test whether `ch` has this property.
+    pub fn contains(self, ch: char) -> bool {
+        match self {
+            Self::Category(mask) => mask & category_bit(ch) != 0,
+            Self::Script(index) => in_ranges(SCRIPTS[index].2, ch),
+            Self::WhiteSpace => ch.is_whitespace(),
+            Self::Alphabetic => ch.is_alphabetic(),
+            Self::Uppercase => ch.is_uppercase(),
+            Self::Lowercase => ch.is_lowercase(),
+            Self::Graph => is_graph(ch),
+            Self::Print => is_graph(ch) || is_space_separator(ch),
+        }
+    }
+}
+
+/// Lowercase `name` and drop spaces, `-` and `_`.
+fn loose(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '_'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn category(name: &str) -> Option<UnicodeClass> {
+    if name == "cn" || name == "unassigned" {
+        return Some(UnicodeClass::Category(UNASSIGNED));
+    }
+    if name == "lc" || name == "casedletter" {
+        return Some(UnicodeClass::Category(mask_of(|short| {
+            matches!(short, "Lu" | "Ll" | "Lt")
+        })));
+    }
+    if let Some(&(major, _)) = MAJOR_CLASSES
+        .iter()
+        .find(|(short, long)| loose(short) == name || loose(long) == name)
+    {
+        let mut mask = mask_of(|short| short.starts_with(major));
+        if major == "C" {
+            mask |= UNASSIGNED;
+        }
+        return Some(UnicodeClass::Category(mask));
+    }
+    let index = CATEGORIES
+        .iter()
+        .position(|(short, long, _)| loose(short) == name || loose(long) == name)?;
+    Some(UnicodeClass::Category(1 << index))
+}
+
+fn script(name: &str) -> Option<UnicodeClass> {
+    SCRIPTS
+        .iter()
+        .position(|(short, long, _)| loose(short) == name || loose(long) == name)
+        .map(UnicodeClass::Script)
+}
+
+/// The mask of the categories whose short names satisfy `pred`.
+fn mask_of(pred: impl Fn(&str) -> bool) -> u32 {
+    CATEGORIES
+        .iter()
+        .enumerate()
+        .filter(|(_, (short, _, _))| pred(short))
+        .fold(0, |mask, (index, _)| mask | 1 << index)
+}
+
+/// The mask bit of the category `ch` belongs to.
+fn category_bit(ch: char) -> u32 {
+    CATEGORIES
+        .iter()
+        .position(|(_, _, ranges)| in_ranges(ranges, ch))
+        .map_or(UNASSIGNED, |index| 1 << index)
+}
+
+fn is_graph(ch: char) -> bool {
+    !ch.is_whitespace() && !ch.is_control() && category_bit(ch) != UNASSIGNED
+}
+
+fn is_space_separator(ch: char) -> bool {
+    CATEGORIES
+        .iter()
+        .any(|(short, _, ranges)| *short == "Zs" && in_ranges(ranges, ch))
+}
+
+fn in_ranges(ranges: &[(char, char)], ch: char) -> bool {
+    ranges
+        .binary_search_by(|&(lo, hi)| {
+            if hi < ch {
+                std::cmp::Ordering::Less
+            } else if lo > ch {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn has(name: &str, ch: char) -> bool {
+        UnicodeClass::lookup(name)
+            .unwrap_or_else(|| panic!("unknown property {name}"))
+            .contains(ch)
+    }
+
+    #[test]
+    fn test_tables_fit_the_category_mask() {
+        assert!(CATEGORIES.len() < 31);
+        for (short, _, ranges) in CATEGORIES.iter().chain(SCRIPTS) {
+            assert!(
+                ranges.windows(2).all(|w| w[0].1 < w[1].0),
+                "{short} ranges are not sorted"
+            );
+        }
+    }
+
+    #[test]
+    fn test_categories() {
+        assert!(has("Lu", 'Δ'));
+        assert!(!has("Lu", 'δ'));
+        assert!(has("uppercase letter", 'Δ'));
+        assert!(has("gc=Ll", 'δ'));
+        assert!(has("L", 'δ') && has("Letter", '中'));
+        assert!(has("LC", 'Δ') && !has("LC", '中'));
+        assert!(has("Nd", '٣'));
+        assert!(has("P", '«') && has("Pi", '«'));
+        assert!(has("Zs", '\u{a0}'));
+        assert!(has("Cn", '\u{378}') && has("C", '\u{378}'));
+        assert!(!has("Cn", 'a'));
+    }
+
+    #[test]
+    fn test_scripts_and_properties() {
+        assert!(has("Greek", 'λ') && has("sc=Grek", 'λ'));
+        assert!(!has("Greek", 'l'));
+        assert!(has("Han", '中'));
+        assert!(has("White_Space", '\u{2003}'));
+        assert!(has("Graph", 'é') && !has("Graph", ' '));
+        assert!(has("Print", ' ') && !has("Print", '\t'));
+    }
+
+    #[test]
+    fn test_unknown_names() {
+        assert_eq!(UnicodeClass::lookup("Klingon"), None);
+        assert_eq!(UnicodeClass::lookup("sc=Lu"), None);
+        assert_eq!(UnicodeClass::lookup("gc=Greek"), None);
+    }
+}
@@ -0,0 +1,387 @@
+//! Turning compiled code back into Ludwig source.
+//!
+//! [`format_code`] writes one command per line in a canonical form: command
+//! names in upper case, leading parameters in their shortest spelling, and
+//! the bodies of compound commands and exit handlers indented by two spaces
+//! per level. A body holding at most one simple command is kept on the line
+//! of the command that owns it, as in `3(A)` or `G/x/[:XF]`.
+//!
+//! Compiling the output gives the same code back. The original layout is
+//! lost, but [`format_code_with_comments`] keeps the comments noted by
+//! [`compile_noting_comments`](crate::compiler::compile_noting_comments):
+//! each goes on a line of its own before the command it came before, or at
+//! the end of the line it ended, and a body with a comment in it is never
+//! kept on one line.
+
+use crate::code::{CompiledCode, ExitHandler, ExitLevels, Instruction, RepeatCount, SourcePos};
+use crate::compiler::{Comment, command_name};
+use crate::lead_param::LeadParam;
+use crate::marks::MarkId;
+
+/// Indentation added for each level of nesting.
+const INDENT: &str = "  ";
+
+/// Format `code` as canonical Ludwig source, one command per line.
+pub fn format_code(code: &CompiledCode) -> String {
+    format_code_with_comments(code, &[])
+}
+
+/// Format `code` as [`format_code`] does, putting back the `comments` from
+/// the source it was compiled from.
+pub fn format_code_with_comments(code: &CompiledCode, comments: &[Comment]) -> String {
+    let mut printer = Printer {
+        out: String::new(),
+        comments,
+        next: 0,
+        comment_end: None,
+    };
+    printer.write_sequence(code, 0, None);
+    printer.out
+}
+
+struct Printer<'c> {
+    out: String,
+    comments: &'c [Comment],
+    /// The first of `comments` not written yet.
+    next: usize,
+    /// How long `out` was after the last comment was written.
+    comment_end: Option<usize>,
+}
+
+impl<'c> Printer<'c> {
+    /// Write each instruction of `code` on a line of its own at `level`,
+    /// with the comments before `bound` that are inside it.
+    fn write_sequence(&mut self, code: &CompiledCode, level: usize, bound: Option<SourcePos>) {
+        let instructions = code.instructions();
+        for (i, instr) in instructions.iter().enumerate() {
+            self.write_comments(Some(start(instr)), level);
+            let next = instructions.get(i + 1).map(start).or(bound);
+            self.out.push_str(&INDENT.repeat(level));
+            self.write_instruction(instr, level, next);
+            self.out.push('\n');
+        }
+        self.write_comments(bound, level);
+    }
+
+    /// Write the comments before `bound` that are at least `level` deep.
+    fn write_comments(&mut self, bound: Option<SourcePos>, level: usize) {
+        while let Some(comment) = self.pending(bound, level) {
+            // A line can end with only one comment.
+            let ends_line = self.out.ends_with('\n') && self.comment_end != Some(self.out.len());
+            if comment.trailing && ends_line {
+                self.out.pop();
+                self.out.push_str(" !");
+            } else {
+                self.out.push_str(&INDENT.repeat(level));
+                self.out.push('!');
+            }
+            self.out.push_str(&comment.text);
+            self.out.push('\n');
+            self.comment_end = Some(self.out.len());
+            self.next += 1;
+        }
+    }
+
+    /// The next comment to write, if it is before `bound` and at least
+    /// `level` deep.
+    fn pending(&self, bound: Option<SourcePos>, level: usize) -> Option<&'c Comment> {
+        self.comments.get(self.next).filter(|comment| {
+            comment.depth >= level && bound.is_none_or(|bound| comment.pos < bound)
+        })
+    }
+
+    /// Write `instr`, whose source ends before `bound`.
+    fn write_instruction(&mut self, instr: &Instruction, level: usize, bound: Option<SourcePos>) {
+        match instr {
+            Instruction::SimpleCmd {
+                op,
+                lead,
+                tpars,
+                exit_handler,
+                ..
+            } => {
+                self.out.push_str(&lead_text(*lead));
+                self.out
+                    .push_str(&command_name(*op).unwrap_or_else(|| format!("{op:?}")));
+                // Trailing parameters share their delimiters: `R/old/new/`.
+                if let Some(first) = tpars.first() {
+                    self.out.push(first.delim);
+                }
+                for tpar in tpars {
+                    self.out.push_str(&tpar.content);
+                    self.out.push(tpar.delim);
+                }
+                if let Some(handler) = exit_handler {
+                    self.write_handler(handler, level, bound);
+                }
+            }
+            Instruction::CompoundCmd {
+                repeat,
+                body,
+                exit_handler,
+                span,
+            } => {
+                match repeat {
+                    RepeatCount::Once => {}
+                    RepeatCount::Times(n) => self.out.push_str(&n.to_string()),
+                    RepeatCount::Indefinite => self.out.push('>'),
+                    RepeatCount::Counter(name) => {
+                        self.out.push('#');
+                        self.out.push(*name);
+                    }
+                }
+                if is_flat(Some(body)) && self.pending(Some(span.end), level + 1).is_none() {
+                    self.out.push('(');
+                    self.write_inline(Some(body));
+                    self.out.push(')');
+                } else {
+                    self.out.push_str("(\n");
+                    self.write_sequence(body, level + 1, Some(span.end));
+                    self.out.push_str(&INDENT.repeat(level));
+                    self.out.push(')');
+                }
+                if let Some(handler) = exit_handler {
+                    self.write_handler(handler, level, bound);
+                }
+            }
+            Instruction::ExitSuccess(levels, _) => {
+                self.out.push_str(&levels_text(*levels));
+                self.out.push_str("XS");
+            }
+            Instruction::ExitFailure(levels, _) => {
+                self.out.push_str(&levels_text(*levels));
+                self.out.push_str("XF");
+            }
+            Instruction::ExitAbort(_) => self.out.push_str("XA"),
+        }
+    }
+
+    /// Write an exit handler, ending before `bound`, on one line if both of
+    /// its branches are flat.
+    fn write_handler(&mut self, handler: &ExitHandler, level: usize, bound: Option<SourcePos>) {
+        let on_success = handler.on_success.as_ref();
+        let on_failure = handler.on_failure.as_ref();
+        if is_flat(on_success) && is_flat(on_failure) && self.pending(bound, level + 1).is_none() {
+            self.out.push('[');
+            self.write_inline(on_success);
+            if on_failure.is_some() {
+                self.out.push(':');
+                self.write_inline(on_failure);
+            }
+            self.out.push(']');
+            return;
+        }
+        self.out.push_str("[\n");
+        if let Some(code) = on_success {
+            // Comments between the branches stay with the first.
+            let failure_start = on_failure
+                .and_then(|code| code.instructions().first())
+                .map(start);
+            self.write_sequence(code, level + 1, failure_start.or(bound));
+        }
+        if let Some(code) = on_failure {
+            self.out.push_str(&INDENT.repeat(level));
+            self.out.push_str(":\n");
+            self.write_sequence(code, level + 1, bound);
+        }
+        self.out.push_str(&INDENT.repeat(level));
+        self.out.push(']');
+    }
+
+    /// Write a flat body on the current line.
+    fn write_inline(&mut self, code: Option<&CompiledCode>) {
+        if let Some(instr) = code.and_then(|code| code.instructions().first()) {
+            self.write_instruction(instr, 0, None);
+        }
+    }
+}
+
+/// Where the source of `instr` starts.
+fn start(instr: &Instruction) -> SourcePos {
+    match instr {
+        Instruction::SimpleCmd { span, .. } | Instruction::CompoundCmd { span, .. } => span.start,
+        Instruction::ExitSuccess(_, span)
+        | Instruction::ExitFailure(_, span)
+        | Instruction::ExitAbort(span) => span.start,
+    }
+}
+
+/// Whether `code` is empty or a single instruction with nothing nested in it,
+/// and so can be written on one line.
+fn is_flat(code: Option<&CompiledCode>) -> bool {
+    let Some(code) = code else {
+        return true;
+    };
+    match code.instructions() {
+        [] => true,
+        [instr] => match instr {
+            Instruction::SimpleCmd { exit_handler, .. } => exit_handler.is_none(),
+            Instruction::CompoundCmd { .. } => false,
+            _ => true,
+        },
+        _ => false,
+    }
+}
+
+/// The canonical spelling of a leading parameter.
+pub(crate) fn lead_text(lead: LeadParam) -> String {
+    match lead {
+        LeadParam::None => String::new(),
+        LeadParam::Plus => "+".to_string(),
+        LeadParam::Minus => "-".to_string(),
+        LeadParam::Pint(n) => n.to_string(),
+        LeadParam::Nint(n) => format!("-{n}"),
+        LeadParam::Pindef => ">".to_string(),
+        LeadParam::Nindef => "<".to_string(),
+        LeadParam::Marker(MarkId::Numbered(n)) => format!("@{n}"),
+        LeadParam::Marker(MarkId::Equals) => "=".to_string(),
+        LeadParam::Marker(MarkId::Modified) => "%".to_string(),
+        LeadParam::Marker(_) => "@".to_string(),
+        LeadParam::Counter(name) => format!("#{name}"),
+    }
+}
+
+/// The leading parameter of `XS` or `XF` exiting `levels`.
+pub(crate) fn levels_text(levels: ExitLevels) -> String {
+    match levels {
+        ExitLevels::Count(1) => String::new(),
+        ExitLevels::Count(n) => n.to_string(),
+        ExitLevels::All => ">".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+    use crate::compiler::compile_noting_comments;
+
+    fn format(source: &str) -> String {
+        format_code(&compile(source).unwrap())
+    }
+
+    fn format_commented(source: &str) -> String {
+        let (code, comments) = compile_noting_comments(source).unwrap();
+        format_code_with_comments(&code, &comments)
+    }
+
+    /// Format `source` keeping its comments, and check that the result
+    /// formats to itself and compiles to the same code.
+    fn assert_formats_commented(source: &str, expected: &str) {
+        let formatted = format_commented(source);
+        assert_eq!(formatted, expected);
+        assert_eq!(format_commented(&formatted), formatted, "not stable");
+        assert_eq!(format(&formatted), format(source), "code changed");
+    }
+
+    /// Format `source`, and check that the result formats to itself.
+    fn assert_formats(source: &str, expected: &str) {
+        let formatted = format(source);
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted), formatted, "not stable");
+    }
+
+    #[test]
+    fn test_simple_commands() {
+        assert_formats("a 2j -3d i/text/", "A\n2J\n-3D\nI/text/\n");
+        assert_formats("+a .j ,d @2a =j %j", "+A\n>J\n<D\n@2A\n=J\n%J\n");
+        assert_formats("+4a r'old'new'", "4A\nR'old'new'\n");
+    }
+
+    #[test]
+    fn test_comments_and_whitespace_dropped() {
+        assert_formats("  a ! advance\n\n   j\n", "A\nJ\n");
+        assert_formats("", "");
+    }
+
+    #[test]
+    fn test_trailing_params_kept_verbatim() {
+        assert_formats("i/two\nlines/ g!x!", "I/two\nlines/\nG!x!\n");
+    }
+
+    #[test]
+    fn test_exits() {
+        assert_formats("xs 1xf 3xs >xf xa", "XS\nXF\n3XS\n>XF\nXA\n");
+    }
+
+    #[test]
+    fn test_flat_bodies_inline() {
+        assert_formats("3(a) >(j) ()", "3(A)\n>(J)\n()\n");
+        assert_formats(
+            "a[j:xf] a[:j] a[j] a[] a[:]",
+            "A[J:XF]\nA[:J]\nA[J]\nA[]\nA[]\n",
+        );
+        assert_formats("2(a)[xs]", "2(A)[XS]\n");
+        assert_formats("#a(j) #bj", "#A(J)\n#BJ\n");
+    }
+
+    #[test]
+    fn test_nested_bodies_indented() {
+        assert_formats(
+            ">(g/x/ 2(d a[:xf]))",
+            ">(\n  G/x/\n  2(\n    D\n    A[:XF]\n  )\n)\n",
+        );
+    }
+
+    #[test]
+    fn test_handler_blocks_indented() {
+        assert_formats("a[j 2a : i/x/]", "A[\n  J\n  2A\n:\n  I/x/\n]\n");
+        assert_formats(
+            "(a[:(j d)])",
+            "(\n  A[\n  :\n    (\n      J\n      D\n    )\n  ]\n)\n",
+        );
+    }
+
+    #[test]
+    fn test_comments_kept() {
+        assert_formats_commented(
+            "! head\na   ! advance\n\n j\n!tail",
+            "! head\nA ! advance\nJ\n!tail\n",
+        );
+        assert_formats_commented("a ! one\n! two\nj", "A ! one\n! two\nJ\n");
+    }
+
+    #[test]
+    fn test_comments_unflatten_bodies() {
+        assert_formats_commented("3(a ! step\n)", "3(\n  A ! step\n)\n");
+        assert_formats_commented("( ! start\n)", "( ! start\n)\n");
+        assert_formats_commented(
+            "a[j ! moved\n : ! failed\nxf]",
+            "A[\n  J ! moved\n  ! failed\n:\n  XF\n]\n",
+        );
+        // Comments after a body stay outside it.
+        assert_formats_commented("2(a) ! twice\nj", "2(A) ! twice\nJ\n");
+        assert_formats_commented("a[j] ! handled\nd", "A[J] ! handled\nD\n");
+    }
+
+    #[test]
+    fn test_commented_macro_round_trips() {
+        let source = "\
+! Swap the first two fields of each line.
+<a                ! from the top
+>( ! each line
+  g/,/ [ : xs ]   ! lines without a comma are left
+  sa/f/-=/        ! the first field
+  ( j ! past the comma
+    sa/s/=/ )
+  a
+)
+";
+        assert_formats_commented(
+            source,
+            "\
+! Swap the first two fields of each line.
+<A ! from the top
+>( ! each line
+  G/,/[:XS] ! lines without a comma are left
+  SA/f/-=/ ! the first field
+  (
+    J ! past the comma
+    SA/s/=/
+  )
+  A
+)
+",
+        );
+    }
+}
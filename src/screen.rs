@@ -258,21 +258,24 @@ impl Screen {
         terminal.move_cursor(col, row);
     }
 
-    /// Show a message on the bottom line(s) of the screen.
+    /// Show a message on the bottom line(s) of the screen, one row per line
+    /// of `msg`. Lines wider than the screen are cut off.
     pub fn show_message(&mut self, terminal: &mut dyn Terminal, msg: &str) {
         let height = self.viewport.params.height;
-        let width = self.viewport.params.width;
-        let row = height - 1;
-        self.msg_rows = 1;
+        let lines: Vec<&str> = msg.lines().collect();
+        self.msg_rows = lines.len().clamp(1, height);
+        let first = height - self.msg_rows;
 
         // Write message into next buffer, diff, swap
-        self.next.clear_row(row);
-        self.next.write_str(0, row, &msg[..msg.len().min(width)]);
+        for (row, line) in (first..height).zip(lines.iter().chain(std::iter::repeat(&""))) {
+            self.next.clear_row(row);
+            self.next.write_str(0, row, line);
+        }
         // Copy all other rows from current
-        for r in 0..row {
+        for r in 0..first {
             self.next.copy_row_from(r, &self.current, r);
         }
-        CellBuffer::diff(&self.current, &self.next, terminal, row);
+        CellBuffer::diff(&self.current, &self.next, terminal, first);
         std::mem::swap(&mut self.current, &mut self.next);
         terminal.flush();
     }
@@ -464,6 +467,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_show_multi_line_message() {
+        let mut screen = Screen::new(TermSize {
+            width: 80,
+            height: 24,
+        });
+        let mut term = MockTerminal::new(80, 24);
+
+        screen.show_message(&mut term, "Bad pattern\n(é\n^");
+        assert_eq!(screen.msg_rows, 3);
+        assert_eq!(screen.text_height(), 21);
+        for line in ["Bad pattern", "(é", "^"] {
+            assert!(
+                term.ops
+                    .iter()
+                    .any(|op| matches!(op, MockOp::WriteStr(s) if s.contains(line))),
+                "{line}"
+            );
+        }
+    }
+
     #[test]
     fn test_clear_message() {
         let mut screen = Screen::new(TermSize {
@@ -5,6 +5,7 @@
 //! its boundary marks so they can be resolved at runtime.
 
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::code::CompiledCode;
 use crate::marks::MarkId;
@@ -18,7 +19,7 @@ pub struct Span {
     /// Later boundary mark (`SpanBound` variant).
     pub mark_end: MarkId,
     /// Cached compiled body, populated by SR and used by EX/EN.
-    code: Option<CompiledCode>,
+    code: Option<Arc<CompiledCode>>,
 }
 
 impl Span {
@@ -33,13 +34,13 @@ impl Span {
     }
 
     /// Get the compiled code for the span
-    pub fn get_code(&self) -> Option<&CompiledCode> {
+    pub fn get_code(&self) -> Option<&Arc<CompiledCode>> {
         self.code.as_ref()
     }
 
     /// Set the compiled code for the span
-    pub fn set_code(&mut self, code: CompiledCode) {
-        self.code = Some(code)
+    pub fn set_code(&mut self, code: impl Into<Arc<CompiledCode>>) {
+        self.code = Some(code.into())
     }
 
     /// Unset the compiled code for the span
@@ -4,10 +4,12 @@
 //! - `CrosstermTerminal` for real terminal interaction
 //! - `MockTerminal` for testing
 
+use std::collections::VecDeque;
 use std::io::Write;
+use std::time::Duration;
 
 use anyhow::Result;
-use crossterm::event::{Event, KeyEvent};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
 
 /// Terminal dimensions.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -57,6 +59,13 @@ pub trait Terminal {
     /// Block until a key event is received.
     fn read_key(&mut self) -> Result<KeyEvent>;
 
+    /// Without blocking, check whether Ctrl-C has been pressed since this
+    /// was last called. Other keys pressed meanwhile are kept for
+    /// [`read_key`](Self::read_key).
+    fn poll_interrupt(&mut self) -> bool {
+        false
+    }
+
     /// Set the scroll region (top_row..=bottom_row inclusive, 0-based).
     fn set_scroll_region(&mut self, top: u16, bottom: u16);
 
@@ -68,6 +77,8 @@ pub trait Terminal {
 pub struct CrosstermTerminal {
     size: TermSize,
     cursor_visible: bool,
+    /// Keys read by `poll_interrupt` that are still to be returned.
+    pending: VecDeque<KeyEvent>,
 }
 
 impl Default for CrosstermTerminal {
@@ -85,6 +96,24 @@ impl CrosstermTerminal {
                 height: h,
             },
             cursor_visible: true,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// The key for `event`, with resizes as the F63 sentinel key, or `None`
+    /// for events that are ignored.
+    fn key_for(&mut self, event: Event) -> Option<KeyEvent> {
+        match event {
+            Event::Key(key) => Some(key),
+            Event::Resize(w, h) => {
+                self.size = TermSize {
+                    width: w,
+                    height: h,
+                };
+                // Resize events are returned as a special key
+                Some(KeyEvent::new(KeyCode::F(63), KeyModifiers::NONE))
+            }
+            _ => None, // Ignore mouse events etc.
         }
     }
 
@@ -179,24 +208,35 @@ impl Terminal for CrosstermTerminal {
     }
 
     fn read_key(&mut self) -> Result<KeyEvent> {
+        if let Some(key) = self.pending.pop_front() {
+            return Ok(key);
+        }
         self.cursor(true);
         loop {
-            match crossterm::event::read()? {
-                Event::Key(key) => return Ok(key),
-                Event::Resize(w, h) => {
-                    self.size = TermSize {
-                        width: w,
-                        height: h,
-                    };
-                    // Resize events are returned as a special key
-                    return Ok(KeyEvent::new(
-                        crossterm::event::KeyCode::F(63),
-                        crossterm::event::KeyModifiers::NONE,
-                    ));
+            let event = crossterm::event::read()?;
+            if let Some(key) = self.key_for(event) {
+                return Ok(key);
+            }
+        }
+    }
+
+    fn poll_interrupt(&mut self) -> bool {
+        while let Ok(true) = crossterm::event::poll(Duration::ZERO) {
+            let Ok(event) = crossterm::event::read() else {
+                break;
+            };
+            match self.key_for(event) {
+                Some(key)
+                    if key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL) =>
+                {
+                    return true;
                 }
-                _ => {} // Ignore mouse events etc.
+                Some(key) => self.pending.push_back(key),
+                None => {}
             }
         }
+        false
     }
 
     fn set_scroll_region(&mut self, top: u16, bottom: u16) {
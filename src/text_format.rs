@@ -0,0 +1,480 @@
+//! On-disk text format: encoding, line endings, byte order mark and final newline.
+//!
+//! Frames always hold text with `\n` line endings. The [`TextFormat`] detected
+//! when a file is loaded is kept on the frame so that saving reproduces the
+//! original layout, byte for byte, unless the user changes it with `EP`.
+//!
+//! Bytes that cannot be decoded in the file's encoding are carried through the
+//! frame as characters in the range `U+10FF00..=U+10FFFF` and written back
+//! unchanged on save. A character in that range that the file really holds
+//! is carried as the raw bytes that encode it, so that it too is written back
+//! as it was.
+
+use std::fmt;
+use std::str::FromStr;
+
+const BOM: char = '\u{feff}';
+
+/// First of the 256 characters used to carry undecodable bytes.
+const RAW_BYTE_BASE: u32 = 0x10_ff00;
+
+/// Character standing in for an undecodable byte.
+fn raw_byte_char(b: u8) -> char {
+    char::from_u32(RAW_BYTE_BASE + u32::from(b)).expect("valid code point")
+}
+
+/// The byte carried by `ch`, if it stands in for an undecodable byte.
+fn raw_byte(ch: char) -> Option<u8> {
+    u32::from(ch)
+        .checked_sub(RAW_BYTE_BASE)
+        .and_then(|b| u8::try_from(b).ok())
+}
+
+/// Character encoding of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    /// ISO-8859-1: every byte maps to the code point of the same value.
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// A character that cannot be represented in the target encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeError {
+    pub ch: char,
+    pub encoding: Encoding,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "character U+{:04X} cannot be encoded in {}",
+            u32::from(self.ch),
+            self.encoding
+        )
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+impl Encoding {
+    /// Guess the encoding of file contents.
+    ///
+    /// A UTF-16 byte order mark selects UTF-16; otherwise valid UTF-8 is taken
+    /// as UTF-8 and anything else as Latin-1, which can decode every byte.
+    pub fn detect(bytes: &[u8]) -> Encoding {
+        if bytes.starts_with(&[0xff, 0xfe]) {
+            Encoding::Utf16Le
+        } else if bytes.starts_with(&[0xfe, 0xff]) {
+            Encoding::Utf16Be
+        } else if std::str::from_utf8(bytes).is_ok() {
+            Encoding::Utf8
+        } else {
+            Encoding::Latin1
+        }
+    }
+
+    /// Decode `bytes`, carrying any invalid sequences through as raw bytes.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => decode_utf8(bytes),
+            Encoding::Latin1 => bytes.iter().map(|&b| char::from(b)).collect(),
+            Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes, |u| u.to_le_bytes()),
+            Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes, |u| u.to_be_bytes()),
+        }
+    }
+
+    /// Encode `text`, writing raw-byte characters back as the original bytes.
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, EncodeError> {
+        let mut out = Vec::with_capacity(text.len());
+        for ch in text.chars() {
+            if let Some(b) = raw_byte(ch) {
+                out.push(b);
+                continue;
+            }
+            match self {
+                Encoding::Utf8 => {
+                    out.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Encoding::Latin1 => match u8::try_from(ch) {
+                    Ok(b) => out.push(b),
+                    Err(_) => {
+                        return Err(EncodeError { ch, encoding: self });
+                    }
+                },
+                Encoding::Utf16Le | Encoding::Utf16Be => {
+                    for unit in ch.encode_utf16(&mut [0; 2]) {
+                        if self == Encoding::Utf16Le {
+                            out.extend_from_slice(&unit.to_le_bytes());
+                        } else {
+                            out.extend_from_slice(&unit.to_be_bytes());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Latin1 => "Latin-1",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+        })
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    /// Parse an encoding name, ignoring case and `-`/`_` separators.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name: String = s
+            .chars()
+            .filter(|c| !matches!(c, '-' | '_'))
+            .collect::<String>()
+            .to_ascii_uppercase();
+        match name.as_str() {
+            "UTF8" => Ok(Encoding::Utf8),
+            "LATIN1" | "ISO88591" => Ok(Encoding::Latin1),
+            "UTF16LE" => Ok(Encoding::Utf16Le),
+            "UTF16BE" => Ok(Encoding::Utf16Be),
+            _ => Err(format!("unknown encoding '{}'", s)),
+        }
+    }
+}
+
+fn decode_utf8(mut bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(s) => {
+                push_decoded(&mut out, s);
+                return out;
+            }
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                push_decoded(
+                    &mut out,
+                    std::str::from_utf8(valid).expect("validated prefix"),
+                );
+                let bad = e.error_len().unwrap_or(rest.len());
+                out.extend(rest[..bad].iter().map(|&b| raw_byte_char(b)));
+                bytes = &rest[bad..];
+            }
+        }
+    }
+}
+
+/// Append decoded UTF-8 `text`, escaping any characters that would be taken
+/// for raw bytes.
+fn push_decoded(out: &mut String, text: &str) {
+    for ch in text.chars() {
+        match raw_byte(ch) {
+            Some(_) => out.extend(ch.encode_utf8(&mut [0; 4]).bytes().map(raw_byte_char)),
+            None => out.push(ch),
+        }
+    }
+}
+
+fn decode_utf16(
+    bytes: &[u8],
+    from_bytes: fn([u8; 2]) -> u16,
+    to_bytes: fn(u16) -> [u8; 2],
+) -> String {
+    let chunks = bytes.chunks_exact(2);
+    let trailing = chunks.remainder();
+    let units = chunks.map(|c| from_bytes([c[0], c[1]]));
+    let mut out = String::with_capacity(bytes.len() / 2);
+    for unit in char::decode_utf16(units) {
+        match unit {
+            Ok(ch) if raw_byte(ch).is_some() => {
+                for unit in ch.encode_utf16(&mut [0; 2]) {
+                    out.extend(to_bytes(*unit).map(raw_byte_char));
+                }
+            }
+            Ok(ch) => out.push(ch),
+            Err(e) => out.extend(to_bytes(e.unpaired_surrogate()).map(raw_byte_char)),
+        }
+    }
+    out.extend(trailing.iter().map(|&b| raw_byte_char(b)));
+    out
+}
+
+/// Line terminator style of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    /// `\n` (Unix).
+    #[default]
+    Lf,
+    /// `\r\n` (Windows, VMS text exchange).
+    CrLf,
+    /// `\r` (classic Mac OS).
+    Cr,
+}
+
+impl LineEnding {
+    /// The terminator as written to disk.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+            LineEnding::Cr => "CR",
+        })
+    }
+}
+
+impl FromStr for LineEnding {
+    type Err = ();
+
+    /// Parse `LF`, `CRLF` or `CR`, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "LF" => Ok(LineEnding::Lf),
+            "CRLF" => Ok(LineEnding::CrLf),
+            "CR" => Ok(LineEnding::Cr),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How a frame's text is laid out on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextFormat {
+    /// Character encoding of the file.
+    pub encoding: Encoding,
+    /// Terminator written at the end of each line.
+    pub line_ending: LineEnding,
+    /// Whether the file starts with a byte order mark.
+    pub bom: bool,
+    /// Whether the last line is terminated.
+    pub final_newline: bool,
+}
+
+impl Default for TextFormat {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::Utf8,
+            line_ending: LineEnding::Lf,
+            bom: false,
+            final_newline: true,
+        }
+    }
+}
+
+impl TextFormat {
+    /// Decode raw file contents and detect their format.
+    ///
+    /// The encoding is guessed with [`Encoding::detect`] unless one is given.
+    pub fn decode(bytes: &[u8], encoding: Option<Encoding>) -> (TextFormat, String) {
+        let encoding = encoding.unwrap_or_else(|| Encoding::detect(bytes));
+        let (format, text) = Self::detect(&encoding.decode(bytes));
+        (TextFormat { encoding, ..format }, text)
+    }
+
+    /// Detect the format of file contents and normalise them to `\n` endings.
+    ///
+    /// The line ending style is taken from the first terminator in the file;
+    /// files with mixed endings are saved using that style throughout.
+    pub fn detect(contents: &str) -> (TextFormat, String) {
+        let (bom, body) = match contents.strip_prefix(BOM) {
+            Some(rest) => (true, rest),
+            None => (false, contents),
+        };
+        let line_ending = match body.find(['\r', '\n']) {
+            Some(i) if body[i..].starts_with("\r\n") => LineEnding::CrLf,
+            Some(i) if body[i..].starts_with('\r') => LineEnding::Cr,
+            _ => LineEnding::Lf,
+        };
+        let final_newline = body.is_empty() || body.ends_with(['\r', '\n']);
+        let normalised = body.replace("\r\n", "\n").replace('\r', "\n");
+        let format = TextFormat {
+            encoding: Encoding::Utf8,
+            line_ending,
+            bom,
+            final_newline,
+        };
+        (format, normalised)
+    }
+
+    /// Convert `\n`-terminated frame text back to file contents in this format.
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, EncodeError> {
+        self.encoding.encode(&self.layout(text))
+    }
+
+    /// Encode a run of complete lines from the middle of a file.
+    ///
+    /// The BOM is only written if `first` is set, and the final newline
+    /// setting is ignored.
+    pub fn encode_chunk(&self, text: &str, first: bool) -> Result<Vec<u8>, EncodeError> {
+        let format = TextFormat {
+            bom: self.bom && first,
+            final_newline: true,
+            ..*self
+        };
+        format.encode(text)
+    }
+
+    /// Apply line endings, BOM and final newline to `\n`-terminated text.
+    fn layout(&self, text: &str) -> String {
+        let text = match text.strip_suffix('\n') {
+            Some(body) if !self.final_newline => body,
+            _ => text,
+        };
+        let mut out = String::with_capacity(text.len() + 1);
+        if self.bom {
+            out.push(BOM);
+        }
+        match self.line_ending {
+            LineEnding::Lf => out.push_str(text),
+            ending => out.push_str(&text.replace('\n', ending.as_str())),
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(contents: &str) -> String {
+        let (format, text) = TextFormat::detect(contents);
+        format.layout(&text)
+    }
+
+    fn round_trip_bytes(bytes: &[u8], encoding: Option<Encoding>) -> Vec<u8> {
+        let (format, text) = TextFormat::decode(bytes, encoding);
+        format.encode(&text).unwrap()
+    }
+
+    #[test]
+    fn test_detect_lf() {
+        let (format, text) = TextFormat::detect("a\nb\n");
+        assert_eq!(format, TextFormat::default());
+        assert_eq!(text, "a\nb\n");
+    }
+
+    #[test]
+    fn test_detect_crlf_normalises() {
+        let (format, text) = TextFormat::detect("a\r\nb\r\n");
+        assert_eq!(format.line_ending, LineEnding::CrLf);
+        assert_eq!(text, "a\nb\n");
+    }
+
+    #[test]
+    fn test_detect_cr_normalises() {
+        let (format, text) = TextFormat::detect("a\rb\r");
+        assert_eq!(format.line_ending, LineEnding::Cr);
+        assert_eq!(text, "a\nb\n");
+    }
+
+    #[test]
+    fn test_detect_bom_and_missing_final_newline() {
+        let (format, text) = TextFormat::detect("\u{feff}a\nb");
+        assert!(format.bom);
+        assert!(!format.final_newline);
+        assert_eq!(text, "a\nb");
+    }
+
+    #[test]
+    fn test_round_trips_are_exact() {
+        for contents in [
+            "",
+            "a\n",
+            "a\r\nb\r\n",
+            "a\rb",
+            "\u{feff}a\r\nb",
+            "\u{feff}",
+        ] {
+            assert_eq!(round_trip(contents), contents);
+        }
+    }
+
+    #[test]
+    fn test_encode_adds_terminator_only_once() {
+        let format = TextFormat {
+            final_newline: false,
+            ..TextFormat::default()
+        };
+        assert_eq!(format.layout("a\nb\n"), "a\nb");
+    }
+
+    #[test]
+    fn test_detect_encoding() {
+        assert_eq!(Encoding::detect(b"caf\xc3\xa9"), Encoding::Utf8);
+        assert_eq!(Encoding::detect(b"caf\xe9"), Encoding::Latin1);
+        assert_eq!(Encoding::detect(b"\xff\xfea\x00"), Encoding::Utf16Le);
+        assert_eq!(Encoding::detect(b"\xfe\xff\x00a"), Encoding::Utf16Be);
+    }
+
+    #[test]
+    fn test_decode_latin1() {
+        let (format, text) = TextFormat::decode(b"caf\xe9\r\n", None);
+        assert_eq!(format.encoding, Encoding::Latin1);
+        assert_eq!(format.line_ending, LineEnding::CrLf);
+        assert_eq!(text, "café\n");
+    }
+
+    #[test]
+    fn test_decode_utf16_with_bom() {
+        let (format, text) = TextFormat::decode(b"\xfe\xff\x00a\x00\n", None);
+        assert_eq!(format.encoding, Encoding::Utf16Be);
+        assert!(format.bom);
+        assert_eq!(text, "a\n");
+    }
+
+    #[test]
+    fn test_byte_round_trips_are_exact() {
+        let cases: [(&[u8], Option<Encoding>); 7] = [
+            (b"caf\xe9\r\nna\xefve", None),
+            // U+10FF41, which is valid text, and not a raw byte.
+            ("x\u{10ff41}y\n".as_bytes(), None),
+            (b"\xff\xfe\xff\xdb\x41\xdf", None),
+            (b"\xff\xfeh\x00i\x00\r\x00\n\x00", None),
+            (b"bad \xff\xc3 utf8\n", Some(Encoding::Utf8)),
+            (b"\xff\xfe\x00\xd8a\x00\x01", None),
+            (b"\xef\xbb\xbfbom\n", None),
+        ];
+        for (bytes, encoding) in cases {
+            assert_eq!(round_trip_bytes(bytes, encoding), bytes);
+        }
+    }
+
+    #[test]
+    fn test_latin1_rejects_wide_characters() {
+        let err = Encoding::Latin1.encode("\u{20ac}").unwrap_err();
+        assert_eq!(err.ch, '\u{20ac}');
+    }
+
+    #[test]
+    fn test_encoding_from_str() {
+        assert_eq!("iso-8859-1".parse(), Ok(Encoding::Latin1));
+        assert_eq!("utf_16le".parse(), Ok(Encoding::Utf16Le));
+        assert!("ebcdic".parse::<Encoding>().is_err());
+    }
+
+    #[test]
+    fn test_line_ending_from_str() {
+        assert_eq!("crlf".parse(), Ok(LineEnding::CrLf));
+        assert_eq!("Cr".parse(), Ok(LineEnding::Cr));
+        assert!("x".parse::<LineEnding>().is_err());
+    }
+}
@@ -0,0 +1,59 @@
+#!/usr/bin/perl
+# Generate src/pattern/unicode_tables.rs from the Unicode Character Database
+# bundled with Perl:
+#
+#     perl tools/gen-unicode-tables.pl > src/pattern/unicode_tables.rs
+
+use strict;
+use warnings;
+use Unicode::UCD qw(prop_invlist prop_values prop_value_aliases);
+
+sub ranges {
+    my @inv = prop_invlist(shift);
+    push @inv, 0x110000 if @inv % 2;
+    my @ranges;
+    while (my ($lo, $end) = splice(@inv, 0, 2)) {
+        my $hi = $end - 1;
+        # Surrogates are not chars.
+        next if $lo >= 0xD800 && $hi <= 0xDFFF;
+        push @ranges, sprintf("('\\u{%X}', '\\u{%X}')", $lo, $hi);
+    }
+    my $out = '';
+    while (my @line = splice(@ranges, 0, 4)) {
+        $out .= "        " . join(", ", @line) . ",\n";
+    }
+    return $out;
+}
+
+sub table {
+    my ($name, $doc, $prop, @values) = @_;
+    print "/// $doc\n";
+    print "pub(crate) const $name: &[(&str, &str, Ranges)] = &[\n";
+    for my $value (@values) {
+        # Some values, such as Katakana_Or_Hiragana, have no code points.
+        my ($short, $long) = prop_value_aliases($prop, $value) or next;
+        print "    (\"$short\", \"$long\", &[\n", ranges("$prop=$short"), "    ]),\n";
+    }
+    print "];\n";
+}
+
+my $version = Unicode::UCD::UnicodeVersion();
+print <<"END";
+//! Unicode $version general category and script tables.
+//!
+//! Generated by `tools/gen-unicode-tables.pl`; do not edit.
+
+/// Sorted, disjoint, inclusive ranges of characters.
+pub(crate) type Ranges = &'static [(char, char)];
+
+END
+
+# Only the two-letter categories: the one-letter classes are unions of them,
+# and unassigned code points (Cn) are those in no other category.
+my @categories = grep { length == 2 && $_ ne 'LC' && $_ ne 'Cn' && $_ ne 'Cs' }
+    sort(prop_values('gc'));
+table('CATEGORIES', 'General categories: short name, long name and ranges.',
+    'gc', @categories);
+print "\n";
+my @scripts = grep { $_ ne 'Zzzz' } sort(prop_values('sc'));
+table('SCRIPTS', 'Scripts: short name, long name and ranges.', 'sc', @scripts);